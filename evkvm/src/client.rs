@@ -1,18 +1,36 @@
 use anyhow::{Context, Error};
-use input::WriterManager;
-use net::{self, Message, PROTOCOL_VERSION};
+use futures::future::{AbortHandle, Abortable};
+use futures::stream::{FuturesUnordered, StreamExt};
+use input::{Direction, Event, InputEvent, KeyKind, WriterManager};
+use net::{self, Hello, Message, Purpose, Role};
 use rustls::ServerName;
+use std::collections::{HashSet, VecDeque};
 use std::convert::Infallible;
+use std::path::PathBuf;
+use std::process::Stdio;
 use std::convert::TryFrom;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio::io::BufReader;
+use tokio::io::{self, AsyncWrite, BufReader};
 use tokio::net::TcpStream;
+use tokio::sync::mpsc;
 use tokio::time;
-use tokio_rustls::rustls;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::{rustls, TlsConnector};
 
-use crate::common::{Identity, get_cert_fingerprint};
-use crate::config::{Sender, DEFAULT_PORT};
+use crate::common::{Identity, check_hello, get_cert_fingerprint, local_hello, set_keepalive};
+use crate::config::{Capability, Sender, DEFAULT_PORT};
+use crate::device_map::DeviceMap;
+use crate::inhibit::IdleInhibitor;
+use crate::relative::RelativeDevices;
+use crate::sanity::InputSanity;
+use crate::transfer::{self, Downloads};
+use crate::trust;
+
+// What to call a sender in logs: its nick if it has one, its address otherwise.
+pub fn sender_name(sender: &Sender) -> &str {
+    sender.nick.as_ref().unwrap_or(&sender.address)
+}
 
 struct ServerVerifier { sender: Sender }
 
@@ -34,10 +52,7 @@ impl rustls::client::ServerCertVerifier for ServerVerifier {
     ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
         let fingerprint = get_cert_fingerprint(end_identity);
 
-        let name = match &self.sender.nick {
-            None => &self.sender.address,
-            Some(nick) => nick,
-        };
+        let name = sender_name(&self.sender);
 
         let fingerprint_matches = match self.sender.fingerprint {
             Some(ref sender_fingerprint) => &fingerprint == sender_fingerprint,
@@ -45,7 +60,6 @@ impl rustls::client::ServerCertVerifier for ServerVerifier {
         };
 
         if fingerprint_matches {
-            log::info!("connected to {}", name);
             Ok(rustls::client::ServerCertVerified::assertion())
         } else {
             let none: String = String::from("<none>");
@@ -61,42 +75,158 @@ impl rustls::client::ServerCertVerifier for ServerVerifier {
     }
 }
 
-pub async fn run_client(
-    senders: Vec<Sender>,
-    identity: Identity,
-) {
-    let handles: Vec<_> = senders.into_iter().map(|sender| {
-        let identity = identity.clone();
-        client_handle_connection(sender, identity)
-    }).collect();
+// Decides when to inject events so that they come out `delay` after the quickest delivery seen
+// lately, with the same spacing they had on the sender. Events that the network delivered in a
+// burst are spread back out, and steady latency doesn't matter, only how much it varies.
+struct Playout {
+    delay: Duration,
+    // Smallest recent difference between arrival and event time, in microseconds. This includes
+    // any error in the clock offset estimate, which is why it is measured rather than assumed to
+    // be zero.
+    base: Option<i64>,
+    last_arrival: u64,
+}
 
-    futures::future::join_all(handles).await;
+impl Playout {
+    fn new(delay: Duration) -> Self {
+        Playout { delay, base: None, last_arrival: 0 }
+    }
+
+    fn due(&mut self, event_time: u64) -> time::Instant {
+        let now = net::timestamp();
+        let transit = now as i64 - event_time as i64;
+
+        // Let the base creep up by 1% of the time passed, so it follows the network when latency
+        // goes up for good instead of holding on to one unusually fast delivery.
+        let creep = (now.saturating_sub(self.last_arrival) / 100) as i64;
+        let base = match self.base {
+            Some(base) => transit.min(base + creep),
+            None => transit,
+        };
+        self.base = Some(base);
+        self.last_arrival = now;
+
+        let wait = base + self.delay.as_micros() as i64 - transit;
+        time::Instant::now() + Duration::from_micros(wait.max(0) as u64)
+    }
 }
 
-async fn client_handle_connection(
-    sender: Sender,
-    identity: Identity,
-) -> Infallible {
-    let mut last_msg: Option<String> = None;
+// Names of the senders that are connected right now, shared by all connections so that a standby
+// sender can tell whether the one it stands by for is there.
+type LiveSenders = Arc<Mutex<HashSet<String>>>;
 
-    loop {
-        if let Err(err) = client(sender.clone(), identity.clone()).await {
-            let msg = err.to_string();
-            if last_msg.as_ref() == Some(&msg) {
-                log::error!("Error: {}", msg);
-            }
-            last_msg = Some(msg);
+// Counts a sender as connected for as long as it is held.
+struct Live {
+    senders: LiveSenders,
+    name: String,
+}
+
+impl Live {
+    fn new(senders: LiveSenders, name: String) -> Self {
+        senders.lock().unwrap().insert(name.clone());
+        Live { senders, name }
+    }
+}
+
+impl Drop for Live {
+    fn drop(&mut self) {
+        self.senders.lock().unwrap().remove(&self.name);
+    }
+}
+
+// Keep track of the keys a standby sender has held down, so they can be released when it steps
+// back.
+fn note_held(held: &mut Vec<(u16, KeyKind)>, event: &Event) {
+    if let Event::Input { device_id, input: InputEvent::Key { direction, kind }, .. } = *event {
+        held.retain(|&entry| entry != (device_id, kind));
+        if direction == Direction::Down {
+            held.push((device_id, kind));
         }
-        tokio::time::sleep(Duration::from_secs(1)).await;
     }
 }
 
-async fn client(
-    sender: Sender,
-    identity: Identity,
-) -> Result<Infallible, Error> {
-    let mut writer_manager = WriterManager::new().await;
+fn run_command(sender: &Sender, name: &str) {
+    let name_for_log = sender_name(sender);
+    if !sender.allow.contains(&Capability::Commands) {
+        log::warn!("{} asked to run \"{}\", but it isn't allowed to run commands", name_for_log, name);
+        return;
+    }
+    let argv = match sender.commands.get(name) {
+        Some(argv) if !argv.is_empty() => argv,
+        _ => {
+            log::warn!("{} asked to run \"{}\", which is not an allowed command", name_for_log, name);
+            return;
+        }
+    };
+
+    log::info!("Running \"{}\" for {}", name, name_for_log);
+    spawn_command(name, argv);
+}
+
+// Commands run on their own so that a slow one doesn't hold up input. Nothing is reported back to
+// the sender.
+fn spawn_command(name: &str, argv: &[String]) {
+    let mut command = tokio::process::Command::new(&argv[0]);
+    command.args(&argv[1..]).stdin(Stdio::null());
+    let name = name.to_owned();
+    tokio::spawn(async move {
+        match command.status().await {
+            Ok(status) if status.success() => {},
+            Ok(status) => log::warn!("Command \"{}\" failed ({})", name, status),
+            Err(err) => log::warn!("Failed to run command \"{}\": {}", name, err),
+        }
+    });
+}
+
+fn fetch_file(
+    sender: &Sender,
+    connector: &TlsConnector,
+    downloads: Option<&Downloads>,
+    id: u64,
+    name: String,
+    size: u64,
+) {
+    let name_for_log = sender_name(sender);
+    if !sender.allow.contains(&Capability::Files) {
+        log::warn!("{} offered {}, but it isn't allowed to send files", name_for_log, name);
+        return;
+    }
+    let downloads = match downloads {
+        Some(downloads) => downloads.clone(),
+        None => {
+            log::warn!("{} offered {}, but receiving files is disabled", name_for_log, name);
+            return;
+        }
+    };
+    if size > downloads.max_size {
+        log::warn!("{} offered {}, which is too large ({} bytes)", name_for_log, name, size);
+        return;
+    }
+    let name = match transfer::file_name(&name) {
+        Some(name) => name.to_owned(),
+        None => {
+            log::warn!("{} offered a file with an invalid name: {:?}", name_for_log, name);
+            return;
+        }
+    };
+
+    log::info!("Receiving {} ({} bytes) from {}", name, size, name_for_log);
+    let sender = sender.clone();
+    let connector = connector.clone();
+    tokio::spawn(async move {
+        let result = async {
+            let (stream, _, _) = connect(&sender, &connector, Purpose::FetchFile { id }).await?;
+            transfer::receive(stream, &downloads, &name, size).await
+        };
+        match result.await {
+            Ok(path) => log::info!("Saved {}", path.display()),
+            Err(err) => log::warn!("Error receiving {}: {:#}", name, err),
+        }
+    });
+}
 
+// Makes connections to `sender` that present our identity, and only accept the sender's.
+pub fn connector(sender: &Sender, identity: Identity) -> TlsConnector {
     let (cert, key) = identity;
     let verifier = ServerVerifier::new(sender.clone());
     let config = rustls::ClientConfig::builder()
@@ -104,39 +234,389 @@ async fn client(
         .with_custom_certificate_verifier(Arc::new(verifier))
         .with_single_cert(vec! [cert], key)
         .expect("Invalid identity!");
-    
-    let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
 
+    TlsConnector::from(Arc::new(config))
+}
+
+// Open a connection to the sender, tell it what the connection is for, and return its Hello.
+pub async fn connect(
+    sender: &Sender,
+    connector: &TlsConnector,
+    purpose: Purpose,
+) -> Result<(TlsStream<BufReader<TcpStream>>, Hello, u16), Error> {
     let address = &sender.address[..];
     let port = sender.port.unwrap_or(DEFAULT_PORT);
 
     let stream = TcpStream::connect((address, port)).await?;
+    set_keepalive(&stream)?;
     let stream = BufReader::new(stream);
     let mut stream = connector
         .connect(ServerName::try_from(address)?, stream)
         .await
         .context("Failed to connect")?;
 
-    log::info!("Connected to {}:{}", sender.address, port);
+    net::write_hello(&mut stream, &local_hello(Role::Receiver, purpose)).await?;
+
+    let hello = time::timeout(net::MESSAGE_TIMEOUT, net::read_hello(&mut stream))
+        .await
+        .context("Read timeout")??;
+    let version = check_hello(&hello, Role::Sender)?;
+    Ok((stream, hello, version))
+}
+
+enum Queued {
+    Event(Event),
+    Probe(u64),
+}
+
+async fn send<W>(writer: W, message: &Message, version: u16) -> Result<(), Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    time::timeout(net::MESSAGE_TIMEOUT, net::write_message(writer, message, version))
+        .await
+        .context("Write timeout")??;
+    Ok(())
+}
 
-    net::write_version(&mut stream, PROTOCOL_VERSION).await?;
+async fn play<W>(
+    queued: Queued,
+    writer_manager: &mut WriterManager,
+    writer: W,
+    version: u16,
+) -> Result<(), Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    match queued {
+        Queued::Event(event) => writer_manager.write(event).await?,
+        Queued::Probe(id) => {
+            send(writer, &Message::ProbeAck { id, time: net::timestamp() }, version).await?
+        }
+    }
+    Ok(())
+}
 
-    let version = net::read_version(&mut stream).await?;
-    if version != PROTOCOL_VERSION {
-        return Err(anyhow::anyhow!(
-            "Incompatible protocol version (got {}, expecting {})",
-            version,
-            PROTOCOL_VERSION
-        ));
+// Play everything still queued right away, because something that has to come after it arrived.
+// Messages on the input channel take effect in the order they were sent, and this is what keeps
+// the dejitter queue from reordering them.
+async fn flush<W>(
+    queue: &mut VecDeque<(time::Instant, Queued)>,
+    writer_manager: &mut WriterManager,
+    mut writer: W,
+    version: u16,
+) -> Result<(), Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    for (_, queued) in queue.drain(..) {
+        play(queued, writer_manager, &mut writer, version).await?;
     }
+    Ok(())
+}
+
+// How input from every sender is handled.
+#[derive(Clone)]
+pub struct ClientOptions {
+    pub dejitter: Duration,
+    pub max_input_rate: Option<u32>,
+    pub downloads: Option<Downloads>,
+    pub inhibitor: Option<IdleInhibitor>,
+    // Names of absolute pointing devices to turn into mice.
+    pub relative_devices: Vec<String>,
+}
+
+// Keep connected to every sender. Senders are added and removed as `trusted_senders_path`
+// changes, while those that are there before and after keep their connections.
+pub async fn run_client(
+    config_senders: Vec<Sender>,
+    trusted_senders_path: PathBuf,
+    identity: Identity,
+    device_map: DeviceMap,
+    options: ClientOptions,
+) {
+    let device_map = Arc::new(Mutex::new(device_map));
+    let live = LiveSenders::default();
+    let mut trusted_senders = trust::FileWatch::new(&trusted_senders_path);
+    let mut senders = match trust::all_senders(&config_senders, &trusted_senders_path) {
+        Ok(senders) => senders,
+        Err(err) => {
+            log::error!("Error loading trusted senders: {:#}", err);
+            config_senders.clone()
+        }
+    };
 
+    let mut running: Vec<(Sender, AbortHandle)> = Vec::new();
+    let mut connections = FuturesUnordered::new();
     loop {
-        let message = time::timeout(net::MESSAGE_TIMEOUT, net::read_message(&mut stream))
-            .await
-            .context("Read timed out")??;
-        match message {
-            Message::Event(event) => writer_manager.write(event).await?,
-            Message::KeepAlive => {},
+        running.retain(|(sender, handle)| {
+            let keep = senders.contains(sender);
+            if !keep {
+                log::info!("{} is no longer trusted, disconnecting", sender_name(sender));
+                handle.abort();
+                // It won't get to say it lost focus itself.
+                if let Some(inhibitor) = &options.inhibitor {
+                    inhibitor.set_focused(&sender.address, false);
+                }
+            }
+            keep
+        });
+        for sender in &senders {
+            if running.iter().any(|(running, _)| running == sender) {
+                continue;
+            }
+            let (handle, registration) = AbortHandle::new_pair();
+            let connection = client_handle_connection(
+                sender.clone(),
+                identity.clone(),
+                device_map.clone(),
+                live.clone(),
+                options.clone(),
+            );
+            connections.push(Abortable::new(connection, registration));
+            running.push((sender.clone(), handle));
+        }
+
+        let next_check = time::Instant::now() + trust::WATCH_INTERVAL;
+        loop {
+            tokio::select! {
+                // Only connections that were stopped ever finish.
+                _ = connections.next(), if !connections.is_empty() => {}
+                _ = time::sleep_until(next_check) => break,
+            }
+        }
+        if !trusted_senders.changed() {
+            continue;
         }
+        match trust::all_senders(&config_senders, &trusted_senders_path) {
+            Ok(all) => {
+                log::info!("{} changed, {} senders in total", trusted_senders_path.display(), all.len());
+                senders = all;
+            }
+            Err(err) => log::error!("Error reloading trusted senders: {:#}", err),
+        }
+    }
+}
+
+async fn client_handle_connection(
+    sender: Sender,
+    identity: Identity,
+    device_map: Arc<Mutex<DeviceMap>>,
+    live: LiveSenders,
+    options: ClientOptions,
+) -> Infallible {
+    let mut last_msg: Option<String> = None;
+
+    loop {
+        let result = client(sender.clone(), identity.clone(), &device_map, &live, &options).await;
+        // A sender that went away doesn't have focus anymore.
+        if let Some(inhibitor) = &options.inhibitor {
+            inhibitor.set_focused(&sender.address, false);
+        }
+        let Err(err) = result;
+        let msg = err.to_string();
+        if last_msg.as_ref() == Some(&msg) {
+            log::error!("{}: {}", sender_name(&sender), msg);
+        }
+        last_msg = Some(msg);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+async fn client(
+    sender: Sender,
+    identity: Identity,
+    device_map: &Mutex<DeviceMap>,
+    live: &LiveSenders,
+    options: &ClientOptions,
+) -> Result<Infallible, Error> {
+    let ClientOptions { dejitter, max_input_rate, ref downloads, ref inhibitor, ref relative_devices } = *options;
+    let downloads = downloads.as_ref();
+    let inhibitor = inhibitor.as_ref();
+    let mut writer_manager = WriterManager::new().await;
+
+    let connector = connector(&sender, identity);
+
+    let (stream, hello, version) = connect(&sender, &connector, Purpose::Input).await?;
+    // Standby senders name the sender they stand by for as it is configured, so it counts as
+    // connected under that name rather than its hostname.
+    let _live = Live::new(live.clone(), sender_name(&sender).to_owned());
+    // A sender without a nick goes by its hostname from here on, which says more than its address.
+    let mut sender = sender;
+    if sender.nick.is_none() && !hello.hostname.is_empty() {
+        sender.nick = Some(hello.hostname.clone());
+    }
+    log::info!(
+        "Connected to {} at {}:{} (evkvm {} on {}, protocol {})",
+        sender_name(&sender),
+        sender.address,
+        sender.port.unwrap_or(DEFAULT_PORT),
+        hello.software_version,
+        hello.hostname,
+        version
+    );
+
+    // Input from a sender that isn't allowed to send any is dropped without a word, since there
+    // is a lot of it.
+    let allow_input = sender.allow.contains(&Capability::Input);
+    if !allow_input {
+        log::info!("Ignoring input from {}, which isn't allowed to send any", sender_name(&sender));
+    }
+
+    let (reader, mut writer) = io::split(stream);
+    let mut reader = net::MessageReader::new(reader, version);
+    let (message_sender, mut messages) = mpsc::unbounded_channel();
+
+    // Reading happens separately so that messages keep being read while queued events wait for
+    // their turn.
+    let read = async {
+        loop {
+            let message = match time::timeout(net::MESSAGE_TIMEOUT, reader.read()).await {
+                Ok(Ok(message)) => message,
+                Ok(Err(err)) => return Err::<Infallible, Error>(err.into()),
+                Err(_) => return Err(anyhow::anyhow!("Read timed out")),
+            };
+            let _ = message_sender.send(message);
+        }
+    };
+
+    let process = async {
+        let mut playout = Playout::new(dejitter);
+        let mut sanity = InputSanity::new(sender_name(&sender).to_owned(), max_input_rate);
+        let mut relative = RelativeDevices::new(relative_devices.clone());
+        let mut queue: VecDeque<(time::Instant, Queued)> = VecDeque::new();
+        // Whether the sender this one stands by for is connected, and what this one holds down.
+        let mut standing_by = false;
+        let mut held: Vec<(u16, KeyKind)> = Vec::new();
+        loop {
+            let next_due = queue.front().map(|(due, _)| *due);
+            tokio::select! {
+                message = messages.recv() => {
+                    let message = match message {
+                        Some(message) => message,
+                        None => return Err::<Infallible, Error>(anyhow::anyhow!("Connection closed")),
+                    };
+                    if let Some(primary) = &sender.standby_for {
+                        let primary_live = live.lock().unwrap().contains(primary);
+                        if primary_live != standing_by {
+                            standing_by = primary_live;
+                            if standing_by {
+                                log::info!("{} is connected, ignoring input from its standby {}", primary, sender_name(&sender));
+                                // Queued presses would otherwise come after the releases.
+                                flush(&mut queue, &mut writer_manager, &mut writer, version).await?;
+                                for (device_id, kind) in held.drain(..).rev() {
+                                    let release = InputEvent::Key { direction: Direction::Up, kind };
+                                    writer_manager.write(Event::Input { device_id, input: release, syn: true }).await?;
+                                }
+                            } else {
+                                log::info!("{} is not connected, taking input from its standby {}", primary, sender_name(&sender));
+                            }
+                        }
+                    }
+                    match message {
+                        Message::Event { .. } | Message::Probe { .. } if !allow_input => {},
+                        Message::Event { event: Event::Input { .. }, .. } | Message::Probe { .. } if standing_by => {},
+                        Message::Event { ref event, .. } if !sanity.allow(event, std::time::Instant::now()) => {},
+                        Message::Event { event: Event::NewDevice(device), .. } => {
+                            // Anything still queued may refer to an earlier device with the same
+                            // id, so it has to go out first.
+                            flush(&mut queue, &mut writer_manager, &mut writer, version).await?;
+                            let sender_fingerprint = sender.fingerprint.as_deref().unwrap_or_default();
+                            let uuid = device_map.lock().unwrap().uuid_for(sender_fingerprint, &device)?;
+                            let device = relative.new_device(device);
+                            writer_manager.create_device(device, Some(format!("evkvm/{}", uuid))).await?
+                        },
+                        Message::Event { event, time: event_time } => {
+                            if let Event::RemoveDevice(id) = event {
+                                relative.remove_device(id);
+                            }
+                            let event = match relative.input(event) {
+                                Some(event) => event,
+                                None => continue,
+                            };
+                            if sender.standby_for.is_some() {
+                                note_held(&mut held, &event);
+                            }
+                            let due = event_time
+                                .map(|event_time| playout.due(event_time))
+                                .unwrap_or_else(time::Instant::now);
+                            if queue.is_empty() && due <= time::Instant::now() {
+                                play(Queued::Event(event), &mut writer_manager, &mut writer, version).await?;
+                            } else {
+                                queue.push_back((due, Queued::Event(event)));
+                            }
+                        },
+                        Message::Probe { id, time: probe_time } => {
+                            let due = probe_time
+                                .map(|probe_time| playout.due(probe_time))
+                                .unwrap_or_else(time::Instant::now);
+                            if queue.is_empty() && due <= time::Instant::now() {
+                                play(Queued::Probe(id), &mut writer_manager, &mut writer, version).await?;
+                            } else {
+                                queue.push_back((due, Queued::Probe(id)));
+                            }
+                        },
+                        Message::Ping { seq, time: ping_time } => {
+                            let pong = Message::Pong { seq, ping_time, time: net::timestamp() };
+                            send(&mut writer, &pong, version).await?;
+                        },
+                        Message::Focus { id } => {
+                            // Acknowledging says everything sent before the switch has been
+                            // played.
+                            flush(&mut queue, &mut writer_manager, &mut writer, version).await?;
+                            send(&mut writer, &Message::FocusAck { id }, version).await?;
+                            // Like waking up the display, so it is on by the time it is looked at.
+                            if !sender.on_focus.is_empty() {
+                                spawn_command("on-focus", &sender.on_focus);
+                            }
+                            if let Some(inhibitor) = inhibitor {
+                                inhibitor.set_focused(&sender.address, true);
+                            }
+                        },
+                        Message::FocusLost => {
+                            // Whatever runs on losing focus, like locking the screen, comes after
+                            // the last of the input.
+                            flush(&mut queue, &mut writer_manager, &mut writer, version).await?;
+                            if let Some(inhibitor) = inhibitor {
+                                inhibitor.set_focused(&sender.address, false);
+                            }
+                            if !sender.on_focus_lost.is_empty() {
+                                spawn_command("on-focus-lost", &sender.on_focus_lost);
+                            }
+                        },
+                        Message::Command { name } => run_command(&sender, &name),
+                        Message::FileOffer { id, name, size } => {
+                            fetch_file(&sender, &connector, downloads, id, name, size);
+                        },
+                        Message::Pong { .. }
+                        | Message::FocusAck { .. }
+                        | Message::ProbeAck { .. }
+                        | Message::FocusChanged { .. }
+                        | Message::ReceiverConnected { .. }
+                        | Message::ReceiverDisconnected { .. }
+                        | Message::Feedback { .. } => {},
+                    }
+                }
+                (device_id, feedback) = writer_manager.feedback() => {
+                    // Like rumble from a game, which the sender plays on the gamepad itself.
+                    if hello.supports(net::FEATURE_FEEDBACK) {
+                        send(&mut writer, &Message::Feedback { device_id, feedback }, version).await?;
+                    }
+                }
+                _ = time::sleep_until(next_due.unwrap_or_else(time::Instant::now)), if next_due.is_some() => {
+                    // Events stay in order even if a later one happens to be due sooner.
+                    let now = time::Instant::now();
+                    while queue.front().map(|(due, _)| *due <= now).unwrap_or(false) {
+                        let (_, queued) = queue.pop_front().unwrap();
+                        play(queued, &mut writer_manager, &mut writer, version).await?;
+                    }
+                }
+            }
+        }
+    };
+
+    tokio::select! {
+        result = read => result,
+        result = process => result,
     }
 }
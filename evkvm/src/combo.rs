@@ -0,0 +1,181 @@
+use input::{Direction, KeyKind};
+use std::time::{Duration, Instant};
+
+// Recognizes a key combo, like the switch keys, as its keys go up and down. A combo can optionally
+// require its keys to be pressed in the order they are listed, or all within a short time of each
+// other, so that keys that are often held together while typing don't set it off by accident.
+pub struct ComboMatcher {
+    keys: Vec<KeyKind>,
+    ordered: bool,
+    window: Option<Duration>,
+    // The keys of the combo that are down, in the order they went down, and when.
+    pressed: Vec<(KeyKind, Instant)>,
+}
+
+impl ComboMatcher {
+    pub fn new(keys: Vec<KeyKind>, ordered: bool, window: Option<Duration>) -> Self {
+        let mut unique = Vec::new();
+        for key in keys {
+            if !unique.contains(&key) {
+                unique.push(key);
+            }
+        }
+        ComboMatcher {
+            keys: unique,
+            ordered,
+            window,
+            pressed: Vec::new(),
+        }
+    }
+
+    pub fn keys(&self) -> &[KeyKind] {
+        &self.keys
+    }
+
+    pub fn contains(&self, kind: &KeyKind) -> bool {
+        self.keys.contains(kind)
+    }
+
+    // Note that a key went up or down. Returns whether every key of the combo is now held, in the
+    // required way. Keys that aren't part of the combo are ignored.
+    pub fn update(&mut self, kind: KeyKind, direction: Direction, now: Instant) -> bool {
+        if !self.contains(&kind) {
+            return false;
+        }
+
+        match direction {
+            Direction::Down => {
+                if !self.pressed.iter().any(|(key, _)| *key == kind) {
+                    self.pressed.push((kind, now));
+                }
+            }
+            Direction::Up => self.pressed.retain(|(key, _)| *key != kind),
+        }
+        self.is_complete()
+    }
+
+    fn is_complete(&self) -> bool {
+        if self.keys.is_empty() || self.pressed.len() != self.keys.len() {
+            return false;
+        }
+
+        if self.ordered && !self.pressed.iter().map(|(key, _)| key).eq(self.keys.iter()) {
+            return false;
+        }
+
+        match self.window {
+            Some(window) => {
+                let first = self.pressed[0].1;
+                let last = self.pressed[self.pressed.len() - 1].1;
+                last.duration_since(first) <= window
+            }
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use input::Key;
+
+    const CTRL: KeyKind = KeyKind::Key(Key::LeftCtrl);
+    const ALT: KeyKind = KeyKind::Key(Key::LeftAlt);
+    const A: KeyKind = KeyKind::Key(Key::A);
+
+    // Press `keys` one after the other, `gap` apart, returning whether the last one completed the
+    // combo.
+    fn press(combo: &mut ComboMatcher, keys: &[KeyKind], start: Instant, gap: Duration) -> bool {
+        let mut complete = false;
+        for (idx, &key) in keys.iter().enumerate() {
+            complete = combo.update(key, Direction::Down, start + gap * idx as u32);
+        }
+        complete
+    }
+
+    #[test]
+    fn unordered_combo_matches_in_any_order() {
+        let now = Instant::now();
+        for keys in [[CTRL, ALT], [ALT, CTRL]] {
+            let mut combo = ComboMatcher::new(vec![CTRL, ALT], false, None);
+            assert!(!combo.update(keys[0], Direction::Down, now));
+            assert!(combo.update(keys[1], Direction::Down, now));
+        }
+    }
+
+    #[test]
+    fn other_keys_are_ignored() {
+        let now = Instant::now();
+        let mut combo = ComboMatcher::new(vec![CTRL, ALT], false, None);
+        assert!(!combo.update(A, Direction::Down, now));
+        assert!(press(&mut combo, &[CTRL, A, ALT], now, Duration::ZERO));
+    }
+
+    #[test]
+    fn ordered_combo_needs_its_order() {
+        let now = Instant::now();
+        let mut combo = ComboMatcher::new(vec![CTRL, ALT], true, None);
+        assert!(!press(&mut combo, &[ALT, CTRL], now, Duration::ZERO));
+
+        let mut combo = ComboMatcher::new(vec![CTRL, ALT], true, None);
+        assert!(press(&mut combo, &[CTRL, ALT], now, Duration::ZERO));
+    }
+
+    #[test]
+    fn windowed_combo_needs_keys_close_together() {
+        let now = Instant::now();
+        let window = Some(Duration::from_millis(100));
+
+        let mut combo = ComboMatcher::new(vec![CTRL, ALT], false, window);
+        assert!(press(&mut combo, &[CTRL, ALT], now, Duration::from_millis(100)));
+
+        let mut combo = ComboMatcher::new(vec![CTRL, ALT], false, window);
+        assert!(!press(&mut combo, &[CTRL, ALT], now, Duration::from_millis(101)));
+    }
+
+    // Autorepeat and devices that report a key twice must not count as pressing it again, which
+    // would move it to the end of the order or restart the window.
+    #[test]
+    fn repeated_presses_keep_the_first() {
+        let now = Instant::now();
+        let mut combo = ComboMatcher::new(vec![CTRL, ALT], true, Some(Duration::from_millis(100)));
+        assert!(!combo.update(CTRL, Direction::Down, now));
+        assert!(!combo.update(CTRL, Direction::Down, now + Duration::from_millis(50)));
+        assert!(!combo.update(ALT, Direction::Down, now + Duration::from_millis(150)));
+
+        let mut combo = ComboMatcher::new(vec![CTRL, ALT], true, None);
+        assert!(!combo.update(CTRL, Direction::Down, now));
+        assert!(combo.update(ALT, Direction::Down, now));
+        assert!(combo.update(CTRL, Direction::Down, now));
+    }
+
+    #[test]
+    fn release_and_press_again() {
+        let now = Instant::now();
+        let mut combo = ComboMatcher::new(vec![CTRL, ALT], true, None);
+        assert!(press(&mut combo, &[CTRL, ALT], now, Duration::ZERO));
+        assert!(!combo.update(ALT, Direction::Up, now));
+        // Pressing it again completes the combo again.
+        assert!(combo.update(ALT, Direction::Down, now));
+
+        // Released and pressed again, the first key now comes last, out of order.
+        assert!(!combo.update(CTRL, Direction::Up, now));
+        assert!(!combo.update(CTRL, Direction::Down, now));
+        assert!(!combo.update(ALT, Direction::Up, now));
+        assert!(combo.update(ALT, Direction::Down, now));
+    }
+
+    #[test]
+    fn repeated_keys_in_the_combo_count_once() {
+        let now = Instant::now();
+        let mut combo = ComboMatcher::new(vec![CTRL, CTRL, ALT], false, None);
+        assert_eq!(combo.keys(), &[CTRL, ALT]);
+        assert!(press(&mut combo, &[CTRL, ALT], now, Duration::ZERO));
+    }
+
+    #[test]
+    fn empty_combo_never_matches() {
+        let mut combo = ComboMatcher::new(Vec::new(), false, None);
+        assert!(!combo.update(A, Direction::Down, Instant::now()));
+    }
+}
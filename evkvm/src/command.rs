@@ -0,0 +1,14 @@
+use anyhow::Error;
+use std::path::Path;
+
+use crate::control::{self, ControlRequest, ControlResponse};
+
+// Ask the running daemon to have a receiver run one of the commands it allows.
+pub async fn send_command(control_socket_path: &Path, nick: String, name: String) -> Result<(), Error> {
+    let request = ControlRequest::Command { receiver: nick, name };
+    match control::request(control_socket_path, &request).await? {
+        ControlResponse::Sent => Ok(()),
+        ControlResponse::Error { message } => Err(anyhow::anyhow!(message)),
+        response => Err(anyhow::anyhow!("Unexpected response from evkvm: {:?}", response)),
+    }
+}
@@ -1,11 +1,139 @@
+use anyhow::{Context, Error};
 use ring::digest::{digest, SHA256};
+use socket2::{SockRef, TcpKeepalive};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::net::TcpStream;
 use tokio_rustls::rustls;
 use hex::ToHex;
+use net::{Hello, Purpose, Role, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
 
 pub type Identity = (rustls::Certificate, rustls::PrivateKey);
 
+// What we tell peers about ourselves at the start of every connection.
+pub fn local_hello(role: Role, purpose: Purpose) -> Hello {
+    Hello {
+        protocol_version: PROTOCOL_VERSION,
+        min_protocol_version: MIN_PROTOCOL_VERSION,
+        role,
+        software_version: env!("CARGO_PKG_VERSION").to_owned(),
+        hostname: hostname(),
+        features: vec![
+            net::FEATURE_FILES.to_owned(),
+            net::FEATURE_COMMANDS.to_owned(),
+            net::FEATURE_FEEDBACK.to_owned(),
+            net::FEATURE_FOCUS_LOST.to_owned(),
+        ],
+        purpose,
+        resumption_token: None,
+    }
+}
+
+// gethostname rather than /proc, which FreeBSD doesn't have.
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut _, buf.len()) } < 0 {
+        return String::new();
+    }
+    let len = buf.iter().position(|&byte| byte == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+// Check that a peer's Hello is one we can work with, and return the protocol version to use with
+// it.
+pub fn check_hello(hello: &Hello, role: Role) -> Result<u16, Error> {
+    let version = hello.negotiate().ok_or_else(|| {
+        anyhow::anyhow!(
+            "Incompatible protocol version (evkvm {} supports {} to {}, expecting {} to {})",
+            hello.software_version,
+            hello.min_version(),
+            hello.protocol_version,
+            MIN_PROTOCOL_VERSION,
+            PROTOCOL_VERSION
+        )
+    })?;
+    if hello.role != role {
+        return Err(anyhow::anyhow!("Peer is a {:?}, expecting a {:?}", hello.role, role));
+    }
+    Ok(version)
+}
+
 pub fn get_cert_fingerprint(cert: &rustls::Certificate) -> String {
     let rustls::Certificate(certificate_bytes) = cert;
     let fingerprint_digest = digest(&SHA256, certificate_bytes);
     fingerprint_digest.as_ref().encode_hex::<String>()
 }
+
+pub fn set_keepalive(stream: &TcpStream) -> Result<(), Error> {
+    // Have the kernel probe idle connections so that a peer that vanished (pulled cable, suspended
+    // laptop) is noticed within a few seconds instead of whenever the next write times out.
+    let keepalive = TcpKeepalive::new()
+        .with_time(Duration::from_secs(2))
+        .with_interval(Duration::from_secs(1))
+        .with_retries(3);
+    SockRef::from(stream)
+        .set_tcp_keepalive(&keepalive)
+        .context("Failed to enable TCP keepalive")
+}
+
+pub fn write_file_atomically<F>(
+    path: &Path,
+    contents: &[u8],
+    mode: u32,
+    verify: F,
+) -> Result<(), Error>
+where
+    F: FnOnce(&Path) -> Result<(), Error>,
+{
+    // Write `contents` to a temporary file next to `path`, flush it to disk, and only then rename
+    // it over `path`. A crash at any point leaves either the old file or the complete new one,
+    // never a partially written file. `verify` is run against the temporary file before it is
+    // moved into place.
+
+    let parent = match path.parent() {
+        Some(parent) if parent != Path::new("") => parent.to_owned(),
+        _ => PathBuf::from("."),
+    };
+    fs::create_dir_all(&parent)?;
+
+    let mut temp_name = path
+        .file_name()
+        .context("Path has no file name")?
+        .to_owned();
+    temp_name.push(".tmp");
+    let temp_path = parent.join(temp_name);
+
+    // A leftover from an earlier crash would keep its old permissions, so start fresh.
+    match fs::remove_file(&temp_path) {
+        Ok(()) => {},
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {},
+        Err(e) => { return Err(e.into()); },
+    }
+
+    let result = (|| -> Result<(), Error> {
+        let mut options = OpenOptions::new();
+        options.write(true);
+        options.create_new(true);
+        options.mode(mode);
+        let mut file = options.open(&temp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        drop(file);
+
+        verify(&temp_path)?;
+
+        fs::rename(&temp_path, path)?;
+
+        // Make sure the rename itself is durable.
+        File::open(&parent)?.sync_all()?;
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
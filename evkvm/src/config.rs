@@ -1,11 +1,18 @@
-use input::Key;
-use serde::Deserialize;
-use std::collections::HashSet;
-use std::path::PathBuf;
+use input::KeyKind;
+use schemars::JsonSchema;
+use schemars::schema::{InstanceType, ObjectValidation, Schema, SchemaObject};
+use serde::de::{self, IntoDeserializer, value::U16Deserializer};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::net::SocketAddr;
 use anyhow::Error;
 
-use figment::{Figment, providers::{Format, Toml}};
+use figment::{Figment, providers::{Data, Format, Serialized, Toml}, value::Dict};
+
+use crate::common::hostname;
 
 pub const DEFAULT_PORT: u16 = 5258;
 
@@ -16,44 +23,552 @@ listen-address = "0.0.0.0:5258"
 # Switch to next client by pressing both alt keys at the same time
 switch-keys = ["LeftAlt", "RightAlt"]
 
+# The switch keys can be pressed in any order, however far apart
+switch-keys-in-order = false
+switch-keys-within-ms = 0
+
+# Release mouse buttons held during a switch instead of carrying them over
+held-buttons-on-switch = "release"
+
+# Keep the modifiers among the switch keys held on the machine being switched to
+transfer-keys-on-switch = "combo-only"
+
+# Never forward the power and sleep keys to receivers
+guarded-keys = ["Power", "Sleep"]
+guarded-keys-confirm-ms = 0
+
+# Media keys go to whichever machine is focused
+media-keys = "follow-focus"
+
+# Keyboard backlight, wireless and touchpad keys keep working on the sender
+local-keys = ["KbdIllumDown", "KbdIllumToggle", "KbdIllumUp", "Wlan", "RfKill", "TouchpadToggle"]
+
+# Mouse input follows focus like everything else
+local-pointer = false
+
+# Keys go through as they are read, without any dropped, remapped or turned into macros
+drop-keys = []
+remap-keys = {}
+macros = {}
+
+# Switch through receivers in the order they are listed
+cycle-order = []
+
+# Fall back to local input as soon as the focused receiver disconnects
+reconnect-grace-ms = 0
+
+# Only the focused machine receives input
+mirror-input = false
+
+# Devices are grabbed from the start
+lazy-grab = false
+
+# Input for the sender goes through virtual copies of its devices
+mirror-local-devices = true
+
+# A receiver connecting again replaces its old connection
+duplicate-connections = "replace"
+
 identity-path = "/var/lib/evkvm/identity.pem"
 
+# Where commands like `evkvm latency` reach the running daemon
+control-socket-path = "/run/evkvm/control.sock"
+
+# Don't read from any virtual input devices
+forward-virtual-devices = []
+
+# Inject received input as soon as it arrives
+dejitter-ms = 0
+
+# Leave idling to the desktop while a sender has focus
+inhibit-idle = false
+
+# Leave processes that are only receivers unsandboxed
+sandbox = false
+
+# Inject input from a sender at any rate
+max-input-rate = 0
+
+# Absolute pointing devices from senders are injected as they are
+relative-devices = []
+
+# Senders remember the last 30 seconds of input for `evkvm debug dump`, without key names
+debug-history-secs = 30
+debug-history-key-names = false
+
+# Receivers only accept offered files up to 1 GiB, once downloads-path is set
+max-file-size-mb = 1024
+
+# Where `evkvm trust` keeps the receivers it adds
+trusted-receivers-path = "/var/lib/evkvm/trusted-receivers.toml"
+
+# Where senders can be listed apart from this file
+trusted-senders-path = "/var/lib/evkvm/trusted-senders.toml"
+
+# Where receivers remember the UUIDs given to virtual devices
+device-map-path = "/var/lib/evkvm/devices.toml"
+
+# Type of key to generate if no identity exists at identity-path
+key-type = "ed25519"
+
 senders = []
 receivers = []
+
+# No rules for where focus goes at certain times
+schedule = []
+
+# No profiles to switch to with `evkvm profile`
+profiles = {}
+
+# Nobody may connect to watch which machine has focus
+observers = []
+
+# Senders for `evkvm observe` to watch
+observe = []
+
+# Subject of the certificate generated for a new identity
+[certificate]
+alt-names = ["localhost"]
 "#;
 
+#[derive(Deserialize, JsonSchema, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyType {
+    Ed25519,
+    EcdsaP256,
+    EcdsaP384,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum HeldButtonPolicy {
+    // Release held buttons on the old target, cancelling any drag in progress.
+    Release,
+    // Release held buttons on the old target and press them on the new one.
+    Transfer,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum IndicatorLed {
+    #[serde(rename = "num-lock")]
+    Num,
+    #[serde(rename = "caps-lock")]
+    Caps,
+    #[serde(rename = "scroll-lock")]
+    Scroll,
+}
+
+impl IndicatorLed {
+    // LED_NUML and friends in linux/input-event-codes.h.
+    pub fn code(self) -> u16 {
+        match self {
+            IndicatorLed::Num => 0,
+            IndicatorLed::Caps => 1,
+            IndicatorLed::Scroll => 2,
+        }
+    }
+}
+
+// Which keys held while switching are pressed again on the machine being switched to.
+#[derive(Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyTransferPolicy {
+    None,
+    // The modifiers among the switch keys.
+    ComboOnly,
+    AllModifiers,
+    AllKeys,
+}
+
+// Where media keys, like volume and play/pause, go.
+#[derive(Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MediaKeyPolicy {
+    FollowFocus,
+    AlwaysLocal,
+    // To the receiver named by media-keys-receiver.
+    AlwaysRemote,
+}
+
+// What a sender is allowed to do on a receiver.
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum Capability {
+    Input,
+    Files,
+    Commands,
+}
+
+fn all_capabilities() -> HashSet<Capability> {
+    [Capability::Input, Capability::Files, Capability::Commands].into_iter().collect()
+}
+
+#[derive(Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicatePolicy {
+    // Close the receiver's old connection and carry on with the new one.
+    Replace,
+    // Keep the old connection and turn the new one away.
+    Reject,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+pub const ALL_DAYS: [Weekday; 7] = [
+    Weekday::Monday,
+    Weekday::Tuesday,
+    Weekday::Wednesday,
+    Weekday::Thursday,
+    Weekday::Friday,
+    Weekday::Saturday,
+    Weekday::Sunday,
+];
+
+fn all_days() -> Vec<Weekday> {
+    ALL_DAYS.to_vec()
+}
+
+// Minutes since midnight, written as "HH:MM".
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct TimeOfDay(pub u16);
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("Invalid time of day \"{}\", expected HH:MM", value);
+        let (hours, minutes) = value.split_once(':').ok_or_else(invalid)?;
+        let hours: u16 = hours.parse().map_err(|_| invalid())?;
+        let minutes: u16 = minutes.parse().map_err(|_| invalid())?;
+        if hours > 23 || minutes > 59 {
+            return Err(invalid());
+        }
+        Ok(TimeOfDay(hours * 60 + minutes))
+    }
+}
+
+// Where focus may go between two times of day, in local time. A rule whose end comes before its
+// start runs past midnight, and counts as part of the day it started on.
+#[derive(Deserialize, JsonSchema, Clone, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct ScheduleRule {
+    #[schemars(with = "String")]
+    pub from: TimeOfDay,
+    #[schemars(with = "String")]
+    pub to: TimeOfDay,
+    #[serde(default = "all_days")]
+    pub days: Vec<Weekday>,
+    // The nick of the receiver to switch to when the rule starts.
+    #[serde(default)]
+    pub default_focus: Option<String>,
+    // Nicks of receivers that can't get focus while the rule applies.
+    #[serde(default)]
+    pub blocked_receivers: Vec<String>,
+}
+
+// How keys are written in the config: by name, as listed in keys.md, or by evdev code. KeyKind
+// reads them itself, so this only describes them in the config schema.
+#[derive(JsonSchema)]
+#[serde(untagged)]
+#[allow(dead_code)]
+enum KeyName {
+    Name(String),
+    Code(u16),
+}
+
+// A chord in a macro: keys pressed in the order given and released in reverse, written as their
+// names or evdev codes joined with "+", like "LeftCtrl+C".
 #[derive(Deserialize, Clone, Debug)]
+#[serde(try_from = "String")]
+pub struct Chord(pub Vec<KeyKind>);
+
+impl TryFrom<String> for Chord {
+    type Error = String;
+
+    fn try_from(chord: String) -> Result<Self, Self::Error> {
+        chord
+            .split('+')
+            .map(|name| match name.parse::<u16>() {
+                // Codes are looked up the same way as anywhere else in the config.
+                Ok(code) => {
+                    let code: U16Deserializer<de::value::Error> = code.into_deserializer();
+                    KeyKind::deserialize(code).map_err(|err| err.to_string())
+                }
+                Err(_) => name.parse(),
+            })
+            .collect::<Result<_, _>>()
+            .map(Chord)
+    }
+}
+
+// Options to switch to at runtime with `evkvm profile`. Those left out keep the value they have in
+// the rest of the config.
+#[derive(Deserialize, JsonSchema, Clone, Debug, Default)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Profile {
+    #[schemars(with = "Option<Vec<KeyName>>")]
+    pub switch_keys: Option<Vec<KeyKind>>,
+    pub switch_keys_in_order: Option<bool>,
+    pub switch_keys_within_ms: Option<u64>,
+    pub held_buttons_on_switch: Option<HeldButtonPolicy>,
+    pub transfer_keys_on_switch: Option<KeyTransferPolicy>,
+    #[schemars(with = "Option<Vec<KeyName>>")]
+    pub guarded_keys: Option<Vec<KeyKind>>,
+    pub guarded_keys_confirm_ms: Option<u64>,
+    pub media_keys: Option<MediaKeyPolicy>,
+    #[schemars(with = "Option<Vec<KeyName>>")]
+    pub local_keys: Option<Vec<KeyKind>>,
+    pub local_pointer: Option<bool>,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct CertificateOptions {
+    pub common_name: Option<String>,
+    pub alt_names: Vec<String>,
+    pub validity_days: Option<u32>,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 #[serde(rename_all = "kebab-case")]
 pub struct Sender {
     pub nick: Option<String>,
     pub address: String,
     pub port: Option<u16>,
     pub fingerprint: Option<String>,
+    // Commands this sender may run here, by name, as program and arguments.
+    #[serde(default)]
+    pub commands: BTreeMap<String, Vec<String>>,
+    #[serde(default = "all_capabilities")]
+    pub allow: HashSet<Capability>,
+    // The nick of the sender this one stands by for. Its input is only injected while that one
+    // isn't connected.
+    #[serde(default)]
+    pub standby_for: Option<String>,
+    // Programs and their arguments to run here when this sender switches to us, and away again.
+    #[serde(default)]
+    pub on_focus: Vec<String>,
+    #[serde(default)]
+    pub on_focus_lost: Vec<String>,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug)]
 #[serde(rename_all = "kebab-case")]
 pub struct Receiver {
     pub nick: Option<String>,
     pub fingerprint: Option<String>,
+    // UNIX time in seconds after which the receiver is no longer accepted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<u64>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Clone, Debug)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
     pub listen_address: SocketAddr,
-    pub switch_keys: HashSet<Key>,
+    #[schemars(with = "Vec<KeyName>")]
+    pub switch_keys: Vec<KeyKind>,
+    pub switch_keys_in_order: bool,
+    pub switch_keys_within_ms: u64,
+    pub held_buttons_on_switch: HeldButtonPolicy,
+    pub transfer_keys_on_switch: KeyTransferPolicy,
+    #[serde(default)]
+    pub switch_indicator_led: Option<IndicatorLed>,
+    #[schemars(with = "Vec<KeyName>")]
+    pub guarded_keys: Vec<KeyKind>,
+    pub guarded_keys_confirm_ms: u64,
+    pub media_keys: MediaKeyPolicy,
+    #[serde(default)]
+    pub media_keys_receiver: Option<String>,
+    #[schemars(with = "Vec<KeyName>")]
+    pub local_keys: Vec<KeyKind>,
+    pub local_pointer: bool,
+    #[schemars(with = "Vec<KeyName>")]
+    pub drop_keys: Vec<KeyKind>,
+    #[schemars(with = "BTreeMap<String, KeyName>")]
+    pub remap_keys: HashMap<KeyKind, KeyKind>,
+    #[schemars(with = "BTreeMap<String, Vec<String>>")]
+    pub macros: HashMap<KeyKind, Vec<Chord>>,
+    // Nicks of receivers, in the order switching goes through them.
+    pub cycle_order: Vec<String>,
+    pub reconnect_grace_ms: u64,
+    pub mirror_input: bool,
+    pub lazy_grab: bool,
+    pub mirror_local_devices: bool,
+    pub duplicate_connections: DuplicatePolicy,
+    pub forward_virtual_devices: Vec<String>,
     pub identity_path: PathBuf,
+    pub control_socket_path: PathBuf,
+    pub trusted_receivers_path: PathBuf,
+    pub trusted_senders_path: PathBuf,
+    pub key_type: KeyType,
+    pub certificate: CertificateOptions,
+    pub device_map_path: PathBuf,
+    pub dejitter_ms: u64,
+    pub inhibit_idle: bool,
+    pub max_input_rate: u32,
+    pub relative_devices: Vec<String>,
+    pub debug_history_secs: u64,
+    pub debug_history_key_names: bool,
+    pub sandbox: bool,
+    #[serde(default)]
+    pub downloads_path: Option<PathBuf>,
+    pub max_file_size_mb: u64,
+    #[serde(default)]
+    pub crash_report_path: Option<PathBuf>,
     pub senders: Vec<Sender>,
     pub receivers: Vec<Receiver>,
+    pub schedule: Vec<ScheduleRule>,
+    pub profiles: BTreeMap<String, Profile>,
+    // Peers that may connect to see which receiver has focus and which are connected. They never
+    // get any input.
+    pub observers: Vec<Receiver>,
+    pub observe: Vec<Sender>,
+}
+
+// Instances keep their state and control socket in a directory of their own, unless their config
+// says otherwise.
+fn instance_defaults(instance: &str) -> String {
+    format!(
+        r#"
+identity-path = "/var/lib/evkvm/{0}/identity.pem"
+control-socket-path = "/run/evkvm/{0}/control.sock"
+trusted-receivers-path = "/var/lib/evkvm/{0}/trusted-receivers.toml"
+trusted-senders-path = "/var/lib/evkvm/{0}/trusted-senders.toml"
+device-map-path = "/var/lib/evkvm/{0}/devices.toml"
+"#,
+        instance
+    )
+}
+
+// Where state goes with --state-dir, unless the config says otherwise. These are serialized rather
+// than written out as TOML, since the directory can be any path.
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct StatePaths {
+    identity_path: PathBuf,
+    trusted_receivers_path: PathBuf,
+    trusted_senders_path: PathBuf,
+    device_map_path: PathBuf,
+}
+
+impl StatePaths {
+    fn new(state_dir: &Path) -> Self {
+        StatePaths {
+            identity_path: state_dir.join("identity.pem"),
+            trusted_receivers_path: state_dir.join("trusted-receivers.toml"),
+            trusted_senders_path: state_dir.join("trusted-senders.toml"),
+            device_map_path: state_dir.join("devices.toml"),
+        }
+    }
+}
+
+// Instance names end up in paths, so they are kept to something that is safe there.
+pub fn parse_instance(name: &str) -> Result<String, String> {
+    let valid = !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(name.to_owned())
+    } else {
+        Err("Instance names may only contain letters, digits, '-' and '_'".to_owned())
+    }
+}
+
+pub fn default_path(instance: Option<&str>) -> PathBuf {
+    match instance {
+        Some(instance) => PathBuf::from(format!("/etc/evkvm/{}.toml", instance)),
+        None => PathBuf::from("/etc/evkvm/config.toml"),
+    }
+}
+
+// Where the machine ID is on systems with systemd, and on those with just D-Bus.
+const MACHINE_ID_PATHS: [&str; 2] = ["/etc/machine-id", "/var/lib/dbus/machine-id"];
+
+fn machine_id() -> Option<String> {
+    MACHINE_ID_PATHS
+        .iter()
+        .find_map(|path| std::fs::read_to_string(path).ok())
+        .map(|id| id.trim().to_owned())
+        .filter(|id| !id.is_empty())
+}
+
+// A config file shared between machines can have sections that only apply on some of them, as
+// `[hosts.<name>]`, where the name is a hostname or a machine ID. Those that apply here are
+// returned in the order they take effect in: the one for the hostname first, then the one for
+// the machine ID, which is the more specific of the two.
+fn host_sections(file: Data<Toml>) -> Result<Vec<Dict>, Error> {
+    let hosts = match Figment::from(file).find_value("hosts") {
+        Ok(hosts) => hosts,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let mut hosts = hosts.into_dict().ok_or_else(|| anyhow::anyhow!("hosts must be a table"))?;
+
+    let mut sections = Vec::new();
+    for name in [Some(hostname()), machine_id()].into_iter().flatten() {
+        if let Some(section) = hosts.remove(&name) {
+            let section = section
+                .into_dict()
+                .ok_or_else(|| anyhow::anyhow!("hosts.\"{}\" must be a table", name))?;
+            sections.push(section);
+        }
+    }
+    Ok(sections)
+}
+
+// A JSON Schema for config files, for tools that generate them. Every option has a default, so
+// none of them is required, and `hosts` sections take the same options as the rest of the file.
+pub fn schema() -> String {
+    let mut schema = schemars::schema_for!(Config);
+    if let Some(object) = schema.schema.object.as_mut() {
+        object.required.clear();
+        let hosts = SchemaObject {
+            instance_type: Some(InstanceType::Object.into()),
+            object: Some(Box::new(ObjectValidation {
+                additional_properties: Some(Box::new(Schema::new_ref("#".to_owned()))),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        object.properties.insert("hosts".to_owned(), hosts.into());
+    }
+    serde_json::to_string_pretty(&schema).expect("Config schema is always valid JSON")
 }
 
 impl Config {
-    pub fn new(config_path: &PathBuf) -> Result<Config, Error> {
-        let config: Config = Figment::new()
-            .merge(Toml::string(DEFAULT_CONFIG_TOML))
-            .merge(Toml::file(config_path))
-            .extract()?;
+    // A `config_path` of "-" reads the config from standard input, so that it never has to be
+    // written to a file.
+    pub fn new(config_path: &Path, instance: Option<&str>, state_dir: Option<&Path>) -> Result<Config, Error> {
+        let mut stdin = String::new();
+        let from_stdin = config_path == Path::new("-");
+        if from_stdin {
+            std::io::stdin().read_to_string(&mut stdin)?;
+        }
+        let file = || if from_stdin { Toml::string(&stdin) } else { Toml::file(config_path) };
+
+        let mut figment = Figment::new().merge(Toml::string(DEFAULT_CONFIG_TOML));
+        if let Some(instance) = instance {
+            figment = figment.merge(Toml::string(&instance_defaults(instance)));
+        }
+        if let Some(state_dir) = state_dir {
+            figment = figment.merge(Serialized::defaults(StatePaths::new(state_dir)));
+        }
+        figment = figment.merge(file());
+        for section in host_sections(file())? {
+            figment = figment.merge(Serialized::defaults(section));
+        }
+        let config: Config = figment.extract()?;
         Ok(config)
     }
 }
@@ -0,0 +1,53 @@
+use anyhow::{Context, Error};
+use std::fs::{self, File};
+use std::path::Path;
+
+use crate::config::Config;
+
+// Receivers can run in a container like Docker or Podman, as long as it is given /dev/uinput and
+// somewhere writable to keep their state; see example/container. Both are easy to miss, and the
+// errors evkvm would otherwise run into later don't say what the container needs, so evkvm checks
+// for them on startup when it finds itself in one.
+
+// Docker and Podman leave these behind in every container they start, and systemd-nspawn, LXC and
+// others set $container.
+const MARKERS: &[&str] = &["/.dockerenv", "/run/.containerenv"];
+
+pub fn in_container() -> bool {
+    MARKERS.iter().any(|marker| Path::new(marker).exists()) || std::env::var_os("container").is_some()
+}
+
+// Whether `dir` exists or can be created, and files can be written to it.
+fn check_writable(dir: &Path) -> Result<(), Error> {
+    let probe = dir.join(".evkvm-write-test");
+    fs::create_dir_all(dir)
+        .and_then(|()| File::create(&probe))
+        .and_then(|_| fs::remove_file(&probe))
+        .with_context(|| {
+            format!(
+                "{} is not writable. Mount a volume there, or pass --state-dir with a directory \
+                 that is mounted writable",
+                dir.display()
+            )
+        })
+}
+
+// Check that this container has what evkvm needs, with `is_receiver` saying whether it will
+// create virtual devices for senders.
+pub fn check(config: &Config, is_receiver: bool) -> Result<(), Error> {
+    let mut dirs = vec![&config.identity_path, &config.trusted_receivers_path];
+    if is_receiver {
+        dirs.push(&config.device_map_path);
+    }
+    for dir in dirs.into_iter().filter_map(|path| path.parent()) {
+        check_writable(dir)?;
+    }
+
+    if is_receiver {
+        input::check_uinput().context(
+            "Running in a container that can't create virtual devices. Pass /dev/uinput through \
+             to it, e.g. with `--device /dev/uinput`",
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use anyhow::{Context, Error};
+use std::fs::{self, Permissions};
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+
+// The control socket lets commands like `evkvm latency` talk to the running daemon. What goes over
+// it is defined in the ipc crate.
+
+pub use ipc::{ControlRequest, ControlResponse, HistoryEntry, PausedDevice, ReceiverStatus, Status, UngrabbedDevice};
+
+pub type ControlCall = (ControlRequest, oneshot::Sender<ControlResponse>);
+
+pub fn listen(path: &Path) -> Result<mpsc::UnboundedReceiver<ControlCall>, Error> {
+    // A socket left behind by an earlier run would make binding fail.
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => {
+            return Err(err).with_context(|| format!("Failed to remove {}", path.display()));
+        }
+        _ => {}
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Failed to listen on {}", path.display()))?;
+    fs::set_permissions(path, Permissions::from_mode(0o600))?;
+
+    let (sender, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    log::error!("Control socket error: {}", err);
+                    return;
+                }
+            };
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                if let Err(err) = handle_connection(stream, sender).await {
+                    log::warn!("Control connection error: {:#}", err);
+                }
+            });
+        }
+    });
+
+    Ok(receiver)
+}
+
+async fn handle_connection(
+    stream: UnixStream,
+    calls: mpsc::UnboundedSender<ControlCall>,
+) -> Result<(), Error> {
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+
+    let response = match serde_json::from_str(&line) {
+        Ok(request) => {
+            let (reply, response) = oneshot::channel();
+            calls
+                .send((request, reply))
+                .map_err(|_| anyhow::anyhow!("Server is shutting down"))?;
+            response.await?
+        }
+        Err(err) => ControlResponse::Error { message: format!("Invalid request: {}", err) },
+    };
+
+    let mut data = serde_json::to_vec(&response)?;
+    data.push(b'\n');
+    writer.write_all(&data).await?;
+    Ok(())
+}
+
+pub async fn request(path: &Path, request: &ControlRequest) -> Result<ControlResponse, Error> {
+    let stream = UnixStream::connect(path).await.with_context(|| {
+        format!("Failed to connect to {}. Is evkvm running?", path.display())
+    })?;
+    let (reader, mut writer) = stream.into_split();
+
+    let mut data = serde_json::to_vec(request)?;
+    data.push(b'\n');
+    writer.write_all(&data).await?;
+
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+    serde_json::from_str(&line).context("Invalid response from evkvm")
+}
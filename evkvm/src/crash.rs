@@ -0,0 +1,164 @@
+use anyhow::{Context, Error};
+use log::{Log, Metadata, Record};
+use std::fmt::Write as _;
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, TryLockError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::{Config, Sender};
+
+// With crash-report-path set, evkvm writes a report there when it panics or stops on an error,
+// holding what it logged last, its config and the kernel's list of input devices, so that a bug
+// report can say more than "it stopped". Addresses, fingerprints, certificate names and commands
+// are left out of the config, so the report can be attached as it is. Nothing is sent anywhere.
+
+// How many of the latest log lines a report holds.
+const LOG_LINES: usize = 500;
+
+const REDACTED: &str = "<redacted>";
+
+// Readable without the runtime or any of evkvm's state, which a panic may have left behind.
+const DEVICES_PATH: &str = "/proc/bus/input/devices";
+const KERNEL_PATH: &str = "/proc/sys/kernel/osrelease";
+
+// The latest log lines, trimmed to LOG_LINES at a time rather than on every line.
+static RECENT: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+// Passes records on to the logger evkvm set up, keeping the latest lines for a report.
+pub struct Recorder<L> {
+    inner: L,
+}
+
+impl<L> Recorder<L> {
+    pub fn new(inner: L) -> Self {
+        Recorder { inner }
+    }
+}
+
+impl<L: Log> Log for Recorder<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.inner.enabled(record.metadata()) {
+            // The panic hook logs too, and a panic while the lock was held on this thread would wait
+            // on it forever, so a busy lock means the line is only passed on.
+            let recent = match RECENT.try_lock() {
+                Ok(recent) => Some(recent),
+                Err(TryLockError::Poisoned(err)) => Some(err.into_inner()),
+                Err(TryLockError::WouldBlock) => None,
+            };
+            if let Some(mut recent) = recent {
+                recent.push(format!("[{:<5} {}] {}", record.level(), record.target(), record.args()));
+                if recent.len() > 2 * LOG_LINES {
+                    recent.drain(..LOG_LINES);
+                }
+            }
+        }
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+// Write a report whenever evkvm panics, if the config asks for reports.
+pub fn install(config: &Config) {
+    let dir = match &config.crash_report_path {
+        Some(dir) => dir.clone(),
+        None => return,
+    };
+    let config = redacted(config);
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        report_to(&dir, &config, &info.to_string());
+    }));
+}
+
+// Write a report for an error evkvm is stopping on, if the config asks for reports.
+pub fn report(config: &Config, reason: &str) {
+    if let Some(dir) = &config.crash_report_path {
+        report_to(dir, &redacted(config), reason);
+    }
+}
+
+fn report_to(dir: &Path, config: &Config, reason: &str) {
+    match write_report(dir, config, reason) {
+        Ok(path) => log::error!("Wrote a crash report to {}", path.display()),
+        Err(err) => log::error!("Error writing crash report: {:#}", err),
+    }
+}
+
+fn write_report(dir: &Path, config: &Config, reason: &str) -> Result<PathBuf, Error> {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+    let kernel = fs::read_to_string(KERNEL_PATH).unwrap_or_default();
+
+    // Writing to a String can't fail.
+    let mut report = String::new();
+    let _ = writeln!(report, "evkvm {} on {} {}", env!("CARGO_PKG_VERSION"), std::env::consts::ARCH, kernel.trim());
+    let _ = writeln!(report, "Time: {}", time);
+    let _ = writeln!(report, "\n# Reason\n{}", reason);
+
+    let _ = writeln!(report, "\n# Log");
+    // A panic while logging would still hold the lock, so the log is left out rather than waited for.
+    match RECENT.try_lock() {
+        Ok(recent) => {
+            let start = recent.len().saturating_sub(LOG_LINES);
+            for line in &recent[start..] {
+                let _ = writeln!(report, "{}", line);
+            }
+        }
+        Err(_) => {
+            let _ = writeln!(report, "Unavailable");
+        }
+    }
+
+    let _ = writeln!(report, "\n# Config\n{:#?}", config);
+
+    let devices = fs::read_to_string(DEVICES_PATH)
+        .unwrap_or_else(|err| format!("Failed to read {}: {}\n", DEVICES_PATH, err));
+    let _ = write!(report, "\n# Input devices\n{}", devices);
+
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let path = dir.join(format!("evkvm-crash-{}.txt", time));
+    fs::write(&path, report).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+fn redact_sender(sender: &mut Sender) {
+    sender.address = REDACTED.to_owned();
+    if sender.fingerprint.is_some() {
+        sender.fingerprint = Some(REDACTED.to_owned());
+    }
+    for command in sender.commands.values_mut() {
+        *command = vec![REDACTED.to_owned()];
+    }
+    for command in [&mut sender.on_focus, &mut sender.on_focus_lost] {
+        if !command.is_empty() {
+            *command = vec![REDACTED.to_owned()];
+        }
+    }
+}
+
+// The config with what identifies peers or could hold secrets taken out.
+fn redacted(config: &Config) -> Config {
+    let mut config = config.clone();
+    config.senders.iter_mut().chain(&mut config.observe).for_each(redact_sender);
+    for receiver in config.receivers.iter_mut().chain(&mut config.observers) {
+        if receiver.fingerprint.is_some() {
+            receiver.fingerprint = Some(REDACTED.to_owned());
+        }
+    }
+    if config.certificate.common_name.is_some() {
+        config.certificate.common_name = Some(REDACTED.to_owned());
+    }
+    for name in &mut config.certificate.alt_names {
+        *name = REDACTED.to_owned();
+    }
+    config
+}
@@ -0,0 +1,43 @@
+use anyhow::Error;
+use std::path::Path;
+
+use crate::control::{self, ControlRequest, ControlResponse};
+
+#[derive(clap::Subcommand)]
+pub enum DebugVerb {
+    /// Print the input the running evkvm read lately and where it sent it
+    Dump {
+        /// Print the entries as JSON, in a format other programs can rely on
+        #[clap(long)]
+        json: bool,
+    },
+}
+
+pub async fn run_debug(control_socket_path: &Path, verb: DebugVerb) -> Result<(), Error> {
+    match verb {
+        DebugVerb::Dump { json } => dump_history(control_socket_path, json).await,
+    }
+}
+
+// Ask the running daemon for its history of recent input and print it, oldest first, with how
+// long ago each entry was recorded.
+async fn dump_history(control_socket_path: &Path, json: bool) -> Result<(), Error> {
+    let entries = match control::request(control_socket_path, &ControlRequest::DebugDump).await? {
+        ControlResponse::History { entries } => entries,
+        ControlResponse::Error { message } => return Err(anyhow::anyhow!(message)),
+        response => return Err(anyhow::anyhow!("Unexpected response from evkvm: {:?}", response)),
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+
+    if entries.is_empty() {
+        println!("Nothing recorded. Is debug-history-secs set to 0?");
+    }
+    for entry in &entries {
+        println!("{:>9.3}s ago  {}", entry.age_ms as f64 / 1000.0, entry.what);
+    }
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use anyhow::{Context, Error};
+use hex::ToHex;
+use input::Device;
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::common::write_file_atomically;
+
+// Receivers give each virtual device a UUID that stays the same across reconnects and reboots, so
+// desktop environments can remember per-device settings like pointer speed or natural scrolling.
+// The UUIDs are stored in a state file keyed by the sender's fingerprint and a hash of the
+// device's identifying properties.
+
+#[derive(Serialize, Deserialize, Default)]
+struct DeviceMapFile {
+    devices: BTreeMap<String, String>,
+}
+
+pub struct DeviceMap {
+    path: PathBuf,
+    file: DeviceMapFile,
+}
+
+fn device_hash(device: &Device) -> String {
+    let mut properties = Vec::new();
+    properties.extend_from_slice(device.name.as_bytes());
+    properties.push(0);
+    for value in [device.vendor, device.product, device.bustype, device.version] {
+        properties.extend_from_slice(&value.to_le_bytes());
+    }
+    // Tells apart identical devices that have serial numbers. Devices without one hash the same
+    // as before uniq was known, so they keep their UUIDs.
+    if !device.uniq.is_empty() {
+        properties.extend_from_slice(device.uniq.as_bytes());
+    }
+    let hash = digest(&SHA256, &properties);
+    hex::encode(&hash.as_ref()[..8])
+}
+
+fn generate_uuid() -> Result<String, Error> {
+    let mut bytes = [0u8; 16];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| anyhow::anyhow!("Failed to generate random device UUID"))?;
+
+    // Random (version 4, variant 1) UUID
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex = bytes.encode_hex::<String>();
+    Ok(format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32]))
+}
+
+impl DeviceMap {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let file = match std::fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents)
+                .with_context(|| format!("Device map at {} could not be parsed", path.display()))?,
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => DeviceMapFile::default(),
+            Err(e) => { return Err(e.into()); },
+        };
+
+        Ok(DeviceMap { path: path.to_owned(), file })
+    }
+
+    pub fn uuid_for(&mut self, sender_fingerprint: &str, device: &Device) -> Result<String, Error> {
+        let key = format!("{}/{}", sender_fingerprint, device_hash(device));
+        if let Some(uuid) = self.file.devices.get(&key) {
+            return Ok(uuid.clone());
+        }
+
+        let uuid = generate_uuid()?;
+        self.file.devices.insert(key, uuid.clone());
+
+        let contents = toml::to_string(&self.file)?;
+        write_file_atomically(&self.path, contents.as_bytes(), 0o644, |_| Ok(()))?;
+
+        Ok(uuid)
+    }
+}
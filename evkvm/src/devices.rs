@@ -0,0 +1,33 @@
+use anyhow::Error;
+
+// Print the input devices evkvm would forward, to help with writing device filters.
+pub async fn print_devices(forward_virtual: &[String]) -> Result<(), Error> {
+    for info in input::list_devices(forward_virtual).await? {
+        let device = &info.device;
+        println!(
+            "{}: \"{}\" ({:04x}:{:04x})",
+            info.path.display(),
+            device.name,
+            device.vendor,
+            device.product,
+        );
+        if !device.phys.is_empty() {
+            println!("    phys: {}", device.phys);
+        }
+        if !device.uniq.is_empty() {
+            println!("    uniq: {}", device.uniq);
+        }
+        if let Some(serial) = &info.serial {
+            println!("    serial: {}", serial);
+        }
+        if !info.classes.is_empty() {
+            println!("    udev: {}", info.classes.join(", "));
+        }
+        println!("    capabilities: {}", device.capability_classes().join(", "));
+        match &info.skip_reason {
+            None => println!("    forwarded"),
+            Some(reason) => println!("    skipped: {}", reason),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,269 @@
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::fs::{self, OpenOptions};
+use std::io::ErrorKind;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::common::get_cert_fingerprint;
+use crate::config::{Config, DEFAULT_PORT};
+use crate::identity::load_identity;
+use crate::trust;
+
+const EVENT_PATH: &str = "/dev/input";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Checks the environment evkvm needs to run in, printing a pass/fail report.
+
+// What a machine is configured to do. Checks for a role it isn't configured for are still run, in
+// case it's about to be, but only for information, so a receiver-only machine can pass.
+struct Role {
+    label: String,
+    configured: bool,
+}
+
+impl Role {
+    fn new(name: &str, configured: bool) -> Self {
+        let label = if configured { name.to_owned() } else { format!("{} (not configured)", name) };
+        Role { label, configured }
+    }
+}
+
+struct Report {
+    failed: bool,
+}
+
+impl Report {
+    fn check(&mut self, role: &Role, description: &str, result: Result<String, String>) {
+        match result {
+            Ok(detail) => println!("[ ok ] {}: {}: {}", role.label, description, detail),
+            Err(detail) if role.configured => {
+                self.failed = true;
+                println!("[FAIL] {}: {}: {}", role.label, description, detail);
+            },
+            Err(detail) => println!("[info] {}: {}: {}", role.label, description, detail),
+        }
+    }
+}
+
+fn event_nodes() -> Result<Vec<PathBuf>, String> {
+    let entries = fs::read_dir(EVENT_PATH)
+        .map_err(|err| format!("Could not list {}: {}", EVENT_PATH, err))?;
+
+    let mut nodes: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.starts_with("event"))
+                .unwrap_or(false)
+        })
+        .collect();
+    nodes.sort();
+
+    if nodes.is_empty() {
+        return Err(format!("No input devices found in {}", EVENT_PATH));
+    }
+    Ok(nodes)
+}
+
+fn check_input_devices() -> Result<String, String> {
+    let nodes = event_nodes()?;
+
+    let denied: Vec<String> = nodes
+        .iter()
+        .filter(|path| {
+            let result = OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(path);
+            matches!(result, Err(ref err) if err.kind() == ErrorKind::PermissionDenied)
+        })
+        .map(|path| path.display().to_string())
+        .collect();
+
+    if !denied.is_empty() {
+        Err(format!(
+            "Permission denied reading {} of {} devices ({}). Add the user running evkvm to the input group",
+            denied.len(),
+            nodes.len(),
+            denied.join(", "),
+        ))
+    } else {
+        Ok(format!("All {} devices are readable", nodes.len()))
+    }
+}
+
+fn check_input_group() -> Result<String, String> {
+    if unsafe { libc::geteuid() } == 0 {
+        return Ok(String::from("Running as root"));
+    }
+
+    let name = CString::new("input").unwrap();
+    let group = unsafe { libc::getgrnam(name.as_ptr()) };
+    if group.is_null() {
+        return Err(String::from("There is no input group on this system"));
+    }
+    let gid = unsafe { (*group).gr_gid };
+
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    let mut groups = vec![0; count.max(0) as usize];
+    let count = unsafe { libc::getgroups(count, groups.as_mut_ptr()) };
+    groups.truncate(count.max(0) as usize);
+
+    if groups.contains(&gid) || unsafe { libc::getegid() } == gid {
+        Ok(String::from("Member of the input group"))
+    } else {
+        Err(String::from("Not a member of the input group. Run evkvm as the evkvm user, or add this user with `sudo usermod -aG input $USER`"))
+    }
+}
+
+fn check_grabs() -> Result<String, String> {
+    let nodes = event_nodes()?;
+
+    // Devices we can't open were already reported above, so only report actual grabs here.
+    let grabbed: Vec<String> = nodes
+        .iter()
+        .filter(|path| input::is_grabbed(path).unwrap_or(false))
+        .map(|path| {
+            let holders = input::grab_holders(path);
+            if holders.is_empty() {
+                path.display().to_string()
+            } else {
+                format!("{} (open in {})", path.display(), holders.join(", "))
+            }
+        })
+        .collect();
+
+    if grabbed.is_empty() {
+        Ok(String::from("No devices are grabbed by another process"))
+    } else {
+        Err(format!(
+            "{} are grabbed by another process and can't be forwarded. This is expected if evkvm is already running; otherwise, check for tools like keyd or kmonad",
+            grabbed.join(", "),
+        ))
+    }
+}
+
+fn check_uinput() -> Result<String, String> {
+    input::check_uinput()
+        .map(|()| String::from("/dev/uinput can be opened for writing"))
+        .map_err(|err| err.to_string())
+}
+
+fn check_reachable(address: &str, port: u16) -> Result<String, String> {
+    let addresses = (address, port)
+        .to_socket_addrs()
+        .map_err(|err| format!("Could not resolve {}: {}", address, err))?;
+
+    let mut last_err = None;
+    for socket_address in addresses {
+        match TcpStream::connect_timeout(&socket_address, CONNECT_TIMEOUT) {
+            Ok(_) => return Ok(format!("Connected to {}", socket_address)),
+            Err(err) => last_err = Some(format!("Could not connect to {}: {}", socket_address, err)),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| format!("{} did not resolve to any addresses", address)))
+}
+
+fn check_identity(config: &Config) -> Result<String, String> {
+    match load_identity(&config.identity_path) {
+        Ok(Some((cert, _))) => Ok(format!("Fingerprint is {}", get_cert_fingerprint(&cert))),
+        Ok(None) => Err(format!(
+            "{} does not exist yet. Run `evkvm` with no arguments to generate it",
+            config.identity_path.display(),
+        )),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn check_fingerprints(config: &Config) -> Result<String, String> {
+    let own_fingerprint = load_identity(&config.identity_path)
+        .ok()
+        .flatten()
+        .map(|(cert, _)| get_cert_fingerprint(&cert));
+
+    let receivers = trust::all_receivers(config).map_err(|err| format!("{:#}", err))?;
+    let senders = trust::all_senders(&config.senders, &config.trusted_senders_path)
+        .map_err(|err| format!("{:#}", err))?;
+
+    // A peer may legitimately be both a sender and a receiver, so duplicates are only a problem
+    // within one list.
+    let mut peers: Vec<(&str, String, &Option<String>)> = Vec::new();
+    for sender in &senders {
+        let name = sender.nick.clone().unwrap_or_else(|| sender.address.clone());
+        peers.push(("sender", name, &sender.fingerprint));
+    }
+    for (i, receiver) in receivers.iter().enumerate() {
+        let name = receiver.nick.clone().unwrap_or_else(|| format!("receiver {}", i + 1));
+        peers.push(("receiver", name, &receiver.fingerprint));
+    }
+
+    let mut problems = Vec::new();
+    let mut seen = HashSet::new();
+    for (list, name, fingerprint) in &peers {
+        let fingerprint = match fingerprint {
+            Some(fingerprint) => fingerprint,
+            None => {
+                problems.push(format!("{} has no fingerprint and will never be authenticated", name));
+                continue;
+            },
+        };
+
+        if fingerprint.len() != 64 || !fingerprint.chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase()) {
+            problems.push(format!("{} has a malformed fingerprint; expected 64 lowercase hex digits", name));
+        }
+        if own_fingerprint.as_ref() == Some(fingerprint) {
+            problems.push(format!("{} has this device's own fingerprint", name));
+        }
+        if !seen.insert((list, fingerprint)) {
+            problems.push(format!("{} has the same fingerprint as another {}", name, list));
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(format!("{} configured fingerprints look valid", peers.len()))
+    } else {
+        Err(problems.join("; "))
+    }
+}
+
+pub fn run_doctor(config: &Config) -> bool {
+    let is_sender = trust::all_receivers(config)
+        .map(|receivers| !receivers.is_empty())
+        .unwrap_or(true);
+    let senders = trust::all_senders(&config.senders, &config.trusted_senders_path);
+    let is_receiver = senders.as_ref().map(|senders| !senders.is_empty()).unwrap_or(true);
+    let senders = senders.unwrap_or_default();
+
+    let both = Role::new("both", true);
+    let sender = Role::new("sender", is_sender);
+    let receiver = Role::new("receiver", is_receiver);
+
+    let mut report = Report { failed: false };
+
+    report.check(&both, "Identity", check_identity(config));
+    report.check(&both, "Peer fingerprints", check_fingerprints(config));
+
+    report.check(&sender, "Input group", check_input_group());
+    report.check(&sender, "Reading input devices", check_input_devices());
+    report.check(&sender, "Grabbing input devices", check_grabs());
+    // The sender mirrors its own devices through uinput too, so both roles need it.
+    report.check(&sender, "Creating virtual devices", check_uinput());
+
+    report.check(&receiver, "Creating virtual devices", check_uinput());
+    for sender_config in &senders {
+        let port = sender_config.port.unwrap_or(DEFAULT_PORT);
+        let name = sender_config.nick.as_ref().unwrap_or(&sender_config.address);
+        report.check(
+            &receiver,
+            &format!("Reaching sender {}", name),
+            check_reachable(&sender_config.address, port),
+        );
+    }
+
+    !report.failed
+}
@@ -0,0 +1,82 @@
+use anyhow::Error;
+use input::{Event, ReaderManager};
+use std::convert::Infallible;
+use std::time::Instant;
+
+use crate::config::Config;
+use crate::focus::{Action, DropReason, Focus};
+use crate::pipeline::Pipeline;
+use crate::server::{new_focus_state, receiver_name};
+use crate::trust;
+
+// Reads from input devices without grabbing them, and logs where the server would send each event
+// instead of writing or sending anything, to try out a config safely. Every trusted receiver is
+// treated as connected, so the switch keys go through all of them.
+pub async fn run_dry_run(config: &Config) -> Result<Infallible, Error> {
+    let receivers = trust::all_receivers(config)?;
+
+    let mut order: Vec<&str> = Vec::new();
+    for fingerprint in receivers.iter().filter_map(|receiver| receiver.fingerprint.as_deref()) {
+        if !order.contains(&fingerprint) {
+            order.push(fingerprint);
+        }
+    }
+    let media_receiver = config
+        .media_keys_receiver
+        .as_deref()
+        .and_then(|nick| receivers.iter().find(|receiver| receiver.nick.as_deref() == Some(nick)))
+        .and_then(|receiver| receiver.fingerprint.as_deref());
+    let name = |focus: &Focus| match focus {
+        Focus::Local => "this machine".to_owned(),
+        Focus::Remote(fingerprint) => receiver_name(&receivers, fingerprint, None),
+    };
+
+    let mut reader_manager = ReaderManager::without_grabbing(config.forward_virtual_devices.clone()).await?;
+    let mut pipeline = Pipeline::from_config(config);
+    let mut focus_state = new_focus_state(config, None);
+
+    log::info!("Dry run: devices are not grabbed, and nothing is written or sent anywhere");
+
+    loop {
+        let event = pipeline.read(&mut reader_manager).await?;
+        match event {
+            Event::NewDevice(ref device) => log::info!("Reading from device {}: {}", device.id, device.name),
+            Event::RemoveDevice(device_id) => log::info!("Device {} was removed", device_id),
+            Event::Input { .. } => {}
+        }
+
+        for action in focus_state.input(event, &order, media_receiver, Instant::now()) {
+            match action {
+                Action::Write(target, event) => log::info!("Would write to {}: {:?}", name(&target), event),
+                Action::Switch(new_focus) => log::info!("Switch keys pressed, would switch to {}", name(&new_focus)),
+                Action::Forward { event, swallowed } => {
+                    let focus = focus_state.focus().clone();
+                    let mut targets = Vec::new();
+                    if let Focus::Remote(_) = focus {
+                        focus_state.delivered(&focus, &event);
+                        targets.push(name(&focus));
+                    }
+                    if !swallowed && (focus == Focus::Local || config.mirror_input) {
+                        focus_state.delivered(&Focus::Local, &event);
+                        targets.push(name(&Focus::Local));
+                    }
+
+                    // Devices coming and going were logged above already.
+                    if let Event::Input { .. } = event {
+                        if targets.is_empty() {
+                            log::info!("Would swallow, as part of the switch keys: {:?}", event);
+                        } else {
+                            log::info!("Would forward to {}: {:?}", targets.join(" and "), event);
+                        }
+                    }
+                }
+                Action::Drop { event, reason: DropReason::Guarded } => {
+                    log::info!("Would hold back guarded key: {:?}", event)
+                }
+                Action::Drop { event, reason: DropReason::Released } => {
+                    log::info!("Would drop the release of a local key, already released here: {:?}", event)
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use input::Direction;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read};
+use std::net::TcpListener;
+use std::os::raw::{c_int, c_ulong};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+use crate::client::{run_client, ClientOptions};
+use crate::common::{get_cert_fingerprint, Identity};
+use crate::config::Config;
+use crate::control::{self, ControlRequest, ControlResponse, Status};
+use crate::device_map::DeviceMap;
+use crate::identity::load_or_generate_identity;
+use crate::server::run_server;
+
+// Brings up a sender and two receivers in this process, connected over TLS on localhost the way
+// they would be across machines, then types on a keyboard made through uinput and checks what
+// comes out of each receiver's virtual copy of it, down to the releases sent when switching away.
+//
+// The sender grabs every input device on the machine while it runs, and the switch keys typed
+// while it has focus reach the desktop, so the test is ignored unless asked for, and then fails
+// without EVKVM_FORWARDING_TEST set. It also needs write access to /dev/uinput and read access to
+// /dev/input, so run it as root in a VM:
+// `EVKVM_FORWARDING_TEST=1 cargo test -p evkvm end_to_end -- --ignored`.
+
+// How long to wait for connections, devices and switches.
+const TIMEOUT: Duration = Duration::from_secs(10);
+// How long to wait for events that shouldn't come after the ones that should.
+const SETTLE: Duration = Duration::from_millis(200);
+
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const SYN_REPORT: u16 = 0x00;
+
+const KEY_D: u16 = 32;
+const KEY_C: u16 = 46;
+const KEY_B: u16 = 48;
+const KEY_LEFTALT: u16 = 56;
+const KEY_RIGHTALT: u16 = 100;
+const KEYS: &[u16] = &[KEY_D, KEY_C, KEY_B, KEY_LEFTALT, KEY_RIGHTALT];
+
+// From linux/uinput.h.
+const UI_DEV_CREATE: c_ulong = 0x5501;
+const UI_DEV_DESTROY: c_ulong = 0x5502;
+const UI_DEV_SETUP: c_ulong = 0x405c_5503;
+const UI_SET_EVBIT: c_ulong = 0x4004_5564;
+const UI_SET_KEYBIT: c_ulong = 0x4004_5565;
+const BUS_USB: u16 = 0x03;
+
+#[repr(C)]
+struct UinputSetup {
+    bustype: u16,
+    vendor: u16,
+    product: u16,
+    version: u16,
+    name: [u8; 80],
+    ff_effects_max: u32,
+}
+
+// A keyboard made through uinput directly rather than with the input crate, whose devices carry
+// evkvm's phys and so are never read by a sender.
+struct Keyboard {
+    fd: c_int,
+}
+
+impl Keyboard {
+    fn create(name: &str) -> Self {
+        let path = CString::new("/dev/uinput").unwrap();
+        let fd = unsafe { libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_NONBLOCK | libc::O_CLOEXEC) };
+        assert!(fd >= 0, "Failed to open /dev/uinput: {}", std::io::Error::last_os_error());
+        let keyboard = Keyboard { fd };
+
+        keyboard.ioctl(UI_SET_EVBIT, EV_KEY as c_int);
+        for &key in KEYS {
+            keyboard.ioctl(UI_SET_KEYBIT, key as c_int);
+        }
+        let mut setup = UinputSetup {
+            bustype: BUS_USB,
+            vendor: 0x1234,
+            product: 0x5678,
+            version: 1,
+            name: [0; 80],
+            ff_effects_max: 0,
+        };
+        setup.name[..name.len()].copy_from_slice(name.as_bytes());
+        assert!(unsafe { libc::ioctl(fd, UI_DEV_SETUP as _, &setup as *const UinputSetup) } >= 0);
+        assert!(unsafe { libc::ioctl(fd, UI_DEV_CREATE as _) } >= 0);
+        keyboard
+    }
+
+    fn ioctl(&self, request: c_ulong, value: c_int) {
+        assert!(unsafe { libc::ioctl(self.fd, request as _, value) } >= 0);
+    }
+
+    fn write(&self, type_: u16, code: u16, value: i32) {
+        let event = libc::input_event {
+            time: libc::timeval { tv_sec: 0, tv_usec: 0 },
+            type_,
+            code,
+            value,
+        };
+        let size = std::mem::size_of::<libc::input_event>();
+        let written = unsafe { libc::write(self.fd, &event as *const libc::input_event as *const libc::c_void, size) };
+        assert_eq!(written, size as isize, "Failed to write to the keyboard");
+    }
+
+    fn key(&self, code: u16, direction: Direction) {
+        self.write(EV_KEY, code, if direction == Direction::Down { 1 } else { 0 });
+        self.write(EV_SYN, SYN_REPORT, 0);
+    }
+
+    fn tap(&self, code: u16) {
+        self.key(code, Direction::Down);
+        self.key(code, Direction::Up);
+    }
+
+    // Press the default switch keys.
+    fn switch(&self) {
+        self.key(KEY_LEFTALT, Direction::Down);
+        self.key(KEY_RIGHTALT, Direction::Down);
+        self.key(KEY_RIGHTALT, Direction::Up);
+        self.key(KEY_LEFTALT, Direction::Up);
+    }
+}
+
+impl Drop for Keyboard {
+    fn drop(&mut self) {
+        unsafe {
+            libc::ioctl(self.fd, UI_DEV_DESTROY as _);
+            libc::close(self.fd);
+        }
+    }
+}
+
+// The virtual copy of the keyboard a receiver made.
+struct VirtualCopy {
+    node: File,
+}
+
+impl VirtualCopy {
+    // Find the copy of `name` the receiver keeping its device map at `device_map_path` made. Its
+    // phys holds the UUID the receiver gave it there.
+    async fn find(name: &str, device_map_path: &Path) -> Self {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let uuids: Vec<String> = fs::read_to_string(device_map_path)
+                .ok()
+                .and_then(|contents| contents.parse::<toml::Value>().ok())
+                .and_then(|map| map.get("devices")?.as_table().cloned())
+                .map(|devices| devices.values().filter_map(|uuid| uuid.as_str().map(str::to_owned)).collect())
+                .unwrap_or_default();
+            let devices = input::list_devices(&[]).await.expect("Failed to list devices");
+            let found = devices.into_iter().find(|info| {
+                info.device.name == name && uuids.iter().any(|uuid| info.device.phys == format!("evkvm/{}", uuid))
+            });
+            if let Some(info) = found {
+                let node = fs::OpenOptions::new()
+                    .read(true)
+                    .custom_flags(libc::O_NONBLOCK)
+                    .open(&info.path)
+                    .expect("Failed to open the copy's node");
+                return VirtualCopy { node };
+            }
+            assert!(Instant::now() < deadline, "{} never showed up", device_map_path.display());
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    // The key events that came out of the copy, as codes and values, once there are `count` of
+    // them and nothing more followed.
+    async fn keys(&mut self, count: usize) -> Vec<(u16, i32)> {
+        let mut keys = Vec::new();
+        let deadline = Instant::now() + TIMEOUT;
+        let mut settled = None;
+        loop {
+            match self.read() {
+                Some(event) if event.type_ == EV_KEY => keys.push((event.code, event.value)),
+                Some(_) => {}
+                None => {
+                    let now = Instant::now();
+                    if keys.len() >= count && now >= *settled.get_or_insert(now + SETTLE) {
+                        return keys;
+                    }
+                    if now > deadline {
+                        return keys;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            }
+        }
+    }
+
+    fn read(&mut self) -> Option<libc::input_event> {
+        let mut buffer = [0u8; std::mem::size_of::<libc::input_event>()];
+        match self.node.read(&mut buffer) {
+            Ok(length) => {
+                assert_eq!(length, buffer.len(), "Short read from the copy");
+                Some(unsafe { std::ptr::read_unaligned(buffer.as_ptr() as *const libc::input_event) })
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => None,
+            Err(err) => panic!("Failed to read from the copy: {}", err),
+        }
+    }
+}
+
+// Load the config in `toml` for an instance keeping its state in `state_dir`.
+fn load_config(state_dir: &Path, toml: &str) -> Config {
+    fs::create_dir_all(state_dir).unwrap();
+    let path = state_dir.join("config.toml");
+    fs::write(&path, toml).unwrap();
+    Config::new(&path, None, Some(state_dir)).expect("Invalid config")
+}
+
+fn identity(state_dir: &Path) -> Identity {
+    let config = load_config(state_dir, "");
+    load_or_generate_identity(&config.identity_path, config.key_type, &config.certificate).unwrap()
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+fn receiver(config: Config, identity: Identity) -> JoinHandle<()> {
+    let device_map = DeviceMap::load(&config.device_map_path).unwrap();
+    let options = ClientOptions {
+        dejitter: Duration::ZERO,
+        max_input_rate: None,
+        downloads: None,
+        inhibitor: None,
+        relative_devices: Vec::new(),
+    };
+    tokio::spawn(run_client(config.senders.clone(), config.trusted_senders_path.clone(), identity, device_map, options))
+}
+
+// Wait until the sender's status is the way `expected` wants it.
+async fn wait_for(control_socket_path: &Path, what: &str, expected: impl Fn(&Status) -> bool) {
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        // The sender may not be listening yet.
+        let status = match control::request(control_socket_path, &ControlRequest::Status).await {
+            Ok(ControlResponse::Status(status)) => Some(status),
+            _ => None,
+        };
+        if status.as_ref().map(&expected).unwrap_or(false) {
+            return;
+        }
+        assert!(Instant::now() < deadline, "Timed out waiting for {}: {:?}", what, status);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+async fn wait_for_focus(control_socket_path: &Path, focus: Option<&str>) {
+    let what = format!("focus to go to {}", focus.unwrap_or("the sender"));
+    wait_for(control_socket_path, &what, |status| status.focus.as_deref() == focus).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs root, uinput and grabs every input device; set EVKVM_FORWARDING_TEST"]
+async fn input_goes_where_focus_is() {
+    assert!(
+        std::env::var_os("EVKVM_FORWARDING_TEST").is_some(),
+        "EVKVM_FORWARDING_TEST is not set, and this grabs every input device"
+    );
+    if let Err(err) = input::check_uinput() {
+        panic!("uinput is not available: {}", err);
+    }
+
+    let dir = std::env::temp_dir().join(format!("evkvm-test-forwarding-{}", std::process::id()));
+    let name = format!("evkvm test keyboard {}", std::process::id());
+    let keyboard = Keyboard::create(&name);
+
+    let state_dirs: Vec<PathBuf> = ["sender", "a", "b"].iter().map(|instance| dir.join(instance)).collect();
+    let identities: Vec<Identity> = state_dirs.iter().map(|state_dir| identity(state_dir)).collect();
+    let fingerprints: Vec<String> = identities.iter().map(|(cert, _)| get_cert_fingerprint(cert)).collect();
+    let port = free_port();
+    let control_socket_path = dir.join("control.sock");
+
+    // Nothing is pressed again on the receiver switched to, so each one only gets what was typed
+    // while it had focus and what was released on it when switching away.
+    let sender_config = load_config(&state_dirs[0], &format!(
+        r#"
+listen-address = "127.0.0.1:{port}"
+control-socket-path = "{socket}"
+forward-virtual-devices = ["{name}"]
+transfer-keys-on-switch = "none"
+reconnect-grace-ms = 0
+
+[[receivers]]
+nick = "a"
+fingerprint = "{a}"
+
+[[receivers]]
+nick = "b"
+fingerprint = "{b}"
+"#,
+        port = port,
+        socket = control_socket_path.display(),
+        name = name,
+        a = fingerprints[1],
+        b = fingerprints[2],
+    ));
+    let receiver_config = |state_dir: &Path| load_config(state_dir, &format!(
+        r#"
+[[senders]]
+nick = "sender"
+address = "127.0.0.1"
+port = {port}
+fingerprint = "{fingerprint}"
+"#,
+        port = port,
+        fingerprint = fingerprints[0],
+    ));
+    let a_config = receiver_config(&state_dirs[1]);
+    let b_config = receiver_config(&state_dirs[2]);
+    let (a_device_map_path, b_device_map_path) = (a_config.device_map_path.clone(), b_config.device_map_path.clone());
+
+    let mut identities = identities.into_iter();
+    let sender_identity = identities.next().unwrap();
+    let server = tokio::spawn(async move {
+        let Err(err) = run_server(&sender_config, sender_identity).await;
+        panic!("Sender stopped: {:#}", err);
+    });
+    wait_for(&control_socket_path, "the sender to start", |_| true).await;
+
+    let a = receiver(a_config, identities.next().unwrap());
+    let b = receiver(b_config, identities.next().unwrap());
+    wait_for(&control_socket_path, "both receivers to connect", |status| status.receivers.len() == 2).await;
+    let mut a_copy = VirtualCopy::find(&name, &a_device_map_path).await;
+    let mut b_copy = VirtualCopy::find(&name, &b_device_map_path).await;
+
+    keyboard.switch();
+    wait_for_focus(&control_socket_path, Some("a")).await;
+    keyboard.tap(KEY_B);
+
+    // The left Alt pressed to switch away reaches the receiver before the switch, and is released
+    // there when it happens.
+    keyboard.switch();
+    wait_for_focus(&control_socket_path, Some("b")).await;
+    keyboard.tap(KEY_C);
+
+    keyboard.switch();
+    wait_for_focus(&control_socket_path, None).await;
+    assert_eq!(a_copy.keys(4).await, [(KEY_B, 1), (KEY_B, 0), (KEY_LEFTALT, 1), (KEY_LEFTALT, 0)]);
+
+    // Losing the focused receiver gives focus back to the sender, and switching then only goes
+    // through the receiver that is left.
+    keyboard.switch();
+    wait_for_focus(&control_socket_path, Some("a")).await;
+    a.abort();
+    wait_for(&control_socket_path, "a to be dropped", |status| status.receivers.len() == 1 && status.focus.is_none()).await;
+
+    keyboard.switch();
+    wait_for_focus(&control_socket_path, Some("b")).await;
+    keyboard.tap(KEY_D);
+    match control::request(&control_socket_path, &ControlRequest::Focus { receiver: None }).await {
+        Ok(ControlResponse::Done) => {}
+        response => panic!("Failed to switch back to the sender: {:?}", response),
+    }
+    assert_eq!(
+        b_copy.keys(6).await,
+        [(KEY_C, 1), (KEY_C, 0), (KEY_LEFTALT, 1), (KEY_LEFTALT, 0), (KEY_D, 1), (KEY_D, 0)],
+    );
+
+    b.abort();
+    server.abort();
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,797 @@
+use input::{Button, Direction, Event, InputEvent, KeyKind};
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+use crate::combo::ComboMatcher;
+use crate::config::{HeldButtonPolicy, KeyTransferPolicy, MediaKeyPolicy};
+use crate::guard::KeyGuard;
+
+// Which machine input goes to, and how it moves between them. This only decides what should
+// happen; the server loop carries it out, so none of it touches the network or any devices.
+
+// Where input goes: this machine, or the connected receiver with the given fingerprint.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Focus {
+    Local,
+    Remote(String),
+}
+
+#[derive(Debug)]
+pub enum Action {
+    // Write an event made up here, like a key release, to the given machine.
+    Write(Focus, Event),
+    // Focus moved to the given machine.
+    Switch(Focus),
+    // Pass an input event on to the focused machine. An event that completed the switch combo is
+    // swallowed, and not written to this machine. Wherever it ends up should be reported with
+    // delivered.
+    Forward { event: Event, swallowed: bool },
+    // An input event that goes nowhere at all, and why.
+    Drop { event: Event, reason: DropReason },
+}
+
+// Why an input event went nowhere.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropReason {
+    // A guarded key, pressed while a receiver has focus.
+    Guarded,
+    // The release of a local key that went down before the switch, and was released here then.
+    Released,
+}
+
+// Scan codes, which keyboards report right before the key they belong to.
+const EV_MSC: u16 = 0x04;
+const MSC_SCAN: u16 = 0x04;
+
+// Pointer motion, from mice on the one hand and touchpads and tablets on the other.
+const EV_REL: u16 = 0x02;
+const EV_ABS: u16 = 0x03;
+
+// The keys and buttons each machine has been told are down, along with the device they came from,
+// in the order they went down.
+type Ledger = Vec<(u16, KeyKind)>;
+
+pub struct FocusState {
+    focus: Focus,
+    combo: ComboMatcher,
+    guard: KeyGuard,
+    held_buttons_on_switch: HeldButtonPolicy,
+    transfer_keys_on_switch: KeyTransferPolicy,
+    media_keys: MediaKeyPolicy,
+    // Keys that always go to this machine, even while a receiver has focus.
+    local_keys: Vec<KeyKind>,
+    // Whether pointer motion and buttons stay on this machine too.
+    local_pointer: bool,
+    // The last scan code from each device, until its next event.
+    scans: HashMap<u16, Event>,
+    // Keys, as opposed to buttons, currently held down, in the order they went down.
+    held_keys: Vec<(u16, KeyKind)>,
+    // Mouse buttons currently held down, along with the device they came from, so that a drag in
+    // progress can be dealt with when switching. Buttons that are part of the switch combo are
+    // handled along with the rest of the combo instead.
+    held_buttons: HashSet<(u16, Button)>,
+    ledgers: HashMap<Focus, Ledger>,
+    // Fingerprints of the receivers the schedule keeps from getting focus right now.
+    blocked: HashSet<String>,
+}
+
+impl FocusState {
+    pub fn new(
+        combo: ComboMatcher,
+        guard: KeyGuard,
+        held_buttons_on_switch: HeldButtonPolicy,
+        transfer_keys_on_switch: KeyTransferPolicy,
+        media_keys: MediaKeyPolicy,
+        local_keys: Vec<KeyKind>,
+        local_pointer: bool,
+    ) -> Self {
+        FocusState {
+            focus: Focus::Local,
+            combo,
+            guard,
+            held_buttons_on_switch,
+            transfer_keys_on_switch,
+            media_keys,
+            local_keys,
+            local_pointer,
+            scans: HashMap::new(),
+            held_keys: Vec::new(),
+            held_buttons: HashSet::new(),
+            ledgers: HashMap::new(),
+            blocked: HashSet::new(),
+        }
+    }
+
+    // Take on the options `options` was made with, e.g. those of another profile, while keeping
+    // track of what is held where. Whatever was kept on this machine while a receiver has focus
+    // is released, since the new options might send the rest of it elsewhere.
+    pub fn reconfigure(&mut self, options: FocusState) -> Vec<Action> {
+        let actions = match self.focus {
+            Focus::Remote(_) => self.release_all(&Focus::Local),
+            Focus::Local => Vec::new(),
+        };
+        self.combo = options.combo;
+        self.guard = options.guard;
+        self.held_buttons_on_switch = options.held_buttons_on_switch;
+        self.transfer_keys_on_switch = options.transfer_keys_on_switch;
+        self.media_keys = options.media_keys;
+        self.local_keys = options.local_keys;
+        self.local_pointer = options.local_pointer;
+        actions
+    }
+
+    pub fn focus(&self) -> &Focus {
+        &self.focus
+    }
+
+    // Move focus without going through the switch keys, e.g. back to a receiver that reconnected.
+    pub fn set_focus(&mut self, focus: Focus) {
+        self.focus = focus;
+    }
+
+    // Nothing is held on `target` anymore, because the devices it was told about went away.
+    pub fn forget(&mut self, target: &Focus) {
+        self.ledgers.remove(target);
+    }
+
+    // The receiver with the given fingerprint is gone. Returns whether it had focus, which then
+    // falls back to this machine.
+    pub fn lost(&mut self, fingerprint: &str) -> bool {
+        self.forget(&Focus::Remote(fingerprint.to_owned()));
+        if !self.is_focused(fingerprint) {
+            return false;
+        }
+        self.focus = Focus::Local;
+        true
+    }
+
+    pub fn is_blocked(&self, fingerprint: &str) -> bool {
+        self.blocked.contains(fingerprint)
+    }
+
+    // The schedule changed: from now on the receivers in `blocked` can't get focus, and focus
+    // moves to `default_focus` if one is given. A blocked receiver that has focus loses it to
+    // this machine.
+    pub fn apply_rules(&mut self, blocked: HashSet<String>, default_focus: Option<Focus>) -> Vec<Action> {
+        self.blocked = blocked;
+        let target = match default_focus {
+            Some(Focus::Remote(fingerprint)) if self.blocked.contains(&fingerprint) => None,
+            Some(target) => Some(target),
+            None => None,
+        };
+        let target = match (target, &self.focus) {
+            (Some(target), _) => target,
+            (None, Focus::Remote(fingerprint)) if self.blocked.contains(fingerprint) => Focus::Local,
+            (None, _) => return Vec::new(),
+        };
+        self.switch_to(target)
+    }
+
+    pub fn is_focused(&self, fingerprint: &str) -> bool {
+        matches!(&self.focus, Focus::Remote(focused) if focused == fingerprint)
+    }
+
+    // Note that an event from a Forward action was written to `target`.
+    pub fn delivered(&mut self, target: &Focus, event: &Event) {
+        if let Event::Input {
+            device_id,
+            input: InputEvent::Key { direction, kind },
+            syn: _,
+        } = *event
+        {
+            self.note(target, device_id, kind, direction);
+        }
+    }
+
+    // Release everything `target` was told is held down.
+    pub fn release_all(&mut self, target: &Focus) -> Vec<Action> {
+        let held = self.ledgers.remove(target).unwrap_or_default();
+        held.into_iter()
+            .rev()
+            .map(|(device_id, kind)| Action::Write(target.clone(), key_event(device_id, kind, Direction::Up)))
+            .collect()
+    }
+
+    // Handle an event read from a local device. `order` has the fingerprints of the connected
+    // receivers, in the order switching goes through them, and `media_receiver` the one media
+    // keys should go to, if it is connected.
+    pub fn input(
+        &mut self,
+        event: Event,
+        order: &[&str],
+        media_receiver: Option<&str>,
+        now: Instant,
+    ) -> Vec<Action> {
+        let mut actions = Vec::new();
+        let mut swallowed = false;
+
+        // Blocked receivers are passed over as if they weren't connected.
+        let order: Vec<&str> = order.iter().copied().filter(|fingerprint| !self.blocked.contains(*fingerprint)).collect();
+        let order = &order[..];
+        let media_receiver = media_receiver.filter(|fingerprint| !self.blocked.contains(*fingerprint));
+
+        let scan = match event {
+            Event::Input {
+                device_id,
+                input: InputEvent::Other { type_: EV_MSC, code: MSC_SCAN, .. },
+                syn: _,
+            } => {
+                self.scans.insert(device_id, event.clone());
+                None
+            }
+            Event::Input { device_id, .. } => self.scans.remove(&device_id),
+            _ => None,
+        };
+
+        let remote = matches!(self.focus, Focus::Remote(_));
+
+        // Mouse, touchpad and tablet input can be kept on this machine as well. Buttons among the
+        // switch keys still take part in switching.
+        if remote && self.local_pointer {
+            match event {
+                Event::Input { input: InputEvent::Other { type_: EV_REL | EV_ABS, .. }, .. } => {
+                    actions.push(Action::Write(Focus::Local, event));
+                    return actions;
+                }
+                Event::Input {
+                    device_id,
+                    input: InputEvent::Key { direction, kind: kind @ KeyKind::Button(_) },
+                    syn: _,
+                } if !self.combo.contains(&kind) => {
+                    let held = self.ledgers.get(&Focus::Local).map(|ledger| ledger.contains(&(device_id, kind)));
+                    // A button that went down before the switch is released where it went down.
+                    if direction == Direction::Down || held == Some(true) {
+                        actions.extend(scan.map(|scan| Action::Write(Focus::Local, scan)));
+                        self.note(&Focus::Local, device_id, kind, direction);
+                        actions.push(Action::Write(Focus::Local, event));
+                        return actions;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if let Event::Input {
+            device_id,
+            input: InputEvent::Key { direction, kind },
+            syn: _,
+        } = event
+        {
+            // Keys that control this machine's own hardware, like its keyboard backlight or Wi-Fi,
+            // are written to it directly. Its scan code goes along, for whatever looks at those.
+            if remote && self.local_keys.contains(&kind) && !self.combo.contains(&kind) {
+                let held = self.ledgers.get(&Focus::Local).map(|ledger| ledger.contains(&(device_id, kind)));
+                // A key that went down before the switch was already released here.
+                if direction == Direction::Down || held == Some(true) {
+                    actions.extend(scan.map(|scan| Action::Write(Focus::Local, scan)));
+                    self.note(&Focus::Local, device_id, kind, direction);
+                    actions.push(Action::Write(Focus::Local, event));
+                } else {
+                    actions.push(Action::Drop { event, reason: DropReason::Released });
+                }
+                return actions;
+            }
+
+            // Media keys can go somewhere else than the rest of the input, without taking part in
+            // switching at all.
+            if let KeyKind::Key(key) = kind {
+                if key.is_media() && !self.combo.contains(&kind) {
+                    if let Some(target) = self.media_target(order, media_receiver) {
+                        self.note(&target, device_id, kind, direction);
+                        actions.push(Action::Write(target, event));
+                        return actions;
+                    }
+                }
+            }
+
+            // Guarded keys pressed while a receiver has focus go nowhere.
+            if remote && !self.combo.contains(&kind) && !self.guard.allow(device_id, kind, direction, now) {
+                actions.push(Action::Drop { event, reason: DropReason::Guarded });
+                return actions;
+            }
+
+            match kind {
+                KeyKind::Button(button) => {
+                    if !self.combo.contains(&kind) {
+                        match direction {
+                            Direction::Down => self.held_buttons.insert((device_id, button)),
+                            Direction::Up => self.held_buttons.remove(&(device_id, button)),
+                        };
+                    }
+                }
+                KeyKind::Key(_) => {
+                    let held = self.held_keys.iter().position(|&entry| entry == (device_id, kind));
+                    match (direction, held) {
+                        (Direction::Down, None) => self.held_keys.push((device_id, kind)),
+                        (Direction::Up, Some(idx)) => {
+                            self.held_keys.remove(idx);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            if self.combo.update(kind, direction, now) {
+                swallowed = true;
+                self.switch(Some(device_id), next(order, &self.focus), &mut actions);
+            }
+        }
+
+        actions.push(Action::Forward { event, swallowed });
+        actions
+    }
+
+    // Switch to `target` on request rather than through the switch keys, e.g. from the control
+    // socket. Nothing happens if it already has focus.
+    pub fn switch_to(&mut self, target: Focus) -> Vec<Action> {
+        let mut actions = Vec::new();
+        if target != self.focus {
+            self.switch(None, target, &mut actions);
+        }
+        actions
+    }
+
+    // `device_id` is the device the switch keys were pressed on, if they were.
+    fn switch(&mut self, device_id: Option<u16>, new_focus: Focus, actions: &mut Vec<Action>) {
+        // Release everything held on the current client, so no key is left stuck down there. This
+        // includes held mouse buttons, so it doesn't think a drag is still in progress.
+        let old_focus = self.focus.clone();
+        actions.extend(self.release_all(&old_focus));
+
+        // Press keys that are still held on the new client, as far as the policy goes.
+        let keys: Vec<(u16, KeyKind)> = match self.transfer_keys_on_switch {
+            KeyTransferPolicy::None => Vec::new(),
+            KeyTransferPolicy::ComboOnly => match device_id {
+                Some(device_id) => self
+                    .combo
+                    .keys()
+                    .iter()
+                    .filter(|key| key.is_modifier())
+                    .map(|&key| (device_id, key))
+                    .collect(),
+                None => Vec::new(),
+            },
+            KeyTransferPolicy::AllModifiers => {
+                self.held_keys.iter().copied().filter(|(_, key)| key.is_modifier()).collect()
+            }
+            KeyTransferPolicy::AllKeys => self.held_keys.clone(),
+        };
+        for (key_device_id, key) in keys {
+            self.press(&new_focus, key_device_id, key, actions);
+        }
+
+        // Carry held mouse buttons over if asked to. A tablet tool that is in range always is:
+        // tablets only report where a tool is once it comes into range, and without that the new
+        // client would ignore it until it leaves and comes back.
+        let transfer_all = self.held_buttons_on_switch == HeldButtonPolicy::Transfer;
+        let buttons: Vec<(u16, Button)> = self
+            .held_buttons
+            .iter()
+            .copied()
+            .filter(|(_, button)| transfer_all || button.is_tablet_tool())
+            .collect();
+        for (button_device_id, button) in buttons {
+            self.press(&new_focus, button_device_id, KeyKind::Button(button), actions);
+        }
+
+        self.focus = new_focus.clone();
+        actions.push(Action::Switch(new_focus));
+    }
+
+    // Where media keys go instead of the focused machine, if anywhere. Always sending them to a
+    // receiver falls back to the first one when the chosen one isn't connected, and to this
+    // machine when none are.
+    fn media_target(&self, order: &[&str], media_receiver: Option<&str>) -> Option<Focus> {
+        match self.media_keys {
+            MediaKeyPolicy::FollowFocus => None,
+            MediaKeyPolicy::AlwaysLocal => Some(Focus::Local),
+            MediaKeyPolicy::AlwaysRemote => Some(
+                media_receiver
+                    .or_else(|| order.first().copied())
+                    .map(|fingerprint| Focus::Remote(fingerprint.to_owned()))
+                    .unwrap_or(Focus::Local),
+            ),
+        }
+    }
+
+    fn note(&mut self, target: &Focus, device_id: u16, kind: KeyKind, direction: Direction) {
+        let ledger = self.ledgers.entry(target.clone()).or_default();
+        let held = ledger.iter().position(|&entry| entry == (device_id, kind));
+        match (direction, held) {
+            (Direction::Down, None) => ledger.push((device_id, kind)),
+            (Direction::Up, Some(idx)) => {
+                ledger.remove(idx);
+            }
+            _ => {}
+        }
+    }
+
+    // Press a key on `target`, unless it is already held there.
+    fn press(&mut self, target: &Focus, device_id: u16, kind: KeyKind, actions: &mut Vec<Action>) {
+        let held = self
+            .ledgers
+            .get(target)
+            .map(|ledger| ledger.contains(&(device_id, kind)))
+            .unwrap_or(false);
+        if !held {
+            self.note(target, device_id, kind, Direction::Down);
+            actions.push(Action::Write(target.clone(), key_event(device_id, kind, Direction::Down)));
+        }
+    }
+}
+
+// Where the switch keys move focus to from `focus`: the next receiver in `order`, or this machine
+// after the last one.
+fn next(order: &[&str], focus: &Focus) -> Focus {
+    let next = match focus {
+        Focus::Local => order.first(),
+        Focus::Remote(fingerprint) => order
+            .iter()
+            .position(|other| *other == fingerprint.as_str())
+            .and_then(|idx| order.get(idx + 1)),
+    };
+    next.map(|fingerprint| Focus::Remote(fingerprint.to_string()))
+        .unwrap_or(Focus::Local)
+}
+
+fn key_event(device_id: u16, kind: KeyKind, direction: Direction) -> Event {
+    Event::Input {
+        device_id,
+        input: InputEvent::Key { direction, kind },
+        syn: true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use input::Key;
+
+    const KEYBOARD: u16 = 1;
+    const MOUSE: u16 = 2;
+
+    const LEFT_ALT: KeyKind = KeyKind::Key(Key::LeftAlt);
+    const RIGHT_ALT: KeyKind = KeyKind::Key(Key::RightAlt);
+    const LEFT_CTRL: KeyKind = KeyKind::Key(Key::LeftCtrl);
+    const A: KeyKind = KeyKind::Key(Key::A);
+    const LEFT: KeyKind = KeyKind::Button(Button::Left);
+
+    // What came of an event, with key events boiled down to their key and direction.
+    #[derive(Debug, PartialEq)]
+    enum Out {
+        Write(Focus, KeyKind, Direction),
+        Switch(Focus),
+        // Forwarded to wherever has focus, which is noted as having gotten it unless swallowed.
+        Forward(Focus, KeyKind, Direction),
+        ForwardOther(Focus, u16),
+        WriteOther(Focus, u16),
+        Drop(KeyKind, DropReason),
+    }
+
+    fn state() -> FocusState {
+        let combo = ComboMatcher::new(vec![LEFT_ALT, RIGHT_ALT], false, None);
+        FocusState::new(
+            combo,
+            KeyGuard::new(Vec::new(), None),
+            HeldButtonPolicy::Release,
+            KeyTransferPolicy::None,
+            MediaKeyPolicy::FollowFocus,
+            Vec::new(),
+            false,
+        )
+    }
+
+    fn remote(fingerprint: &str) -> Focus {
+        Focus::Remote(fingerprint.to_owned())
+    }
+
+    fn motion() -> Event {
+        Event::Input { device_id: MOUSE, input: InputEvent::Other { type_: EV_REL, code: 0, value: 1 }, syn: false }
+    }
+
+    // Boil actions down, and note where forwarded events went the way the server loop does.
+    fn carry_out(state: &mut FocusState, actions: Vec<Action>) -> Vec<Out> {
+        let mut out = Vec::new();
+        for action in actions {
+            match action {
+                Action::Write(target, Event::Input { input: InputEvent::Key { direction, kind }, .. }) => {
+                    out.push(Out::Write(target, kind, direction))
+                }
+                Action::Write(target, Event::Input { input: InputEvent::Other { type_, .. }, .. }) => {
+                    out.push(Out::WriteOther(target, type_))
+                }
+                Action::Write(..) => panic!("Wrote something other than input"),
+                Action::Switch(target) => out.push(Out::Switch(target)),
+                Action::Drop { event: Event::Input { input: InputEvent::Key { kind, .. }, .. }, reason } => {
+                    out.push(Out::Drop(kind, reason))
+                }
+                Action::Drop { .. } => panic!("Dropped something other than a key"),
+                Action::Forward { event, swallowed } => {
+                    let target = state.focus().clone();
+                    if swallowed && target == Focus::Local {
+                        continue;
+                    }
+                    state.delivered(&target, &event);
+                    match event {
+                        Event::Input { input: InputEvent::Key { direction, kind }, .. } => {
+                            out.push(Out::Forward(target, kind, direction))
+                        }
+                        Event::Input { input: InputEvent::Other { type_, .. }, .. } => {
+                            out.push(Out::ForwardOther(target, type_))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    fn feed(state: &mut FocusState, event: Event, order: &[&str], media_receiver: Option<&str>) -> Vec<Out> {
+        let actions = state.input(event, order, media_receiver, Instant::now());
+        carry_out(state, actions)
+    }
+
+    fn key(state: &mut FocusState, device_id: u16, kind: KeyKind, direction: Direction, order: &[&str]) -> Vec<Out> {
+        feed(state, key_event(device_id, kind, direction), order, None)
+    }
+
+    // Press the switch keys and let go of them again, returning what pressing them did.
+    fn switch(state: &mut FocusState, order: &[&str]) -> Vec<Out> {
+        key(state, KEYBOARD, LEFT_ALT, Direction::Down, order);
+        let out = key(state, KEYBOARD, RIGHT_ALT, Direction::Down, order);
+        key(state, KEYBOARD, RIGHT_ALT, Direction::Up, order);
+        key(state, KEYBOARD, LEFT_ALT, Direction::Up, order);
+        out
+    }
+
+    #[test]
+    fn switch_keys_go_through_receivers_in_order() {
+        let mut state = state();
+        let order = ["a", "b"];
+        for expected in [remote("a"), remote("b"), Focus::Local, remote("a")] {
+            let out = switch(&mut state, &order);
+            assert!(out.contains(&Out::Switch(expected.clone())), "{:?}", out);
+            assert_eq!(state.focus(), &expected);
+        }
+    }
+
+    #[test]
+    fn key_completing_switch_keys_is_swallowed() {
+        let mut state = state();
+        let order = ["a"];
+        assert_eq!(
+            key(&mut state, KEYBOARD, LEFT_ALT, Direction::Down, &order),
+            vec![Out::Forward(Focus::Local, LEFT_ALT, Direction::Down)]
+        );
+        assert_eq!(
+            key(&mut state, KEYBOARD, RIGHT_ALT, Direction::Down, &order),
+            vec![
+                Out::Write(Focus::Local, LEFT_ALT, Direction::Up),
+                Out::Switch(remote("a")),
+                Out::Forward(remote("a"), RIGHT_ALT, Direction::Down),
+            ]
+        );
+    }
+
+    #[test]
+    fn held_keys_are_released_and_pressed_by_policy() {
+        let cases = [
+            (KeyTransferPolicy::None, vec![]),
+            (KeyTransferPolicy::ComboOnly, vec![LEFT_ALT, RIGHT_ALT]),
+            (KeyTransferPolicy::AllModifiers, vec![LEFT_CTRL, LEFT_ALT, RIGHT_ALT]),
+            (KeyTransferPolicy::AllKeys, vec![LEFT_CTRL, A, LEFT_ALT, RIGHT_ALT]),
+        ];
+        for (policy, pressed) in cases {
+            let mut state = state();
+            state.transfer_keys_on_switch = policy;
+            let order = ["a"];
+            key(&mut state, KEYBOARD, LEFT_CTRL, Direction::Down, &order);
+            key(&mut state, KEYBOARD, A, Direction::Down, &order);
+            key(&mut state, KEYBOARD, LEFT_ALT, Direction::Down, &order);
+            let out = key(&mut state, KEYBOARD, RIGHT_ALT, Direction::Down, &order);
+
+            // Everything held here is released here, most recent first.
+            let mut expected = vec![
+                Out::Write(Focus::Local, LEFT_ALT, Direction::Up),
+                Out::Write(Focus::Local, A, Direction::Up),
+                Out::Write(Focus::Local, LEFT_CTRL, Direction::Up),
+            ];
+            expected.extend(pressed.iter().map(|&kind| Out::Write(remote("a"), kind, Direction::Down)));
+            expected.push(Out::Switch(remote("a")));
+            expected.push(Out::Forward(remote("a"), RIGHT_ALT, Direction::Down));
+            assert_eq!(out, expected, "{:?}", policy);
+
+            // Whatever is still held on the receiver is released there on the way back, along with
+            // the first of the switch keys.
+            key(&mut state, KEYBOARD, RIGHT_ALT, Direction::Up, &order);
+            key(&mut state, KEYBOARD, LEFT_ALT, Direction::Up, &order);
+            let mut expected: Vec<KeyKind> =
+                pressed.iter().copied().filter(|kind| *kind != LEFT_ALT && *kind != RIGHT_ALT).collect();
+            expected.push(LEFT_ALT);
+            expected.reverse();
+            let out = switch(&mut state, &order);
+            let released: Vec<KeyKind> = out
+                .iter()
+                .filter_map(|out| match out {
+                    Out::Write(target, kind, Direction::Up) if *target == remote("a") => Some(*kind),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(released, expected, "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn held_buttons_are_released_or_transferred_by_policy() {
+        for (policy, transferred) in [(HeldButtonPolicy::Release, false), (HeldButtonPolicy::Transfer, true)] {
+            let mut state = state();
+            state.held_buttons_on_switch = policy;
+            let order = ["a"];
+            key(&mut state, MOUSE, LEFT, Direction::Down, &order);
+            let out = switch(&mut state, &order);
+            assert!(out.contains(&Out::Write(Focus::Local, LEFT, Direction::Up)), "{:?}", out);
+            assert_eq!(out.contains(&Out::Write(remote("a"), LEFT, Direction::Down)), transferred, "{:?}", out);
+        }
+    }
+
+    #[test]
+    fn tablet_tools_in_range_are_always_transferred() {
+        let mut state = state();
+        let pen = KeyKind::Button(Button::ToolPen);
+        let order = ["a"];
+        key(&mut state, MOUSE, pen, Direction::Down, &order);
+        let out = switch(&mut state, &order);
+        assert!(out.contains(&Out::Write(Focus::Local, pen, Direction::Up)), "{:?}", out);
+        assert!(out.contains(&Out::Write(remote("a"), pen, Direction::Down)), "{:?}", out);
+    }
+
+    #[test]
+    fn lost_receiver_gives_focus_back() {
+        let mut state = state();
+        let order = ["a", "b"];
+        switch(&mut state, &order);
+        assert!(!state.lost("b"));
+        assert_eq!(state.focus(), &remote("a"));
+        assert!(state.lost("a"));
+        assert_eq!(state.focus(), &Focus::Local);
+    }
+
+    #[test]
+    fn forgotten_keys_are_not_released() {
+        let mut state = state();
+        let order = ["a"];
+        switch(&mut state, &order);
+        key(&mut state, KEYBOARD, A, Direction::Down, &order);
+        state.forget(&remote("a"));
+        assert!(state.release_all(&remote("a")).is_empty());
+
+        key(&mut state, KEYBOARD, A, Direction::Down, &order);
+        assert_eq!(state.release_all(&remote("a")).len(), 1);
+    }
+
+    #[test]
+    fn local_keys_stay_here() {
+        let mut state = state();
+        let backlight = KeyKind::Key(Key::KbdIllumUp);
+        state.local_keys = vec![backlight];
+        let order = ["a"];
+
+        // Held before the switch, it is released here with everything else, and its release
+        // afterwards goes nowhere.
+        key(&mut state, KEYBOARD, backlight, Direction::Down, &order);
+        let out = switch(&mut state, &order);
+        assert!(out.contains(&Out::Write(Focus::Local, backlight, Direction::Up)), "{:?}", out);
+        assert_eq!(
+            key(&mut state, KEYBOARD, backlight, Direction::Up, &order),
+            vec![Out::Drop(backlight, DropReason::Released)]
+        );
+
+        assert_eq!(
+            key(&mut state, KEYBOARD, backlight, Direction::Down, &order),
+            vec![Out::Write(Focus::Local, backlight, Direction::Down)]
+        );
+        assert_eq!(
+            key(&mut state, KEYBOARD, backlight, Direction::Up, &order),
+            vec![Out::Write(Focus::Local, backlight, Direction::Up)]
+        );
+        assert_eq!(
+            key(&mut state, KEYBOARD, A, Direction::Down, &order),
+            vec![Out::Forward(remote("a"), A, Direction::Down)]
+        );
+    }
+
+    #[test]
+    fn guarded_keys_are_dropped_while_a_receiver_has_focus() {
+        let mut state = state();
+        let power = KeyKind::Key(Key::Power);
+        state.guard = KeyGuard::new(vec![power], None);
+        let order = ["a"];
+
+        assert_eq!(
+            key(&mut state, KEYBOARD, power, Direction::Down, &order),
+            vec![Out::Forward(Focus::Local, power, Direction::Down)]
+        );
+        key(&mut state, KEYBOARD, power, Direction::Up, &order);
+
+        switch(&mut state, &order);
+        assert_eq!(
+            key(&mut state, KEYBOARD, power, Direction::Down, &order),
+            vec![Out::Drop(power, DropReason::Guarded)]
+        );
+    }
+
+    #[test]
+    fn local_pointer_stays_here() {
+        let mut state = state();
+        state.local_pointer = true;
+        let order = ["a"];
+        switch(&mut state, &order);
+
+        assert_eq!(feed(&mut state, motion(), &order, None), vec![Out::WriteOther(Focus::Local, EV_REL)]);
+        assert_eq!(
+            key(&mut state, MOUSE, LEFT, Direction::Down, &order),
+            vec![Out::Write(Focus::Local, LEFT, Direction::Down)]
+        );
+        assert_eq!(
+            key(&mut state, MOUSE, LEFT, Direction::Up, &order),
+            vec![Out::Write(Focus::Local, LEFT, Direction::Up)]
+        );
+        assert_eq!(
+            key(&mut state, KEYBOARD, A, Direction::Down, &order),
+            vec![Out::Forward(remote("a"), A, Direction::Down)]
+        );
+
+        // Without a receiver in focus, it goes the usual way.
+        state.set_focus(Focus::Local);
+        assert_eq!(feed(&mut state, motion(), &order, None), vec![Out::ForwardOther(Focus::Local, EV_REL)]);
+    }
+
+    #[test]
+    fn media_keys_go_where_the_policy_says() {
+        let mute = KeyKind::Key(Key::Mute);
+        let cases = [
+            (MediaKeyPolicy::FollowFocus, &["a", "b"][..], Some("b"), Out::Forward(remote("a"), mute, Direction::Down)),
+            (MediaKeyPolicy::AlwaysLocal, &["a", "b"][..], Some("b"), Out::Write(Focus::Local, mute, Direction::Down)),
+            (MediaKeyPolicy::AlwaysRemote, &["a", "b"][..], Some("b"), Out::Write(remote("b"), mute, Direction::Down)),
+            // Without the chosen receiver, the first one gets them, or this machine without any.
+            (MediaKeyPolicy::AlwaysRemote, &["a", "b"][..], None, Out::Write(remote("a"), mute, Direction::Down)),
+            (MediaKeyPolicy::AlwaysRemote, &[][..], None, Out::Write(Focus::Local, mute, Direction::Down)),
+        ];
+        for (policy, order, media_receiver, expected) in cases {
+            let mut state = state();
+            state.media_keys = policy;
+            state.set_focus(remote("a"));
+            let out = feed(&mut state, key_event(KEYBOARD, mute, Direction::Down), order, media_receiver);
+            assert_eq!(out, vec![expected], "{:?}", policy);
+            assert_eq!(state.focus(), &remote("a"));
+        }
+    }
+
+    #[test]
+    fn rules_block_receivers_and_move_focus() {
+        let mut state = state();
+        let order = ["a", "b"];
+        let blocked = |fingerprints: &[&str]| fingerprints.iter().map(|fingerprint| fingerprint.to_string()).collect();
+
+        // A default focus moves focus there.
+        let actions = state.apply_rules(blocked(&[]), Some(remote("b")));
+        let out = carry_out(&mut state, actions);
+        assert_eq!(out, vec![Out::Switch(remote("b"))]);
+
+        // Blocking the receiver with focus hands it back to this machine.
+        let actions = state.apply_rules(blocked(&["b"]), None);
+        let out = carry_out(&mut state, actions);
+        assert_eq!(out, vec![Out::Switch(Focus::Local)]);
+        assert!(state.is_blocked("b"));
+
+        // A blocked default is passed over, and so are blocked receivers when switching.
+        let actions = state.apply_rules(blocked(&["a"]), Some(remote("a")));
+        let out = carry_out(&mut state, actions);
+        assert!(out.is_empty());
+        switch(&mut state, &order);
+        assert_eq!(state.focus(), &remote("b"));
+        switch(&mut state, &order);
+        assert_eq!(state.focus(), &Focus::Local);
+    }
+}
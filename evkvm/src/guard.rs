@@ -0,0 +1,106 @@
+use input::{Direction, KeyKind};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+// Keeps dangerous keys, like power and sleep, from reaching a receiver. A flaky power button that
+// bounces while a receiver has focus shouldn't be able to suspend it. With a confirmation window,
+// pressing a guarded key twice within it lets the second press through.
+
+// How long a guarded key has to stay quiet before a second press confirms it. Bounces come in quick
+// bursts, and each press in one starts the wait over, so a bouncing button can't confirm itself.
+const CONFIRM_MIN_GAP: Duration = Duration::from_millis(150);
+
+pub struct KeyGuard {
+    keys: Vec<KeyKind>,
+    confirm_window: Option<Duration>,
+    // When each guarded key was last held back.
+    refused: HashMap<KeyKind, Instant>,
+    // Guarded keys that were let through and are still down, so that they are released too.
+    passed: HashSet<(u16, KeyKind)>,
+}
+
+impl KeyGuard {
+    pub fn new(keys: Vec<KeyKind>, confirm_window: Option<Duration>) -> Self {
+        KeyGuard {
+            keys,
+            confirm_window,
+            refused: HashMap::new(),
+            passed: HashSet::new(),
+        }
+    }
+
+    // Note that a key went up or down while a receiver has focus. Returns whether it may be
+    // forwarded.
+    pub fn allow(&mut self, device_id: u16, kind: KeyKind, direction: Direction, now: Instant) -> bool {
+        if !self.keys.contains(&kind) {
+            return true;
+        }
+
+        match direction {
+            Direction::Down => {
+                let confirmed = match (self.confirm_window, self.refused.get(&kind)) {
+                    (Some(window), Some(&refused)) => {
+                        let gap = now.duration_since(refused);
+                        gap >= CONFIRM_MIN_GAP && gap <= window
+                    }
+                    _ => false,
+                };
+                if confirmed {
+                    self.refused.remove(&kind);
+                    self.passed.insert((device_id, kind));
+                } else {
+                    self.refused.insert(kind, now);
+                }
+                confirmed
+            }
+            Direction::Up => self.passed.remove(&(device_id, kind)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use input::Key;
+
+    const POWER: KeyKind = KeyKind::Key(Key::Power);
+
+    // Press and release the power key at each of `times`, in milliseconds after `start`, returning
+    // which presses were let through.
+    fn presses(guard: &mut KeyGuard, start: Instant, times: &[u64]) -> Vec<bool> {
+        times
+            .iter()
+            .map(|&ms| {
+                let now = start + Duration::from_millis(ms);
+                let allowed = guard.allow(0, POWER, Direction::Down, now);
+                assert_eq!(guard.allow(0, POWER, Direction::Up, now), allowed);
+                allowed
+            })
+            .collect()
+    }
+
+    #[test]
+    fn guarded_keys_are_held_back_without_a_window() {
+        let mut guard = KeyGuard::new(vec![POWER], None);
+        assert_eq!(presses(&mut guard, Instant::now(), &[0, 300, 600]), [false, false, false]);
+        assert!(guard.allow(0, KeyKind::Key(Key::A), Direction::Down, Instant::now()));
+    }
+
+    #[test]
+    fn a_second_press_within_the_window_goes_through() {
+        let mut guard = KeyGuard::new(vec![POWER], Some(Duration::from_millis(1000)));
+        let start = Instant::now();
+        assert_eq!(presses(&mut guard, start, &[0, 300]), [false, true]);
+        // Confirming uses the first press up, and a press after the window starts over.
+        assert_eq!(presses(&mut guard, start, &[400, 1500, 1800]), [false, false, true]);
+    }
+
+    #[test]
+    fn bouncing_keys_never_confirm_themselves() {
+        let mut guard = KeyGuard::new(vec![POWER], Some(Duration::from_millis(1000)));
+        let start = Instant::now();
+        assert_eq!(presses(&mut guard, start, &[0, 10, 25, 40, 60, 100, 140]), [false; 7]);
+        // The burst is over, so a deliberate press confirms.
+        assert_eq!(presses(&mut guard, start, &[300]), [true]);
+    }
+}
@@ -0,0 +1,101 @@
+use input::{Direction, Event, InputEvent, KeyKind};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::control::HistoryEntry;
+
+// Senders remember the input they read over the last few seconds and where it went, so that
+// `evkvm debug dump` can show why a keystroke ended up on the wrong machine after the fact, without
+// logging every event all the time. Unless debug-history-key-names is set, keys are only recorded
+// as keys or buttons, not which ones, since the history would otherwise hold whatever was typed.
+
+// Fast mice send a thousand events a second, so the history is capped at this many entries
+// whatever its window.
+const MAX_ENTRIES: usize = 100_000;
+
+const EV_SYN: u16 = 0x00;
+const EV_MSC: u16 = 0x04;
+
+pub struct History {
+    window: Duration,
+    key_names: bool,
+    entries: VecDeque<(Instant, String)>,
+}
+
+impl History {
+    // Keep entries for `window`, where a zero window keeps none.
+    pub fn new(window: Duration, key_names: bool) -> Self {
+        History { window, key_names, entries: VecDeque::new() }
+    }
+
+    // Record what happened to `event`. Events that only end a report aren't worth the space.
+    pub fn event(&mut self, event: &Event, what: impl FnOnce() -> String) {
+        if let Event::Input { input: InputEvent::Other { type_: EV_SYN, .. }, .. } = event {
+            return;
+        }
+        if !self.window.is_zero() {
+            let entry = format!("{}: {}", self.describe(event), what());
+            self.push(entry);
+        }
+    }
+
+    // Record a decision that isn't about one event, like a switch.
+    pub fn note(&mut self, what: impl FnOnce() -> String) {
+        if !self.window.is_zero() {
+            self.push(what());
+        }
+    }
+
+    // The entries still within the window, oldest first.
+    pub fn dump(&self) -> Vec<HistoryEntry> {
+        let now = Instant::now();
+        self.entries
+            .iter()
+            .filter(|(time, _)| now.duration_since(*time) <= self.window)
+            .map(|(time, what)| HistoryEntry {
+                age_ms: now.duration_since(*time).as_millis() as u64,
+                what: what.clone(),
+            })
+            .collect()
+    }
+
+    fn push(&mut self, entry: String) {
+        let now = Instant::now();
+        while let Some((time, _)) = self.entries.front() {
+            if self.entries.len() < MAX_ENTRIES && now.duration_since(*time) <= self.window {
+                break;
+            }
+            self.entries.pop_front();
+        }
+        self.entries.push_back((now, entry));
+    }
+
+    fn describe(&self, event: &Event) -> String {
+        match event {
+            Event::Input { device_id, input: InputEvent::Key { direction, kind }, .. } => {
+                let direction = match direction {
+                    Direction::Down => "down",
+                    Direction::Up => "up",
+                };
+                if self.key_names {
+                    format!("device {} {:?} {}", device_id, kind, direction)
+                } else {
+                    let kind = match kind {
+                        KeyKind::Key(_) => "key",
+                        KeyKind::Button(_) => "button",
+                    };
+                    format!("device {} {} {}", device_id, kind, direction)
+                }
+            }
+            // Scan codes give away which key was pressed as much as its name does.
+            Event::Input { device_id, input: InputEvent::Other { type_: EV_MSC, code, .. }, .. } if !self.key_names => {
+                format!("device {} type {} code {}", device_id, EV_MSC, code)
+            }
+            Event::Input { device_id, input: InputEvent::Other { type_, code, value }, .. } => {
+                format!("device {} type {} code {} value {}", device_id, type_, code, value)
+            }
+            Event::NewDevice(device) => format!("new device {} \"{}\"", device.id, device.name),
+            Event::RemoveDevice(id) => format!("device {} removed", id),
+        }
+    }
+}
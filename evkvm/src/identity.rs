@@ -0,0 +1,172 @@
+use anyhow::{Error, anyhow};
+use rcgen::{Certificate, CertificateParams, DistinguishedName, DnType, SanType};
+use ring::signature::{self, EcdsaKeyPair};
+use std::net::IpAddr;
+use std::path::Path;
+use tokio_rustls::rustls;
+
+use crate::common::{Identity, write_file_atomically};
+use crate::config::{CertificateOptions, KeyType};
+
+// AlgorithmIdentifier contents (id-ecPublicKey plus the named curve) for the curves rustls
+// can sign with, used to wrap SEC1 "EC PRIVATE KEY" files into PKCS#8.
+const EC_ALGORITHMS: [(&[u8], &signature::EcdsaSigningAlgorithm); 2] = [
+    (
+        b"\x06\x07\x2a\x86\x48\xce\x3d\x02\x01\x06\x08\x2a\x86\x48\xce\x3d\x03\x01\x07",
+        &signature::ECDSA_P256_SHA256_ASN1_SIGNING,
+    ),
+    (
+        b"\x06\x07\x2a\x86\x48\xce\x3d\x02\x01\x06\x05\x2b\x81\x04\x00\x22",
+        &signature::ECDSA_P384_SHA384_ASN1_SIGNING,
+    ),
+];
+
+fn der_encode(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+    let length = contents.len();
+    if length < 0x80 {
+        encoded.push(length as u8);
+    } else {
+        let length_bytes = length.to_be_bytes();
+        let length_bytes: Vec<u8> = length_bytes.iter().copied().skip_while(|&b| b == 0).collect();
+        encoded.push(0x80 | length_bytes.len() as u8);
+        encoded.extend_from_slice(&length_bytes);
+    }
+    encoded.extend_from_slice(contents);
+    encoded
+}
+
+fn sec1_to_pkcs8(sec1: &[u8]) -> Option<Vec<u8>> {
+    // rustls only accepts ECDSA keys in PKCS#8 form, but OpenSSL and friends commonly produce
+    // SEC1 keys. The two differ only by a small wrapper, so add it and let ring figure out which
+    // curve the key is on.
+    EC_ALGORITHMS.iter().find_map(|(algorithm, signing_algorithm)| {
+        let mut contents = vec![0x02, 0x01, 0x00]; // version 0
+        contents.extend(der_encode(0x30, algorithm));
+        contents.extend(der_encode(0x04, sec1));
+        let pkcs8 = der_encode(0x30, &contents);
+
+        EcdsaKeyPair::from_pkcs8(signing_algorithm, &pkcs8)
+            .ok()
+            .map(|_| pkcs8)
+    })
+}
+
+pub fn load_identity(
+    certificate_path: &Path,
+) -> Result<Option<Identity>, Error> {
+    // Try loading the identity file at `certificate_path`. If no file exists, return None.
+
+    let file = match std::fs::File::open(certificate_path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(None)
+        },
+        Err(e) => { return Err(e.into()); },
+    };
+
+    let mut reader = std::io::BufReader::new(file);
+    let mut certificate: Option<rustls::Certificate> = None;
+    let mut private_key: Option<rustls::PrivateKey> = None;
+    loop {
+        let item = rustls_pemfile::read_one(&mut reader).map_err(|err| {
+            anyhow!("Identity file at {} could not be parsed: {}", &certificate_path.display(), err)
+        })?;
+        match item {
+            Some(rustls_pemfile::Item::X509Certificate(cert)) => {
+                certificate = Some(rustls::Certificate(cert));
+            },
+            // rustls accepts both PKCS#1 and PKCS#8 RSA keys as they are.
+            Some(rustls_pemfile::Item::PKCS8Key(key)) | Some(rustls_pemfile::Item::RSAKey(key)) => {
+                private_key = Some(rustls::PrivateKey(key));
+            },
+            Some(rustls_pemfile::Item::ECKey(key)) => {
+                let key = sec1_to_pkcs8(&key).ok_or_else(|| anyhow!(
+                    "Identity file at {} contains an EC private key on an unsupported curve! Only P-256 and P-384 are supported.",
+                    &certificate_path.display(),
+                ))?;
+                private_key = Some(rustls::PrivateKey(key));
+            },
+            None => { break; },
+            _ => {},
+        }
+    }
+    match (certificate, private_key) {
+        (Some(cert), Some(key)) => Ok(Some((cert, key))),
+        (Some(_), None) => Err(anyhow!("Identity file at {} is missing a private key!", &certificate_path.display())),
+        (None, Some(_)) => Err(anyhow!("Identity file at {} is missing a certificate!", &certificate_path.display())),
+        (None, None) => Err(anyhow!("Identity file at {} is missing both a certificate and a private key!", &certificate_path.display())),
+    }
+}
+
+fn certificate_params(
+    key_type: KeyType,
+    options: &CertificateOptions,
+) -> CertificateParams {
+    let mut params = CertificateParams::default();
+
+    params.alg = match key_type {
+        KeyType::Ed25519 => &rcgen::PKCS_ED25519,
+        KeyType::EcdsaP256 => &rcgen::PKCS_ECDSA_P256_SHA256,
+        KeyType::EcdsaP384 => &rcgen::PKCS_ECDSA_P384_SHA384,
+    };
+
+    if let Some(common_name) = &options.common_name {
+        params.distinguished_name = DistinguishedName::new();
+        params.distinguished_name.push(DnType::CommonName, common_name.clone());
+    }
+
+    params.subject_alt_names = options.alt_names.iter().map(|name| {
+        match name.parse::<IpAddr>() {
+            Ok(ip) => SanType::IpAddress(ip),
+            Err(_) => SanType::DnsName(name.clone()),
+        }
+    }).collect();
+
+    if let Some(validity_days) = options.validity_days {
+        let now = time::OffsetDateTime::now_utc();
+        params.not_before = now;
+        params.not_after = now + time::Duration::days(validity_days.into());
+    }
+
+    params
+}
+
+pub fn load_or_generate_identity(
+    certificate_path: &Path,
+    key_type: KeyType,
+    options: &CertificateOptions,
+) -> Result<Identity, Error> {
+    // Try loading the identity file at `certificate_path`, or create a new one if no file exists.
+
+    let identity = load_identity(certificate_path)?;
+    match identity {
+        // Use existing identity
+        Some(identity) => Ok(identity),
+
+        // Identity did not already exist, create it
+        None => {
+            let cert = Certificate::from_params(certificate_params(key_type, options))?;
+
+            let pem = cert.serialize_pem()?;
+            let private_key_pem = cert.serialize_private_key_pem();
+
+            // Only move the new identity into place once it is known to parse back correctly, so
+            // an interrupted or botched write can't break the next start. The identity we return
+            // is the one read back from the file, so it always matches what's on disk.
+            let contents = format!("{}{}", pem, private_key_pem);
+            let mut written = None;
+            write_file_atomically(certificate_path, contents.as_bytes(), 0o600, |temp_path| {
+                match load_identity(temp_path)? {
+                    Some(identity) if identity.1.0 == cert.serialize_private_key_der() => {
+                        written = Some(identity);
+                        Ok(())
+                    },
+                    _ => Err(anyhow!("Generated identity did not round-trip")),
+                }
+            })?;
+
+            Ok(written.unwrap())
+        },
+    }
+}
@@ -0,0 +1,68 @@
+use std::collections::HashSet;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+// Keeps a receiver's screen from blanking while a sender has focus on it, by holding a logind idle
+// inhibitor for as long as any sender does. Injected input doesn't count as activity everywhere,
+// and logind's own idle hint ignores it. The sender needs nothing of the sort: while its devices
+// are grabbed, its session sees no input and goes idle as usual.
+//
+// The inhibitor is taken with systemd-inhibit, which holds it until the program it runs exits.
+// That program is cat, which exits when we close its input.
+
+#[derive(Clone, Default)]
+pub struct IdleInhibitor {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    // The senders that have focus here, by address as configured, which unlike their names stays
+    // the same for as long as they are trusted.
+    focused: HashSet<String>,
+    inhibitor: Option<Child>,
+}
+
+impl IdleInhibitor {
+    // Record whether the sender at `address` has focus here.
+    pub fn set_focused(&self, address: &str, focused: bool) {
+        let mut state = self.state.lock().unwrap();
+        if focused {
+            state.focused.insert(address.to_owned());
+        } else {
+            state.focused.remove(address);
+        }
+
+        match (state.focused.is_empty(), state.inhibitor.take()) {
+            (false, None) => state.inhibitor = inhibit(),
+            (true, Some(inhibitor)) => release(inhibitor),
+            (_, inhibitor) => state.inhibitor = inhibitor,
+        }
+    }
+}
+
+fn inhibit() -> Option<Child> {
+    let result = Command::new("systemd-inhibit")
+        .args(["--what=idle", "--who=evkvm", "--why=A sender has focus here", "--mode=block", "cat"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn();
+    match result {
+        Ok(child) => Some(child),
+        Err(err) => {
+            log::warn!("Failed to take an idle inhibitor with systemd-inhibit: {}", err);
+            None
+        }
+    }
+}
+
+// cat exits as soon as its input is closed, but it is still waited for elsewhere, since this is
+// called with the state locked and from async code.
+fn release(mut inhibitor: Child) {
+    drop(inhibitor.stdin.take());
+    tokio::task::spawn_blocking(move || {
+        if let Err(err) = inhibitor.wait() {
+            log::warn!("Failed to release idle inhibitor: {}", err);
+        }
+    });
+}
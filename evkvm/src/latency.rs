@@ -0,0 +1,32 @@
+use anyhow::Error;
+use std::path::Path;
+
+use crate::control::{self, ControlRequest, ControlResponse};
+
+fn format_micros(micros: u64) -> String {
+    format!("{:.2} ms", micros as f64 / 1000.0)
+}
+
+// Ask the running daemon to probe a receiver and print how long the probes took to be injected.
+pub async fn print_latency(control_socket_path: &Path, nick: String, count: u32) -> Result<(), Error> {
+    let request = ControlRequest::Latency { receiver: nick, count };
+    let (mut samples, lost) = match control::request(control_socket_path, &request).await? {
+        ControlResponse::Latency { samples, lost } => (samples, lost),
+        ControlResponse::Error { message } => return Err(anyhow::anyhow!(message)),
+        response => return Err(anyhow::anyhow!("Unexpected response from evkvm: {:?}", response)),
+    };
+
+    println!("{} probes, {} lost", count, lost);
+    if samples.is_empty() {
+        return Ok(());
+    }
+
+    samples.sort_unstable();
+    let percentile = |p: usize| samples[(samples.len() - 1) * p / 100];
+    println!("min:    {}", format_micros(samples[0]));
+    println!("median: {}", format_micros(percentile(50)));
+    println!("p90:    {}", format_micros(percentile(90)));
+    println!("p99:    {}", format_micros(percentile(99)));
+    println!("max:    {}", format_micros(samples[samples.len() - 1]));
+    Ok(())
+}
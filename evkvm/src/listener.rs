@@ -0,0 +1,130 @@
+use futures::future::BoxFuture;
+use std::io::Error;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time;
+
+// How accepting connections is retried after an error, e.g. when running out of file descriptors.
+// The delay doubles with every failure in a row, and after enough of them the server gives up.
+const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(100);
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(5);
+const ACCEPT_MAX_FAILURES: u32 = 10;
+
+// Where the server gets connections from, so that accepting them can be tried out without a
+// network.
+pub trait Listener: Send {
+    type Stream;
+
+    fn accept(&mut self) -> BoxFuture<'_, Result<(Self::Stream, SocketAddr), Error>>;
+}
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    fn accept(&mut self) -> BoxFuture<'_, Result<(TcpStream, SocketAddr), Error>> {
+        Box::pin(TcpListener::accept(self))
+    }
+}
+
+// Accept the next connection, retrying after errors. Returns the last error once there have been
+// ACCEPT_MAX_FAILURES of them in a row.
+pub async fn accept<L: Listener>(listener: &mut L) -> Result<(L::Stream, SocketAddr), Error> {
+    let mut failures = 0;
+    let mut backoff = ACCEPT_BACKOFF_MIN;
+    loop {
+        match listener.accept().await {
+            Ok(accepted) => return Ok(accepted),
+            Err(err) => {
+                failures += 1;
+                if failures >= ACCEPT_MAX_FAILURES {
+                    return Err(err);
+                }
+                log::warn!("Error accepting connection ({}), retrying in {:?}", err, backoff);
+                time::sleep(backoff).await;
+                backoff = (backoff * 2).min(ACCEPT_BACKOFF_MAX);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use tokio::time::Instant;
+
+    // Fails as many times as it is told to before each connection, noting when it was asked.
+    struct MockListener {
+        failures: VecDeque<u32>,
+        calls: Vec<Instant>,
+    }
+
+    impl MockListener {
+        fn new(failures: &[u32]) -> Self {
+            MockListener { failures: failures.iter().copied().collect(), calls: Vec::new() }
+        }
+
+        // How long accept waited before each call after the first.
+        fn delays(&self) -> Vec<Duration> {
+            self.calls.windows(2).map(|calls| calls[1] - calls[0]).collect()
+        }
+    }
+
+    impl Listener for MockListener {
+        type Stream = ();
+
+        fn accept(&mut self) -> BoxFuture<'_, Result<((), SocketAddr), Error>> {
+            self.calls.push(Instant::now());
+            let result = match self.failures.front_mut() {
+                Some(0) => {
+                    self.failures.pop_front();
+                    Ok(((), SocketAddr::from(([127, 0, 0, 1], 5258))))
+                }
+                Some(failures) => {
+                    *failures -= 1;
+                    Err(Error::other("Too many open files"))
+                }
+                None => panic!("Accepted more connections than there are"),
+            };
+            Box::pin(async move { result })
+        }
+    }
+
+    fn millis(delays: &[u64]) -> Vec<Duration> {
+        delays.iter().copied().map(Duration::from_millis).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connections_are_accepted_right_away() {
+        let mut listener = MockListener::new(&[0, 0]);
+        accept(&mut listener).await.unwrap();
+        accept(&mut listener).await.unwrap();
+        assert_eq!(listener.delays(), millis(&[0]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backoff_doubles_up_to_its_maximum() {
+        let mut listener = MockListener::new(&[7]);
+        accept(&mut listener).await.unwrap();
+        assert_eq!(listener.delays(), millis(&[100, 200, 400, 800, 1600, 3200, 5000]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backoff_starts_over_after_a_connection() {
+        let mut listener = MockListener::new(&[2, 1]);
+        accept(&mut listener).await.unwrap();
+        accept(&mut listener).await.unwrap();
+        assert_eq!(listener.delays(), millis(&[100, 200, 0, 100]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn too_many_failures_give_up() {
+        let mut listener = MockListener::new(&[ACCEPT_MAX_FAILURES - 1]);
+        accept(&mut listener).await.unwrap();
+
+        let mut listener = MockListener::new(&[ACCEPT_MAX_FAILURES]);
+        assert!(accept(&mut listener).await.is_err());
+        assert_eq!(listener.calls.len(), ACCEPT_MAX_FAILURES as usize);
+    }
+}
@@ -1,99 +1,143 @@
 mod config;
 mod common;
+mod combo;
+mod command;
+mod container;
+mod control;
+mod crash;
+mod debug;
+mod device_map;
+mod devices;
+mod doctor;
+mod dry_run;
+#[cfg(test)]
+mod end_to_end;
+mod focus;
+mod guard;
+mod history;
+mod identity;
+mod inhibit;
+mod latency;
+mod listener;
+mod observe;
+mod pause;
+mod pipeline;
+mod profile;
+mod relative;
+mod sandbox;
+mod sanity;
+mod schedule;
 mod server;
+mod status;
+mod trust;
+mod transfer;
 mod client;
 
-use anyhow::{Error, anyhow};
 use clap::{Parser};
 use config::Config;
 use log::LevelFilter;
-use rcgen::generate_simple_self_signed;
-use std::fs::OpenOptions;
 use std::io::Write;
-use std::os::unix::fs::OpenOptionsExt;
 use std::path::{Path, PathBuf};
 use std::process;
-use tokio_rustls::rustls;
+use std::time::Duration;
 
-use common::{Identity, get_cert_fingerprint};
+use command::send_command;
+use common::{get_cert_fingerprint, Identity};
+use debug::{run_debug, DebugVerb};
+use device_map::DeviceMap;
+use devices::print_devices;
+use doctor::run_doctor;
+use dry_run::run_dry_run;
+use identity::{load_identity, load_or_generate_identity};
+use inhibit::IdleInhibitor;
+use latency::print_latency;
+use observe::observe;
+use pause::pause_device;
+use profile::switch_profile;
 use server::run_server;
-use client::run_client;
+use status::print_status;
+use transfer::{send_file, Downloads};
+use trust::trust;
+use client::{run_client, ClientOptions};
 
-fn load_identity(
-    certificate_path: &Path,
-) -> Result<Option<Identity>, Error> {
-    // Try loading the identity file at `certificate_path`. If no file exists, return None.
-
-    let file = match std::fs::File::open(&certificate_path) {
-        Ok(file) => file,
-        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
-            return Ok(None)
-        },
-        Err(e) => { return Err(e.into()); },
-    };
-
-    let mut reader = std::io::BufReader::new(file);
-    let mut certificate: Option<rustls::Certificate> = None;
-    let mut private_key: Option<rustls::PrivateKey> = None;
-    loop {
-        match rustls_pemfile::read_one(&mut reader).expect("cannot parse private file") {
-            Some(rustls_pemfile::Item::X509Certificate(cert)) => {
-                certificate = Some(rustls::Certificate(cert));
-            },
-            Some(rustls_pemfile::Item::PKCS8Key(key)) => {
-                private_key = Some(rustls::PrivateKey(key));
-            },
-            None => { break; },
-            _ => {},
-        }
-    }
-    match (certificate, private_key) {
-        (Some(cert), Some(key)) => Ok(Some((cert, key))),
-        (Some(_), None) => Err(anyhow!("Identity file at {} is missing a certificate!", &certificate_path.display())),
-        (None, Some(_)) => Err(anyhow!("Identity file at {} is missing a private key!", &certificate_path.display())),
-        (None, None) => Err(anyhow!("Identity file at {} is missing both a certificate and a private key!", &certificate_path.display())),
-    }
-}
-
-fn load_or_generate_identity(
-    certificate_path: &Path,
-) -> Result<Identity, Error> {
-    // Try loading the identity file at `certificate_path`, or create a new one if no file exists.
-
-    let identity = load_identity(certificate_path)?;
-    match identity {
-        // Use existing identity
-        Some(identity) => Ok(identity),
-
-        // Identity did not already exist, create it
-        None => {
-            let cert = generate_simple_self_signed([String::from("localhost")]).unwrap();
-
-            let pem = cert.serialize_pem()?;
-            let private_key_pem = cert.serialize_private_key_pem();
-
-            std::fs::create_dir_all(certificate_path.parent().unwrap())?;
-            let mut options = OpenOptions::new();
-            options.write(true);
-            options.create(true);
-            options.mode(0o600);
-            let mut keyfile = options.open(certificate_path)?;
+#[derive(clap::Subcommand)]
+enum Verb {
+    Fingerprint,
+    /// Check that the environment is set up correctly for evkvm to run
+    Doctor,
+    /// List the input devices evkvm would forward
+    Devices,
+    /// Measure how long input takes to be injected on a receiver
+    Latency {
+        /// The nick of the receiver, as given in the config
+        nick: String,
 
-            let _ = keyfile.write((&pem).as_bytes())?;
-            let _ = keyfile.write((&private_key_pem).as_bytes())?;
+        /// How many probes to send
+        #[clap(short = 'n', long, value_parser, default_value = "100")]
+        count: u32,
+    },
+    /// Show what the running evkvm is doing
+    Status {
+        /// Print the status as JSON, in a format other programs can rely on
+        #[clap(long)]
+        json: bool,
+    },
+    /// Ask a receiver to run one of the commands it allows this machine to run
+    Command {
+        /// The nick of the receiver, as given in the config
+        nick: String,
 
-            let certificate_der = cert.serialize_der()?;
-            let private_key_der = cert.serialize_private_key_der();
+        /// The name of the command, as given in the receiver's config
+        name: String,
+    },
+    /// Trust a receiver, and have the running evkvm accept it right away
+    Trust {
+        /// The receiver's fingerprint, as printed by `evkvm fingerprint` on it
+        fingerprint: String,
 
-            Ok((rustls::Certificate(certificate_der), rustls::PrivateKey(private_key_der)))
-        },
-    }
-}
+        /// A nickname for the receiver
+        #[clap(long, value_parser)]
+        nick: Option<String>,
 
+        /// Only trust the receiver for this long, e.g. "30m" or "2h"
+        #[clap(long, value_parser = trust::parse_ttl)]
+        ttl: Option<Duration>,
+    },
+    /// Watch the senders listed under `observe` for which receiver has focus, without getting input
+    Observe {
+        /// Print one JSON object per line, for other programs to read
+        #[clap(long)]
+        json: bool,
+    },
+    /// Offer a file to a receiver, which saves it to its downloads directory
+    SendFile {
+        /// The file to send
+        path: PathBuf,
 
-#[derive(clap::Subcommand)]
-enum Verb {
-    Fingerprint,
+        /// The nick of the receiver, as given in the config. Defaults to the focused receiver
+        #[clap(long, value_parser)]
+        to: Option<String>,
+    },
+    /// Switch the running evkvm to one of the profiles in its config
+    Profile {
+        /// The name of the profile. Without one, the options outside of any profile apply again
+        name: Option<String>,
+    },
+    /// Stop forwarding an input device, so that it works on this machine, until it is resumed
+    PauseDevice {
+        /// The device's path from `evkvm devices`, like /dev/input/event5, or just its number
+        device: String,
+    },
+    /// Start forwarding an input device paused with pause-device again
+    ResumeDevice {
+        /// The device's path from `evkvm devices`, like /dev/input/event5, or just its number
+        device: String,
+    },
+    /// Look into what the running evkvm did, to debug input going somewhere unexpected
+    Debug {
+        #[clap(subcommand)]
+        verb: DebugVerb,
+    },
 }
 
 #[derive(clap::Parser)]
@@ -102,8 +146,26 @@ struct Args {
     #[clap(subcommand)]
     verb: Option<Verb>,
 
-    #[clap(short, long, value_parser, default_value = "/etc/evkvm/config.toml")]
-    config_path: PathBuf,
+    /// Defaults to /etc/evkvm/config.toml, or /etc/evkvm/<INSTANCE>.toml for an instance. Use - to
+    /// read the config from standard input
+    #[clap(short, long, alias = "config", value_parser)]
+    config_path: Option<PathBuf>,
+
+    /// Print a JSON Schema of the config file and exit
+    #[clap(long)]
+    print_config_schema: bool,
+
+    /// Run as a separate instance, with its own config, state files and control socket
+    #[clap(short, long, value_parser = config::parse_instance, global = true)]
+    instance: Option<String>,
+
+    /// Keep the identity, trusted peers and device map in this directory instead of /var/lib/evkvm
+    #[clap(long, value_parser, global = true)]
+    state_dir: Option<PathBuf>,
+
+    /// Read from devices without grabbing them, and log where input would go instead of sending it
+    #[clap(long)]
+    dry_run: bool,
 }
 
 fn print_fingerprint(identity_path: &Path) {
@@ -124,16 +186,128 @@ fn print_fingerprint(identity_path: &Path) {
     println!("{}", fingerprint);
 }
 
-#[tokio::main]
-async fn main() {
-    env_logger::builder()
-        .format_timestamp(None)
-        .filter(None, LevelFilter::Info)
-        .init();
+// What running as a daemon needs, set up before the runtime starts.
+struct Daemon {
+    identity: Identity,
+    should_run_server: bool,
+    device_map: Option<DeviceMap>,
+}
+
+fn prepare_daemon(config: &Config) -> Daemon {
+    let receivers = match trust::all_receivers(config) {
+        Ok(receivers) => receivers,
+        Err(err) => {
+            log::error!("Error loading trusted receivers: {:#}", err);
+            process::exit(1);
+        }
+    };
+
+    let senders = match trust::all_senders(&config.senders, &config.trusted_senders_path) {
+        Ok(senders) => senders,
+        Err(err) => {
+            log::error!("Error loading trusted senders: {:#}", err);
+            process::exit(1);
+        }
+    };
+
+    let should_run_server = !receivers.is_empty();
+    let should_run_client = !senders.is_empty();
+
+    if !(should_run_server || should_run_client) {
+        log::error!("No senders or receivers configured, exiting.");
+        process::exit(1);
+    }
+
+    if container::in_container() {
+        if let Err(err) = container::check(config, should_run_client) {
+            log::error!("{:#}", err);
+            process::exit(1);
+        }
+    }
+
+    let identity = match load_or_generate_identity(
+        &config.identity_path,
+        config.key_type,
+        &config.certificate,
+    ) {
+        Ok(identity) => identity,
+        Err(err) => {
+            log::error!("Error loading or generating identity: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let (cert, _) = &identity;
+    let fingerprint = get_cert_fingerprint(cert);
+    log::info!("This device has fingerprint {}", fingerprint);
+
+    let device_map = if should_run_client {
+        match DeviceMap::load(&config.device_map_path) {
+            Ok(device_map) => Some(device_map),
+            Err(err) => {
+                log::error!("Error loading device map: {:#}", err);
+                process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    // Only a process that is just a receiver is sandboxed, since a sender needs to do a lot more,
+    // like grabbing devices and managing trust.
+    if config.sandbox && !should_run_server {
+        if let Err(err) = sandbox_receiver(config) {
+            log::error!("Error setting up sandbox: {:#}", err);
+            process::exit(1);
+        }
+    }
+
+    Daemon { identity, should_run_server, device_map }
+}
+
+fn sandbox_receiver(config: &Config) -> Result<(), anyhow::Error> {
+    let mut writable = Vec::new();
+    if let Some(parent) = config.device_map_path.parent() {
+        std::fs::create_dir_all(parent)?;
+        writable.push(parent.to_owned());
+    }
+    if let Some(downloads_path) = &config.downloads_path {
+        std::fs::create_dir_all(downloads_path)?;
+        writable.push(downloads_path.clone());
+    }
+    if let Some(crash_report_path) = &config.crash_report_path {
+        std::fs::create_dir_all(crash_report_path)?;
+        writable.push(crash_report_path.clone());
+    }
+    let writable: Vec<&Path> = writable.iter().map(PathBuf::as_path).collect();
+    sandbox::apply(&writable)
+}
 
+fn main() {
     let args = Args::parse();
-    
-    let config = match Config::new(&args.config_path) {
+
+    let mut logger = env_logger::builder();
+    logger.format_timestamp(None).filter(None, LevelFilter::Info);
+    // Tell instances apart when they all log to the same place.
+    if let Some(instance) = args.instance.clone() {
+        logger.format(move |buf, record| {
+            writeln!(buf, "[{}] [{:<5} {}] {}", instance, record.level(), record.target(), record.args())
+        });
+    }
+    let logger = logger.build();
+    log::set_max_level(logger.filter());
+    log::set_boxed_logger(Box::new(crash::Recorder::new(logger))).expect("Failed to set logger");
+
+    if args.print_config_schema {
+        println!("{}", config::schema());
+        return;
+    }
+
+    let config_path = args
+        .config_path
+        .clone()
+        .unwrap_or_else(|| config::default_path(args.instance.as_deref()));
+    let config = match Config::new(&config_path, args.instance.as_deref(), args.state_dir.as_deref()) {
         Ok(config) => config,
         Err(err) => {
             log::error!("Error reading config: {}", err);
@@ -141,46 +315,151 @@ async fn main() {
         },
     };
 
-    match args.verb {
+    if args.dry_run && args.verb.is_some() {
+        log::error!("--dry-run can't be used with a subcommand");
+        process::exit(1);
+    }
+
+    // The sandbox only covers threads started after it is set up, so this can't wait until the
+    // runtime is running.
+    let daemon = match args.verb {
+        None if !args.dry_run => {
+            crash::install(&config);
+            Some(prepare_daemon(&config))
+        },
+        _ => None,
+    };
+
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            log::error!("Error starting runtime: {}", err);
+            process::exit(1);
+        }
+    };
+    runtime.block_on(run(args.verb, config, daemon));
+}
+
+async fn run(verb: Option<Verb>, config: Config, daemon: Option<Daemon>) {
+    match verb {
         Some(Verb::Fingerprint) => print_fingerprint(&config.identity_path),
-        None => {
-            let identity = match load_or_generate_identity(&config.identity_path) {
+        Some(Verb::Doctor) => {
+            if !run_doctor(&config) {
+                process::exit(1);
+            }
+        },
+        Some(Verb::Devices) => {
+            if let Err(err) = print_devices(&config.forward_virtual_devices).await {
+                log::error!("Error listing devices: {:#}", err);
+                process::exit(1);
+            }
+        },
+        Some(Verb::Latency { nick, count }) => {
+            if let Err(err) = print_latency(&config.control_socket_path, nick, count).await {
+                log::error!("Error measuring latency: {:#}", err);
+                process::exit(1);
+            }
+        },
+        Some(Verb::Status { json }) => {
+            if let Err(err) = print_status(&config.control_socket_path, json).await {
+                log::error!("Error getting status: {:#}", err);
+                process::exit(1);
+            }
+        },
+        Some(Verb::Command { nick, name }) => {
+            if let Err(err) = send_command(&config.control_socket_path, nick, name).await {
+                log::error!("Error sending command: {:#}", err);
+                process::exit(1);
+            }
+        },
+        Some(Verb::Trust { fingerprint, nick, ttl }) => {
+            if let Err(err) = trust(&config, fingerprint, nick, ttl).await {
+                log::error!("Error trusting receiver: {:#}", err);
+                process::exit(1);
+            }
+        },
+        Some(Verb::Observe { json }) => {
+            // Senders only let observers in by fingerprint, so this needs an identity as much as
+            // the daemon does.
+            let identity = match load_or_generate_identity(&config.identity_path, config.key_type, &config.certificate) {
                 Ok(identity) => identity,
                 Err(err) => {
                     log::error!("Error loading or generating identity: {}", err);
                     process::exit(1);
                 }
             };
-
-            let (cert, _) = &identity;
-            let fingerprint = get_cert_fingerprint(cert);
-            log::info!("This device has fingerprint {}", fingerprint);
-
-            let should_run_server = !config.receivers.is_empty();
-            let should_run_client = !config.senders.is_empty();
-
-            if !(should_run_server || should_run_client) {
-                log::error!("No senders or receivers configured, exiting.");
+            log::info!("This device has fingerprint {}", get_cert_fingerprint(&identity.0));
+            if let Err(err) = observe(config.observe.clone(), identity, json).await {
+                log::error!("Error observing: {:#}", err);
+                process::exit(1);
+            }
+        },
+        Some(Verb::SendFile { path, to }) => {
+            if let Err(err) = send_file(&config.control_socket_path, to, &path).await {
+                log::error!("Error sending file: {:#}", err);
+                process::exit(1);
+            }
+        },
+        Some(Verb::Profile { name }) => {
+            if let Err(err) = switch_profile(&config.control_socket_path, name).await {
+                log::error!("Error switching profile: {:#}", err);
+                process::exit(1);
+            }
+        },
+        Some(Verb::PauseDevice { device }) => {
+            if let Err(err) = pause_device(&config.control_socket_path, &device, true).await {
+                log::error!("Error pausing device: {:#}", err);
+                process::exit(1);
+            }
+        },
+        Some(Verb::ResumeDevice { device }) => {
+            if let Err(err) = pause_device(&config.control_socket_path, &device, false).await {
+                log::error!("Error resuming device: {:#}", err);
+                process::exit(1);
+            }
+        },
+        Some(Verb::Debug { verb }) => {
+            if let Err(err) = run_debug(&config.control_socket_path, verb).await {
+                log::error!("Error getting debug information: {:#}", err);
                 process::exit(1);
             }
+        },
+        None if daemon.is_none() => {
+            let Err(err) = run_dry_run(&config).await;
+            log::error!("Error: {:#}", err);
+            process::exit(1);
+        },
+        None => {
+            let Daemon { identity, should_run_server, device_map } = daemon.unwrap();
+            let should_run_client = device_map.is_some();
 
             tokio::select! {
                 result = async {
-                    run_server(
-                        config.listen_address,
-                        &config.switch_keys,
-                        identity.clone(),
-                        config.receivers
-                    ).await
+                    run_server(&config, identity.clone()).await
                 }, if should_run_server => {
-                    if let Err(err) = result {
-                        log::error!("Error: {:#}", err);
-                        process::exit(1);
-                    }
+                    let Err(err) = result;
+                    log::error!("Error: {:#}", err);
+                    crash::report(&config, &format!("{:#}", err));
+                    process::exit(1);
                 }
 
                 _ = async {
-                    run_client(config.senders, identity.clone()).await
+                    let options = ClientOptions {
+                        dejitter: Duration::from_millis(config.dejitter_ms),
+                        max_input_rate: match config.max_input_rate {
+                            0 => None,
+                            rate => Some(rate),
+                        },
+                        downloads: config.downloads_path.clone().map(|path| Downloads {
+                            path,
+                            max_size: config.max_file_size_mb * 1024 * 1024,
+                        }),
+                        inhibitor: config.inhibit_idle.then(IdleInhibitor::default),
+                        relative_devices: config.relative_devices.clone(),
+                    };
+                    let senders = config.senders.clone();
+                    let trusted_senders_path = config.trusted_senders_path.clone();
+                    run_client(senders, trusted_senders_path, identity.clone(), device_map.unwrap(), options).await
                 }, if should_run_client => {}
 
                 result = tokio::signal::ctrl_c() => {
@@ -0,0 +1,123 @@
+use anyhow::{Context, Error};
+use net::{Message, Purpose};
+use serde::Serialize;
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::io::{self, AsyncRead, AsyncWrite};
+use tokio::time;
+use tokio_rustls::TlsConnector;
+
+use crate::client::{connect, connector, sender_name};
+use crate::common::Identity;
+use crate::config::Sender;
+
+// `evkvm observe` watches senders without receiving any input from them, and prints which
+// receiver has focus on each and which ones are connected, for dashboards and status bars.
+
+// How long to wait before connecting to a sender again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+// What gets printed, with `--json` as one object per line.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+enum Observation<'a> {
+    SenderConnected { sender: &'a str },
+    SenderDisconnected { sender: &'a str },
+    // No receiver means the sender itself has focus.
+    Focus { sender: &'a str, receiver: Option<String> },
+    ReceiverConnected { sender: &'a str, receiver: String },
+    ReceiverDisconnected { sender: &'a str, receiver: String },
+}
+
+fn report(observation: &Observation, json: bool) {
+    if json {
+        match serde_json::to_string(observation) {
+            Ok(line) => println!("{}", line),
+            Err(err) => log::error!("Error printing observation: {}", err),
+        }
+        return;
+    }
+
+    match observation {
+        Observation::SenderConnected { sender } => println!("{}: connected", sender),
+        Observation::SenderDisconnected { sender } => println!("{}: disconnected", sender),
+        Observation::Focus { sender, receiver: Some(receiver) } => println!("{}: focus on {}", sender, receiver),
+        Observation::Focus { sender, receiver: None } => println!("{}: focus on the sender itself", sender),
+        Observation::ReceiverConnected { sender, receiver } => println!("{}: {} connected", sender, receiver),
+        Observation::ReceiverDisconnected { sender, receiver } => println!("{}: {} disconnected", sender, receiver),
+    }
+}
+
+pub async fn observe(senders: Vec<Sender>, identity: Identity, json: bool) -> Result<(), Error> {
+    if senders.is_empty() {
+        return Err(anyhow::anyhow!("No senders to observe. List them under `observe` in the config."));
+    }
+
+    let watches: Vec<_> = senders.into_iter().map(|sender| watch(sender, identity.clone(), json)).collect();
+    futures::future::join_all(watches).await;
+    Ok(())
+}
+
+async fn watch(sender: Sender, identity: Identity, json: bool) -> Infallible {
+    let connector = connector(&sender, identity);
+    let mut last_msg: Option<String> = None;
+
+    loop {
+        let err = watch_once(&sender, &connector, json).await.unwrap_err();
+        let msg = format!("{:#}", err);
+        // Only complain once about a sender that stays unreachable.
+        if last_msg.as_ref() != Some(&msg) {
+            log::warn!("{}: {}", sender_name(&sender), msg);
+        }
+        last_msg = Some(msg);
+        time::sleep(RETRY_INTERVAL).await;
+    }
+}
+
+async fn watch_once(sender: &Sender, connector: &TlsConnector, json: bool) -> Result<Infallible, Error> {
+    let (stream, hello, version) = connect(sender, connector, Purpose::Observe).await?;
+    // A sender without a nick goes by its hostname, like it does for receivers.
+    let name = match &sender.nick {
+        Some(nick) => nick.clone(),
+        None if !hello.hostname.is_empty() => hello.hostname.clone(),
+        None => sender.address.clone(),
+    };
+    report(&Observation::SenderConnected { sender: &name }, json);
+
+    let result = relay(stream, version, &name, json).await;
+    report(&Observation::SenderDisconnected { sender: &name }, json);
+    result
+}
+
+// Print what the sender tells us about, answering its pings along the way.
+async fn relay<T>(stream: T, version: u16, name: &str, json: bool) -> Result<Infallible, Error>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let (reader, mut writer) = io::split(stream);
+    let mut reader = net::MessageReader::new(reader, version);
+    loop {
+        let message = time::timeout(net::MESSAGE_TIMEOUT, reader.read())
+            .await
+            .context("Read timed out")??;
+        let observation = match message {
+            Message::Ping { seq, time: ping_time } => {
+                let pong = Message::Pong { seq, ping_time, time: net::timestamp() };
+                time::timeout(net::MESSAGE_TIMEOUT, net::write_message(&mut writer, &pong, version))
+                    .await
+                    .context("Write timeout")??;
+                continue;
+            }
+            Message::FocusChanged { receiver } => Observation::Focus { sender: name, receiver },
+            Message::ReceiverConnected { name: receiver } => {
+                Observation::ReceiverConnected { sender: name, receiver }
+            }
+            Message::ReceiverDisconnected { name: receiver } => {
+                Observation::ReceiverDisconnected { sender: name, receiver }
+            }
+            // Nothing else is sent to observers.
+            _ => continue,
+        };
+        report(&observation, json);
+    }
+}
@@ -0,0 +1,30 @@
+use anyhow::{Context, Error};
+use std::path::Path;
+
+use crate::control::{self, ControlRequest, ControlResponse};
+
+// Devices are known to the daemon by the number at the end of their /dev/input/eventN path, and can
+// be given as that path, as eventN or as just the number.
+fn parse_device(device: &str) -> Result<u16, Error> {
+    let number = device.strip_prefix("/dev/input/").unwrap_or(device);
+    let number = number.strip_prefix("event").unwrap_or(number);
+    number
+        .parse()
+        .with_context(|| format!("\"{}\" is not a device like /dev/input/event5", device))
+}
+
+// Ask the running daemon to stop forwarding `device` and give it back to this machine, or with
+// `pause` false, to start forwarding it again.
+pub async fn pause_device(control_socket_path: &Path, device: &str, pause: bool) -> Result<(), Error> {
+    let id = parse_device(device)?;
+    let request = if pause {
+        ControlRequest::PauseDevice { id }
+    } else {
+        ControlRequest::ResumeDevice { id }
+    };
+    match control::request(control_socket_path, &request).await? {
+        ControlResponse::Done => Ok(()),
+        ControlResponse::Error { message } => Err(anyhow::anyhow!(message)),
+        response => Err(anyhow::anyhow!("Unexpected response from evkvm: {:?}", response)),
+    }
+}
@@ -0,0 +1,356 @@
+use input::{Direction, Event, InputEvent, KeyKind, ReaderManager};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Error;
+use std::iter;
+
+use crate::config::{Chord, Config};
+
+// The stages input read on the sender goes through before FocusState decides where it goes: first
+// keys are dropped, then remapped, then turned into macros. Each stage only sees what the ones
+// before it let through, so a remapped key can set off a macro, and the switch keys, guarded keys
+// and the rest apply to keys as they come out at the end. Like FocusState, stages only transform
+// events, so none of them touch any devices.
+
+// Scan codes, which keyboards report right before the key they belong to.
+const EV_MSC: u16 = 0x04;
+const MSC_SCAN: u16 = 0x04;
+
+pub trait Stage {
+    // Take an event and return what goes on in its place: the event itself, nothing, or any
+    // number of other events.
+    fn process(&mut self, event: Event) -> Vec<Event>;
+}
+
+// The key an event presses or releases, and on which device, if it is a key event.
+fn key(event: &Event) -> Option<(u16, Direction, KeyKind)> {
+    match *event {
+        Event::Input {
+            device_id,
+            input: InputEvent::Key { direction, kind },
+            syn: _,
+        } => Some((device_id, direction, kind)),
+        _ => None,
+    }
+}
+
+fn key_event(device_id: u16, kind: KeyKind, direction: Direction) -> Event {
+    Event::Input {
+        device_id,
+        input: InputEvent::Key { direction, kind },
+        syn: true,
+    }
+}
+
+// Drops the given keys, so that they reach no machine at all.
+pub struct KeyFilter {
+    keys: HashSet<KeyKind>,
+}
+
+impl KeyFilter {
+    pub fn new(keys: HashSet<KeyKind>) -> Self {
+        KeyFilter { keys }
+    }
+}
+
+impl Stage for KeyFilter {
+    fn process(&mut self, event: Event) -> Vec<Event> {
+        match key(&event) {
+            Some((_, _, kind)) if self.keys.contains(&kind) => Vec::new(),
+            _ => vec![event],
+        }
+    }
+}
+
+// Turns keys into others.
+pub struct KeyRemap {
+    map: HashMap<KeyKind, KeyKind>,
+}
+
+impl KeyRemap {
+    pub fn new(map: HashMap<KeyKind, KeyKind>) -> Self {
+        KeyRemap { map }
+    }
+}
+
+impl Stage for KeyRemap {
+    fn process(&mut self, event: Event) -> Vec<Event> {
+        match event {
+            Event::Input {
+                device_id,
+                input: InputEvent::Key { direction, kind },
+                syn,
+            } if self.map.contains_key(&kind) => {
+                let input = InputEvent::Key { direction, kind: self.map[&kind] };
+                vec![Event::Input { device_id, input, syn }]
+            }
+            event => vec![event],
+        }
+    }
+}
+
+// Pressing one of the given keys taps its chords one after the other instead. Releasing it does
+// nothing, since the chords were already released.
+pub struct Macros {
+    macros: HashMap<KeyKind, Vec<Chord>>,
+}
+
+impl Macros {
+    pub fn new(macros: HashMap<KeyKind, Vec<Chord>>) -> Self {
+        Macros { macros }
+    }
+}
+
+impl Stage for Macros {
+    fn process(&mut self, event: Event) -> Vec<Event> {
+        let (device_id, direction, chords) = match key(&event) {
+            Some((device_id, direction, kind)) => match self.macros.get(&kind) {
+                Some(chords) => (device_id, direction, chords),
+                None => return vec![event],
+            },
+            None => return vec![event],
+        };
+
+        let mut events = Vec::new();
+        if direction == Direction::Down {
+            for Chord(keys) in chords {
+                events.extend(keys.iter().map(|&kind| key_event(device_id, kind, Direction::Down)));
+                events.extend(keys.iter().rev().map(|&kind| key_event(device_id, kind, Direction::Up)));
+            }
+        }
+        events
+    }
+}
+
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage + Send>>,
+    // The last scan code from each device, held back until its next event. It only goes along if
+    // that event comes out of the stages as it went in, since it belongs to the key that was read.
+    scans: HashMap<u16, Event>,
+    // Events that came out of the stages and haven't been read yet.
+    queue: VecDeque<Event>,
+}
+
+impl Pipeline {
+    pub fn new(stages: Vec<Box<dyn Stage + Send>>) -> Self {
+        Pipeline {
+            stages,
+            scans: HashMap::new(),
+            queue: VecDeque::new(),
+        }
+    }
+
+    // The stages `config` asks for. Those it doesn't use are left out.
+    pub fn from_config(config: &Config) -> Self {
+        let mut stages: Vec<Box<dyn Stage + Send>> = Vec::new();
+        if !config.drop_keys.is_empty() {
+            stages.push(Box::new(KeyFilter::new(config.drop_keys.iter().copied().collect())));
+        }
+        if !config.remap_keys.is_empty() {
+            stages.push(Box::new(KeyRemap::new(config.remap_keys.clone())));
+        }
+        if !config.macros.is_empty() {
+            stages.push(Box::new(Macros::new(config.macros.clone())));
+        }
+        Pipeline::new(stages)
+    }
+
+    // Put an event through every stage, and return what comes out at the end, in order.
+    pub fn process(&mut self, event: Event) -> Vec<Event> {
+        if self.stages.is_empty() {
+            return vec![event];
+        }
+
+        match event {
+            Event::Input {
+                device_id,
+                input: InputEvent::Other { type_: EV_MSC, code: MSC_SCAN, .. },
+                syn: _,
+            } => {
+                // A scan code without a key after it goes on as it is.
+                return self.scans.insert(device_id, event).into_iter().collect();
+            }
+            Event::RemoveDevice(device_id) => {
+                self.scans.remove(&device_id);
+            }
+            _ => {}
+        }
+        let scan = match event {
+            Event::Input { device_id, .. } => self.scans.remove(&device_id),
+            _ => None,
+        };
+
+        let read = key(&event);
+        let mut events = vec![event];
+        for stage in &mut self.stages {
+            events = events.into_iter().flat_map(|event| stage.process(event)).collect();
+        }
+
+        match scan {
+            Some(scan) if events.len() == 1 && key(&events[0]) == read => iter::once(scan).chain(events).collect(),
+            _ => events,
+        }
+    }
+
+    // Read events from `reader_manager` until one comes out of the stages. Like
+    // ReaderManager::read, this can be cancelled without losing any.
+    pub async fn read(&mut self, reader_manager: &mut ReaderManager) -> Result<Event, Error> {
+        loop {
+            if let Some(event) = self.queue.pop_front() {
+                return Ok(event);
+            }
+            let event = reader_manager.read().await?;
+            let events = self.process(event);
+            self.queue.extend(events);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use input::Key;
+
+    const DEVICE: u16 = 1;
+
+    const A: KeyKind = KeyKind::Key(Key::A);
+    const B: KeyKind = KeyKind::Key(Key::B);
+    const C: KeyKind = KeyKind::Key(Key::C);
+    const CAPS_LOCK: KeyKind = KeyKind::Key(Key::CapsLock);
+    const LEFT_CTRL: KeyKind = KeyKind::Key(Key::LeftCtrl);
+    const F1: KeyKind = KeyKind::Key(Key::F1);
+
+    fn down(kind: KeyKind) -> Event {
+        key_event(DEVICE, kind, Direction::Down)
+    }
+
+    fn up(kind: KeyKind) -> Event {
+        key_event(DEVICE, kind, Direction::Up)
+    }
+
+    fn scan(value: i32) -> Event {
+        Event::Input { device_id: DEVICE, input: InputEvent::Other { type_: EV_MSC, code: MSC_SCAN, value }, syn: false }
+    }
+
+    // What came out, with key events boiled down to their key and direction, and scan codes to
+    // their value.
+    fn keys(events: Vec<Event>) -> Vec<(KeyKind, Direction)> {
+        events.iter().filter_map(key).map(|(_, direction, kind)| (kind, direction)).collect()
+    }
+
+    fn scans(events: &[Event]) -> Vec<i32> {
+        events
+            .iter()
+            .filter_map(|event| match *event {
+                Event::Input { input: InputEvent::Other { type_: EV_MSC, code: MSC_SCAN, value }, .. } => Some(value),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn filter(keys: &[KeyKind]) -> KeyFilter {
+        KeyFilter::new(keys.iter().copied().collect())
+    }
+
+    fn remap(pairs: &[(KeyKind, KeyKind)]) -> KeyRemap {
+        KeyRemap::new(pairs.iter().copied().collect())
+    }
+
+    fn macros(pairs: &[(KeyKind, &[&[KeyKind]])]) -> Macros {
+        Macros::new(
+            pairs
+                .iter()
+                .map(|&(kind, chords)| (kind, chords.iter().map(|keys| Chord(keys.to_vec())).collect()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn filter_drops_its_keys() {
+        let mut filter = filter(&[CAPS_LOCK]);
+        assert!(filter.process(down(CAPS_LOCK)).is_empty());
+        assert!(filter.process(up(CAPS_LOCK)).is_empty());
+        assert_eq!(keys(filter.process(down(A))), vec![(A, Direction::Down)]);
+        assert_eq!(filter.process(scan(30)).len(), 1);
+    }
+
+    #[test]
+    fn remap_turns_keys_into_others() {
+        let mut remap = remap(&[(CAPS_LOCK, LEFT_CTRL)]);
+        assert_eq!(keys(remap.process(down(CAPS_LOCK))), vec![(LEFT_CTRL, Direction::Down)]);
+        assert_eq!(keys(remap.process(up(CAPS_LOCK))), vec![(LEFT_CTRL, Direction::Up)]);
+        assert_eq!(keys(remap.process(down(A))), vec![(A, Direction::Down)]);
+    }
+
+    #[test]
+    fn remap_keeps_syn() {
+        let mut remap = remap(&[(A, B)]);
+        let read = Event::Input { device_id: DEVICE, input: InputEvent::Key { direction: Direction::Down, kind: A }, syn: false };
+        match remap.process(read)[..] {
+            [Event::Input { syn: false, .. }] => {}
+            ref events => panic!("Remapped to {:?}", events),
+        }
+    }
+
+    #[test]
+    fn macros_tap_their_chords_on_press() {
+        let mut macros = macros(&[(F1, &[&[LEFT_CTRL, C], &[A]])]);
+        assert_eq!(
+            keys(macros.process(down(F1))),
+            vec![
+                (LEFT_CTRL, Direction::Down),
+                (C, Direction::Down),
+                (C, Direction::Up),
+                (LEFT_CTRL, Direction::Up),
+                (A, Direction::Down),
+                (A, Direction::Up),
+            ]
+        );
+        assert!(macros.process(up(F1)).is_empty());
+        assert_eq!(keys(macros.process(down(A))), vec![(A, Direction::Down)]);
+    }
+
+    #[test]
+    fn stages_apply_in_order() {
+        // B is remapped to F1, which sets off its macro, while A is dropped before it could be
+        // remapped, and C is left alone.
+        let mut pipeline = Pipeline::new(vec![
+            Box::new(filter(&[A])),
+            Box::new(remap(&[(A, C), (B, F1)])),
+            Box::new(macros(&[(F1, &[&[C]])])),
+        ]);
+        assert!(pipeline.process(down(A)).is_empty());
+        assert_eq!(keys(pipeline.process(down(B))), vec![(C, Direction::Down), (C, Direction::Up)]);
+        assert!(pipeline.process(up(B)).is_empty());
+        assert_eq!(keys(pipeline.process(down(C))), vec![(C, Direction::Down)]);
+    }
+
+    #[test]
+    fn empty_pipeline_passes_everything() {
+        let mut pipeline = Pipeline::new(Vec::new());
+        assert_eq!(scans(&pipeline.process(scan(30))), vec![30]);
+        assert_eq!(keys(pipeline.process(down(A))), vec![(A, Direction::Down)]);
+    }
+
+    #[test]
+    fn scan_codes_only_go_with_untouched_keys() {
+        let mut pipeline = Pipeline::new(vec![Box::new(filter(&[B])), Box::new(remap(&[(C, A)]))]);
+
+        // Held back until the key it belongs to, and then sent along with it.
+        assert!(pipeline.process(scan(30)).is_empty());
+        let events = pipeline.process(down(A));
+        assert_eq!(scans(&events), vec![30]);
+        assert_eq!(keys(events), vec![(A, Direction::Down)]);
+
+        // Dropped with a dropped key, and with a remapped key, which it no longer belongs to.
+        assert!(pipeline.process(scan(48)).is_empty());
+        assert!(pipeline.process(down(B)).is_empty());
+        assert!(pipeline.process(scan(46)).is_empty());
+        let events = pipeline.process(down(C));
+        assert!(scans(&events).is_empty());
+        assert_eq!(keys(events), vec![(A, Direction::Down)]);
+
+        // A scan code followed by another goes on as it is.
+        assert!(pipeline.process(scan(1)).is_empty());
+        assert_eq!(scans(&pipeline.process(scan(2))), vec![1]);
+    }
+}
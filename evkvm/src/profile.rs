@@ -0,0 +1,15 @@
+use anyhow::Error;
+use std::path::Path;
+
+use crate::control::{self, ControlRequest, ControlResponse};
+
+// Ask the running daemon to switch to one of the profiles in its config, or back to the default
+// one if `name` is None.
+pub async fn switch_profile(control_socket_path: &Path, name: Option<String>) -> Result<(), Error> {
+    let request = ControlRequest::Profile { name };
+    match control::request(control_socket_path, &request).await? {
+        ControlResponse::Done => Ok(()),
+        ControlResponse::Error { message } => Err(anyhow::anyhow!(message)),
+        response => Err(anyhow::anyhow!("Unexpected response from evkvm: {:?}", response)),
+    }
+}
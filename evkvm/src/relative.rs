@@ -0,0 +1,269 @@
+use input::{Button, Capability, Device, Direction, Event, InputEvent, KeyKind};
+use std::collections::HashMap;
+
+// Absolute pointing devices, like touchscreens and tablets, map their surface onto the whole
+// screen. Forwarded to a receiver with a screen of another shape or size, or several screens,
+// that mapping is rarely what anyone wants. Receivers can turn them into mice instead: moving the
+// pen or finger moves the pointer from wherever it is, touching the surface clicks, and the pen's
+// buttons click the other mouse buttons.
+
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_REL: u16 = 0x02;
+const EV_ABS: u16 = 0x03;
+const SYN_REPORT: u16 = 0x00;
+// ABS_X and ABS_Y, and REL_X and REL_Y, which have the same codes.
+const AXES: [u16; 2] = [0x00, 0x01];
+// BTN_LEFT, BTN_RIGHT and BTN_MIDDLE.
+const BUTTONS: [u16; 3] = [0x110, 0x111, 0x112];
+
+// Movement is scaled to what a 1000 dpi mouse would report for the same distance. Axes that
+// don't say how big they are count the whole range as this many of a mouse's counts.
+const COUNTS_PER_MM: f64 = 1000.0 / 25.4;
+const COUNTS_PER_RANGE: f64 = 2000.0;
+
+struct Axis {
+    // Mouse counts per unit of the axis.
+    scale: f64,
+    // Where the pen or finger was last, unless it was lifted since.
+    last: Option<i32>,
+    // What was left over of the movement after rounding, for the next event.
+    remainder: f64,
+}
+
+impl Axis {
+    fn new(resolution: i32, minimum: i32, maximum: i32) -> Self {
+        let scale = if resolution > 0 {
+            COUNTS_PER_MM / resolution as f64
+        } else {
+            COUNTS_PER_RANGE / (maximum as f64 - minimum as f64).max(1.0)
+        };
+        Axis { scale, last: None, remainder: 0.0 }
+    }
+
+    // The relative movement to report for the axis moving to `value`.
+    fn motion(&mut self, value: i32) -> i32 {
+        let last = self.last.replace(value);
+        let delta = match last {
+            Some(last) => (value - last) as f64 * self.scale + self.remainder,
+            None => return 0,
+        };
+        let counts = delta.trunc();
+        self.remainder = delta - counts;
+        counts as i32
+    }
+
+    fn lift(&mut self) {
+        self.last = None;
+        self.remainder = 0.0;
+    }
+}
+
+pub struct RelativeDevices {
+    // Names of the devices to turn into mice.
+    names: Vec<String>,
+    devices: HashMap<u16, [Axis; 2]>,
+}
+
+impl RelativeDevices {
+    pub fn new(names: Vec<String>) -> Self {
+        RelativeDevices { names, devices: HashMap::new() }
+    }
+
+    // The device to create for `device`: a mouse in its place if it is one of ours and has both
+    // absolute axes, or the device itself otherwise.
+    pub fn new_device(&mut self, device: Device) -> Device {
+        self.devices.remove(&device.id);
+        if !self.names.contains(&device.name) {
+            return device;
+        }
+
+        let axis = |code| {
+            device.capabilities.iter().find_map(|capability| match *capability {
+                Capability::Abs { code: other, info } if other == code => {
+                    Some(Axis::new(info.resolution, info.minimum, info.maximum))
+                }
+                _ => None,
+            })
+        };
+        let axes = match (axis(AXES[0]), axis(AXES[1])) {
+            (Some(x), Some(y)) => [x, y],
+            _ => {
+                log::warn!("{} has no absolute axes to turn into relative ones", device.name);
+                return device;
+            }
+        };
+        self.devices.insert(device.id, axes);
+
+        let mut capabilities = vec![Capability::Other { type_: EV_SYN, code: SYN_REPORT }];
+        capabilities.extend(AXES.iter().map(|&code| Capability::Other { type_: EV_REL, code }));
+        capabilities.extend(BUTTONS.iter().map(|&code| Capability::Other { type_: EV_KEY, code }));
+        // Whatever was held down on the original has no counterpart on the mouse.
+        Device {
+            capabilities,
+            properties: Vec::new(),
+            held_keys: Vec::new(),
+            ..device
+        }
+    }
+
+    pub fn remove_device(&mut self, id: u16) {
+        self.devices.remove(&id);
+    }
+
+    // What to write in place of an input event, if anything.
+    pub fn input(&mut self, event: Event) -> Option<Event> {
+        let (device_id, input, syn) = match event {
+            Event::Input { device_id, input, syn } => (device_id, input, syn),
+            event => return Some(event),
+        };
+        let axes = match self.devices.get_mut(&device_id) {
+            Some(axes) => axes,
+            None => return Some(event),
+        };
+        let write = |input| Some(Event::Input { device_id, input, syn });
+
+        match input {
+            InputEvent::Other { type_: EV_SYN, .. } => Some(event),
+            InputEvent::Other { type_: EV_ABS, code, value } => {
+                let index = AXES.iter().position(|&axis| axis == code)?;
+                match axes[index].motion(value) {
+                    0 => None,
+                    value => write(InputEvent::Other { type_: EV_REL, code, value }),
+                }
+            }
+            InputEvent::Key { direction, kind: KeyKind::Button(button) } => {
+                // Whatever touches the surface next starts from where the pointer is.
+                let lifted = (button == Button::Touch && direction == Direction::Up)
+                    || button.is_tablet_tool()
+                    || button == Button::ToolFinger;
+                if lifted {
+                    axes.iter_mut().for_each(Axis::lift);
+                }
+                let button = match button {
+                    Button::Touch | Button::Left => Button::Left,
+                    Button::Stylus | Button::Right => Button::Right,
+                    Button::Stylus2 | Button::Middle => Button::Middle,
+                    _ => return None,
+                };
+                write(InputEvent::Key { direction, kind: KeyKind::Button(button) })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use input::AbsInfo;
+
+    const DEVICE: u16 = 1;
+    const ABS_PRESSURE: u16 = 0x18;
+
+    fn info(minimum: i32, maximum: i32, resolution: i32) -> AbsInfo {
+        AbsInfo { value: 0, minimum, maximum, fuzz: 0, flat: 0, resolution }
+    }
+
+    fn device(name: &str, axes: &[u16]) -> Device {
+        Device {
+            id: DEVICE,
+            name: String::from(name),
+            uniq: String::new(),
+            phys: String::new(),
+            vendor: 0,
+            product: 0,
+            bustype: 0,
+            version: 0,
+            capabilities: axes.iter().map(|&code| Capability::Abs { code, info: info(0, 4000, 0) }).collect(),
+            properties: Vec::new(),
+            held_keys: Vec::new(),
+            lit_leds: Vec::new(),
+        }
+    }
+
+    // A tablet whose axes are 4000 units across, so that each unit of movement is half a count.
+    fn tablet() -> RelativeDevices {
+        let mut devices = RelativeDevices::new(vec![String::from("tablet")]);
+        devices.new_device(device("tablet", &AXES));
+        devices
+    }
+
+    fn input(devices: &mut RelativeDevices, input: InputEvent) -> Option<InputEvent> {
+        match devices.input(Event::Input { device_id: DEVICE, input, syn: false }) {
+            Some(Event::Input { input, .. }) => Some(input),
+            Some(event) => panic!("Wrote something other than input: {:?}", event),
+            None => None,
+        }
+    }
+
+    fn abs(code: u16, value: i32) -> InputEvent {
+        InputEvent::Other { type_: EV_ABS, code, value }
+    }
+
+    fn button(button: Button, direction: Direction) -> InputEvent {
+        InputEvent::Key { direction, kind: KeyKind::Button(button) }
+    }
+
+    #[test]
+    fn what_is_left_after_rounding_carries_over_until_lifted() {
+        let mut axis = Axis::new(0, 0, 4000);
+        let motion: Vec<i32> = [0, 1, 2, 3].iter().map(|&value| axis.motion(value)).collect();
+        assert_eq!(motion, vec![0, 0, 1, 0]);
+
+        // Half a count was left over, but lifting starts over from nothing.
+        axis.lift();
+        assert_eq!(axis.motion(100), 0);
+        assert_eq!(axis.motion(101), 0);
+        assert_eq!(axis.motion(102), 1);
+    }
+
+    #[test]
+    fn axes_without_a_resolution_are_scaled_by_their_range() {
+        assert_eq!(Axis::new(10, 0, 4000).scale, COUNTS_PER_MM / 10.0);
+        assert_eq!(Axis::new(0, -2000, 2000).scale, 0.5);
+        // An axis with no range at all doesn't divide by zero.
+        assert_eq!(Axis::new(0, 5, 5).scale, COUNTS_PER_RANGE);
+    }
+
+    #[test]
+    fn absolute_motion_becomes_relative() {
+        let mut devices = tablet();
+        assert!(input(&mut devices, abs(AXES[0], 1000)).is_none());
+        assert!(matches!(
+            input(&mut devices, abs(AXES[0], 1010)),
+            Some(InputEvent::Other { type_: EV_REL, code, value: 5 }) if code == AXES[0]
+        ));
+        assert!(input(&mut devices, abs(ABS_PRESSURE, 500)).is_none());
+    }
+
+    #[test]
+    fn touching_and_the_pen_buttons_click() {
+        let mut devices = tablet();
+        let clicks = [(Button::Touch, Button::Left), (Button::Stylus, Button::Right), (Button::Stylus2, Button::Middle)];
+        for (from, to) in clicks {
+            let written = input(&mut devices, button(from, Direction::Down));
+            assert!(
+                matches!(written, Some(InputEvent::Key { kind: KeyKind::Button(button), .. }) if button == to),
+                "{:?} became {:?}",
+                from,
+                written
+            );
+        }
+        assert!(input(&mut devices, button(Button::ToolPen, Direction::Down)).is_none());
+    }
+
+    #[test]
+    fn devices_without_both_axes_are_left_alone() {
+        let mut devices = RelativeDevices::new(vec![String::from("tablet")]);
+        for device in [device("tablet", &AXES[..1]), device("touchscreen", &AXES)] {
+            let created = devices.new_device(device.clone());
+            assert_eq!(created.capabilities.len(), device.capabilities.len());
+            assert!(matches!(created.capabilities[0], Capability::Abs { code, .. } if code == AXES[0]));
+            assert!(matches!(
+                input(&mut devices, abs(AXES[0], 1000)),
+                Some(InputEvent::Other { type_: EV_ABS, value: 1000, .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use anyhow::Error;
+use std::path::Path;
+
+// Locks down a receiver, which handles input from the network while running as root. Landlock
+// makes the whole filesystem read-only, except for uinput and the given directories, and seccomp
+// turns away syscalls that nothing in evkvm needs but an attacker would love, like ptrace, mount
+// and module loading. Both only cover threads started afterwards, so this has to happen before
+// the runtime starts any. Commands run for senders are restricted the same way.
+//
+// That is why it happens at startup rather than once the virtual devices are set up: they are
+// created whenever a sender announces a device, long after the runtime's threads are running. For
+// the same reason seccomp turns away what is known to be dangerous rather than allowing only what
+// evkvm uses, since the commands run for senders can be any program and need whatever it calls.
+
+#[cfg(target_os = "linux")]
+pub fn apply(writable: &[&Path]) -> Result<(), Error> {
+    use anyhow::Context;
+
+    // Required for unprivileged seccomp and Landlock, and keeps anything run from here from
+    // gaining privileges, e.g. through setuid binaries.
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } < 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to set no_new_privs");
+    }
+
+    match landlock::restrict(writable) {
+        Ok(true) => {}
+        Ok(false) => log::warn!("Landlock isn't available, so the filesystem is not restricted"),
+        Err(err) => return Err(err).context("Failed to set up Landlock"),
+    }
+
+    if !seccomp::apply().context("Failed to set up seccomp")? {
+        log::warn!("seccomp isn't supported on this architecture, so syscalls are not restricted");
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn apply(_writable: &[&Path]) -> Result<(), Error> {
+    log::warn!("Sandboxing is only supported on Linux");
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+mod landlock {
+    use std::ffi::CString;
+    use std::io::{Error, ErrorKind};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    // From linux/landlock.h, ABI version 1.
+    const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+    const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+    const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+    const ACCESS_FS_ALL: u64 = (1 << 13) - 1;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    const DEVICES: &[&str] = &["/dev/uinput", "/dev/null"];
+
+    // Returns false if the kernel doesn't support Landlock, or something like a container's seccomp
+    // profile keeps us from using it.
+    pub fn restrict(writable: &[&Path]) -> Result<bool, Error> {
+        let attr = RulesetAttr { handled_access_fs: ACCESS_FS_ALL };
+        let ruleset = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0,
+            )
+        };
+        if ruleset < 0 {
+            let err = Error::last_os_error();
+            return match err.raw_os_error() {
+                Some(libc::ENOSYS) | Some(libc::EOPNOTSUPP) | Some(libc::EPERM) => Ok(false),
+                _ => Err(err),
+            };
+        }
+        let ruleset = ruleset as libc::c_int;
+
+        let result = restrict_to(ruleset, writable);
+        unsafe { libc::close(ruleset) };
+        result.map(|()| true)
+    }
+
+    fn restrict_to(ruleset: libc::c_int, writable: &[&Path]) -> Result<(), Error> {
+        allow(ruleset, Path::new("/"), ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR)?;
+        for device in DEVICES {
+            allow(ruleset, Path::new(device), ACCESS_FS_READ_FILE | ACCESS_FS_WRITE_FILE)?;
+        }
+        for path in writable {
+            allow(ruleset, path, ACCESS_FS_ALL)?;
+        }
+        if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0) } < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn allow(ruleset: libc::c_int, path: &Path, access: u64) -> Result<(), Error> {
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "Path contains a null byte"))?;
+        let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+        if fd < 0 {
+            let err = Error::last_os_error();
+            // Devices that don't exist here don't need to be allowed.
+            if err.kind() == ErrorKind::NotFound {
+                return Ok(());
+            }
+            return Err(Error::new(err.kind(), format!("{}: {}", path.display(), err)));
+        }
+        let attr = PathBeneathAttr { allowed_access: access, parent_fd: fd };
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_add_rule,
+                ruleset,
+                RULE_PATH_BENEATH,
+                &attr as *const PathBeneathAttr,
+                0,
+            )
+        };
+        let result = if ret < 0 { Err(Error::last_os_error()) } else { Ok(()) };
+        unsafe { libc::close(fd) };
+        result
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod seccomp {
+    use std::io::Error;
+
+    // From linux/filter.h, linux/seccomp.h and linux/audit.h.
+    const BPF_LD_W_ABS: u16 = 0x20;
+    const BPF_JEQ_K: u16 = 0x15;
+    const BPF_JGE_K: u16 = 0x35;
+    const BPF_RET_K: u16 = 0x06;
+    const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+    const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+    // Offsets into struct seccomp_data.
+    const DATA_NR: u32 = 0;
+    const DATA_ARCH: u32 = 4;
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    const AUDIT_ARCH: Option<u32> = None;
+
+    // x32 syscalls on x86_64 have this bit set, and would otherwise get around the filter.
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+    const DENIED: &[libc::c_long] = &[
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_setns,
+        libc::SYS_unshare,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_kexec_load,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_userfaultfd,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+        libc::SYS_open_by_handle_at,
+        libc::SYS_reboot,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        libc::SYS_acct,
+        libc::SYS_settimeofday,
+        libc::SYS_clock_settime,
+    ];
+
+    #[cfg(target_arch = "x86_64")]
+    const ARCH_DENIED: &[libc::c_long] = &[libc::SYS_kexec_file_load, libc::SYS_iopl, libc::SYS_ioperm];
+    #[cfg(not(target_arch = "x86_64"))]
+    const ARCH_DENIED: &[libc::c_long] = &[];
+
+    fn statement(code: u16, k: u32) -> libc::sock_filter {
+        libc::sock_filter { code, jt: 0, jf: 0, k }
+    }
+
+    fn jump(code: u16, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+        libc::sock_filter { code, jt, jf, k }
+    }
+
+    // Returns false if there is no filter for this architecture.
+    pub fn apply() -> Result<bool, Error> {
+        let arch = match AUDIT_ARCH {
+            Some(arch) => arch,
+            None => return Ok(false),
+        };
+        let deny = SECCOMP_RET_ERRNO | libc::EPERM as u32;
+
+        let mut filter = vec![
+            statement(BPF_LD_W_ABS, DATA_ARCH),
+            jump(BPF_JEQ_K, arch, 1, 0),
+            statement(BPF_RET_K, deny),
+            statement(BPF_LD_W_ABS, DATA_NR),
+        ];
+        if cfg!(target_arch = "x86_64") {
+            filter.push(jump(BPF_JGE_K, X32_SYSCALL_BIT, 0, 1));
+            filter.push(statement(BPF_RET_K, deny));
+        }
+        for &syscall in DENIED.iter().chain(ARCH_DENIED) {
+            filter.push(jump(BPF_JEQ_K, syscall as u32, 0, 1));
+            filter.push(statement(BPF_RET_K, deny));
+        }
+        filter.push(statement(BPF_RET_K, SECCOMP_RET_ALLOW));
+
+        let program = libc::sock_fprog {
+            len: filter.len() as u16,
+            filter: filter.as_mut_ptr(),
+        };
+        let ret = unsafe {
+            libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &program as *const libc::sock_fprog,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(true)
+    }
+}
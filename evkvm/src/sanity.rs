@@ -0,0 +1,331 @@
+use input::{Capability, Device, Direction, Event, InputEvent, KeyKind};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+// Checks input from a sender before it is injected, as a last line of defense against an
+// authorized sender that has been compromised or has gone haywire. A real device never holds down
+// dozens of keys at once, or reports an axis way outside the range it advertised, so input like
+// that is dropped. Input beyond a rate limit is dropped too, counted in reports, the events a
+// device sends together up to an EV_SYN, so that a busy report doesn't count for more than an idle
+// one. Key releases, EV_SYN and touches being lifted always get through, so nothing is left stuck
+// down.
+
+// No keyboard can hold down this many keys at once.
+const MAX_HELD_KEYS: usize = 32;
+
+// In linux/input-event-codes.h.
+const EV_SYN: u16 = 0x00;
+const EV_ABS: u16 = 0x03;
+const SYN_REPORT: u16 = 0x00;
+const ABS_MT_SLOT: u16 = 0x2f;
+const ABS_MT_TRACKING_ID: u16 = 0x39;
+
+// How often to complain about a sender, at most.
+const WARNING_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+struct DeviceState {
+    // The advertised range of each absolute axis.
+    ranges: HashMap<u16, (i32, i32)>,
+    held: HashSet<KeyKind>,
+    // Whether the report under way is let through, or None between reports.
+    report: Option<bool>,
+}
+
+struct RateLimit {
+    // Reports allowed per second.
+    max_rate: u32,
+    // Tokens left in the bucket, and when it was last filled up.
+    tokens: f64,
+    filled: Instant,
+}
+
+impl RateLimit {
+    // Take a token for a new report, if there is one left.
+    fn take(&mut self, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.filled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.max_rate as f64).min(self.max_rate as f64);
+        self.filled = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+pub struct InputSanity {
+    // What to call the sender in warnings.
+    name: String,
+    // None for no limit.
+    rate: Option<RateLimit>,
+    devices: HashMap<u16, DeviceState>,
+    last_warning: Option<Instant>,
+}
+
+impl InputSanity {
+    // Allow at most `max_rate` reports per second, or any number with None.
+    pub fn new(name: String, max_rate: Option<u32>) -> Self {
+        InputSanity {
+            name,
+            rate: max_rate.map(|max_rate| RateLimit {
+                max_rate,
+                tokens: max_rate as f64,
+                filled: Instant::now(),
+            }),
+            devices: HashMap::new(),
+            last_warning: None,
+        }
+    }
+
+    // Returns whether `event` may be injected, and warns now and then when it may not.
+    pub fn allow(&mut self, event: &Event, now: Instant) -> bool {
+        match self.check(event, now) {
+            Ok(()) => true,
+            Err(reason) => {
+                let quiet = self
+                    .last_warning
+                    .map(|last| now.duration_since(last) < WARNING_INTERVAL)
+                    .unwrap_or(false);
+                if !quiet {
+                    log::warn!("Dropping input from {}: {}", self.name, reason);
+                    self.last_warning = Some(now);
+                }
+                false
+            }
+        }
+    }
+
+    fn check(&mut self, event: &Event, now: Instant) -> Result<(), String> {
+        let (device_id, input, syn) = match *event {
+            Event::NewDevice(ref device) => {
+                self.devices.insert(device.id, device_state(device));
+                return Ok(());
+            }
+            Event::RemoveDevice(id) => {
+                self.devices.remove(&id);
+                return Ok(());
+            }
+            Event::Input { device_id, input, syn } => (device_id, input, syn),
+        };
+
+        let device = self.devices.entry(device_id).or_default();
+        match input {
+            InputEvent::Key { direction: Direction::Up, kind } => {
+                device.held.remove(&kind);
+                return Ok(());
+            }
+            InputEvent::Other { type_: EV_SYN, code, .. } => {
+                if code == SYN_REPORT {
+                    device.report = None;
+                }
+                return Ok(());
+            }
+            // The slot goes with the lift, so that it lifts the right touch.
+            InputEvent::Other { type_: EV_ABS, code: ABS_MT_SLOT, .. }
+            | InputEvent::Other { type_: EV_ABS, code: ABS_MT_TRACKING_ID, value: -1 } => {
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        // The first event of a report decides whether the whole report gets through. Events sent
+        // with a SYN_REPORT of their own make up a report by themselves.
+        if let Some(rate) = &mut self.rate {
+            let allowed = *device.report.get_or_insert_with(|| rate.take(now));
+            if syn {
+                device.report = None;
+            }
+            if !allowed {
+                return Err(format!("more than {} reports per second", rate.max_rate));
+            }
+        }
+
+        match input {
+            InputEvent::Key { direction: Direction::Down, kind } => {
+                if !device.held.contains(&kind) && device.held.len() >= MAX_HELD_KEYS {
+                    return Err(format!("more than {} keys held down on device {}", MAX_HELD_KEYS, device_id));
+                }
+                device.held.insert(kind);
+            }
+            InputEvent::Other { type_: EV_ABS, code, value } => {
+                if let Some(&(minimum, maximum)) = device.ranges.get(&code) {
+                    // Some devices overshoot their range a little, so only values far outside it
+                    // count.
+                    let slack = (maximum as i64 - minimum as i64).max(1);
+                    let value = value as i64;
+                    if value < minimum as i64 - slack || value > maximum as i64 + slack {
+                        return Err(format!(
+                            "axis {} on device {} at {}, outside {}..{}",
+                            code, device_id, value, minimum, maximum
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn device_state(device: &Device) -> DeviceState {
+    let ranges = device
+        .capabilities
+        .iter()
+        .filter_map(|capability| match *capability {
+            Capability::Abs { code, info } => Some((code, (info.minimum, info.maximum))),
+            _ => None,
+        })
+        .collect();
+    let held = device.held_keys.iter().copied().collect();
+    DeviceState { ranges, held, report: None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use input::{AbsInfo, Key};
+
+    const ABS_X: u16 = 0x00;
+    const DEVICE: u16 = 1;
+
+    fn key(code: u16) -> KeyKind {
+        KeyKind::Key(Key::Other(code))
+    }
+
+    fn down(kind: KeyKind) -> InputEvent {
+        InputEvent::Key { direction: Direction::Down, kind }
+    }
+
+    fn up(kind: KeyKind) -> InputEvent {
+        InputEvent::Key { direction: Direction::Up, kind }
+    }
+
+    fn abs(code: u16, value: i32) -> InputEvent {
+        InputEvent::Other { type_: EV_ABS, code, value }
+    }
+
+    fn syn_report() -> InputEvent {
+        InputEvent::Other { type_: EV_SYN, code: SYN_REPORT, value: 0 }
+    }
+
+    fn device(capabilities: Vec<Capability>, held_keys: Vec<KeyKind>) -> Event {
+        Event::NewDevice(Device {
+            id: DEVICE,
+            name: String::from("test"),
+            uniq: String::new(),
+            phys: String::new(),
+            vendor: 0,
+            product: 0,
+            bustype: 0,
+            version: 0,
+            capabilities,
+            properties: Vec::new(),
+            held_keys,
+            lit_leds: Vec::new(),
+        })
+    }
+
+    // Whether `input` gets through at `now`, as part of a report that goes on unless `syn` is set.
+    fn check(sanity: &mut InputSanity, input: InputEvent, syn: bool, now: Instant) -> bool {
+        sanity.allow(&Event::Input { device_id: DEVICE, input, syn }, now)
+    }
+
+    #[test]
+    fn releases_and_lifts_get_past_the_rate_limit() {
+        let mut sanity = InputSanity::new(String::from("test"), Some(2));
+        let now = Instant::now();
+        assert!(check(&mut sanity, down(key(1)), true, now));
+        assert!(check(&mut sanity, down(key(2)), true, now));
+        assert!(!check(&mut sanity, down(key(3)), true, now));
+
+        assert!(check(&mut sanity, up(key(1)), false, now));
+        assert!(check(&mut sanity, abs(ABS_MT_SLOT, 1), false, now));
+        assert!(check(&mut sanity, abs(ABS_MT_TRACKING_ID, -1), false, now));
+        assert!(check(&mut sanity, syn_report(), false, now));
+        // A new touch is no lift.
+        assert!(!check(&mut sanity, abs(ABS_MT_TRACKING_ID, 7), false, now));
+    }
+
+    #[test]
+    fn the_first_event_of_a_report_decides_it() {
+        let mut sanity = InputSanity::new(String::from("test"), Some(1));
+        let start = Instant::now();
+        assert!(check(&mut sanity, abs(ABS_X, 1), false, start));
+        assert!(check(&mut sanity, abs(ABS_X, 2), false, start));
+        assert!(check(&mut sanity, syn_report(), false, start));
+
+        assert!(!check(&mut sanity, abs(ABS_X, 3), false, start));
+        // There is a token again by now, but the report was already turned away.
+        let later = start + Duration::from_secs(2);
+        assert!(!check(&mut sanity, abs(ABS_X, 4), false, later));
+        assert!(check(&mut sanity, syn_report(), false, later));
+
+        assert!(check(&mut sanity, abs(ABS_X, 5), false, later));
+    }
+
+    #[test]
+    fn events_with_their_own_syn_are_reports_of_their_own() {
+        let mut sanity = InputSanity::new(String::from("test"), Some(1));
+        let now = Instant::now();
+        assert!(check(&mut sanity, down(key(1)), true, now));
+        assert!(!check(&mut sanity, down(key(2)), true, now));
+    }
+
+    #[test]
+    fn too_many_held_keys_are_refused() {
+        let mut sanity = InputSanity::new(String::from("test"), None);
+        let now = Instant::now();
+        for code in 0..MAX_HELD_KEYS as u16 {
+            assert!(check(&mut sanity, down(key(code)), true, now));
+        }
+        // Pressing a held key again doesn't hold down any more of them.
+        assert!(check(&mut sanity, down(key(0)), true, now));
+        assert!(!check(&mut sanity, down(key(MAX_HELD_KEYS as u16)), true, now));
+
+        assert!(check(&mut sanity, up(key(0)), true, now));
+        assert!(check(&mut sanity, down(key(MAX_HELD_KEYS as u16)), true, now));
+    }
+
+    #[test]
+    fn keys_held_when_a_device_appears_count() {
+        let mut sanity = InputSanity::new(String::from("test"), None);
+        let now = Instant::now();
+        let held: Vec<KeyKind> = (0..MAX_HELD_KEYS as u16).map(key).collect();
+        assert!(sanity.allow(&device(Vec::new(), held), now));
+
+        assert!(check(&mut sanity, down(key(0)), true, now));
+        assert!(!check(&mut sanity, down(key(MAX_HELD_KEYS as u16)), true, now));
+    }
+
+    #[test]
+    fn axes_may_only_overshoot_their_range_by_so_much() {
+        let mut sanity = InputSanity::new(String::from("test"), None);
+        let now = Instant::now();
+        let info = AbsInfo { value: 0, minimum: 0, maximum: 100, fuzz: 0, flat: 0, resolution: 0 };
+        assert!(sanity.allow(&device(vec![Capability::Abs { code: ABS_X, info }], Vec::new()), now));
+
+        // The slack is as wide as the range.
+        assert!(check(&mut sanity, abs(ABS_X, -100), true, now));
+        assert!(check(&mut sanity, abs(ABS_X, 200), true, now));
+        assert!(!check(&mut sanity, abs(ABS_X, -101), true, now));
+        assert!(!check(&mut sanity, abs(ABS_X, 201), true, now));
+    }
+
+    #[test]
+    fn warnings_are_throttled() {
+        let mut sanity = InputSanity::new(String::from("test"), None);
+        let start = Instant::now();
+        let info = AbsInfo { value: 0, minimum: 0, maximum: 100, fuzz: 0, flat: 0, resolution: 0 };
+        assert!(sanity.allow(&device(vec![Capability::Abs { code: ABS_X, info }], Vec::new()), start));
+
+        assert!(!check(&mut sanity, abs(ABS_X, 1000), true, start));
+        assert_eq!(sanity.last_warning, Some(start));
+        let soon = start + WARNING_INTERVAL - Duration::from_millis(1);
+        assert!(!check(&mut sanity, abs(ABS_X, 1000), true, soon));
+        assert_eq!(sanity.last_warning, Some(start));
+        let later = start + WARNING_INTERVAL;
+        assert!(!check(&mut sanity, abs(ABS_X, 1000), true, later));
+        assert_eq!(sanity.last_warning, Some(later));
+    }
+}
@@ -0,0 +1,156 @@
+use std::time::Duration;
+
+use crate::config::{ScheduleRule, TimeOfDay, Weekday, ALL_DAYS};
+
+// Works out which schedule rules apply, and when to look again. What the rules mean for focus is
+// up to FocusState::apply_rules.
+
+// Look again at least this often, so that clock changes and suspends are noticed eventually.
+const MAX_WAIT: Duration = Duration::from_secs(10 * 60);
+
+const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+
+#[derive(Clone, Copy, Debug)]
+pub struct LocalTime {
+    pub weekday: Weekday,
+    // Since midnight.
+    pub seconds: u32,
+}
+
+// The current time in the system's time zone.
+pub fn local_now() -> LocalTime {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        libc::localtime_r(&now, &mut tm);
+    }
+    // tm_wday counts from Sunday.
+    let weekday = ALL_DAYS[(tm.tm_wday as usize + 6) % 7];
+    let seconds = (tm.tm_hour * 3600 + tm.tm_min * 60 + tm.tm_sec.min(59)) as u32;
+    LocalTime { weekday, seconds }
+}
+
+fn day_before(weekday: Weekday) -> Weekday {
+    let index = ALL_DAYS.iter().position(|&day| day == weekday).unwrap_or(0);
+    ALL_DAYS[(index + 6) % 7]
+}
+
+// A rule that ends when it starts lasts a whole day, which like any rule that runs past midnight
+// counts as part of the day it started on.
+pub fn applies(rule: &ScheduleRule, now: LocalTime) -> bool {
+    let minute = (now.seconds / 60) as u16;
+    let (TimeOfDay(from), TimeOfDay(to)) = (rule.from, rule.to);
+    if from < to {
+        from <= minute && minute < to && rule.days.contains(&now.weekday)
+    } else {
+        (minute >= from && rule.days.contains(&now.weekday))
+            || (minute < to && rule.days.contains(&day_before(now.weekday)))
+    }
+}
+
+// The indices of the rules that apply at `now`.
+pub fn active(rules: &[ScheduleRule], now: LocalTime) -> Vec<usize> {
+    (0..rules.len()).filter(|&index| applies(&rules[index], now)).collect()
+}
+
+// How long until any rule might start or stop applying.
+pub fn until_next_change(rules: &[ScheduleRule], now: LocalTime) -> Duration {
+    rules
+        .iter()
+        .flat_map(|rule| [rule.from, rule.to])
+        .map(|TimeOfDay(minute)| {
+            let wait = match (minute as u32 * 60 + SECONDS_PER_DAY - now.seconds) % SECONDS_PER_DAY {
+                0 => SECONDS_PER_DAY,
+                wait => wait,
+            };
+            Duration::from_secs(wait as u64)
+        })
+        .min()
+        .unwrap_or(MAX_WAIT)
+        .min(MAX_WAIT)
+}
+
+// How to call a rule in logs, e.g. "09:00-17:00".
+pub fn describe(rule: &ScheduleRule) -> String {
+    let (TimeOfDay(from), TimeOfDay(to)) = (rule.from, rule.to);
+    format!("{:02}:{:02}-{:02}:{:02}", from / 60, from % 60, to / 60, to % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Weekday::{Friday, Monday, Saturday, Tuesday};
+
+    fn rule(from: &str, to: &str, days: &[Weekday]) -> ScheduleRule {
+        ScheduleRule {
+            from: TimeOfDay::try_from(from.to_owned()).unwrap(),
+            to: TimeOfDay::try_from(to.to_owned()).unwrap(),
+            days: days.to_vec(),
+            default_focus: None,
+            blocked_receivers: Vec::new(),
+        }
+    }
+
+    fn at(weekday: Weekday, time: &str) -> LocalTime {
+        let TimeOfDay(minute) = TimeOfDay::try_from(time.to_owned()).unwrap();
+        LocalTime { weekday, seconds: minute as u32 * 60 }
+    }
+
+    #[test]
+    fn rules_apply_from_their_start_until_their_end() {
+        let rule = rule("09:00", "17:00", &[Monday]);
+        assert!(!applies(&rule, at(Monday, "08:59")));
+        assert!(applies(&rule, at(Monday, "09:00")));
+        assert!(applies(&rule, at(Monday, "16:59")));
+        assert!(!applies(&rule, at(Monday, "17:00")));
+        assert!(!applies(&rule, at(Tuesday, "12:00")));
+    }
+
+    #[test]
+    fn rules_past_midnight_belong_to_the_day_they_start_on() {
+        let rule = rule("22:00", "06:00", &[Friday]);
+        assert!(!applies(&rule, at(Friday, "21:59")));
+        assert!(applies(&rule, at(Friday, "22:00")));
+        assert!(applies(&rule, at(Saturday, "00:00")));
+        assert!(applies(&rule, at(Saturday, "05:59")));
+        assert!(!applies(&rule, at(Saturday, "06:00")));
+        // Saturday isn't listed, so its night isn't covered, and neither is the morning after it.
+        assert!(!applies(&rule, at(Saturday, "23:00")));
+        // Nor is the morning of the listed day, which belongs to the night before.
+        assert!(!applies(&rule, at(Friday, "05:00")));
+    }
+
+    #[test]
+    fn rules_that_end_when_they_start_last_a_day() {
+        let midnight = rule("00:00", "00:00", &[Monday]);
+        assert!(applies(&midnight, at(Monday, "00:00")));
+        assert!(applies(&midnight, at(Monday, "23:59")));
+        assert!(!applies(&midnight, at(Tuesday, "00:00")));
+
+        let morning = rule("08:00", "08:00", &[Monday]);
+        assert!(!applies(&morning, at(Monday, "07:59")));
+        assert!(applies(&morning, at(Monday, "08:00")));
+        assert!(applies(&morning, at(Tuesday, "07:59")));
+        assert!(!applies(&morning, at(Tuesday, "08:00")));
+    }
+
+    #[test]
+    fn next_change_is_the_next_boundary() {
+        let rules = [rule("09:00", "17:00", &[Monday])];
+        assert_eq!(until_next_change(&rules, at(Monday, "08:58")), Duration::from_secs(2 * 60));
+        assert_eq!(until_next_change(&rules, at(Monday, "16:55")), Duration::from_secs(5 * 60));
+        // Nothing changes for longer than MAX_WAIT, but it is checked again by then.
+        assert_eq!(until_next_change(&rules, at(Monday, "12:00")), MAX_WAIT);
+        assert_eq!(until_next_change(&[], at(Monday, "12:00")), MAX_WAIT);
+    }
+
+    #[test]
+    fn next_change_on_a_boundary_is_the_next_one() {
+        // Right on a boundary, that boundary is a day away rather than now.
+        let rules = [rule("00:00", "00:00", &[Monday])];
+        assert_eq!(until_next_change(&rules, at(Monday, "00:00")), MAX_WAIT);
+
+        let rules = [rule("00:00", "00:00", &[Monday]), rule("09:00", "09:05", &[Monday])];
+        assert_eq!(until_next_change(&rules, at(Monday, "09:00")), Duration::from_secs(5 * 60));
+    }
+}
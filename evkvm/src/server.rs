@@ -1,28 +1,48 @@
 use anyhow::{Context, Error};
-use input::{Direction, Event, InputEvent, ReaderManager, WriterManager, Key, KeyKind};
-use net::{self, Message, PROTOCOL_VERSION};
-use std::collections::{HashMap, HashSet};
+use input::{Event, Feedback, ReaderManager, WriterManager};
+use net::{self, Channel, FrameQueue, Hello, Message, Purpose, Role};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::Infallible;
-use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio::io::{AsyncRead, AsyncWrite};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::io::{self, AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
-use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::mpsc::{self, error::TryRecvError, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
 use tokio::time;
 use tokio_rustls::rustls;
 
-use crate::config::Receiver;
-use crate::common::{Identity, get_cert_fingerprint};
+use crate::combo::ComboMatcher;
+use crate::config::{Config, DuplicatePolicy, Profile, Receiver};
+use crate::common::{Identity, check_hello, get_cert_fingerprint, local_hello, set_keepalive};
+use crate::control::{self, ControlRequest, ControlResponse, PausedDevice, ReceiverStatus, Status, UngrabbedDevice};
+use crate::focus::{Action, Focus, FocusState};
+use crate::guard::KeyGuard;
+use crate::history::History;
+use crate::listener;
+use crate::pipeline::Pipeline;
+use crate::schedule;
+use crate::transfer::Offers;
+use crate::trust;
 
-struct ClientVerifier { receivers: Vec<Receiver> }
+// Receivers from the config and from `evkvm trust`, which can change while the server runs.
+type Receivers = Arc<RwLock<Vec<Receiver>>>;
+
+struct ClientVerifier {
+    receivers: Receivers,
+    // Fingerprints of the observers from the config.
+    observers: Vec<String>,
+}
 
 impl ClientVerifier {
-    fn new(receivers: Vec<Receiver>) -> Self {
-        ClientVerifier { receivers }
+    fn new(receivers: Receivers, observers: Vec<String>) -> Self {
+        ClientVerifier { receivers, observers }
     }
 }
 
-impl<'a> rustls::server::ClientCertVerifier for ClientVerifier {
+impl rustls::server::ClientCertVerifier for ClientVerifier {
     fn client_auth_root_subjects(&self) -> Option<rustls::DistinguishedNames> {
         Some(vec! [])
     }
@@ -37,101 +57,683 @@ impl<'a> rustls::server::ClientCertVerifier for ClientVerifier {
     ) -> Result<rustls::server::ClientCertVerified, rustls::Error> {
         let fingerprint = get_cert_fingerprint(end_identity);
 
-        let receiver = self.receivers.iter().find(|&receiver|
+        let receivers = self.receivers.read().unwrap();
+        let receiver = receivers.iter().find(|&receiver|
             match receiver.fingerprint {
                 Some(ref receiver_fingerprint) => receiver_fingerprint == &fingerprint,
                 None => false,
-            }
+            } && !trust::is_expired(receiver)
         );
 
+        // Whether an observer opened the connection to observe is checked once it says so.
+        if receiver.is_none() && self.observers.contains(&fingerprint) {
+            return Ok(rustls::server::ClientCertVerified::assertion());
+        }
+
         match receiver {
             None => {
                 log::info!("Fingerprint \"{}\" not authorized!", fingerprint);
                 Err(rustls::Error::InvalidCertificateSignature)
             },
-            Some(receiver) => {
-                let name = match &receiver.nick {
-                    None => &fingerprint,
-                    Some(nick) => nick,
-                };
-                log::info!("{} connected", name);
-                Ok(rustls::server::ClientCertVerified::assertion())
+            // The connection logs that it is connected once the handshake is done.
+            Some(_) => Ok(rustls::server::ClientCertVerified::assertion()),
+        }
+    }
+}
+
+// Most events a disconnected receiver may miss before we stop waiting for it to come back.
+const DRAIN_LIMIT: usize = 4096;
+
+// How many recent pings to consider when estimating a receiver's clock offset.
+const CLOCK_SAMPLES: usize = 8;
+
+// How long to wait for a receiver to answer a latency probe, and how long to wait between probes.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+const PROBE_INTERVAL: Duration = Duration::from_millis(20);
+
+// Ping in intervals of half of the timeout just to be on the safe side. Unlike a keep alive sent
+// only when idle, this keeps the clock offset fresh while input is flowing.
+const PING_INTERVAL: Duration = Duration::from_millis(net::MESSAGE_TIMEOUT.as_millis() as u64 / 2);
+
+// How long a ping may go unanswered before the connection is considered dead. Writes can keep
+// succeeding for a long time after the receiver has stopped reading, since they only fill up
+// buffers, so only answers show that it is still there.
+const PING_TIMEOUT: Duration = net::MESSAGE_TIMEOUT;
+
+// Bytes per second the bulk channel may use, so that it can't fill up buffers along the way that
+// input would then have to wait behind.
+const BULK_RATE: u64 = 4 * 1024 * 1024;
+
+// The origin of feedback asked of our own virtual devices, as opposed to a receiver's, whose
+// origins are their connection ids.
+const LOCAL_FEEDBACK: u64 = u64::MAX;
+
+// The LEDs that show a machine's lock state, which follow focus: num, caps and scroll lock,
+// compose and kana. The rest, like mute or the one some keyboards light for their backlight, are
+// about the sender's own hardware, and only the sender sets them.
+const LOCK_LEDS: std::ops::RangeInclusive<u16> = 0..=4;
+
+struct Client {
+    id: u64,
+    fingerprint: String,
+    // What the receiver said about itself when it connected.
+    hello: Hello,
+    // The protocol version negotiated with it.
+    version: u16,
+    sender: UnboundedSender<Message>,
+}
+
+// Connected receivers by fingerprint. Switching goes through them in the order they are listed in
+// the config, followed by the ones added with `evkvm trust`, so a receiver that reconnects keeps
+// its place.
+#[derive(Default)]
+struct Registry {
+    clients: HashMap<String, Client>,
+}
+
+impl Registry {
+    fn get(&self, focus: &Focus) -> Option<&Client> {
+        match focus {
+            Focus::Local => None,
+            Focus::Remote(fingerprint) => self.clients.get(fingerprint),
+        }
+    }
+
+    // Add a client, returning the one it replaces if its receiver was already connected.
+    fn insert(&mut self, client: Client) -> Option<Client> {
+        self.clients.insert(client.fingerprint.clone(), client)
+    }
+
+    fn remove(&mut self, fingerprint: &str) -> Option<Client> {
+        self.clients.remove(fingerprint)
+    }
+
+    // The client for the connection with the given id, if it hasn't been replaced.
+    fn connection(&self, id: u64) -> Option<&Client> {
+        self.clients.values().find(|client| client.id == id)
+    }
+
+    // Remove the client for the connection with the given id, if it hasn't been replaced yet.
+    fn remove_connection(&mut self, id: u64) -> Option<Client> {
+        let fingerprint = self.clients.values().find(|client| client.id == id)?.fingerprint.clone();
+        self.clients.remove(&fingerprint)
+    }
+
+    // The connected client for the receiver with the given nick, or failing that, the one that
+    // reported the given hostname.
+    fn find(&self, receivers: &[Receiver], nick: &str) -> Option<&Client> {
+        let fingerprint = receivers
+            .iter()
+            .find(|receiver| receiver.nick.as_deref() == Some(nick))
+            .and_then(|receiver| receiver.fingerprint.as_ref());
+        match fingerprint {
+            Some(fingerprint) => self.clients.get(fingerprint),
+            None => self.clients.values().find(|client| client.hello.hostname == nick),
+        }
+    }
+
+    // Connected clients in the order switching goes through them.
+    fn in_order<'a>(&'a self, receivers: &'a [Receiver]) -> Vec<&'a Client> {
+        let mut seen = HashSet::new();
+        receivers
+            .iter()
+            .filter_map(|receiver| receiver.fingerprint.as_ref())
+            .filter(|fingerprint| seen.insert(*fingerprint))
+            .filter_map(|fingerprint| self.clients.get(fingerprint))
+            .collect()
+    }
+}
+
+// A connection opened to watch focus and receivers come and go.
+struct Observer {
+    id: u64,
+    sender: UnboundedSender<Message>,
+}
+
+// What observers are told about: the name of the focused receiver, if any, and the names of the
+// connected ones.
+#[derive(Default, PartialEq)]
+struct Observed {
+    focus: Option<String>,
+    connected: Vec<String>,
+}
+
+impl Observed {
+    fn new(receivers: &[Receiver], registry: &Registry, focus: &Focus) -> Self {
+        Observed {
+            focus: registry.get(focus).map(|client| client_name(receivers, client)),
+            connected: registry
+                .in_order(receivers)
+                .into_iter()
+                .map(|client| client_name(receivers, client))
+                .collect(),
+        }
+    }
+
+    // What a new observer is told first.
+    fn announce(&self) -> Vec<Message> {
+        let mut messages: Vec<Message> = self
+            .connected
+            .iter()
+            .map(|name| Message::ReceiverConnected { name: name.clone() })
+            .collect();
+        messages.push(Message::FocusChanged { receiver: self.focus.clone() });
+        messages
+    }
+
+    // What observers are told when things went from `self` to `new`.
+    fn changes(&self, new: &Observed) -> Vec<Message> {
+        let mut messages: Vec<Message> = self
+            .connected
+            .iter()
+            .filter(|name| !new.connected.contains(*name))
+            .map(|name| Message::ReceiverDisconnected { name: name.clone() })
+            .collect();
+        messages.extend(
+            new.connected
+                .iter()
+                .filter(|name| !self.connected.contains(*name))
+                .map(|name| Message::ReceiverConnected { name: name.clone() }),
+        );
+        if self.focus != new.focus {
+            messages.push(Message::FocusChanged { receiver: new.focus.clone() });
+        }
+        messages
+    }
+}
+
+// Send `messages` to every observer, dropping those whose connection is gone.
+fn notify(observers: &mut Vec<Observer>, messages: &[Message]) {
+    observers.retain(|observer| messages.iter().all(|message| observer.sender.send(message.clone()).is_ok()));
+}
+
+// Events held for a focused receiver that dropped, to be delivered if it reconnects in time.
+struct Drain {
+    fingerprint: String,
+    deadline: time::Instant,
+    events: Vec<(Event, u64)>,
+}
+
+fn start_drain(receivers: &[Receiver], client: &Client, grace: Duration) -> Option<Drain> {
+    if grace.is_zero() {
+        return None;
+    }
+    log::info!("{} dropped, waiting up to {:?} for it to reconnect", client_name(receivers, client), grace);
+    Some(Drain {
+        fingerprint: client.fingerprint.clone(),
+        deadline: time::Instant::now() + grace,
+        events: Vec::new(),
+    })
+}
+
+// The focus state `config` asks for, with the options `profile` sets in place of its own.
+pub fn new_focus_state(config: &Config, profile: Option<&Profile>) -> FocusState {
+    let profile = profile.cloned().unwrap_or_default();
+    let switch_keys_window = match profile.switch_keys_within_ms.unwrap_or(config.switch_keys_within_ms) {
+        0 => None,
+        ms => Some(Duration::from_millis(ms)),
+    };
+    let switch_combo = ComboMatcher::new(
+        profile.switch_keys.unwrap_or_else(|| config.switch_keys.clone()),
+        profile.switch_keys_in_order.unwrap_or(config.switch_keys_in_order),
+        switch_keys_window,
+    );
+    let guard_confirm_window = match profile.guarded_keys_confirm_ms.unwrap_or(config.guarded_keys_confirm_ms) {
+        0 => None,
+        ms => Some(Duration::from_millis(ms)),
+    };
+    let key_guard = KeyGuard::new(
+        profile.guarded_keys.unwrap_or_else(|| config.guarded_keys.clone()),
+        guard_confirm_window,
+    );
+    FocusState::new(
+        switch_combo,
+        key_guard,
+        profile.held_buttons_on_switch.unwrap_or(config.held_buttons_on_switch),
+        profile.transfer_keys_on_switch.unwrap_or(config.transfer_keys_on_switch),
+        profile.media_keys.unwrap_or(config.media_keys),
+        profile.local_keys.unwrap_or_else(|| config.local_keys.clone()),
+        profile.local_pointer.unwrap_or(config.local_pointer),
+    )
+}
+
+// What to call a receiver in logs and status output: its nick if it has one, the hostname it
+// reported if it is connected, and its fingerprint otherwise.
+pub fn receiver_name(receivers: &[Receiver], fingerprint: &str, hostname: Option<&str>) -> String {
+    receivers
+        .iter()
+        .find(|receiver| receiver.fingerprint.as_deref() == Some(fingerprint))
+        .and_then(|receiver| receiver.nick.clone())
+        .or_else(|| hostname.filter(|hostname| !hostname.is_empty()).map(str::to_owned))
+        .unwrap_or_else(|| fingerprint.to_owned())
+}
+
+// What to call a connection in logs: the receiver's nick along with the start of its
+// fingerprint, e.g. "laptop (ab12cd34…)", or just the latter for receivers without a nick.
+fn peer_label(receivers: &[Receiver], fingerprint: &str) -> String {
+    let short = format!("{}…", fingerprint.get(..8).unwrap_or(fingerprint));
+    match receivers
+        .iter()
+        .find(|receiver| receiver.fingerprint.as_deref() == Some(fingerprint))
+        .and_then(|receiver| receiver.nick.as_ref())
+    {
+        Some(nick) => format!("{} ({})", nick, short),
+        None => short,
+    }
+}
+
+fn client_name(receivers: &[Receiver], client: &Client) -> String {
+    receiver_name(receivers, &client.fingerprint, Some(&client.hello.hostname))
+}
+
+fn focus_name(receivers: &[Receiver], registry: &Registry, focus: &Focus) -> String {
+    match focus {
+        Focus::Local => "this machine".to_owned(),
+        Focus::Remote(fingerprint) => {
+            let hostname = registry.get(focus).map(|client| client.hello.hostname.as_str());
+            receiver_name(receivers, fingerprint, hostname)
+        }
+    }
+}
+
+// A focus request sent to a receiver that hasn't been acknowledged yet.
+#[derive(Clone, Copy)]
+struct PendingFocus {
+    // The connection it was sent on, since only that one can acknowledge it.
+    connection: u64,
+    id: u64,
+    deadline: time::Instant,
+}
+
+fn request_focus(client: &Client, focus_id: &mut u64) -> PendingFocus {
+    *focus_id += 1;
+    let _ = client.sender.send(Message::Focus { id: *focus_id });
+    PendingFocus {
+        connection: client.id,
+        id: *focus_id,
+        deadline: time::Instant::now() + net::FOCUS_ACK_TIMEOUT,
+    }
+}
+
+// Latency probes waiting for their receiver to answer, shared with all connections. Answers carry
+// the time the probe was injected, in our clock.
+#[derive(Default)]
+struct Probes {
+    next_id: AtomicU64,
+    waiting: Mutex<HashMap<u64, oneshot::Sender<u64>>>,
+}
+
+async fn measure_latency(
+    sender: UnboundedSender<Message>,
+    probes: Arc<Probes>,
+    count: u32,
+) -> ControlResponse {
+    let mut samples = Vec::new();
+    let mut lost = 0;
+    for _ in 0..count {
+        let id = probes.next_id.fetch_add(1, Ordering::Relaxed);
+        let (reply, injected) = oneshot::channel();
+        probes.waiting.lock().unwrap().insert(id, reply);
+
+        let sent = net::timestamp();
+        if sender.send(Message::Probe { id, time: Some(sent) }).is_err() {
+            probes.waiting.lock().unwrap().remove(&id);
+            return ControlResponse::Error { message: String::from("Receiver disconnected") };
+        }
+
+        match time::timeout(PROBE_TIMEOUT, injected).await {
+            Ok(Ok(injected)) => samples.push(injected.saturating_sub(sent)),
+            _ => {
+                probes.waiting.lock().unwrap().remove(&id);
+                lost += 1;
             }
         }
+        time::sleep(PROBE_INTERVAL).await;
     }
+    ControlResponse::Latency { samples, lost }
+}
+
+fn offer_file(client: &Client, offers: &Arc<Offers>, path: PathBuf) -> Result<(), Error> {
+    if !client.hello.supports(net::FEATURE_FILES) {
+        return Err(anyhow::anyhow!("Receiver doesn't support file transfer"));
+    }
+    let metadata = std::fs::metadata(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    if !metadata.is_file() {
+        return Err(anyhow::anyhow!("{} is not a file", path.display()));
+    }
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .with_context(|| format!("{} doesn't have a valid file name", path.display()))?
+        .to_owned();
+
+    let size = metadata.len();
+    let id = offers.add(client.fingerprint.clone(), path, size);
+    client
+        .sender
+        .send(Message::FileOffer { id, name, size })
+        .map_err(|_| anyhow::anyhow!("Receiver disconnected"))
+}
+
+// Exchange Hellos with the receiver, which among other things says what it opened the
+// connection for, and settle on a protocol version.
+async fn handshake<T>(stream: &mut T) -> Result<(Hello, u16), Error>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    net::write_hello(&mut *stream, &local_hello(Role::Sender, Purpose::Input)).await?;
+
+    let hello = time::timeout(net::MESSAGE_TIMEOUT, net::read_hello(&mut *stream))
+        .await
+        .context("Read timeout")??;
+    let version = check_hello(&hello, Role::Receiver)?;
+    Ok((hello, version))
 }
 
 async fn server_handle_connection<T>(
-    mut stream: T,
-    mut receiver: UnboundedReceiver<Event>,
+    stream: T,
+    id: u64,
+    version: u16,
+    mut receiver: UnboundedReceiver<Message>,
+    acks: UnboundedSender<(u64, u64)>,
+    feedback: UnboundedSender<(u64, u16, Feedback)>,
+    probes: Arc<Probes>,
 ) -> Result<(), Error>
 where
     T: AsyncRead + AsyncWrite + Unpin,
 {
-    net::write_version(&mut stream, PROTOCOL_VERSION).await?;
-
-    let version = net::read_version(&mut stream).await?;
-    if version != PROTOCOL_VERSION {
-        return Err(anyhow::anyhow!(
-            "Incompatible protocol version (got {}, expecting {})",
-            version,
-            PROTOCOL_VERSION
-        ));
+    let (reader, mut writer) = io::split(stream);
+    let mut reader = net::MessageReader::new(reader, version);
+
+    // Receiver clock minus our clock, in microseconds.
+    let offset: Mutex<Option<i64>> = Mutex::new(None);
+    // Pings that haven't been answered yet, oldest first, with when they were sent.
+    let unanswered: Mutex<VecDeque<(u64, time::Instant)>> = Mutex::new(VecDeque::new());
+
+    let to_receiver_clock = |time: Option<u64>| {
+        time.zip(*offset.lock().unwrap())
+            .map(|(time, offset)| (time as i64 + offset) as u64)
+    };
+
+    let convert = |message: Message| match message {
+        Message::Event { event, time } => Message::Event {
+            event,
+            time: to_receiver_clock(time),
+        },
+        Message::Probe { id, time } => Message::Probe {
+            id,
+            time: to_receiver_clock(time),
+        },
+        message => message,
+    };
+
+    // Messages are sent in the order they were queued on each channel, which is the order the
+    // server loop handled them in. See Channel for what that means for the receiver.
+    let write = async {
+        let mut queue = FrameQueue::new(version);
+        let mut next_ping = time::Instant::now();
+        let mut next_seq: u64 = 0;
+        // When the bulk channel may send its next frame.
+        let mut next_bulk = time::Instant::now();
+        loop {
+            loop {
+                match receiver.try_recv() {
+                    Ok(message) => queue.push(&convert(message))?,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return Ok::<(), Error>(()),
+                }
+            }
+
+            let now = time::Instant::now();
+            if next_ping <= now {
+                if let Some(&(_, sent)) = unanswered.lock().unwrap().front() {
+                    if now - sent > PING_TIMEOUT {
+                        return Err(anyhow::anyhow!("No answer to pings for {:?}", now - sent));
+                    }
+                }
+                queue.push(&Message::Ping { seq: next_seq, time: net::timestamp() })?;
+                unanswered.lock().unwrap().push_back((next_seq, now));
+                next_seq += 1;
+                next_ping = now + PING_INTERVAL;
+            }
+
+            let frame = match queue.pop(next_bulk <= now) {
+                Some(frame) => frame,
+                None => {
+                    let bulk_waiting = queue.is_waiting(Channel::Bulk);
+                    tokio::select! {
+                        message = receiver.recv() => match message {
+                            Some(message) => queue.push(&convert(message))?,
+                            None => return Ok(()),
+                        },
+                        _ = time::sleep_until(next_ping) => {}
+                        _ = time::sleep_until(next_bulk), if bulk_waiting => {}
+                    }
+                    continue;
+                }
+            };
+
+            if frame.channel == Channel::Bulk {
+                let cost = Duration::from_secs_f64(frame.data.len() as f64 / BULK_RATE as f64);
+                next_bulk = next_bulk.max(now) + cost;
+            }
+
+            time::timeout(net::MESSAGE_TIMEOUT, net::write_frame(&mut writer, &frame))
+                .await
+                .context("Write timeout")??;
+        }
+    };
+
+    // The receiver doesn't send keep alives of its own, so there's no read timeout here. Whether it
+    // is still there is judged by its answers to pings instead.
+    let read = async {
+        let mut samples: VecDeque<(u64, i64)> = VecDeque::new();
+        loop {
+            match reader.read().await {
+                Ok(Message::FocusAck { id: focus_id }) => {
+                    let _ = acks.send((id, focus_id));
+                }
+                Ok(Message::Pong { seq, ping_time, time }) => {
+                    // Pongs come back in order, so this answers every earlier ping as well.
+                    unanswered.lock().unwrap().retain(|&(unanswered_seq, _)| unanswered_seq > seq);
+
+                    // Assume the ping and pong took equally long, and trust the sample with the
+                    // shortest round trip since it has the least room for asymmetry.
+                    let now = net::timestamp();
+                    let round_trip = now.saturating_sub(ping_time);
+                    let midpoint = ping_time + round_trip / 2;
+                    samples.push_back((round_trip, time as i64 - midpoint as i64));
+                    if samples.len() > CLOCK_SAMPLES {
+                        samples.pop_front();
+                    }
+                    *offset.lock().unwrap() = samples.iter().min_by_key(|(round_trip, _)| *round_trip).map(|(_, offset)| *offset);
+                }
+                Ok(Message::ProbeAck { id, time }) => {
+                    let injected = match *offset.lock().unwrap() {
+                        Some(offset) => (time as i64 - offset) as u64,
+                        None => net::timestamp(),
+                    };
+                    if let Some(reply) = probes.waiting.lock().unwrap().remove(&id) {
+                        let _ = reply.send(injected);
+                    }
+                }
+                Ok(Message::Feedback { device_id, feedback: asked }) => {
+                    let _ = feedback.send((id, device_id, asked));
+                }
+                Ok(_) => {}
+                Err(err) => return Err::<(), Error>(err.into()),
+            }
+        }
+    };
+
+    tokio::select! {
+        result = write => result,
+        result = read => result,
     }
+}
 
-    loop {
-        // Send a keep alive message in intervals of half of the timeout just to be on the safe
-        // side.
-        let message = match time::timeout(net::MESSAGE_TIMEOUT / 2, receiver.recv()).await {
-            Ok(Some(message)) => Message::Event(message),
-            Ok(None) => return Ok(()),
-            Err(_) => Message::KeepAlive,
-        };
-
-        time::timeout(
-            net::MESSAGE_TIMEOUT,
-            net::write_message(&mut stream, &message),
-        )
-        .await
-        .context("Write timeout")??;
+// Clean up after a receiver that is gone, whether its connection dropped or its access expired.
+// What it was playing on our devices stops, the LEDs it set are forgotten, and if it had focus,
+// focus comes back here, which is returned.
+fn forget_client(
+    client: &Client,
+    reader_manager: &mut ReaderManager,
+    leds: &mut HashMap<Focus, HashMap<(u16, u16), bool>>,
+    focus_state: &mut FocusState,
+    pending_focus: &mut Option<PendingFocus>,
+) -> bool {
+    // Closing the devices' files stops the effects the receiver played.
+    reader_manager.end_feedback(client.id);
+    leds.remove(&Focus::Remote(client.fingerprint.clone()));
+    let lost = focus_state.lost(&client.fingerprint);
+    if lost {
+        *pending_focus = None;
+    }
+    lost
+}
+
+// Carry out feedback from `from` on our devices. Lock LEDs are remembered for whoever set them and
+// only shown while they have focus, so that e.g. caps lock lights up for the machine it is on for.
+fn relay_feedback(
+    reader_manager: &mut ReaderManager,
+    leds: &mut HashMap<Focus, HashMap<(u16, u16), bool>>,
+    focus: &Focus,
+    from: Focus,
+    origin: u64,
+    device_id: u16,
+    feedback: Feedback,
+) {
+    let origin = match feedback {
+        Feedback::Led { code, .. } if !LOCK_LEDS.contains(&code) => {
+            if from != Focus::Local {
+                return;
+            }
+            LOCAL_FEEDBACK
+        }
+        Feedback::Led { code, lit } => {
+            let shown = from == *focus;
+            leds.entry(from).or_default().insert((device_id, code), lit);
+            if !shown {
+                return;
+            }
+            // LEDs aren't tied to whoever set them, unlike effects.
+            LOCAL_FEEDBACK
+        }
+        _ => origin,
+    };
+    if let Err(err) = reader_manager.feedback(origin, device_id, feedback) {
+        log::debug!("Error passing on feedback to device {}: {}", device_id, err);
     }
 }
 
-pub async fn run_server<'a>(
-    listen_address: SocketAddr,
-    switch_keys: &HashSet<Key>,
-    identity: Identity,
-    receivers: Vec<Receiver>,
-) -> Result<Infallible, Error> {
+// Failing to write one event locally isn't worth stopping the server over, so it is only logged.
+async fn write_local(writer_manager: &mut WriterManager, event: Event) {
+    if let Err(err) = writer_manager.write(event).await {
+        log::error!("Error writing event: {}", err);
+    }
+}
+
+// While our devices aren't grabbed, their input already reaches this machine, so nothing is
+// written to it.
+async fn write_to_target(
+    target: &Focus,
+    event: Event,
+    writer_manager: &mut WriterManager,
+    registry: &Registry,
+    grabbing: bool,
+    receivers: &Receivers,
+    history: &mut History,
+) {
+    history.event(&event, || format!("made up for {}", focus_name(&receivers.read().unwrap(), registry, target)));
+    match registry.get(target) {
+        // We cannot remove broken client here, to not crash in next iteration,
+        // and it will be removed later one anyways, therefore we just ignore error here
+        Some(client) => {
+            let _ = client.sender.send(Message::Event { event, time: Some(net::timestamp()) });
+        }
+        None if grabbing => write_local(writer_manager, event).await,
+        None => {}
+    }
+}
+
+pub async fn run_server(config: &Config, identity: Identity) -> Result<Infallible, Error> {
+    let listen_address = config.listen_address;
+    let switch_indicator_led = config.switch_indicator_led;
+    let media_keys_receiver = config.media_keys_receiver.clone();
+    let reconnect_grace = Duration::from_millis(config.reconnect_grace_ms);
+    let mirror_input = config.mirror_input;
+    let mirror_local_devices = config.mirror_local_devices;
+    // Without virtual copies of our devices, input can only reach this machine from the devices
+    // themselves, so they are left ungrabbed while it has focus.
+    let lazy_grab = config.lazy_grab || !mirror_local_devices;
+    let duplicate_connections = config.duplicate_connections;
+
     let (cert, key) = identity;
 
-    let verifier = ClientVerifier::new(receivers);
-    let config = rustls::ServerConfig::builder()
+    let receivers: Receivers = Arc::new(RwLock::new(trust::all_receivers(config)?));
+    for nick in &config.cycle_order {
+        if !receivers.read().unwrap().iter().any(|receiver| receiver.nick.as_ref() == Some(nick)) {
+            log::warn!("cycle-order lists {}, which is not a trusted receiver", nick);
+        }
+    }
+    let observer_fingerprints: Vec<String> = config
+        .observers
+        .iter()
+        .filter_map(|observer| observer.fingerprint.clone())
+        .collect();
+    let verifier = ClientVerifier::new(receivers.clone(), observer_fingerprints.clone());
+    let tls_config = rustls::ServerConfig::builder()
         .with_safe_defaults()
         .with_client_cert_verifier(Arc::new(verifier))
         .with_single_cert(vec! [cert], key)
         .expect("Identity is invalid.");
     
-    let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
-    let listener = TcpListener::bind(listen_address).await?;
+    let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(tls_config));
+    let mut listener = TcpListener::bind(listen_address).await?;
 
     log::info!("Listening on {}", listen_address);
 
-    let mut reader_manager = ReaderManager::new().await?;
-    let mut writer_manager = WriterManager::new().await;
+    let mut control_calls = control::listen(&config.control_socket_path)?;
+    let probes = Arc::new(Probes::default());
+    let offers = Arc::new(Offers::default());
+
+    let mut reader_manager = if lazy_grab {
+        ReaderManager::without_grabbing(config.forward_virtual_devices.clone()).await?
+    } else {
+        ReaderManager::new(config.forward_virtual_devices.clone()).await?
+    };
+    // With lazy grabbing, virtual copies of our devices are only needed for the little that is
+    // written to this machine while a receiver has focus, so they only exist while they are used.
+    let mut writer_manager = if lazy_grab { WriterManager::on_demand().await } else { WriterManager::new().await };
+    let mut pipeline = Pipeline::from_config(config);
+    let mut history = History::new(Duration::from_secs(config.debug_history_secs), config.debug_history_key_names);
 
     let (client_sender, mut client_receiver) = mpsc::unbounded_channel();
+    let (ack_sender, mut ack_receiver) = mpsc::unbounded_channel();
+    let (feedback_sender, mut feedback_receiver) = mpsc::unbounded_channel();
+    let (disconnect_sender, mut disconnect_receiver) = mpsc::unbounded_channel();
+    let (observer_sender, mut observer_receiver) = mpsc::unbounded_channel();
+    let connection_observers: Vec<Receiver> = config.observers.clone();
+    let connection_probes = probes.clone();
+    let connection_offers = offers.clone();
+    let connection_receivers = receivers.clone();
     tokio::spawn(async move {
+        let mut next_id: u64 = 0;
         loop {
-            let (stream, address) = match listener.accept().await {
-                Ok(sa) => sa,
+            let (stream, address) = match listener::accept(&mut listener).await {
+                Ok(accepted) => accepted,
                 Err(err) => {
                     let _ = client_sender.send(Err(err));
                     return;
                 }
             };
 
-            let stream = match acceptor.accept(stream).await {
+            if let Err(err) = set_keepalive(&stream) {
+                log::warn!("{}: {}", address, err);
+            }
+
+            let mut stream = match acceptor.accept(stream).await {
                 Ok(stream) => stream,
                 Err(err) => {
                     log::error!("{}: TLS error: {}", address, err);
@@ -139,124 +741,680 @@ pub async fn run_server<'a>(
                 }
             };
 
-            let (sender, receiver) = mpsc::unbounded_channel();
+            // The verifier only lets in clients with a certificate, but a connection without one
+            // must never be mistaken for a receiver.
+            let fingerprint = match stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(get_cert_fingerprint)
+            {
+                Some(fingerprint) => fingerprint,
+                None => {
+                    log::error!("{}: no client certificate", address);
+                    continue;
+                }
+            };
+            let peer = peer_label(&connection_receivers.read().unwrap(), &fingerprint);
 
-            if client_sender.send(Ok(sender)).is_err() {
-                return;
-            }
+            let id = next_id;
+            next_id += 1;
 
+            let clients = client_sender.clone();
+            let acks = ack_sender.clone();
+            let feedback = feedback_sender.clone();
+            let disconnects = disconnect_sender.clone();
+            let observers = observer_sender.clone();
+            let probes = connection_probes.clone();
+            let offers = connection_offers.clone();
+            let observer = connection_observers
+                .iter()
+                .find(|observer| observer.fingerprint.as_ref() == Some(&fingerprint))
+                .map(|observer| observer.nick.clone().unwrap_or_else(|| peer.clone()));
             tokio::spawn(async move {
-                log::info!("{}: connected", address);
-                let message = server_handle_connection(stream, receiver)
+                let (hello, version) = match handshake(&mut stream).await {
+                    Ok(handshake) => handshake,
+                    Err(err) => {
+                        log::error!("{}: handshake failed: {:#}", peer, err);
+                        return;
+                    }
+                };
+                if let Purpose::FetchFile { id: offer } = hello.purpose {
+                    if let Err(err) = offers.serve(stream, offer, &fingerprint).await {
+                        log::warn!("{}: error sending file: {:#}", peer, err);
+                    }
+                    return;
+                }
+                if hello.purpose == Purpose::Observe {
+                    let name = match observer {
+                        Some(name) => name,
+                        None => {
+                            log::warn!("{}: not an observer, rejecting connection to observe", peer);
+                            return;
+                        }
+                    };
+                    log::info!("{}: observing from {} (evkvm {} on {})", name, address, hello.software_version, hello.hostname);
+                    let (sender, receiver) = mpsc::unbounded_channel();
+                    if observers.send(Observer { id, sender }).is_err() {
+                        return;
+                    }
+                    let message = server_handle_connection(stream, id, version, receiver, acks, feedback, probes)
+                        .await
+                        .err()
+                        .map(|err| format!(" ({})", err))
+                        .unwrap_or_else(String::new);
+                    log::info!("{}: stopped observing{}", name, message);
+                    let _ = disconnects.send(id);
+                    return;
+                }
+
+                log::info!(
+                    "{}: connected from {} (evkvm {} on {}, protocol {})",
+                    peer,
+                    address,
+                    hello.software_version,
+                    hello.hostname,
+                    version
+                );
+                let (sender, receiver) = mpsc::unbounded_channel();
+                if clients.send(Ok(Client { id, fingerprint, hello, version, sender })).is_err() {
+                    return;
+                }
+                let message = server_handle_connection(stream, id, version, receiver, acks, feedback, probes)
                     .await
                     .err()
                     .map(|err| format!(" ({})", err))
                     .unwrap_or_else(String::new);
-                log::info!("{}: disconnected{}", address, message);
+                log::info!("{}: disconnected{}", peer, message);
+                // Let the dispatcher drop the client right away instead of waiting for its next
+                // send to fail.
+                let _ = disconnects.send(id);
             });
         }
     });
 
-    let mut clients: Vec<UnboundedSender<Event>> = Vec::new();
-    let mut current = 0;
+    let mut registry = Registry::default();
+
+    // The focus request sent to the current client that hasn't been acknowledged yet.
+    let mut focus_id: u64 = 0;
+    let mut pending_focus: Option<PendingFocus> = None;
+
+    let mut draining: Option<Drain> = None;
+
+    let mut focus_state = new_focus_state(config, None);
+    // The profile switched to with `evkvm profile`, if any.
+    let mut profile: Option<String> = None;
+
+    let mut observers: Vec<Observer> = Vec::new();
+    let mut observed = Observed::default();
+
+    // The schedule rules that applied when they were last looked at, and when to look again.
+    let schedule_rules = config.schedule.clone();
+    let mut active_rules: Vec<usize> = Vec::new();
+    let mut next_schedule_check = time::Instant::now();
+
+    // Changes to the trusted receivers file take effect without asking for a reload.
+    let mut trusted_receivers = trust::FileWatch::new(&config.trusted_receivers_path);
+    let mut next_trust_check = time::Instant::now() + trust::WATCH_INTERVAL;
+
+    // The LEDs each receiver and this machine have set, and the focus they were last shown for.
+    let mut leds: HashMap<Focus, HashMap<(u16, u16), bool>> = HashMap::new();
+    let mut last_focus = Focus::Local;
 
-    let mut key_states: HashMap<_, _> = switch_keys
-        .iter()
-        .copied()
-        .map(|key| (key, false))
-        .collect();
     loop {
-        let mut swallow_input = false;
+        // Everything that changes focus or who is connected happens in the loop below, so looking
+        // once per turn catches all of it.
+        let now_observed = Observed::new(&receivers.read().unwrap(), &registry, focus_state.focus());
+        if now_observed != observed {
+            notify(&mut observers, &observed.changes(&now_observed));
+            observed = now_observed;
+        }
+
+        // With lazy grabbing, input goes to the sender straight from its devices while it has
+        // focus, and evkvm only writes it there itself while they are grabbed.
+        let grabbing = !lazy_grab || *focus_state.focus() != Focus::Local || draining.is_some();
+        reader_manager.set_grab(grabbing);
+        if !grabbing {
+            writer_manager.park();
+        }
+
+        if *focus_state.focus() != last_focus {
+            // Receivers can let their display sleep once they lose focus, for example.
+            if let Some(client) = registry.get(&last_focus) {
+                if client.hello.supports(net::FEATURE_FOCUS_LOST) {
+                    let _ = client.sender.send(Message::FocusLost);
+                }
+            }
+            last_focus = focus_state.focus().clone();
+            history.note(|| format!("focus moved to {}", focus_name(&receivers.read().unwrap(), &registry, &last_focus)));
+            for (&(device_id, code), &lit) in leds.get(&last_focus).into_iter().flatten() {
+                let led = Feedback::Led { code, lit };
+                if let Err(err) = reader_manager.feedback(LOCAL_FEEDBACK, device_id, led) {
+                    log::debug!("Error setting LEDs of device {}: {}", device_id, err);
+                }
+            }
+        }
+
+        let next_expiry = trust::next_expiry(&receivers.read().unwrap());
         tokio::select! {
-            event = reader_manager.read() => {
+            event = pipeline.read(&mut reader_manager) => {
                 let event = event?;
+                let event_time = net::timestamp();
+                history.event(&event, || "read".to_owned());
 
-                if let Event::Input {
-                    device_id,
-                    input: InputEvent::Key { direction, kind: KeyKind::Key(key) },
-                    syn: _
-                } = event {
-                    if let Some(state) = key_states.get_mut(&key) {
-                        *state = direction == Direction::Down;
-                        if key_states.iter().filter(|(_, state)| **state).count() == key_states.len() {
-                            swallow_input = true;
-
-                            let new_current = (current + 1) % (clients.len() + 1);
-
-                            for (other_key, _) in key_states.iter() {
-                                // On current client, release all currently pressed keys from the combo
-                                // NOTE: This will NOT release other keys that are not part of the combo
-                                let release_input = InputEvent::Key {
-                                    direction: Direction::Up,
-                                    kind: KeyKind::Key(*other_key),
-                                };
-                                let release_event = Event::Input {
-                                    device_id,
-                                    input: release_input,
-                                    syn: true,
-                                };
-                                if current == 0 {
-                                    writer_manager.write(release_event).await?;
-                                } else {
-                                    let idx = current - 1;
-                                    // We cannot remove broken client here, to not crash in next iteration,
-                                    // and it will be removed later one anyways, therefore we just ignore error here
-                                    let _ = clients[idx].send(release_event);
-                                }
+                // Virtual copies of our devices come and go with the devices, whichever machine
+                // has focus.
+                let device_event = !matches!(event, Event::Input { .. });
+                if device_event && mirror_local_devices {
+                    write_local(&mut writer_manager, event.clone()).await;
+                }
 
-                                // On new client, press all currently pressed modifier keys from the combo
-
-                                if other_key.is_modifier() {
-                                    let press_input = InputEvent::Key {
-                                        direction: Direction::Down,
-                                        kind: KeyKind::Key(*other_key),
-                                    };
-                                    if new_current == 0 {
-                                        let press_event = Event::Input {
-                                            device_id,
-                                            input: press_input,
-                                            syn: true,
-                                        };
-                                        writer_manager.write(press_event).await?
-                                    } else {
-                                        let press_event = Event::Input {
-                                            device_id,
-                                            input: press_input,
-                                            syn: true,
-                                        };
-                                        let idx = new_current - 1;
-                                        let _ = clients[idx].send(press_event);
-                                    }
+                let order: Vec<String> = registry
+                    .in_order(&receivers.read().unwrap())
+                    .into_iter()
+                    .map(|client| client.fingerprint.clone())
+                    .collect();
+                let order: Vec<&str> = order.iter().map(String::as_str).collect();
+                let media_receiver = media_keys_receiver
+                    .as_deref()
+                    .and_then(|nick| registry.find(&receivers.read().unwrap(), nick))
+                    .map(|client| client.fingerprint.clone());
+
+                let mut forward = None;
+                for action in focus_state.input(event, &order, media_receiver.as_deref(), std::time::Instant::now()) {
+                    match action {
+                        Action::Write(target, event) => {
+                            write_to_target(&target, event, &mut writer_manager, &registry, grabbing, &receivers, &mut history).await;
+                        }
+                        Action::Switch(new_focus) => {
+                            // Switching away gives up on a dropped client. Whatever it missed
+                            // is discarded.
+                            if draining.take().is_some() {
+                                log::info!("No longer waiting for dropped client");
+                            }
+
+                            log::info!("Switching to {}", focus_name(&receivers.read().unwrap(), &registry, &new_focus));
+
+                            // Blink once for the first receiver, twice for the second, and so on.
+                            // Receivers in cycle-order keep their place in it, whether or not
+                            // those before them are connected.
+                            if let (Some(led), Focus::Remote(fingerprint)) = (switch_indicator_led, &new_focus) {
+                                let slot = receivers
+                                    .read()
+                                    .unwrap()
+                                    .iter()
+                                    .find(|receiver| receiver.fingerprint.as_ref() == Some(fingerprint))
+                                    .and_then(|receiver| trust::cycle_slot(&config.cycle_order, receiver))
+                                    .or_else(|| order.iter().position(|other| *other == fingerprint.as_str()));
+                                if let Some(position) = slot {
+                                    reader_manager.blink_led(led.code(), position as u32 + 1);
                                 }
                             }
 
-                            current = new_current;
-                            log::info!("Switching to client {}", current);
+                            pending_focus = registry.get(&new_focus).map(|client| request_focus(client, &mut focus_id));
                         }
+                        Action::Forward { event, swallowed } => forward = Some((event, swallowed)),
+                        Action::Drop { .. } => {}
                     }
                 }
+                let (event, swallow_input) = match forward {
+                    Some(forward) => forward,
+                    None => continue,
+                };
 
-                if current != 0 {
-                    let idx = current - 1;
-                    if clients[idx].send(event.clone()).is_ok() {
+                if let Focus::Remote(fingerprint) = focus_state.focus().clone() {
+                    let message = Message::Event { event: event.clone(), time: Some(event_time) };
+                    if registry.get(focus_state.focus()).map(|client| client.sender.send(message).is_ok()).unwrap_or(false) {
+                        history.event(&event, || {
+                            format!("sent to {}", focus_name(&receivers.read().unwrap(), &registry, focus_state.focus()))
+                        });
+                        focus_state.delivered(&Focus::Remote(fingerprint), &event);
+                        if mirror_input && !swallow_input && !device_event {
+                            focus_state.delivered(&Focus::Local, &event);
+                            write_local(&mut writer_manager, event).await;
+                        }
                         continue;
                     }
 
-                    clients.remove(idx);
-                    current = 0;
-                    log::info!("Switching to client {}", current);
+                    match registry.remove(&fingerprint) {
+                        Some(client) => {
+                            forget_client(&client, &mut reader_manager, &mut leds, &mut focus_state, &mut pending_focus);
+                            draining = start_drain(&receivers.read().unwrap(), &client, reconnect_grace);
+                        }
+                        None => {
+                            focus_state.lost(&fingerprint);
+                            pending_focus = None;
+                        }
+                    }
+                    if draining.is_none() {
+                        log::info!("Switching to {}", focus_name(&receivers.read().unwrap(), &registry, focus_state.focus()));
+                    }
                 }
 
-                if !swallow_input {
-                    writer_manager.write(event).await?;
+                if let Some(drain) = &mut draining {
+                    if drain.events.len() < DRAIN_LIMIT {
+                        history.event(&event, || {
+                            format!("kept for {} to reconnect", peer_label(&receivers.read().unwrap(), &drain.fingerprint))
+                        });
+                        drain.events.push((event.clone(), event_time));
+                        if mirror_input && !swallow_input && !device_event {
+                            focus_state.delivered(&Focus::Local, &event);
+                            write_local(&mut writer_manager, event).await;
+                        }
+                        continue;
+                    }
+                    log::info!("Too many events for dropped client, switching to this machine");
+                    draining = None;
+                }
+
+                if !swallow_input && grabbing && !device_event {
+                    history.event(&event, || "written to this machine".to_owned());
+                    focus_state.delivered(&Focus::Local, &event);
+                    write_local(&mut writer_manager, event).await;
+                } else if swallow_input {
+                    history.event(&event, || "swallowed by the switch keys".to_owned());
                 }
             }
             sender = client_receiver.recv() => {
-                let sender = sender.unwrap()?;
+                // The accept loop only stops after repeated failures, and reports why.
+                let client = match sender {
+                    Some(Ok(client)) => client,
+                    Some(Err(err)) => return Err(Error::from(err).context("Failed to accept connections")),
+                    None => return Err(anyhow::anyhow!("Accept loop exited unexpectedly")),
+                };
+
+                // Trust may have been withdrawn or expired while the connection was being set up.
+                let trusted = receivers.read().unwrap().iter().any(|receiver| {
+                    receiver.fingerprint.as_ref() == Some(&client.fingerprint) && !trust::is_expired(receiver)
+                });
+                if !trusted {
+                    // Dropping the client closes its connection.
+                    log::warn!(
+                        "{} is no longer trusted, rejecting connection",
+                        peer_label(&receivers.read().unwrap(), &client.fingerprint)
+                    );
+                    continue;
+                }
+
+                // A receiver that is already connected may be left with a dead connection that
+                // hasn't timed out yet, e.g. after its network dropped.
+                let existing = registry.clients.contains_key(&client.fingerprint);
+                if existing && duplicate_connections == DuplicatePolicy::Reject {
+                    // Dropping the client closes its connection.
+                    log::warn!("{} is already connected, rejecting new connection", client_name(&receivers.read().unwrap(), &client));
+                    continue;
+                }
+
+                // Whatever an old connection of the receiver's was holding down went away with
+                // its devices.
+                focus_state.forget(&Focus::Remote(client.fingerprint.clone()));
+
+                // If the connection is already gone, its task will report the disconnect and
+                // nothing else needs to happen here.
                 for device in reader_manager.devices.values() {
-                    sender.send(Event::NewDevice(device.clone()))?;
+                    let _ = client.sender.send(Message::Event { event: Event::NewDevice(device.clone()), time: None });
+                }
+
+                let resumed = matches!(&draining, Some(drain) if drain.fingerprint == client.fingerprint);
+                if resumed {
+                    let target = Focus::Remote(client.fingerprint.clone());
+                    for (event, time) in draining.take().unwrap().events {
+                        // The devices announced above are the ones there are now, so devices
+                        // that came or went meanwhile are accounted for, and input from those that
+                        // went has no device to go to.
+                        match event {
+                            Event::Input { device_id, .. } if reader_manager.devices.contains_key(&device_id) => {}
+                            _ => continue,
+                        }
+                        focus_state.delivered(&target, &event);
+                        let _ = client.sender.send(Message::Event { event, time: Some(time) });
+                    }
+                }
+
+                let name = client_name(&receivers.read().unwrap(), &client);
+                let fingerprint = client.fingerprint.clone();
+                // The new connection takes the old one's place, and its focus along with it.
+                if registry.insert(client).is_some() {
+                    log::info!("{} reconnected, closing old connection", name);
+                }
+
+                if resumed {
+                    focus_state.set_focus(Focus::Remote(fingerprint.clone()));
+                    log::info!("{} reconnected, switching back to it", name);
+                }
+                if focus_state.is_focused(&fingerprint) {
+                    pending_focus = registry.get(focus_state.focus()).map(|client| request_focus(client, &mut focus_id));
+                }
+            }
+            observer = observer_receiver.recv() => {
+                // The accept loop holds on to a sender for as long as it runs.
+                if let Some(observer) = observer {
+                    let mut new = vec![observer];
+                    notify(&mut new, &observed.announce());
+                    observers.extend(new);
+                }
+            }
+            id = disconnect_receiver.recv() => {
+                if let Some(id) = id {
+                    observers.retain(|observer| observer.id != id);
+                    match registry.remove_connection(id) {
+                        Some(client) => {
+                            if forget_client(&client, &mut reader_manager, &mut leds, &mut focus_state, &mut pending_focus) {
+                                draining = start_drain(&receivers.read().unwrap(), &client, reconnect_grace);
+                                if draining.is_none() {
+                                    log::info!("Switching to {}", focus_name(&receivers.read().unwrap(), &registry, focus_state.focus()));
+                                }
+                            }
+                        }
+                        // A connection that was replaced may still have effects playing.
+                        None => reader_manager.end_feedback(id),
+                    }
+                }
+            }
+            Some((id, device_id, feedback)) = feedback_receiver.recv() => {
+                // Feedback from a connection that was replaced is dropped along with it.
+                if let Some(client) = registry.connection(id) {
+                    let from = Focus::Remote(client.fingerprint.clone());
+                    relay_feedback(&mut reader_manager, &mut leds, focus_state.focus(), from, id, device_id, feedback);
+                }
+            }
+            (device_id, feedback) = writer_manager.feedback() => {
+                relay_feedback(&mut reader_manager, &mut leds, focus_state.focus(), Focus::Local, LOCAL_FEEDBACK, device_id, feedback);
+            }
+            Some((connection, id)) = ack_receiver.recv() => {
+                // Acks for an earlier switch are stale, and acks from another connection, like one
+                // the receiver has since replaced, aren't for this one. Neither counts.
+                if pending_focus.map(|pending| (pending.connection, pending.id)) == Some((connection, id)) {
+                    pending_focus = None;
+                }
+            }
+            _ = time::sleep_until(pending_focus.map(|pending| pending.deadline).unwrap_or_else(time::Instant::now)), if pending_focus.is_some() => {
+                // The connection is probably dead but hasn't timed out yet. Take focus back rather
+                // than sending input nowhere.
+                log::warn!("{} did not acknowledge switch, switching to this machine", focus_name(&receivers.read().unwrap(), &registry, focus_state.focus()));
+                pending_focus = None;
+                // If it is only slow, what was pressed there before the switch is released once it
+                // catches up rather than left stuck down.
+                for action in focus_state.switch_to(Focus::Local) {
+                    if let Action::Write(target, event) = action {
+                        write_to_target(&target, event, &mut writer_manager, &registry, grabbing, &receivers, &mut history).await;
+                    }
+                }
+            }
+            _ = time::sleep_until(next_expiry.unwrap_or_else(time::Instant::now)), if next_expiry.is_some() => {
+                let expired: Vec<Receiver> = {
+                    let mut known = receivers.write().unwrap();
+                    let (expired, kept): (Vec<_>, Vec<_>) = known.drain(..).partition(trust::is_expired);
+                    *known = kept;
+                    expired
+                };
+                for receiver in expired {
+                    // It is no longer among the trusted receivers, so it is named from its own entry.
+                    let fingerprint = receiver.fingerprint.clone().unwrap_or_default();
+                    log::info!("Access for {} expired", peer_label(std::slice::from_ref(&receiver), &fingerprint));
+                    // Dropping a client closes its connection.
+                    let client = receiver.fingerprint.as_ref().and_then(|fingerprint| registry.remove(fingerprint));
+                    if let Some(client) = client {
+                        if forget_client(&client, &mut reader_manager, &mut leds, &mut focus_state, &mut pending_focus) {
+                            log::info!("Switching to {}", focus_name(&receivers.read().unwrap(), &registry, focus_state.focus()));
+                        }
+                    }
+                }
+            }
+            _ = time::sleep_until(next_trust_check) => {
+                next_trust_check = time::Instant::now() + trust::WATCH_INTERVAL;
+                if !trusted_receivers.changed() {
+                    continue;
+                }
+                // Like a reload, this leaves receivers no longer trusted connected.
+                match trust::all_receivers(config) {
+                    Ok(all) => {
+                        log::info!("{} changed, {} trusted receivers in total", config.trusted_receivers_path.display(), all.len());
+                        *receivers.write().unwrap() = all;
+                    }
+                    Err(err) => log::error!("Error reloading trusted receivers: {:#}", err),
+                }
+            }
+            Some((request, reply)) = control_calls.recv() => {
+                match request {
+                    ControlRequest::Latency { receiver, count } => {
+                        let client = registry.find(&receivers.read().unwrap(), &receiver);
+                        match client {
+                            Some(client) => {
+                                let sender = client.sender.clone();
+                                let probes = probes.clone();
+                                tokio::spawn(async move {
+                                    let _ = reply.send(measure_latency(sender, probes, count).await);
+                                });
+                            }
+                            None => {
+                                let _ = reply.send(ControlResponse::Error {
+                                    message: format!("Receiver \"{}\" is not connected", receiver),
+                                });
+                            }
+                        }
+                    }
+                    ControlRequest::Command { receiver, name } => {
+                        // Whether the receiver actually runs it is up to the receiver, which
+                        // logs what it does with it.
+                        let client = registry.find(&receivers.read().unwrap(), &receiver);
+                        let response = match client {
+                            Some(client) if !client.hello.supports(net::FEATURE_COMMANDS) => ControlResponse::Error {
+                                message: format!("Receiver \"{}\" doesn't support commands", receiver),
+                            },
+                            Some(client) if client.sender.send(Message::Command { name }).is_ok() => ControlResponse::Sent,
+                            _ => ControlResponse::Error {
+                                message: format!("Receiver \"{}\" is not connected", receiver),
+                            },
+                        };
+                        let _ = reply.send(response);
+                    }
+                    ControlRequest::SendFile { receiver, path } => {
+                        let client = match &receiver {
+                            Some(receiver) => registry.find(&receivers.read().unwrap(), receiver),
+                            None => registry.get(focus_state.focus()),
+                        };
+                        let response = match (client, receiver) {
+                            (Some(client), _) => match offer_file(client, &offers, path) {
+                                Ok(()) => ControlResponse::Sent,
+                                Err(err) => ControlResponse::Error { message: format!("{:#}", err) },
+                            },
+                            (None, Some(receiver)) => ControlResponse::Error {
+                                message: format!("Receiver \"{}\" is not connected", receiver),
+                            },
+                            (None, None) => ControlResponse::Error {
+                                message: "No receiver is focused".to_owned(),
+                            },
+                        };
+                        let _ = reply.send(response);
+                    }
+                    ControlRequest::Focus { receiver } => {
+                        let target = match &receiver {
+                            Some(receiver) => registry
+                                .find(&receivers.read().unwrap(), receiver)
+                                .map(|client| Focus::Remote(client.fingerprint.clone())),
+                            None => Some(Focus::Local),
+                        };
+                        let target = match target {
+                            Some(Focus::Remote(fingerprint)) if focus_state.is_blocked(&fingerprint) => {
+                                let message = format!("Receiver \"{}\" is blocked by the schedule", receiver.unwrap_or_default());
+                                let _ = reply.send(ControlResponse::Error { message });
+                                continue;
+                            }
+                            Some(target) => target,
+                            None => {
+                                let message = format!("Receiver \"{}\" is not connected", receiver.unwrap_or_default());
+                                let _ = reply.send(ControlResponse::Error { message });
+                                continue;
+                            }
+                        };
+                        for action in focus_state.switch_to(target) {
+                            match action {
+                                Action::Write(target, event) => {
+                                    write_to_target(&target, event, &mut writer_manager, &registry, grabbing, &receivers, &mut history).await;
+                                }
+                                Action::Switch(new_focus) => {
+                                    if draining.take().is_some() {
+                                        log::info!("No longer waiting for dropped client");
+                                    }
+                                    log::info!("Switching to {}", focus_name(&receivers.read().unwrap(), &registry, &new_focus));
+                                    pending_focus = registry.get(&new_focus).map(|client| request_focus(client, &mut focus_id));
+                                }
+                                Action::Forward { .. } | Action::Drop { .. } => {}
+                            }
+                        }
+                        let _ = reply.send(ControlResponse::Done);
+                    }
+                    ControlRequest::Profile { name } => {
+                        let found = match &name {
+                            Some(name) => match config.profiles.get(name) {
+                                Some(found) => Some(found),
+                                None => {
+                                    let message = format!("No profile named \"{}\"", name);
+                                    let _ = reply.send(ControlResponse::Error { message });
+                                    continue;
+                                }
+                            },
+                            None => None,
+                        };
+                        for action in focus_state.reconfigure(new_focus_state(config, found)) {
+                            if let Action::Write(target, event) = action {
+                                write_to_target(&target, event, &mut writer_manager, &registry, grabbing, &receivers, &mut history).await;
+                            }
+                        }
+                        match &name {
+                            Some(name) => log::info!("Switched to profile {}", name),
+                            None => log::info!("Switched back to the default profile"),
+                        }
+                        profile = name;
+                        let _ = reply.send(ControlResponse::Done);
+                    }
+                    ControlRequest::PauseDevice { id } | ControlRequest::ResumeDevice { id } => {
+                        let pause = matches!(request, ControlRequest::PauseDevice { .. });
+                        if !reader_manager.pause(id, pause) {
+                            let message = format!("No device at /dev/input/event{}", id);
+                            let _ = reply.send(ControlResponse::Error { message });
+                            continue;
+                        }
+                        if pause {
+                            log::info!("Paused /dev/input/event{}, it works on this machine now", id);
+                        } else {
+                            log::info!("Resumed /dev/input/event{}", id);
+                        }
+                        let _ = reply.send(ControlResponse::Done);
+                    }
+                    ControlRequest::DebugDump => {
+                        let _ = reply.send(ControlResponse::History { entries: history.dump() });
+                    }
+                    ControlRequest::Reload => {
+                        // Receivers no longer trusted stay connected until they disconnect.
+                        let response = match trust::all_receivers(config) {
+                            Ok(all) => {
+                                log::info!("Reloaded trusted receivers, {} in total", all.len());
+                                *receivers.write().unwrap() = all;
+                                ControlResponse::Done
+                            }
+                            Err(err) => ControlResponse::Error { message: format!("{:#}", err) },
+                        };
+                        let _ = reply.send(response);
+                    }
+                    ControlRequest::Status => {
+                        let known = receivers.read().unwrap();
+                        let status = Status {
+                            schema_version: ipc::SCHEMA_VERSION,
+                            receivers: registry
+                                .in_order(&known)
+                                .into_iter()
+                                .map(|client| ReceiverStatus {
+                                    name: client_name(&known, client),
+                                    fingerprint: client.fingerprint.clone(),
+                                    hostname: client.hello.hostname.clone(),
+                                    protocol_version: client.version,
+                                    focused: focus_state.is_focused(&client.fingerprint),
+                                })
+                                .collect(),
+                            focus: registry.get(focus_state.focus()).map(|client| client_name(&known, client)),
+                            profile: profile.clone(),
+                            ungrabbed_devices: reader_manager
+                                .ungrabbed_devices()
+                                .into_iter()
+                                .map(|device| UngrabbedDevice {
+                                    path: device.path.display().to_string(),
+                                    name: device.name,
+                                    holders: device.holders,
+                                })
+                                .collect(),
+                            paused_devices: reader_manager
+                                .paused_devices()
+                                .into_iter()
+                                .map(|(id, name)| PausedDevice { id, name })
+                                .collect(),
+                        };
+                        let _ = reply.send(ControlResponse::Status(status));
+                    }
+                }
+            }
+            _ = time::sleep_until(next_schedule_check), if !schedule_rules.is_empty() => {
+                let now = schedule::local_now();
+                next_schedule_check = time::Instant::now() + schedule::until_next_change(&schedule_rules, now);
+                let active = schedule::active(&schedule_rules, now);
+                if active == active_rules {
+                    continue;
+                }
+
+                for &index in active.iter().filter(|index| !active_rules.contains(*index)) {
+                    log::info!("Schedule rule {} now applies", schedule::describe(&schedule_rules[index]));
+                }
+                for &index in active_rules.iter().filter(|index| !active.contains(*index)) {
+                    log::info!("Schedule rule {} no longer applies", schedule::describe(&schedule_rules[index]));
+                }
+
+                let known = receivers.read().unwrap().clone();
+                let blocked: HashSet<String> = active
+                    .iter()
+                    .flat_map(|&index| &schedule_rules[index].blocked_receivers)
+                    .filter_map(|nick| {
+                        known
+                            .iter()
+                            .find(|receiver| receiver.nick.as_deref() == Some(nick.as_str()))
+                            .and_then(|receiver| receiver.fingerprint.clone())
+                            .or_else(|| registry.find(&known, nick).map(|client| client.fingerprint.clone()))
+                    })
+                    .collect();
+                // Only a rule that just started moves focus, and the last one listed wins.
+                let default_focus = active
+                    .iter()
+                    .filter(|index| !active_rules.contains(*index))
+                    .filter_map(|&index| schedule_rules[index].default_focus.as_deref())
+                    .next_back()
+                    .and_then(|nick| registry.find(&known, nick))
+                    .map(|client| Focus::Remote(client.fingerprint.clone()));
+                active_rules = active;
+
+                for action in focus_state.apply_rules(blocked, default_focus) {
+                    match action {
+                        Action::Write(target, event) => {
+                            write_to_target(&target, event, &mut writer_manager, &registry, grabbing, &receivers, &mut history).await;
+                        }
+                        Action::Switch(new_focus) => {
+                            if draining.take().is_some() {
+                                log::info!("No longer waiting for dropped client");
+                            }
+                            log::info!("Switching to {}", focus_name(&known, &registry, &new_focus));
+                            pending_focus = registry.get(&new_focus).map(|client| request_focus(client, &mut focus_id));
+                        }
+                        Action::Forward { .. } | Action::Drop { .. } => {}
+                    }
+                }
+            }
+            _ = time::sleep_until(draining.as_ref().map(|drain| drain.deadline).unwrap_or_else(time::Instant::now)), if draining.is_some() => {
+                if let Some(drain) = draining.take() {
+                    log::info!(
+                        "{} did not reconnect, switching to this machine",
+                        peer_label(&receivers.read().unwrap(), &drain.fingerprint)
+                    );
                 }
-                clients.push(sender);
             }
         }
     }
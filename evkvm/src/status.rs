@@ -0,0 +1,54 @@
+use anyhow::Error;
+use std::path::Path;
+
+use crate::control::{self, ControlRequest, ControlResponse};
+
+// Ask the running daemon what it is up to and print it, either for people or, with `json`, as the
+// Status from the ipc crate for other programs.
+pub async fn print_status(control_socket_path: &Path, json: bool) -> Result<(), Error> {
+    let status = match control::request(control_socket_path, &ControlRequest::Status).await? {
+        ControlResponse::Status(status) => status,
+        ControlResponse::Error { message } => return Err(anyhow::anyhow!(message)),
+        response => return Err(anyhow::anyhow!("Unexpected response from evkvm: {:?}", response)),
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(());
+    }
+
+    println!("focus: {}", status.focus.as_deref().unwrap_or("this machine"));
+    if let Some(profile) = &status.profile {
+        println!("profile: {}", profile);
+    }
+
+    println!("connected receivers:");
+    if status.receivers.is_empty() {
+        println!("    none");
+    }
+    for receiver in &status.receivers {
+        if receiver.hostname.is_empty() || receiver.hostname == receiver.name {
+            println!("    {} (protocol {})", receiver.name, receiver.protocol_version);
+        } else {
+            println!("    {} on {} (protocol {})", receiver.name, receiver.hostname, receiver.protocol_version);
+        }
+    }
+
+    if !status.ungrabbed_devices.is_empty() {
+        println!("devices grabbed by another process, not forwarded:");
+        for device in &status.ungrabbed_devices {
+            println!("    {}: \"{}\"", device.path, device.name);
+            if !device.holders.is_empty() {
+                println!("        open in {}", device.holders.join(", "));
+            }
+        }
+    }
+
+    if !status.paused_devices.is_empty() {
+        println!("paused devices, working on this machine:");
+        for device in &status.paused_devices {
+            println!("    /dev/input/event{}: \"{}\"", device.id, device.name);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,168 @@
+use anyhow::{Context, Error};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::fs::{self, File};
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time;
+
+use crate::control::{self, ControlRequest, ControlResponse};
+
+// Files are offered to a receiver over its connection, and the receiver fetches the ones it wants
+// over a connection of its own, so that a large file doesn't hold up input.
+
+// How long a receiver has to start fetching a file after it was offered.
+const OFFER_TIMEOUT: Duration = Duration::from_secs(60);
+const CHUNK_SIZE: usize = 64 * 1024;
+
+struct Offer {
+    fingerprint: String,
+    path: PathBuf,
+    size: u64,
+}
+
+#[derive(Default)]
+pub struct Offers {
+    next_id: AtomicU64,
+    offers: Mutex<HashMap<u64, Offer>>,
+}
+
+impl Offers {
+    // Remember a file offered to the receiver with the given fingerprint, and return the id it
+    // can fetch it by.
+    pub fn add(self: &Arc<Self>, fingerprint: String, path: PathBuf, size: u64) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.offers.lock().unwrap().insert(id, Offer { fingerprint, path, size });
+
+        let offers = self.clone();
+        tokio::spawn(async move {
+            time::sleep(OFFER_TIMEOUT).await;
+            offers.offers.lock().unwrap().remove(&id);
+        });
+        id
+    }
+
+    // Write an offered file to the receiver that asked for it. Each offer can be fetched once.
+    pub async fn serve<W>(&self, mut writer: W, id: u64, fingerprint: &str) -> Result<(), Error>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let offer = self.offers.lock().unwrap().remove(&id);
+        let offer = match offer {
+            Some(offer) if offer.fingerprint == fingerprint => offer,
+            _ => return Err(anyhow::anyhow!("Requested file {} was not offered", id)),
+        };
+
+        let file = File::open(&offer.path)
+            .await
+            .with_context(|| format!("Failed to open {}", offer.path.display()))?;
+        // Only send as much as was offered, in case the file grew since.
+        let sent = io::copy(&mut file.take(offer.size), &mut writer).await?;
+        writer.shutdown().await?;
+        if sent != offer.size {
+            return Err(anyhow::anyhow!("{} shrank while it was being sent", offer.path.display()));
+        }
+
+        log::info!("Sent {} ({} bytes)", offer.path.display(), offer.size);
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Downloads {
+    pub path: PathBuf,
+    pub max_size: u64,
+}
+
+// The name to save an offered file as, without any directories the sender may have put in it.
+pub fn file_name(name: &str) -> Option<&str> {
+    Path::new(name).file_name().and_then(|name| name.to_str())
+}
+
+// `name` in `dir`, or if that is taken, the first of "name (1)", "name (2)" and so on that isn't.
+fn unused_path(dir: &Path, name: &str) -> PathBuf {
+    let path = dir.join(name);
+    if !path.exists() {
+        return path;
+    }
+
+    let name = Path::new(name);
+    let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = name
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| dir.join(format!("{} ({}){}", stem, n, extension)))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+// Save `size` bytes read from `reader` as `name` in the downloads directory, and return where
+// they ended up. Nothing is left behind if the transfer fails.
+pub async fn receive<R>(reader: R, downloads: &Downloads, name: &str, size: u64) -> Result<PathBuf, Error>
+where
+    R: AsyncRead + Unpin,
+{
+    fs::create_dir_all(&downloads.path)
+        .await
+        .with_context(|| format!("Failed to create {}", downloads.path.display()))?;
+
+    let partial = downloads.path.join(format!(".{}.part", name));
+    if let Err(err) = receive_into(reader, &partial, name, size).await {
+        let _ = fs::remove_file(&partial).await;
+        return Err(err);
+    }
+
+    let path = unused_path(&downloads.path, name);
+    fs::rename(&partial, &path).await?;
+    Ok(path)
+}
+
+async fn receive_into<R>(mut reader: R, path: &Path, name: &str, size: u64) -> Result<(), Error>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut file = File::create(path)
+        .await
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut received: u64 = 0;
+    let mut reported: u64 = 0;
+    while received < size {
+        let wanted = (size - received).min(CHUNK_SIZE as u64) as usize;
+        let count = time::timeout(net::MESSAGE_TIMEOUT, reader.read(&mut buffer[..wanted]))
+            .await
+            .context("Read timed out")??;
+        if count == 0 {
+            return Err(anyhow::anyhow!("Connection closed after {} of {} bytes", received, size));
+        }
+        file.write_all(&buffer[..count]).await?;
+        received += count as u64;
+
+        let percent = received * 100 / size;
+        if percent >= reported + 10 {
+            reported = percent - percent % 10;
+            log::info!("Receiving {}: {}%", name, reported);
+        }
+    }
+
+    file.sync_all().await?;
+    Ok(())
+}
+
+// Ask the running daemon to offer a file to a receiver, or to the focused one if none is given.
+pub async fn send_file(control_socket_path: &Path, nick: Option<String>, path: &Path) -> Result<(), Error> {
+    // The daemon doesn't share our working directory.
+    let path = std::fs::canonicalize(path)
+        .with_context(|| format!("Failed to find {}", path.display()))?;
+    let request = ControlRequest::SendFile { receiver: nick, path };
+    match control::request(control_socket_path, &request).await? {
+        ControlResponse::Sent => Ok(()),
+        ControlResponse::Error { message } => Err(anyhow::anyhow!(message)),
+        response => Err(anyhow::anyhow!("Unexpected response from evkvm: {:?}", response)),
+    }
+}
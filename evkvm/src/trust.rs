@@ -0,0 +1,165 @@
+use anyhow::{Context, Error};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time;
+
+use crate::common::write_file_atomically;
+use crate::config::{Config, Receiver, Sender};
+use crate::control::{self, ControlRequest, ControlResponse};
+
+// Receivers added with `evkvm trust` are kept in a file of their own instead of config.toml, so
+// that evkvm never rewrites a file people edit by hand. The daemon reads it on startup, whenever
+// it changes, and whenever it is asked to reload. Senders can be kept in a file of their own too,
+// which is read the same way, so that config.toml holds nothing that says who is trusted.
+
+// How often the trust files are looked at for changes.
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Deserialize, Default)]
+struct TrustFile {
+    #[serde(default)]
+    receivers: Vec<Receiver>,
+}
+
+#[derive(Deserialize, Default)]
+struct SendersFile {
+    #[serde(default)]
+    senders: Vec<Sender>,
+}
+
+// Tells when a file changed, appeared or went away, going by its modification time.
+pub struct FileWatch {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl FileWatch {
+    pub fn new(path: &Path) -> Self {
+        FileWatch { path: path.to_owned(), modified: modified(path) }
+    }
+
+    // Whether the file is any different than when this was last called.
+    pub fn changed(&mut self) -> bool {
+        let modified = modified(&self.path);
+        let changed = modified != self.modified;
+        self.modified = modified;
+        changed
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn is_expired(receiver: &Receiver) -> bool {
+    receiver.expires.map(|expires| expires <= now()).unwrap_or(false)
+}
+
+// When the first of the receivers that are only trusted for a while stops being trusted.
+pub fn next_expiry(receivers: &[Receiver]) -> Option<time::Instant> {
+    let now = now();
+    receivers
+        .iter()
+        .filter_map(|receiver| receiver.expires)
+        .min()
+        .map(|expires| time::Instant::now() + Duration::from_secs(expires.saturating_sub(now)))
+}
+
+// Parse a duration like "90s", "15m", "2h", or "7d".
+pub fn parse_ttl(ttl: &str) -> Result<Duration, String> {
+    let split = ttl.find(|c: char| !c.is_ascii_digit()).unwrap_or(ttl.len());
+    let (count, unit) = ttl.split_at(split);
+    let count: u64 = count.parse().map_err(|_| format!("Invalid duration \"{}\"", ttl))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("Invalid duration \"{}\"; use a unit of s, m, h, or d", ttl)),
+    };
+    Ok(Duration::from_secs(count * seconds))
+}
+
+fn load_file(path: &Path) -> Result<TrustFile, Error> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => toml::from_str(&contents)
+            .with_context(|| format!("Trusted receivers at {} could not be parsed", path.display())),
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Ok(TrustFile::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// The receivers from the config followed by the ones added with `evkvm trust`, leaving out
+// those that have expired. Those named in cycle-order come first, in that order, since this is
+// also the order switching goes through them.
+pub fn all_receivers(config: &Config) -> Result<Vec<Receiver>, Error> {
+    let mut receivers = config.receivers.clone();
+    receivers.extend(load_file(&config.trusted_receivers_path)?.receivers);
+    receivers.retain(|receiver| !is_expired(receiver));
+    receivers.sort_by_key(|receiver| cycle_slot(&config.cycle_order, receiver).unwrap_or(config.cycle_order.len()));
+    Ok(receivers)
+}
+
+// Where `receiver` is in `cycle_order`, if it is there.
+pub fn cycle_slot(cycle_order: &[String], receiver: &Receiver) -> Option<usize> {
+    let nick = receiver.nick.as_ref()?;
+    cycle_order.iter().position(|other| other == nick)
+}
+
+// The senders from the config followed by the ones in the file at `path`, which may not exist.
+// The daemon's client side watches that file without holding on to the whole config.
+pub fn all_senders(config_senders: &[Sender], path: &Path) -> Result<Vec<Sender>, Error> {
+    let mut senders = config_senders.to_vec();
+    match std::fs::read_to_string(path) {
+        Ok(contents) => {
+            let file: SendersFile = toml::from_str(&contents)
+                .with_context(|| format!("Trusted senders at {} could not be parsed", path.display()))?;
+            senders.extend(file.senders);
+        }
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    Ok(senders)
+}
+
+// Add a receiver to the trusted receivers, replacing any with the same fingerprint, and have
+// the running daemon pick it up. With a `ttl`, the receiver is only trusted for that long.
+pub async fn trust(
+    config: &Config,
+    fingerprint: String,
+    nick: Option<String>,
+    ttl: Option<Duration>,
+) -> Result<(), Error> {
+    let fingerprint = fingerprint.to_lowercase();
+    if fingerprint.len() != 64 || !fingerprint.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow::anyhow!("Malformed fingerprint; expected 64 hex digits"));
+    }
+
+    let path = &config.trusted_receivers_path;
+    let mut file = load_file(path)?;
+    file.receivers.retain(|receiver| {
+        receiver.fingerprint.as_deref() != Some(&fingerprint[..]) && !is_expired(receiver)
+    });
+    let expires = ttl.map(|ttl| now() + ttl.as_secs());
+    file.receivers.push(Receiver { nick, fingerprint: Some(fingerprint), expires });
+
+    let contents = toml::to_string(&file)?;
+    write_file_atomically(path, contents.as_bytes(), 0o644, |_| Ok(()))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    match control::request(&config.control_socket_path, &ControlRequest::Reload).await {
+        Ok(ControlResponse::Done) => println!("Trusted; evkvm accepts the receiver now"),
+        Ok(ControlResponse::Error { message }) => return Err(anyhow::anyhow!(message)),
+        Ok(response) => return Err(anyhow::anyhow!("Unexpected response from evkvm: {:?}", response)),
+        Err(_) => println!("Trusted; evkvm isn't running, and will accept the receiver once started"),
+    }
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// A receiver in a container only gets its config from a read-only mount and keeps everything else
+// in the directory given with --state-dir, as example/container sets it up. The first test checks
+// that evkvm looks for its state there, using the example's own config. The second one runs the
+// example with Docker Compose and waits for the receiver to come up, which needs Docker,
+// /dev/uinput and network access to build the image, so it only runs with EVKVM_CONTAINER_TEST
+// set: `EVKVM_CONTAINER_TEST=1 cargo test -p evkvm --test container`.
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+fn example() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../example/container")
+}
+
+#[test]
+fn state_dir_holds_state() {
+    let state_dir = std::env::temp_dir().join(format!("evkvm-test-state-{}", std::process::id()));
+    let output = Command::new(env!("CARGO_BIN_EXE_evkvm"))
+        .arg("--config-path")
+        .arg(example().join("config.toml"))
+        .arg("--state-dir")
+        .arg(&state_dir)
+        .arg("fingerprint")
+        .output()
+        .expect("Failed to run evkvm");
+
+    // There is no identity yet, and evkvm says where it looked for one.
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let identity_path = state_dir.join("identity.pem");
+    assert!(
+        stderr.contains(&identity_path.display().to_string()),
+        "Didn't look for the identity in the state directory: {}",
+        stderr,
+    );
+}
+
+fn compose(args: &[&str]) -> std::process::Output {
+    Command::new("docker")
+        .arg("compose")
+        .arg("--project-directory")
+        .arg(example())
+        .args(args)
+        .output()
+        .expect("Failed to run docker compose")
+}
+
+#[test]
+fn compose_example_starts() {
+    if std::env::var_os("EVKVM_CONTAINER_TEST").is_none() {
+        eprintln!("Skipping, EVKVM_CONTAINER_TEST is not set");
+        return;
+    }
+
+    let up = compose(&["up", "--build", "--detach"]);
+    assert!(up.status.success(), "docker compose up failed: {}", String::from_utf8_lossy(&up.stderr));
+
+    // A receiver that made it through its startup checks generates an identity and logs its
+    // fingerprint, then keeps trying to reach the example's sender, which isn't there.
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    let result = loop {
+        let logs = compose(&["logs", "evkvm"]);
+        let logs = String::from_utf8_lossy(&logs.stdout).into_owned() + &String::from_utf8_lossy(&logs.stderr);
+        if logs.contains("This device has fingerprint") {
+            break Ok(());
+        }
+        if Instant::now() > deadline {
+            break Err(logs);
+        }
+        thread::sleep(Duration::from_secs(1));
+    };
+
+    compose(&["down", "--volumes"]);
+    if let Err(logs) = result {
+        panic!("The receiver didn't start: {}", logs);
+    }
+}
@@ -1,14 +1,29 @@
+#[cfg(feature = "libevdev")]
 use bindgen::{Builder, CargoCallbacks};
+#[cfg(feature = "libevdev")]
 use pkg_config::Config;
 use std::env;
+#[cfg(feature = "libevdev")]
 use std::path::PathBuf;
 
 fn main() {
     match env::var("CARGO_CFG_TARGET_OS").unwrap().as_str() {
-        "linux" => {}
+        "linux" | "android" | "freebsd" => {}
         _ => panic!("Unsupported target OS"),
     }
 
+    // There's no libevdev to link against on Android.
+    #[cfg(feature = "libevdev")]
+    if env::var("CARGO_CFG_TARGET_OS").unwrap() == "android" {
+        panic!("libevdev isn't available on Android; build with --no-default-features");
+    }
+
+    #[cfg(feature = "libevdev")]
+    generate_bindings();
+}
+
+#[cfg(feature = "libevdev")]
+fn generate_bindings() {
     println!("cargo:rerun-if-changed=glue/glue.h");
 
     let library = Config::new()
@@ -1,10 +1,47 @@
+// Declare a fieldless enum along with the names of all of its variants and a list of them, in
+// declaration order. The order matters: it is how variants are encoded on the wire.
+macro_rules! listed_enum {
+    ($(#[$meta:meta])* pub enum $name:ident { $($variant:ident,)* }) => {
+        $(#[$meta])*
+        pub enum $name {
+            $($variant,)*
+        }
+
+        impl $name {
+            pub(crate) const VARIANTS: &'static [&'static str] = &[$(stringify!($variant),)*];
+            pub(crate) const ALL: &'static [$name] = &[$($name::$variant,)*];
+        }
+    };
+    // An enum whose last variant holds the raw code of anything the named variants don't cover.
+    // VARIANTS and ALL only list the named ones.
+    ($(#[$meta:meta])* pub enum $name:ident { $($variant:ident,)* #[fallback] $fallback:ident(u16), }) => {
+        $(#[$meta])*
+        pub enum $name {
+            $($variant,)*
+            $fallback(u16),
+        }
+
+        impl $name {
+            pub(crate) const VARIANTS: &'static [&'static str] = &[$(stringify!($variant),)*];
+            pub(crate) const ALL: &'static [$name] = &[$($name::$variant,)*];
+        }
+    };
+}
+
 mod button;
 mod key;
+mod names;
+mod normalized;
 
 pub use button::Button;
 pub use key::Key;
+pub use normalized::NormalizedEvent;
 
-use serde::{Deserialize, Serialize};
+use serde::de::{self, EnumAccess, VariantAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Event {
@@ -23,11 +60,21 @@ pub enum InputEvent {
 pub struct Device {
     pub id: u16,
     pub name: String,
+    // Identifiers the sender's kernel gives the device, empty if it has none. `uniq` is usually a
+    // serial number or Bluetooth address, and `phys` says where the device is attached.
+    pub uniq: String,
+    pub phys: String,
     pub vendor: u16,
     pub product: u16,
     pub bustype: u16,
     pub version: u16,
     pub capabilities: Vec<Capability>,
+    // INPUT_PROP_* codes, which tell libinput things like whether a touchpad is a clickpad.
+    pub properties: Vec<u16>,
+    // What is currently held down and lit up on the device, so that a virtual copy of it can start
+    // out in the same state.
+    pub held_keys: Vec<KeyKind>,
+    pub lit_leds: Vec<u16>,
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
@@ -47,7 +94,62 @@ pub struct AbsInfo {
     pub resolution: i32,
 }
 
+// A request made of a virtual device's force feedback by a program using it, like a game making a
+// gamepad rumble, to be passed back to the device it is a copy of. Effect ids are the virtual
+// device's.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Feedback {
+    // Store `effect` under `id`, replacing the one there if any.
+    Upload { id: i16, effect: Effect },
+    Erase { id: i16 },
+    // An EV_FF event: play the effect with the id in `code` `value` times, or stop it with 0, or
+    // set FF_GAIN or FF_AUTOCENTER.
+    Play { code: u16, value: i32 },
+    // An LED was turned on or off, like Caps Lock's.
+    Led { code: u16, lit: bool },
+}
+
+// A force feedback effect, as in linux/input.h. Custom periodic waveforms aren't supported.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Effect {
+    pub kind: EffectKind,
+    pub direction: u16,
+    pub trigger_button: u16,
+    pub trigger_interval: u16,
+    // How long the effect plays and how long to wait before playing it, in milliseconds.
+    pub replay_length: u16,
+    pub replay_delay: u16,
+}
+
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum EffectKind {
+    Rumble { strong_magnitude: u16, weak_magnitude: u16 },
+    Periodic { waveform: u16, period: u16, magnitude: i16, offset: i16, phase: u16, envelope: Envelope },
+    Constant { level: i16, envelope: Envelope },
+    Ramp { start_level: i16, end_level: i16, envelope: Envelope },
+    // FF_SPRING, FF_FRICTION, FF_DAMPER or FF_INERTIA, with a condition for each axis.
+    Condition { type_: u16, axes: [Condition; 2] },
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Envelope {
+    pub attack_length: u16,
+    pub attack_level: u16,
+    pub fade_length: u16,
+    pub fade_level: u16,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Condition {
+    pub right_saturation: u16,
+    pub left_saturation: u16,
+    pub right_coeff: i16,
+    pub left_coeff: i16,
+    pub deadband: u16,
+    pub center: i16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Axis {
     X,
     Y,
@@ -59,8 +161,119 @@ pub enum Direction {
     Down, // The key is pressed.
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Hash)]
 pub enum KeyKind {
     Key(Key),
     Button(Button),
 }
+
+impl KeyKind {
+    pub fn is_modifier(&self) -> bool {
+        match self {
+            KeyKind::Key(key) => key.is_modifier(),
+            KeyKind::Button(_) => false,
+        }
+    }
+}
+
+impl FromStr for KeyKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        // Some names, like "Left" or "A", are both keys and buttons. Those are taken to mean keys
+        // unless given with evdev's BTN_ prefix.
+        let has_prefix = |prefix: &str| {
+            name.get(..prefix.len())
+                .map(|start| start.eq_ignore_ascii_case(prefix))
+                .unwrap_or(false)
+        };
+        if has_prefix("BTN_") {
+            return name.parse().map(KeyKind::Button);
+        }
+        if has_prefix("KEY_") {
+            return name.parse().map(KeyKind::Key);
+        }
+
+        match name.parse::<Key>() {
+            Ok(key) => Ok(KeyKind::Key(key)),
+            Err(key_err) => match name.parse::<Button>() {
+                Ok(button) => Ok(KeyKind::Button(button)),
+                Err(_) => Err(key_err),
+            },
+        }
+    }
+}
+
+struct KeyKindVisitor;
+
+impl<'de> Visitor<'de> for KeyKindVisitor {
+    type Value = KeyKind;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a key or button")
+    }
+
+    fn visit_enum<A>(self, data: A) -> Result<KeyKind, A::Error>
+    where
+        A: EnumAccess<'de>,
+    {
+        let (index, variant): (u32, _) = data.variant()?;
+        match index {
+            0 => variant.newtype_variant().map(KeyKind::Key),
+            1 => variant.newtype_variant().map(KeyKind::Button),
+            _ => Err(de::Error::invalid_value(de::Unexpected::Unsigned(index as u64), &self)),
+        }
+    }
+}
+
+// Configs can give a key by name, or by its evdev code, which doesn't depend on what the key is
+// called or on the keyboard layout.
+struct KeyKindNameVisitor;
+
+impl<'de> Visitor<'de> for KeyKindNameVisitor {
+    type Value = KeyKind;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a key or button name, or an evdev key code")
+    }
+
+    fn visit_str<E>(self, name: &str) -> Result<KeyKind, E>
+    where
+        E: de::Error,
+    {
+        name.parse().map_err(de::Error::custom)
+    }
+
+    fn visit_u64<E>(self, code: u64) -> Result<KeyKind, E>
+    where
+        E: de::Error,
+    {
+        u16::try_from(code)
+            .ok()
+            .and_then(KeyKind::from_raw)
+            .ok_or_else(|| de::Error::custom(format!("No key or button has evdev code {}", code)))
+    }
+
+    fn visit_i64<E>(self, code: i64) -> Result<KeyKind, E>
+    where
+        E: de::Error,
+    {
+        match u64::try_from(code) {
+            Ok(code) => self.visit_u64(code),
+            Err(_) => Err(de::Error::invalid_value(de::Unexpected::Signed(code), &self)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyKind {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(KeyKindNameVisitor)
+        } else {
+            deserializer.deserialize_enum("KeyKind", &["Key", "Button"], KeyKindVisitor)
+        }
+    }
+}
@@ -1,6 +1,9 @@
-use serde::{Deserialize, Serialize};
+use crate::event::names::{parse_name, IndexVisitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::str::FromStr;
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+listed_enum! {
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize)]
 pub enum Button {
     A,
     B,
@@ -122,3 +125,66 @@ pub enum Button {
     Y,
     Z,
 }
+}
+
+// Common names for buttons that differ from the variant names, in normalized form (lowercase
+// letters and digits only).
+const ALIASES: &[(&str, Button)] = &[
+    ("mouseleft", Button::Left),
+    ("leftmouse", Button::Left),
+    ("leftmousebutton", Button::Left),
+    ("lmb", Button::Left),
+    ("mouseright", Button::Right),
+    ("rightmouse", Button::Right),
+    ("rightmousebutton", Button::Right),
+    ("rmb", Button::Right),
+    ("mousemiddle", Button::Middle),
+    ("middlemouse", Button::Middle),
+    ("middlemousebutton", Button::Middle),
+    ("mmb", Button::Middle),
+    ("mouse4", Button::Side),
+    ("mouse5", Button::Extra),
+];
+
+impl FromStr for Button {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        parse_name(name, "button", "BTN_", Button::VARIANTS, Button::ALL, ALIASES)
+    }
+}
+
+impl<'de> Deserialize<'de> for Button {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            let name = String::deserialize(deserializer)?;
+            name.parse().map_err(serde::de::Error::custom)
+        } else {
+            let visitor = IndexVisitor {
+                name: "Button",
+                all: Button::ALL,
+                fallback: None,
+            };
+            deserializer.deserialize_enum("Button", Button::VARIANTS, visitor)
+        }
+    }
+}
+
+impl Button {
+    // The tools a graphics tablet reports coming into range, as opposed to buttons on them.
+    pub fn is_tablet_tool(&self) -> bool {
+        matches!(
+            self,
+            Button::ToolAirbrush
+                | Button::ToolBrush
+                | Button::ToolLens
+                | Button::ToolMouse
+                | Button::ToolPen
+                | Button::ToolPencil
+                | Button::ToolRubber
+        )
+    }
+}
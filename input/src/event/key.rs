@@ -1,6 +1,9 @@
-use serde::{Deserialize, Serialize};
+use crate::event::names::{parse_name, IndexVisitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::str::FromStr;
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+listed_enum! {
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize)]
 pub enum Key {
     A,
     Ab,
@@ -490,22 +493,186 @@ pub enum Key {
     ZoomIn,
     ZoomOut,
     ZoomReset,
+    // Added later, after the others so that those keep their bincode indices. Receivers older
+    // than protocol version 13 don't know them.
+    AutopilotEngageToggle,
+    BrightnessMenu,
+    ClearvuSonar,
+    Dictate,
+    DualRangeRadar,
+    EmojiPicker,
+    FishingChart,
+    FnRightShift,
+    HangupPhone,
+    LinkPhone,
+    MarkWaypoint,
+    NavChart,
+    NavInfo,
+    NextElement,
+    NotificationCenter,
+    PickupPhone,
+    PreviousElement,
+    RadarOverlay,
+    RefreshRateToggle,
+    SidevuSonar,
+    SingleRangeRadar,
+    Sos,
+    TraditionalSonar,
+    // Any other key, by evdev code. Also unknown to receivers older than protocol version 13.
+    #[fallback]
+    Other(u16),
+}
 }
 
-impl Key {
-    pub fn is_modifier(self: &Self) -> bool {
-        match self {
-            &Key::Fn |
-            &Key::LeftAlt |
-            &Key::LeftCtrl |
-            &Key::LeftMeta |
-            &Key::LeftShift |
-            &Key::RightAlt |
-            &Key::RightCtrl |
-            &Key::RightMeta |
-            &Key::RightShift => true,
-            _ => false,
+// Common names for keys that differ from the variant names, in normalized form (lowercase
+// letters and digits only).
+const ALIASES: &[(&str, Key)] = &[
+    ("alt", Key::LeftAlt),
+    ("lalt", Key::LeftAlt),
+    ("ralt", Key::RightAlt),
+    ("altgr", Key::RightAlt),
+    ("ctrl", Key::LeftCtrl),
+    ("control", Key::LeftCtrl),
+    ("lctrl", Key::LeftCtrl),
+    ("lcontrol", Key::LeftCtrl),
+    ("leftcontrol", Key::LeftCtrl),
+    ("rctrl", Key::RightCtrl),
+    ("rcontrol", Key::RightCtrl),
+    ("rightcontrol", Key::RightCtrl),
+    ("shift", Key::LeftShift),
+    ("lshift", Key::LeftShift),
+    ("rshift", Key::RightShift),
+    ("meta", Key::LeftMeta),
+    ("super", Key::LeftMeta),
+    ("win", Key::LeftMeta),
+    ("lmeta", Key::LeftMeta),
+    ("lsuper", Key::LeftMeta),
+    ("leftsuper", Key::LeftMeta),
+    ("lwin", Key::LeftMeta),
+    ("leftwin", Key::LeftMeta),
+    ("rmeta", Key::RightMeta),
+    ("rsuper", Key::RightMeta),
+    ("rightsuper", Key::RightMeta),
+    ("rwin", Key::RightMeta),
+    ("rightwin", Key::RightMeta),
+    ("escape", Key::Esc),
+    ("return", Key::Enter),
+    ("del", Key::Delete),
+    ("ins", Key::Insert),
+    ("pgup", Key::PageUp),
+    ("pgdn", Key::PageDown),
+    ("pgdown", Key::PageDown),
+    ("spacebar", Key::Space),
+    ("caps", Key::CapsLock),
+    ("printscreen", Key::SysRq),
+    ("prtsc", Key::SysRq),
+    // Names from linux/input-event-codes.h that don't match the variant names
+    ("kpdot", Key::KpDott),
+    ("allapplications", Key::Dashboard),
+];
+
+impl FromStr for Key {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        parse_name(name, "key", "KEY_", Key::VARIANTS, Key::ALL, ALIASES)
+    }
+}
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // Config files name keys loosely, while the wire format needs to stay exactly what
+        // serde would derive.
+        if deserializer.is_human_readable() {
+            let name = String::deserialize(deserializer)?;
+            name.parse().map_err(serde::de::Error::custom)
+        } else {
+            let visitor = IndexVisitor {
+                name: "Key",
+                all: Key::ALL,
+                fallback: Some(Key::Other),
+            };
+            deserializer.deserialize_enum("Key", Key::VARIANTS, visitor)
         }
     }
 }
 
+impl Key {
+    pub fn is_modifier(&self) -> bool {
+        matches!(
+            self,
+            Key::Fn
+                | Key::LeftAlt
+                | Key::LeftCtrl
+                | Key::LeftMeta
+                | Key::LeftShift
+                | Key::RightAlt
+                | Key::RightCtrl
+                | Key::RightMeta
+                | Key::RightShift
+        )
+    }
+
+    // Keys for controlling playback, volume and brightness, as opposed to typing.
+    pub fn is_media(&self) -> bool {
+        matches!(
+            self,
+            Key::BrightnessDown
+                | Key::BrightnessUp
+                | Key::FastForward
+                | Key::KbdIllumDown
+                | Key::KbdIllumToggle
+                | Key::KbdIllumUp
+                | Key::Media
+                | Key::MicMute
+                | Key::Mute
+                | Key::NextSong
+                | Key::Pause
+                | Key::PauseCd
+                | Key::Play
+                | Key::PlayCd
+                | Key::PlayPause
+                | Key::PreviousSong
+                | Key::Rewind
+                | Key::StopCd
+                | Key::VolumeDown
+                | Key::VolumeUp
+        )
+    }
+
+    // The keys listed after ZoomReset and Key::Other, which only peers on protocol version 13 or
+    // later can decode.
+    pub fn is_extended(&self) -> bool {
+        matches!(
+            self,
+            Key::AutopilotEngageToggle
+                | Key::BrightnessMenu
+                | Key::ClearvuSonar
+                | Key::Dictate
+                | Key::DualRangeRadar
+                | Key::EmojiPicker
+                | Key::FishingChart
+                | Key::FnRightShift
+                | Key::HangupPhone
+                | Key::LinkPhone
+                | Key::MarkWaypoint
+                | Key::NavChart
+                | Key::NavInfo
+                | Key::NextElement
+                | Key::NotificationCenter
+                | Key::PickupPhone
+                | Key::PreviousElement
+                | Key::RadarOverlay
+                | Key::RefreshRateToggle
+                | Key::SidevuSonar
+                | Key::SingleRangeRadar
+                | Key::Sos
+                | Key::TraditionalSonar
+                | Key::Other(_)
+        )
+    }
+}
+
@@ -0,0 +1,114 @@
+use serde::de::{self, EnumAccess, Unexpected, VariantAccess, Visitor};
+use std::fmt;
+
+// Tolerant parsing of key and button names, so configs can say "LeftAlt", "left_alt", "LALT" or
+// evdev's "KEY_LEFTALT" and all mean the same thing.
+
+fn normalize(name: &str, evdev_prefix: &str) -> String {
+    let name = match name.get(..evdev_prefix.len()) {
+        Some(prefix) if prefix.eq_ignore_ascii_case(evdev_prefix) => &name[evdev_prefix.len()..],
+        _ => name,
+    };
+
+    let normalized: String = name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+
+    // Variant names can't start with a digit, so e.g. KEY_1 is called N1.
+    if normalized.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("n{}", normalized)
+    } else {
+        normalized
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, &b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + if a_char == b_char { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+pub(crate) fn parse_name<T: Copy>(
+    name: &str,
+    kind: &str,
+    evdev_prefix: &str,
+    variants: &[&str],
+    all: &[T],
+    aliases: &[(&str, T)],
+) -> Result<T, String> {
+    let normalized = normalize(name, evdev_prefix);
+
+    if let Some(index) = variants.iter().position(|variant| variant.to_ascii_lowercase() == normalized) {
+        return Ok(all[index]);
+    }
+    if let Some(&(_, value)) = aliases.iter().find(|(alias, _)| *alias == normalized) {
+        return Ok(value);
+    }
+
+    let max_distance = (normalized.len() / 3).max(2);
+    let mut near_matches: Vec<(usize, &str)> = variants
+        .iter()
+        .filter_map(|variant| {
+            let lowercase = variant.to_ascii_lowercase();
+            let distance = edit_distance(&lowercase, &normalized);
+            let contains = normalized.len() >= 3 && lowercase.contains(&normalized);
+            if distance <= max_distance || contains {
+                Some((distance, *variant))
+            } else {
+                None
+            }
+        })
+        .collect();
+    near_matches.sort();
+
+    if near_matches.is_empty() {
+        Err(format!("Unknown {} name \"{}\"", kind, name))
+    } else {
+        let suggestions: Vec<&str> = near_matches.iter().take(5).map(|&(_, variant)| variant).collect();
+        Err(format!("Unknown {} name \"{}\". Did you mean {}?", kind, name, suggestions.join(", ")))
+    }
+}
+
+// Deserializes a fieldless enum from its variant index, exactly like serde's derived
+// implementation does for non-human-readable formats like bincode. An enum with a fallback variant
+// holding a raw code has it right after the fieldless ones.
+pub(crate) struct IndexVisitor<T: 'static> {
+    pub name: &'static str,
+    pub all: &'static [T],
+    pub fallback: Option<fn(u16) -> T>,
+}
+
+impl<'de, T: Copy> Visitor<'de> for IndexVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a {} variant", self.name)
+    }
+
+    fn visit_enum<A>(self, data: A) -> Result<T, A::Error>
+    where
+        A: EnumAccess<'de>,
+    {
+        let (index, variant): (u32, _) = data.variant()?;
+        if let Some(fallback) = self.fallback {
+            if index as usize == self.all.len() {
+                return variant.newtype_variant().map(fallback);
+            }
+        }
+        variant.unit_variant()?;
+        self.all
+            .get(index as usize)
+            .copied()
+            .ok_or_else(|| de::Error::invalid_value(Unexpected::Unsigned(index as u64), &self))
+    }
+}
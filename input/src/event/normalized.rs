@@ -0,0 +1,21 @@
+use crate::event::{Axis, Button, Direction, Key};
+use serde::{Deserialize, Serialize};
+
+// A platform-neutral description of keyboard and pointer input. Platform backends translate their
+// native events (evdev type/code/value triples on Linux) to and from this, so code that needs to
+// know what an event means doesn't have to know how a particular platform encodes it.
+//
+// Pointer motion is relative only; absolute devices like touchpads and tablets have no
+// normalized form and are passed through as raw events.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NormalizedEvent {
+    Key { direction: Direction, key: Key },
+    Button { direction: Direction, button: Button },
+    // Relative pointer motion in device units. Positive X is right, positive Y is down.
+    Motion { axis: Axis, delta: i32 },
+    // Scrolling in whole wheel detents. Positive Y is away from the user, positive X is right.
+    Scroll { axis: Axis, detents: i32 },
+    // Scrolling in 1/120ths of a detent, for devices that support high-resolution scrolling.
+    // Devices reporting these also report `Scroll`, so consumers should only handle one of them.
+    HighResScroll { axis: Axis, delta: i32 },
+}
@@ -1,9 +1,15 @@
 mod event;
 
-#[cfg(target_os = "linux")]
+// FreeBSD's evdev and uinput are compatible with Linux's, and Android's are Linux's, so all of them
+// use the same backend.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
 mod linux;
 
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+pub use linux::{check_uinput, grab_holders, is_grabbed, list_devices, DeviceError, DeviceInfo, DeviceWatcher, MockWatcher, PollWatcher, ReaderManager, UngrabbedDevice, WriterManager};
+#[cfg(target_os = "freebsd")]
+pub use linux::DevdWatcher;
 #[cfg(target_os = "linux")]
-pub use linux::{ReaderManager, WriterManager};
+pub use linux::InotifyWatcher;
 
-pub use event::{Axis, Button, Direction, Event, InputEvent, Device, Key, KeyKind};
+pub use event::{AbsInfo, Axis, Button, Capability, Condition, Direction, Effect, EffectKind, Envelope, Event, Feedback, InputEvent, Device, Key, KeyKind, NormalizedEvent};
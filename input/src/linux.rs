@@ -1,7 +1,30 @@
+mod device_error;
+mod device_watcher;
 mod event;
 mod event_reader;
 mod event_writer;
+mod force_feedback;
+mod ioctl;
+mod udev;
+
+#[cfg(feature = "libevdev")]
+mod evdev;
+#[cfg(feature = "libevdev")]
+mod glue;
+
+// Without libevdev, the kernel is talked to directly.
+#[cfg(not(feature = "libevdev"))]
+#[path = "linux/ioctl_evdev.rs"]
+mod evdev;
+#[cfg(not(feature = "libevdev"))]
+#[path = "linux/kernel_glue.rs"]
 mod glue;
 
+pub use device_error::{check_uinput, DeviceError};
+pub use device_watcher::{DeviceWatcher, MockWatcher, PollWatcher};
+#[cfg(target_os = "freebsd")]
+pub use device_watcher::DevdWatcher;
+#[cfg(target_os = "linux")]
+pub use device_watcher::InotifyWatcher;
 pub use event_writer::WriterManager;
-pub use event_reader::ReaderManager;
+pub use event_reader::{grab_holders, is_grabbed, list_devices, DeviceInfo, ReaderManager, UngrabbedDevice};
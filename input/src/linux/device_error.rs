@@ -0,0 +1,105 @@
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{Error, ErrorKind};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+const UINPUT_PATH: &str = "/dev/uinput";
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const UINPUT_MODULE_PATH: &str = "/sys/module/uinput";
+
+#[cfg(target_os = "linux")]
+const LOAD_UINPUT: &str = "Load it with `sudo modprobe uinput`, and see example/evkvm-uinput.conf to load it on boot";
+#[cfg(target_os = "android")]
+const LOAD_UINPUT: &str = "Most Android kernels have it built in; on one that doesn't, load it as root with `insmod`";
+#[cfg(target_os = "freebsd")]
+const LOAD_UINPUT: &str = "Load it with `kldload uinput`, and add uinput to kld_list in /etc/rc.conf to load it on boot";
+
+#[derive(Debug)]
+pub enum DeviceError {
+    // The uinput kernel module isn't loaded, so there's no /dev/uinput.
+    UinputModuleNotLoaded,
+    // The uinput module is loaded but /dev/uinput doesn't exist, e.g. in a container that wasn't
+    // given the device node.
+    UinputNodeMissing,
+    // /dev/uinput exists but we aren't allowed to open it.
+    UinputPermissionDenied,
+    Io(Error),
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceError::UinputModuleNotLoaded => write!(
+                f,
+                "{} does not exist because the uinput kernel module is not loaded. {}",
+                UINPUT_PATH,
+                LOAD_UINPUT,
+            ),
+            DeviceError::UinputNodeMissing => write!(
+                f,
+                "The uinput kernel module is loaded, but {} does not exist. \
+                 If evkvm is running in a container, pass the device through to it, \
+                 e.g. with `--device /dev/uinput`",
+                UINPUT_PATH,
+            ),
+            DeviceError::UinputPermissionDenied => write!(
+                f,
+                "Permission denied opening {}. Make sure the user running evkvm may write to it, \
+                 e.g. using example/40-evkvm-uinput.rules",
+                UINPUT_PATH,
+            ),
+            DeviceError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for DeviceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DeviceError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for DeviceError {
+    fn from(err: Error) -> Self {
+        DeviceError::Io(err)
+    }
+}
+
+// Check that virtual devices can be created, explaining what's wrong if they can't.
+pub fn check_uinput() -> Result<(), DeviceError> {
+    let result = OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(UINPUT_PATH);
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(err) => Err(diagnose_uinput(err)),
+    }
+}
+
+// Turn an error from creating a uinput device into a more specific DeviceError if we can tell
+// what caused it.
+pub(crate) fn diagnose_uinput(err: Error) -> DeviceError {
+    match err.kind() {
+        ErrorKind::NotFound if !uinput_module_loaded() => DeviceError::UinputModuleNotLoaded,
+        ErrorKind::NotFound => DeviceError::UinputNodeMissing,
+        ErrorKind::PermissionDenied => DeviceError::UinputPermissionDenied,
+        _ => DeviceError::Io(err),
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn uinput_module_loaded() -> bool {
+    Path::new(UINPUT_MODULE_PATH).exists()
+}
+
+// The module creates /dev/uinput as soon as it is loaded, so it being missing says enough.
+#[cfg(target_os = "freebsd")]
+fn uinput_module_loaded() -> bool {
+    false
+}
@@ -0,0 +1,189 @@
+use futures::stream::{self, BoxStream, StreamExt};
+use std::collections::{BTreeSet, VecDeque};
+use std::io::Error;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+#[cfg(target_os = "linux")]
+use inotify::{Inotify, WatchMask};
+#[cfg(target_os = "linux")]
+use std::path::Path;
+
+#[cfg(target_os = "freebsd")]
+use tokio::io::{AsyncBufReadExt, BufReader};
+#[cfg(target_os = "freebsd")]
+use tokio::net::UnixStream;
+
+pub(crate) const EVENT_PATH: &str = "/dev/input";
+
+// Tells the reader about device nodes that appear after it has started. Nodes that already exist
+// are found by listing EVENT_PATH, so a watcher only has to report new ones. When the stream fails
+// or ends, the reader lists EVENT_PATH again and calls watch again after a while.
+pub trait DeviceWatcher: Send + Sync {
+    fn watch(&self) -> Result<BoxStream<'static, Result<PathBuf, Error>>, Error>;
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn default_watcher() -> Arc<dyn DeviceWatcher> {
+    Arc::new(InotifyWatcher)
+}
+
+#[cfg(target_os = "freebsd")]
+pub(crate) fn default_watcher() -> Arc<dyn DeviceWatcher> {
+    Arc::new(DevdWatcher)
+}
+
+#[cfg(target_os = "android")]
+pub(crate) fn default_watcher() -> Arc<dyn DeviceWatcher> {
+    Arc::new(PollWatcher { interval: Duration::from_secs(1) })
+}
+
+// Watches /dev/input with inotify. udev creates the nodes there, so this sees the same devices it
+// does, without depending on libudev.
+#[cfg(target_os = "linux")]
+pub struct InotifyWatcher;
+
+#[cfg(target_os = "linux")]
+impl DeviceWatcher for InotifyWatcher {
+    fn watch(&self) -> Result<BoxStream<'static, Result<PathBuf, Error>>, Error> {
+        let mut inotify = Inotify::init()?;
+        inotify.add_watch(EVENT_PATH, WatchMask::CREATE)?;
+
+        // This buffer size should be OK, since we don't expect a lot of devices
+        // to be plugged in frequently.
+        let events = inotify.event_stream([0u8; 512])?;
+        let paths = events.filter_map(|event| async move {
+            match event {
+                Ok(event) => event.name.map(|name| Ok(Path::new(EVENT_PATH).join(name))),
+                Err(err) => Some(Err(err)),
+            }
+        });
+        Ok(paths.boxed())
+    }
+}
+
+// Listens to devd, which announces every device node devfs creates on its pipe.
+#[cfg(target_os = "freebsd")]
+pub struct DevdWatcher;
+
+#[cfg(target_os = "freebsd")]
+const DEVD_PIPE: &str = "/var/run/devd.pipe";
+
+#[cfg(target_os = "freebsd")]
+impl DeviceWatcher for DevdWatcher {
+    fn watch(&self) -> Result<BoxStream<'static, Result<PathBuf, Error>>, Error> {
+        let socket = std::os::unix::net::UnixStream::connect(DEVD_PIPE).map_err(|err| {
+            Error::new(err.kind(), format!("Failed to connect to {}, is devd running? {}", DEVD_PIPE, err))
+        })?;
+        socket.set_nonblocking(true)?;
+        let lines = BufReader::new(UnixStream::from_std(socket)?).lines();
+
+        let paths = stream::unfold(lines, |mut lines| async move {
+            loop {
+                match lines.next_line().await {
+                    Ok(Some(line)) => {
+                        if let Some(path) = created_node(&line) {
+                            return Some((Ok(path), lines));
+                        }
+                    }
+                    Ok(None) => return None,
+                    Err(err) => return Some((Err(err), lines)),
+                }
+            }
+        });
+        Ok(paths.boxed())
+    }
+}
+
+// Picks out notifications like `!system=DEVFS subsystem=CDEV type=CREATE cdev=input/event5`.
+#[cfg(target_os = "freebsd")]
+fn created_node(line: &str) -> Option<PathBuf> {
+    let mut system = None;
+    let mut type_ = None;
+    let mut cdev = None;
+    for field in line.strip_prefix('!')?.split_whitespace() {
+        match field.split_once('=')? {
+            ("system", value) => system = Some(value),
+            ("type", value) => type_ = Some(value),
+            ("cdev", value) => cdev = Some(value),
+            _ => {}
+        }
+    }
+    let cdev = cdev?;
+    if system? != "DEVFS" || type_? != "CREATE" || !cdev.starts_with("input/") {
+        return None;
+    }
+    Some(PathBuf::from("/dev").join(cdev))
+}
+
+// Lists EVENT_PATH every `interval` and reports the nodes that weren't there the time before. This
+// works anywhere, at the cost of noticing devices a little late, so it is what Android uses: there
+// is no udev there, ueventd creates the nodes, and nothing announces them that a process outside
+// of the system image is sure to be allowed to listen to.
+pub struct PollWatcher {
+    pub interval: Duration,
+}
+
+fn list_nodes() -> Result<BTreeSet<PathBuf>, Error> {
+    std::fs::read_dir(EVENT_PATH)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect()
+}
+
+impl DeviceWatcher for PollWatcher {
+    fn watch(&self) -> Result<BoxStream<'static, Result<PathBuf, Error>>, Error> {
+        let state = (list_nodes()?, VecDeque::new(), self.interval);
+        let paths = stream::unfold(state, |(mut known, mut new, interval)| async move {
+            loop {
+                if let Some(path) = new.pop_front() {
+                    return Some((Ok(path), (known, new, interval)));
+                }
+                tokio::time::sleep(interval).await;
+                match list_nodes() {
+                    Ok(nodes) => {
+                        new.extend(nodes.difference(&known).cloned());
+                        known = nodes;
+                    }
+                    Err(err) => return Some((Err(err), (known, new, interval))),
+                }
+            }
+        });
+        Ok(paths.boxed())
+    }
+}
+
+// A watcher that reports whatever paths it is handed, for simulating hotplug in tests. It can only
+// be watched once, so it doesn't survive the reader restarting it.
+pub struct MockWatcher {
+    receiver: Mutex<Option<mpsc::UnboundedReceiver<PathBuf>>>,
+}
+
+impl MockWatcher {
+    // Paths sent on the returned sender are reported as new devices.
+    pub fn new() -> (Self, mpsc::UnboundedSender<PathBuf>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let watcher = MockWatcher {
+            receiver: Mutex::new(Some(receiver)),
+        };
+        (watcher, sender)
+    }
+}
+
+impl DeviceWatcher for MockWatcher {
+    fn watch(&self) -> Result<BoxStream<'static, Result<PathBuf, Error>>, Error> {
+        let receiver = self
+            .receiver
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| Error::other("Mock watcher was already watched"))?;
+
+        let paths = stream::unfold(receiver, |mut receiver| async move {
+            let path = receiver.recv().await?;
+            Some((Ok(path), receiver))
+        });
+        Ok(paths.boxed())
+    }
+}
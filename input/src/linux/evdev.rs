@@ -0,0 +1,322 @@
+use crate::event::{AbsInfo, Capability};
+use crate::linux::glue::{self, input_event};
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+use std::mem::{self, MaybeUninit};
+use std::os::raw::c_char;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr;
+
+// Safe wrappers around libevdev. An Evdev is either attached to a device node, for reading from
+// it, or set up from scratch to describe a virtual device, which is then created with uinput.
+
+pub(crate) struct Evdev {
+    raw: *mut glue::libevdev,
+    // The device node, if attached to one. libevdev doesn't own it, so it is kept open here for as
+    // long as libevdev may use it.
+    file: Option<File>,
+}
+
+// libevdev devices aren't tied to the thread that created them.
+unsafe impl Send for Evdev {}
+
+// libevdev returns negative errno values on failure.
+fn check(ret: i32) -> Result<(), Error> {
+    if ret < 0 {
+        Err(Error::from_raw_os_error(-ret))
+    } else {
+        Ok(())
+    }
+}
+
+// libevdev gives null for strings a device doesn't have.
+unsafe fn to_string(buf: *const c_char) -> String {
+    if buf.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(buf).to_string_lossy().into_owned()
+}
+
+fn to_c_string(value: &str) -> Result<CString, Error> {
+    CString::new(value).map_err(|err| Error::new(ErrorKind::InvalidInput, err))
+}
+
+// The highest code of the given event type. Nothing forwarded uses it, so it is left out.
+fn code_max(type_: u32) -> u32 {
+    let max = unsafe { glue::libevdev_event_type_get_max(type_) };
+    max.max(0) as u32
+}
+
+impl Evdev {
+    pub fn new() -> Result<Self, Error> {
+        let raw = unsafe { glue::libevdev_new() };
+        if raw.is_null() {
+            return Err(Error::new(ErrorKind::Other, "Failed to create device"));
+        }
+        Ok(Evdev { raw, file: None })
+    }
+
+    pub fn from_file(file: File) -> Result<Self, Error> {
+        let mut raw = MaybeUninit::uninit();
+        check(unsafe { glue::libevdev_new_from_fd(file.as_raw_fd(), raw.as_mut_ptr()) })?;
+        Ok(Evdev {
+            raw: unsafe { raw.assume_init() },
+            file: Some(file),
+        })
+    }
+
+    pub fn name(&self) -> String {
+        unsafe { to_string(glue::libevdev_get_name(self.raw)) }
+    }
+
+    pub fn uniq(&self) -> String {
+        unsafe { to_string(glue::libevdev_get_uniq(self.raw)) }
+    }
+
+    pub fn phys(&self) -> String {
+        unsafe { to_string(glue::libevdev_get_phys(self.raw)) }
+    }
+
+    pub fn vendor(&self) -> u16 {
+        unsafe { glue::libevdev_get_id_vendor(self.raw) as u16 }
+    }
+
+    pub fn product(&self) -> u16 {
+        unsafe { glue::libevdev_get_id_product(self.raw) as u16 }
+    }
+
+    pub fn bustype(&self) -> u16 {
+        unsafe { glue::libevdev_get_id_bustype(self.raw) as u16 }
+    }
+
+    pub fn version(&self) -> u16 {
+        unsafe { glue::libevdev_get_id_version(self.raw) as u16 }
+    }
+
+    pub fn is_virtual(&self) -> bool {
+        self.bustype() as u32 == glue::BUS_VIRTUAL
+    }
+
+    pub fn has_event_type(&self, type_: u32) -> bool {
+        unsafe { glue::libevdev_has_event_type(self.raw, type_) == 1 }
+    }
+
+    pub fn has_event_code(&self, type_: u32, code: u32) -> bool {
+        unsafe { glue::libevdev_has_event_code(self.raw, type_, code) == 1 }
+    }
+
+    // The current value of the given code, as libevdev last saw it.
+    pub fn event_value(&self, type_: u32, code: u32) -> i32 {
+        unsafe { glue::libevdev_get_event_value(self.raw, type_, code) }
+    }
+
+    pub fn abs_info(&self, code: u32) -> Option<AbsInfo> {
+        let info = unsafe { glue::libevdev_get_abs_info(self.raw, code).as_ref()? };
+        Some(AbsInfo {
+            value: info.value,
+            minimum: info.minimum,
+            maximum: info.maximum,
+            fuzz: info.fuzz,
+            flat: info.flat,
+            resolution: info.resolution,
+        })
+    }
+
+    // Everything the device can do, except for switches.
+    pub fn capabilities(&self) -> Vec<Capability> {
+        let mut capabilities = Vec::new();
+        for type_ in 0..glue::EV_MAX {
+            if type_ == glue::EV_SW || !self.has_event_type(type_) {
+                continue;
+            }
+            for code in (0..code_max(type_)).filter(|&code| self.has_event_code(type_, code)) {
+                let capability = match type_ {
+                    glue::EV_ABS => match self.abs_info(code) {
+                        Some(info) => Capability::Abs { code: code as u16, info },
+                        None => continue,
+                    },
+                    glue::EV_REP => Capability::Rep {
+                        code: code as u16,
+                        value: self.event_value(type_, code),
+                    },
+                    _ => Capability::Other {
+                        type_: type_ as u16,
+                        code: code as u16,
+                    },
+                };
+                capabilities.push(capability);
+            }
+        }
+        capabilities
+    }
+
+    // Codes of the given event type that are currently on (held down, for keys, or lit, for
+    // LEDs). libevdev fetches this state from the kernel (EVIOCGKEY, EVIOCGLED) when it is
+    // initialized.
+    pub fn active_codes(&self, type_: u32) -> Vec<u16> {
+        (0..code_max(type_))
+            .filter(|&code| self.event_value(type_, code) != 0)
+            .map(|code| code as u16)
+            .collect()
+    }
+
+    // The device's INPUT_PROP_* properties.
+    pub fn properties(&self) -> Vec<u16> {
+        (0..=glue::INPUT_PROP_MAX)
+            .filter(|&property| unsafe { glue::libevdev_has_property(self.raw, property) == 1 })
+            .map(|property| property as u16)
+            .collect()
+    }
+
+    pub fn grab(&mut self) -> Result<(), Error> {
+        check(unsafe { glue::libevdev_grab(self.raw, glue::libevdev_grab_mode_LIBEVDEV_GRAB) })
+    }
+
+    pub fn ungrab(&mut self) -> Result<(), Error> {
+        check(unsafe { glue::libevdev_grab(self.raw, glue::libevdev_grab_mode_LIBEVDEV_UNGRAB) })
+    }
+
+    // The next event from the device. Fails with WouldBlock if there is none yet.
+    pub fn next_event(&mut self) -> Result<input_event, Error> {
+        let mut event = MaybeUninit::uninit();
+        check(unsafe {
+            glue::libevdev_next_event(
+                self.raw,
+                glue::libevdev_read_flag_LIBEVDEV_READ_FLAG_NORMAL,
+                event.as_mut_ptr(),
+            )
+        })?;
+        Ok(unsafe { event.assume_init() })
+    }
+
+    // Send an event to the device itself, like EV_LED to turn a keyboard's LED on or off.
+    pub fn write_event(&mut self, event: &input_event) -> Result<(), Error> {
+        let size = mem::size_of::<input_event>();
+        let ret = unsafe { libc::write(self.as_raw_fd(), event as *const input_event as *const _, size) };
+        if ret < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn set_ids(&mut self, vendor: u16, product: u16, bustype: u16, version: u16) {
+        unsafe {
+            glue::libevdev_set_id_vendor(self.raw, vendor as _);
+            glue::libevdev_set_id_product(self.raw, product as _);
+            glue::libevdev_set_id_bustype(self.raw, bustype as _);
+            glue::libevdev_set_id_version(self.raw, version as _);
+        }
+    }
+
+    // libevdev keeps copies of the strings it is given.
+    pub fn set_name(&mut self, name: &str) -> Result<(), Error> {
+        let name = to_c_string(name)?;
+        unsafe { glue::libevdev_set_name(self.raw, name.as_ptr()) };
+        Ok(())
+    }
+
+    pub fn set_phys(&mut self, phys: &str) -> Result<(), Error> {
+        let phys = to_c_string(phys)?;
+        unsafe { glue::libevdev_set_phys(self.raw, phys.as_ptr()) };
+        Ok(())
+    }
+
+    pub fn enable(&mut self, capability: &Capability) -> Result<(), Error> {
+        let ret = match *capability {
+            Capability::Abs { code, info } => {
+                let absinfo = glue::input_absinfo {
+                    value: info.value,
+                    minimum: info.minimum,
+                    maximum: info.maximum,
+                    fuzz: info.fuzz,
+                    flat: info.flat,
+                    resolution: info.resolution,
+                };
+                unsafe {
+                    glue::libevdev_enable_event_code(
+                        self.raw,
+                        glue::EV_ABS,
+                        code as _,
+                        &absinfo as *const glue::input_absinfo as *const _,
+                    )
+                }
+            }
+            Capability::Rep { code, value } => unsafe {
+                glue::libevdev_enable_event_code(
+                    self.raw,
+                    glue::EV_REP,
+                    code as _,
+                    &value as *const i32 as *const _,
+                )
+            },
+            Capability::Other { type_, code } => unsafe {
+                glue::libevdev_enable_event_code(self.raw, type_ as _, code as _, ptr::null())
+            },
+        };
+        check(ret)
+    }
+
+    pub fn enable_property(&mut self, property: u16) -> Result<(), Error> {
+        if unsafe { glue::libevdev_enable_property(self.raw, property as _) } < 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Unknown property {}", property)));
+        }
+        Ok(())
+    }
+
+    // Create a virtual device that is a copy of this one.
+    pub fn create_uinput(&self) -> Result<Uinput, Error> {
+        let mut raw = MaybeUninit::uninit();
+        check(unsafe {
+            glue::libevdev_uinput_create_from_device(
+                self.raw,
+                glue::libevdev_uinput_open_mode_LIBEVDEV_UINPUT_OPEN_MANAGED,
+                raw.as_mut_ptr(),
+            )
+        })?;
+        Ok(Uinput {
+            raw: unsafe { raw.assume_init() },
+        })
+    }
+}
+
+impl AsRawFd for Evdev {
+    fn as_raw_fd(&self) -> RawFd {
+        unsafe { glue::libevdev_get_fd(self.raw) }
+    }
+}
+
+impl Drop for Evdev {
+    fn drop(&mut self) {
+        unsafe { glue::libevdev_free(self.raw) };
+        // Only now that libevdev is done with it can the device node be closed.
+        self.file.take();
+    }
+}
+
+// A virtual device, which is destroyed when dropped.
+pub(crate) struct Uinput {
+    raw: *mut glue::libevdev_uinput,
+}
+
+unsafe impl Send for Uinput {}
+
+impl Uinput {
+    pub fn write_event(&mut self, event: &input_event) -> Result<(), Error> {
+        check(unsafe {
+            glue::libevdev_uinput_write_event(self.raw, event.type_ as _, event.code as _, event.value)
+        })
+    }
+}
+
+impl AsRawFd for Uinput {
+    fn as_raw_fd(&self) -> RawFd {
+        unsafe { glue::libevdev_uinput_get_fd(self.raw) }
+    }
+}
+
+impl Drop for Uinput {
+    fn drop(&mut self) {
+        unsafe { glue::libevdev_uinput_destroy(self.raw) };
+    }
+}
@@ -1,7 +1,7 @@
 pub mod button;
 pub mod key;
 
-use crate::event::{Button, Direction, InputEvent, Key, KeyKind};
+use crate::event::{Axis, Button, Capability, Device, Direction, InputEvent, Key, KeyKind, NormalizedEvent};
 use crate::linux::glue;
 
 impl InputEvent {
@@ -52,6 +52,56 @@ impl InputEvent {
 
         Some(event)
     }
+
+    pub fn to_normalized(self) -> Option<NormalizedEvent> {
+        let event = match self {
+            InputEvent::Key { direction, kind: KeyKind::Key(key) } => {
+                NormalizedEvent::Key { direction, key }
+            },
+            InputEvent::Key { direction, kind: KeyKind::Button(button) } => {
+                NormalizedEvent::Button { direction, button }
+            },
+            InputEvent::Other { type_, code, value } if type_ as u32 == glue::EV_REL => {
+                match code as u32 {
+                    glue::REL_X => NormalizedEvent::Motion { axis: Axis::X, delta: value },
+                    glue::REL_Y => NormalizedEvent::Motion { axis: Axis::Y, delta: value },
+                    glue::REL_HWHEEL => NormalizedEvent::Scroll { axis: Axis::X, detents: value },
+                    glue::REL_WHEEL => NormalizedEvent::Scroll { axis: Axis::Y, detents: value },
+                    glue::REL_HWHEEL_HI_RES => NormalizedEvent::HighResScroll { axis: Axis::X, delta: value },
+                    glue::REL_WHEEL_HI_RES => NormalizedEvent::HighResScroll { axis: Axis::Y, delta: value },
+                    _ => return None,
+                }
+            },
+            InputEvent::Other { .. } => return None,
+        };
+
+        Some(event)
+    }
+}
+
+impl From<NormalizedEvent> for InputEvent {
+    fn from(event: NormalizedEvent) -> Self {
+        let (code, value) = match event {
+            NormalizedEvent::Key { direction, key } => {
+                return InputEvent::Key { direction, kind: KeyKind::Key(key) };
+            },
+            NormalizedEvent::Button { direction, button } => {
+                return InputEvent::Key { direction, kind: KeyKind::Button(button) };
+            },
+            NormalizedEvent::Motion { axis: Axis::X, delta } => (glue::REL_X, delta),
+            NormalizedEvent::Motion { axis: Axis::Y, delta } => (glue::REL_Y, delta),
+            NormalizedEvent::Scroll { axis: Axis::X, detents } => (glue::REL_HWHEEL, detents),
+            NormalizedEvent::Scroll { axis: Axis::Y, detents } => (glue::REL_WHEEL, detents),
+            NormalizedEvent::HighResScroll { axis: Axis::X, delta } => (glue::REL_HWHEEL_HI_RES, delta),
+            NormalizedEvent::HighResScroll { axis: Axis::Y, delta } => (glue::REL_WHEEL_HI_RES, delta),
+        };
+
+        InputEvent::Other {
+            type_: glue::EV_REL as _,
+            code: code as _,
+            value,
+        }
+    }
 }
 
 impl KeyKind {
@@ -59,6 +109,7 @@ impl KeyKind {
         Key::from_raw(code)
             .map(KeyKind::Key)
             .or_else(|| Button::from_raw(code).map(KeyKind::Button))
+            .or_else(|| (code as u32 <= glue::KEY_MAX).then_some(KeyKind::Key(Key::Other(code))))
     }
 
     pub(crate) fn to_raw(self) -> u16 {
@@ -68,3 +119,53 @@ impl KeyKind {
         }
     }
 }
+
+impl Device {
+    // A human-readable summary of the kinds of events this device can produce.
+    pub fn capability_classes(&self) -> Vec<&'static str> {
+        let mut classes = Vec::new();
+        for capability in &self.capabilities {
+            let class = match *capability {
+                Capability::Abs { .. } => "absolute axes",
+                Capability::Rep { .. } => "autorepeat",
+                Capability::Other { type_, code } => match type_ as u32 {
+                    glue::EV_KEY => match KeyKind::from_raw(code) {
+                        Some(KeyKind::Button(_)) => "buttons",
+                        _ => "keys",
+                    },
+                    glue::EV_REL => "relative axes",
+                    glue::EV_MSC => "misc",
+                    glue::EV_LED => "LEDs",
+                    glue::EV_SND => "sounds",
+                    glue::EV_FF => "force feedback",
+                    _ => "other",
+                },
+            };
+            if !classes.contains(&class) {
+                classes.push(class);
+            }
+        }
+        classes
+    }
+
+    // Keep `held_keys` and `lit_leds` up to date with an event read from the device.
+    pub(crate) fn update_state(&mut self, input: &InputEvent) {
+        match *input {
+            InputEvent::Key { direction: Direction::Down, kind } => {
+                if !self.held_keys.contains(&kind) {
+                    self.held_keys.push(kind);
+                }
+            }
+            InputEvent::Key { direction: Direction::Up, kind } => {
+                self.held_keys.retain(|held| *held != kind);
+            }
+            InputEvent::Other { type_, code, value } if type_ as u32 == glue::EV_LED => {
+                self.lit_leds.retain(|lit| *lit != code);
+                if value != 0 {
+                    self.lit_leds.push(code);
+                }
+            }
+            InputEvent::Other { .. } => {}
+        }
+    }
+}
@@ -22,6 +22,7 @@ impl Key {
             AttendantToggle => 0x021D,
             Audio => 0x0188,
             AudioDesc => 0x026E,
+            AutopilotEngageToggle => 0x027D,
             Aux => 0x0186,
             B => 0x0030,
             Back => 0x009E,
@@ -36,6 +37,7 @@ impl Key {
             BrightnessAuto => 0x00F4,
             BrightnessCycle => 0x00F3,
             BrightnessMax => 0x0251,
+            BrightnessMenu => 0x0289,
             BrightnessMin => 0x0250,
             BrightnessToggle => 0x01AF,
             BrightnessZero => 0x00F4,
@@ -71,6 +73,7 @@ impl Key {
             ChannelUp => 0x0192,
             Chat => 0x00D8,
             Clear => 0x0163,
+            ClearvuSonar => 0x0286,
             Close => 0x00CE,
             CloseCd => 0x00A0,
             Coffee => 0x0098,
@@ -93,6 +96,7 @@ impl Key {
             DelLine => 0x01C3,
             Delete => 0x006F,
             DeleteFile => 0x0092,
+            Dictate => 0x024A,
             Digits => 0x019D,
             Direction => 0x0099,
             Directory => 0x018A,
@@ -102,6 +106,7 @@ impl Key {
             Dollar => 0x01B2,
             Dot => 0x0034,
             Down => 0x006C,
+            DualRangeRadar => 0x0283,
             Dvd => 0x0185,
             E => 0x0012,
             Edit => 0x00B0,
@@ -109,6 +114,7 @@ impl Key {
             EjectCd => 0x00A1,
             EjectCloseCd => 0x00A2,
             Email => 0x00D7,
+            EmojiPicker => 0x0249,
             End => 0x006B,
             Enter => 0x001C,
             Epg => 0x016D,
@@ -148,6 +154,7 @@ impl Key {
             Finance => 0x00DB,
             Find => 0x0088,
             First => 0x0194,
+            FishingChart => 0x0281,
             Fn => 0x01D0,
             Fn1 => 0x01DE,
             Fn2 => 0x01DF,
@@ -168,6 +175,7 @@ impl Key {
             FnF7 => 0x01D8,
             FnF8 => 0x01D9,
             FnF9 => 0x01DA,
+            FnRightShift => 0x01E5,
             FnS => 0x01E3,
             Forward => 0x009F,
             ForwardMail => 0x00E9,
@@ -183,6 +191,7 @@ impl Key {
             Green => 0x018F,
             H => 0x0023,
             Hangeul => 0x007A,
+            HangupPhone => 0x01BE,
             Hanja => 0x007B,
             Help => 0x008A,
             Henkan => 0x005C,
@@ -252,6 +261,7 @@ impl Key {
             LeftShift => 0x002A,
             LightsToggle => 0x021E,
             LineFeed => 0x0065,
+            LinkPhone => 0x01BF,
             List => 0x018B,
             LogOff => 0x01B1,
             M => 0x0032,
@@ -293,6 +303,7 @@ impl Key {
             MacroRecordStart => 0x02B0,
             MacroRecordStop => 0x02B1,
             Mail => 0x009B,
+            MarkWaypoint => 0x027E,
             Media => 0x00E2,
             MediaRepeat => 0x01B7,
             MediaTopMenu => 0x026B,
@@ -323,11 +334,15 @@ impl Key {
             N7 => 0x0008,
             N8 => 0x0009,
             N9 => 0x000A,
+            NavChart => 0x0280,
+            NavInfo => 0x0288,
             New => 0x00B5,
             News => 0x01AB,
             Next => 0x0197,
+            NextElement => 0x027B,
             NextFavorite => 0x0270,
             NextSong => 0x00A3,
+            NotificationCenter => 0x01BC,
             Numeric0 => 0x0200,
             Numeric1 => 0x0201,
             Numeric11 => 0x026C,
@@ -361,6 +376,7 @@ impl Key {
             PauseCd => 0x00C9,
             Pc => 0x0178,
             Phone => 0x00A9,
+            PickupPhone => 0x01BD,
             Play => 0x00CF,
             PlayCd => 0x00C8,
             Player => 0x0183,
@@ -369,6 +385,7 @@ impl Key {
             Power2 => 0x0164,
             Presentation => 0x01A9,
             Previous => 0x019C,
+            PreviousElement => 0x027C,
             PreviousSong => 0x00A5,
             Print => 0x00D2,
             PrivacyScreenToggle => 0x0279,
@@ -382,11 +399,13 @@ impl Key {
             Q => 0x0010,
             Question => 0x00D6,
             R => 0x0013,
+            RadarOverlay => 0x0284,
             Radio => 0x0181,
             Record => 0x00A7,
             Red => 0x018E,
             Redo => 0x00B6,
             Refresh => 0x00AD,
+            RefreshRateToggle => 0x0232,
             Reply => 0x00E8,
             Reserved => 0x0000,
             Restart => 0x0198,
@@ -424,10 +443,13 @@ impl Key {
             Setup => 0x008D,
             Shop => 0x00DD,
             Shuffle => 0x019A,
+            SidevuSonar => 0x0287,
+            SingleRangeRadar => 0x0282,
             Slash => 0x0035,
             Sleep => 0x008E,
             Slow => 0x0199,
             SlowReverse => 0x0276,
+            Sos => 0x027F,
             Sound => 0x00D5,
             Space => 0x0039,
             Spellcheck => 0x01B0,
@@ -451,6 +473,7 @@ impl Key {
             TouchpadOff => 0x0214,
             TouchpadOn => 0x0213,
             TouchpadToggle => 0x0212,
+            TraditionalSonar => 0x0285,
             Tuner => 0x0182,
             Tv => 0x0179,
             Tv2 => 0x017A,
@@ -493,6 +516,7 @@ impl Key {
             ZoomIn => 0x01A2,
             ZoomOut => 0x01A3,
             ZoomReset => 0x01A4,
+            Other(code) => code,
         }
     }
 
@@ -519,6 +543,7 @@ impl Key {
             0x021D => AttendantToggle,
             0x0188 => Audio,
             0x026E => AudioDesc,
+            0x027D => AutopilotEngageToggle,
             0x0186 => Aux,
             0x0030 => B,
             0x009E => Back,
@@ -533,6 +558,7 @@ impl Key {
             0x00F4 => BrightnessAuto,
             0x00F3 => BrightnessCycle,
             0x0251 => BrightnessMax,
+            0x0289 => BrightnessMenu,
             0x0250 => BrightnessMin,
             0x00E0 => BrightnessDown,
             0x00E1 => BrightnessUp,
@@ -566,6 +592,7 @@ impl Key {
             0x0192 => ChannelUp,
             0x00D8 => Chat,
             0x0163 => Clear,
+            0x0286 => ClearvuSonar,
             0x00CE => Close,
             0x00A0 => CloseCd,
             0x0098 => Coffee,
@@ -588,6 +615,7 @@ impl Key {
             0x01C3 => DelLine,
             0x006F => Delete,
             0x0092 => DeleteFile,
+            0x024A => Dictate,
             0x019D => Digits,
             0x018A => Directory,
             0x00F5 => DisplayOff,
@@ -596,6 +624,7 @@ impl Key {
             0x01B2 => Dollar,
             0x0034 => Dot,
             0x006C => Down,
+            0x0283 => DualRangeRadar,
             0x0185 => Dvd,
             0x0012 => E,
             0x00B0 => Edit,
@@ -603,6 +632,7 @@ impl Key {
             0x00A1 => EjectCd,
             0x00A2 => EjectCloseCd,
             0x00D7 => Email,
+            0x0249 => EmojiPicker,
             0x006B => End,
             0x001C => Enter,
             0x016D => Epg,
@@ -642,6 +672,7 @@ impl Key {
             0x00DB => Finance,
             0x0088 => Find,
             0x0194 => First,
+            0x0281 => FishingChart,
             0x01D0 => Fn,
             0x01DE => Fn1,
             0x01DF => Fn2,
@@ -662,6 +693,7 @@ impl Key {
             0x01D8 => FnF7,
             0x01D9 => FnF8,
             0x01DA => FnF9,
+            0x01E5 => FnRightShift,
             0x01E3 => FnS,
             0x009F => Forward,
             0x00E9 => ForwardMail,
@@ -677,6 +709,7 @@ impl Key {
             0x018F => Green,
             0x0023 => H,
             0x007A => Hangeul,
+            0x01BE => HangupPhone,
             0x007B => Hanja,
             0x008A => Help,
             0x005C => Henkan,
@@ -746,6 +779,7 @@ impl Key {
             0x002A => LeftShift,
             0x021E => LightsToggle,
             0x0065 => LineFeed,
+            0x01BF => LinkPhone,
             0x018B => List,
             0x01B1 => LogOff,
             0x0032 => M,
@@ -787,6 +821,7 @@ impl Key {
             0x02B0 => MacroRecordStart,
             0x02B1 => MacroRecordStop,
             0x009B => Mail,
+            0x027E => MarkWaypoint,
             0x00E2 => Media,
             0x01B7 => MediaRepeat,
             0x026B => MediaTopMenu,
@@ -817,11 +852,15 @@ impl Key {
             0x0008 => N7,
             0x0009 => N8,
             0x000A => N9,
+            0x0280 => NavChart,
+            0x0288 => NavInfo,
             0x00B5 => New,
             0x01AB => News,
             0x0197 => Next,
+            0x027B => NextElement,
             0x0270 => NextFavorite,
             0x00A3 => NextSong,
+            0x01BC => NotificationCenter,
             0x0200 => Numeric0,
             0x0201 => Numeric1,
             0x026C => Numeric11,
@@ -855,6 +894,7 @@ impl Key {
             0x00C9 => PauseCd,
             0x0178 => Pc,
             0x00A9 => Phone,
+            0x01BD => PickupPhone,
             0x00CF => Play,
             0x00C8 => PlayCd,
             0x0183 => Player,
@@ -863,6 +903,7 @@ impl Key {
             0x0164 => Power2,
             0x01A9 => Presentation,
             0x019C => Previous,
+            0x027C => PreviousElement,
             0x00A5 => PreviousSong,
             0x00D2 => Print,
             0x0279 => PrivacyScreenToggle,
@@ -876,11 +917,13 @@ impl Key {
             0x0010 => Q,
             0x00D6 => Question,
             0x0013 => R,
+            0x0284 => RadarOverlay,
             0x0181 => Radio,
             0x00A7 => Record,
             0x018E => Red,
             0x00B6 => Redo,
             0x00AD => Refresh,
+            0x0232 => RefreshRateToggle,
             0x00E8 => Reply,
             0x0000 => Reserved,
             0x0198 => Restart,
@@ -916,10 +959,13 @@ impl Key {
             0x008D => Setup,
             0x00DD => Shop,
             0x019A => Shuffle,
+            0x0287 => SidevuSonar,
+            0x0282 => SingleRangeRadar,
             0x0035 => Slash,
             0x008E => Sleep,
             0x0199 => Slow,
             0x0276 => SlowReverse,
+            0x027F => Sos,
             0x00D5 => Sound,
             0x0039 => Space,
             0x01B0 => Spellcheck,
@@ -943,6 +989,7 @@ impl Key {
             0x0214 => TouchpadOff,
             0x0213 => TouchpadOn,
             0x0212 => TouchpadToggle,
+            0x0285 => TraditionalSonar,
             0x0182 => Tuner,
             0x0179 => Tv,
             0x017A => Tv2,
@@ -1,201 +1,342 @@
-use crate::event::{Event, Capability, AbsInfo, Device, InputEvent};
-use std::ffi;
-use std::fs::{File, OpenOptions};
-use std::mem::MaybeUninit;
+use crate::event::{Capability, Event, Device, Direction, Feedback, InputEvent, KeyKind};
+use std::fs::OpenOptions;
 use std::os::unix::fs::OpenOptionsExt;
-use std::os::unix::io::AsRawFd;
 use std::str::FromStr;
 use tokio::io::unix::AsyncFd;
+use crate::linux::event_writer::OWN_PHYS;
+use crate::linux::evdev::Evdev;
+use crate::linux::force_feedback::FeedbackTarget;
 use crate::linux::glue;
+use crate::linux::udev::UdevProperties;
+use crate::linux::device_watcher::{self, DeviceWatcher, EVENT_PATH};
+use futures::future::{self, Either};
 use futures::StreamExt;
-use inotify::{Inotify, WatchMask};
 use std::io::{Error, ErrorKind};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
-use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 use tokio::fs;
 use tokio::sync::mpsc;
 use tokio::sync::watch;
 use tokio::sync::oneshot;
 use tokio::time;
 
-const EVENT_PATH: &str = "/dev/input";
+// How the device watcher is restarted when it fails. The delay doubles with every failure in a
+// row, and after enough of them the error is passed on to whoever is reading events. A watcher
+// that ran for a while before failing starts over with a clean slate.
+const WATCHER_BACKOFF_MIN: Duration = Duration::from_millis(100);
+const WATCHER_BACKOFF_MAX: Duration = Duration::from_secs(5);
+const WATCHER_MAX_FAILURES: u32 = 5;
+const WATCHER_STABLE_AFTER: Duration = Duration::from_secs(60);
+
+// How often to try again to grab devices that another process had grabbed.
+const GRAB_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+// How long an LED stays on and off for each blink of ReaderManager::blink_led.
+const BLINK_ON: Duration = Duration::from_millis(150);
+const BLINK_OFF: Duration = Duration::from_millis(200);
+
+struct ReaderState {
+    // Names of virtual devices to read from anyway.
+    forward_virtual: Vec<String>,
+    // Whether to grab devices, as set with ReaderManager::set_grab. Without grabbing, whatever
+    // else reads from them still gets their input, and nothing is ever reported as ungrabbed.
+    grab: watch::Receiver<bool>,
+    // Devices we have grabbed and are reading from, or are opening.
+    grabbed: HashSet<PathBuf>,
+    // Devices we would forward but another process has grabbed, along with their names.
+    ungrabbed: BTreeMap<PathBuf, String>,
+    // Devices paused with ReaderManager::pause, by id, along with their names. They are left
+    // ungrabbed, and their events aren't passed on.
+    paused: BTreeMap<u16, String>,
+    descriptors: DescriptorCache,
+}
+
+// Identifies a device well enough to tell that it is still the one its capabilities were
+// described for: a different device on the same node, or the same one after a firmware update,
+// gets a key of its own.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct DescriptorKey {
+    path: PathBuf,
+    vendor: u16,
+    product: u16,
+    version: u16,
+    name: String,
+}
+
+// What describing a device finds out that doesn't change while it is plugged in. Walking every
+// event code a device might have takes a while with libevdev, and devices that another process
+// has grabbed are described again every time grabbing them is retried, as are devices that come
+// and go, like Bluetooth ones.
+type DescriptorCache = HashMap<DescriptorKey, (Vec<Capability>, Vec<u16>)>;
+
+type SharedState = Arc<Mutex<ReaderState>>;
 
 pub(crate) struct EventReader {
     pub device: Device,
-    file: AsyncFd<File>,
-    evdev: *mut glue::libevdev,
+    evdev: AsyncFd<Evdev>,
+    // Whether the device was grabbed when this was last looked at.
+    grabbed: bool,
 }
 
-impl EventReader {
-    pub async fn new(path: &Path) -> Result<Self, OpenError> {
-        let file_name = path
-            .file_name()
-            .and_then(|file_name| file_name.to_str())
-            .unwrap();
-        let num_str = &file_name[String::from("event").len()..];
-        let id = u16::from_str(num_str).unwrap_or(0);
-
-        // When running as non-root, we have to wait for udev to set the proper permissions on new
-        // devices. Sometimes (always?), our inotify event comes through before udev sets the
-        // permissions. We could use `udevadm settle`, or set up an inotify on the file attributes,
-        // but it's simpler to just poll.
-
-        let mut total_slept_millis = 0;
-        let timeout_millis = 1000;
-
-        let file = loop {
-            let file = OpenOptions::new()
-                .read(true)
-                .custom_flags(libc::O_NONBLOCK)
-                .open(path)
-                .and_then(AsyncFd::new);
-
-            match file {
-                Ok(file) => { break file },
-                Err(err) => {
-                    match err.kind() {
-                        ErrorKind::NotFound => { return Err(OpenError::AlreadyOpened); },
-                        ErrorKind::PermissionDenied => {
-                            if total_slept_millis < timeout_millis {
-                                let millis = 10;
-                                tokio::time::sleep(Duration::from_millis(millis)).await;
-                                total_slept_millis += millis;
-                            } else {
-                                return Err(OpenError::Io(err));
-                            }
-                        },
-                        _ => { return Err(OpenError::Io(err)); },
-                    };
-                }
+// A device that would be forwarded if another process didn't have it grabbed.
+pub struct UngrabbedDevice {
+    pub path: PathBuf,
+    pub name: String,
+    // Processes that have the device open, as described by grab_holders.
+    pub holders: Vec<String>,
+}
+
+// Information about an input device node, for listing devices without grabbing them.
+pub struct DeviceInfo {
+    pub path: PathBuf,
+    pub device: Device,
+    // Why the device would not be forwarded, if it wouldn't be.
+    pub skip_reason: Option<String>,
+    // What udev classified the device as, like "keyboard" or "touchpad".
+    pub classes: Vec<String>,
+    pub serial: Option<String>,
+}
+
+fn device_id(path: &Path) -> u16 {
+    let file_name = path
+        .file_name()
+        .and_then(|file_name| file_name.to_str())
+        .unwrap();
+    let num_str = &file_name[String::from("event").len()..];
+    u16::from_str(num_str).unwrap_or(0)
+}
+
+async fn open_evdev(path: &Path) -> Result<Evdev, OpenError> {
+    // When running as non-root, we have to wait for udev to set the proper permissions on new
+    // devices. Sometimes (always?), our inotify event comes through before udev sets the
+    // permissions. We could use `udevadm settle`, or set up an inotify on the file attributes,
+    // but it's simpler to just poll.
+
+    let mut total_slept_millis = 0;
+    let timeout_millis = 1000;
+
+    let file = loop {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path);
+
+        match file {
+            Ok(file) => { break file },
+            Err(err) => {
+                match err.kind() {
+                    ErrorKind::NotFound => { return Err(OpenError::AlreadyOpened); },
+                    ErrorKind::PermissionDenied if total_slept_millis < timeout_millis => {
+                        let millis = 10;
+                        tokio::time::sleep(Duration::from_millis(millis)).await;
+                        total_slept_millis += millis;
+                    },
+                    _ => { return Err(OpenError::Io(err)); },
+                };
             }
-        };
+        }
+    };
 
-        let mut evdev = MaybeUninit::uninit();
-        let ret = unsafe { glue::libevdev_new_from_fd(file.as_raw_fd(), evdev.as_mut_ptr()) };
-        if ret < 0 {
+    match Evdev::from_file(file) {
+        Ok(evdev) => Ok(evdev),
+        Err(err) => {
             log::error!("Error creating reader");
-            return Err(Error::from_raw_os_error(-ret).into());
+            Err(err.into())
         }
+    }
+}
 
-        let evdev = unsafe { evdev.assume_init() };
-        let (product, vendor, bustype, version) = unsafe {
-            (
-                glue::libevdev_get_id_product(evdev),
-                glue::libevdev_get_id_vendor(evdev),
-                glue::libevdev_get_id_bustype(evdev),
-                glue::libevdev_get_id_version(evdev),
-            )
-        };
+// Why a device shouldn't be read from, if it shouldn't, judging by the device alone. Virtual
+// devices are skipped unless their name is in `forward_virtual`, which is how the output of key
+// remappers like keyd, kmonad, or interception-tools gets forwarded.
+fn skip_reason(
+    evdev: &Evdev,
+    udev: &UdevProperties,
+    forward_virtual: &[String],
+) -> Option<&'static str> {
+    // Never read the devices we create, whatever they are called, or input would go in circles.
+    if evdev.phys().starts_with(OWN_PHYS) {
+        return Some("created by evkvm");
+    }
 
-        let name_c_str = unsafe {
-            let name_buf = glue::libevdev_get_name(evdev);
-            ffi::CStr::from_ptr(name_buf)
-        };
-        let name = name_c_str.to_str().unwrap().to_owned();
+    if udev.ignored() {
+        return Some("ignored by a udev rule (EVKVM_IGNORE=1)");
+    }
 
-        if (bustype as u32) == glue::BUS_VIRTUAL {
-            unsafe {
-                glue::libevdev_free(evdev);
-            }
+    if evdev.is_virtual() && !forward_virtual.contains(&evdev.name()) {
+        return Some("virtual device");
+    }
 
-            return Err(OpenError::AlreadyOpened);
+    None
+}
+
+fn describe_device(evdev: &Evdev, id: u16) -> Device {
+    describe_device_with(evdev, id, evdev.capabilities(), evdev.properties())
+}
+
+// Like describe_device, but takes the capabilities and properties from the cache if the device has
+// been described before.
+fn describe_cached_device(evdev: &Evdev, path: &Path, shared: &SharedState) -> Device {
+    let key = DescriptorKey {
+        path: path.to_owned(),
+        vendor: evdev.vendor(),
+        product: evdev.product(),
+        version: evdev.version(),
+        name: evdev.name(),
+    };
+    let cached = shared.lock().unwrap().descriptors.get(&key).cloned();
+    let (capabilities, properties) = match cached {
+        Some((capabilities, properties)) => {
+            // Axes report where they are along with their range, and that is only current now.
+            let capabilities = capabilities
+                .into_iter()
+                .map(|capability| match capability {
+                    Capability::Abs { code, info } => Capability::Abs {
+                        code,
+                        info: evdev.abs_info(code as u32).unwrap_or(info),
+                    },
+                    capability => capability,
+                })
+                .collect();
+            (capabilities, properties)
         }
+        None => {
+            let described = (evdev.capabilities(), evdev.properties());
+            // Whatever was on this node before is gone.
+            let mut state = shared.lock().unwrap();
+            state.descriptors.retain(|cached, _| cached.path != key.path);
+            state.descriptors.insert(key, described.clone());
+            described
+        }
+    };
+    describe_device_with(evdev, device_id(path), capabilities, properties)
+}
 
-        let mut capabilities = Vec::new();
-        for type_ in 0..glue::EV_MAX {
-            if type_ == glue::EV_SW { continue; } // ignore EV_SW for now
-            let has_type = unsafe {
-                glue::libevdev_has_event_type(evdev, type_)
-            } == 1;
-            if !has_type { continue; }
-            let code_max = unsafe {
-                glue::libevdev_event_type_get_max(type_)
-            } as u32;
-            for code in 0..code_max {
-                let has_code = unsafe {
-                    glue::libevdev_has_event_code(evdev, type_, code)
-                } == 1;
-                if has_code {
-                    let capability = match type_ {
-                        glue::EV_ABS => {
-                            let info = unsafe {
-                                let raw_info = glue::libevdev_get_abs_info(evdev, code);
-                                if raw_info.is_null() {
-                                    panic!("absinfo is null!");
-                                }
-                                AbsInfo {
-                                    value: (*raw_info).value,
-                                    minimum: (*raw_info).minimum,
-                                    maximum: (*raw_info).maximum,
-                                    fuzz: (*raw_info).fuzz,
-                                    flat: (*raw_info).flat,
-                                    resolution: (*raw_info).resolution,
-                                }
-                            };
-                            Capability::Abs { code: code as u16, info }
-                        },
-                        glue::EV_REP => {
-                            let value = unsafe {
-                                glue::libevdev_get_event_value(evdev, type_, code)
-                            };
-                            Capability::Rep { code: code as u16, value }
-                        },
-                        _ => Capability::Other {
-                            type_: type_ as u16,
-                            code: code as u16
-                        },
-                    };
-                    capabilities.push(capability);
-                }
-            }
+fn describe_device_with(evdev: &Evdev, id: u16, capabilities: Vec<Capability>, properties: Vec<u16>) -> Device {
+    Device {
+        id,
+        name: evdev.name(),
+        uniq: evdev.uniq(),
+        phys: evdev.phys(),
+        vendor: evdev.vendor(),
+        product: evdev.product(),
+        bustype: evdev.bustype(),
+        version: evdev.version(),
+        capabilities,
+        properties,
+        held_keys: evdev
+            .active_codes(glue::EV_KEY)
+            .into_iter()
+            .filter_map(KeyKind::from_raw)
+            .collect(),
+        lit_leds: evdev.active_codes(glue::EV_LED),
+    }
+}
+
+// List the input devices that a ReaderManager would find, without grabbing any of them.
+pub async fn list_devices(forward_virtual: &[String]) -> Result<Vec<DeviceInfo>, Error> {
+    let mut paths = Vec::new();
+    let mut read_dir = fs::read_dir(EVENT_PATH).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        if is_event_node(&entry.path()) {
+            paths.push(entry.path());
         }
+    }
+    paths.sort();
+
+    let mut devices = Vec::new();
+    for path in paths {
+        let evdev = match open_evdev(&path).await {
+            Ok(evdev) => evdev,
+            Err(OpenError::AlreadyOpened) | Err(OpenError::Busy(_)) => continue,
+            Err(OpenError::Io(err)) => return Err(err),
+        };
 
-        let device = Device {
-            id,
-            name,
-            vendor: vendor as u16,
-            product: product as u16,
-            bustype: bustype as u16,
-            version: version as u16,
-            capabilities,
+        let udev = UdevProperties::for_node(&path);
+        let device = describe_device(&evdev, device_id(&path));
+        let skip_reason = skip_reason(&evdev, &udev, forward_virtual);
+        drop(evdev);
+
+        let skip_reason = if let Some(reason) = skip_reason {
+            Some(String::from(reason))
+        } else if is_grabbed(&path).unwrap_or(false) {
+            Some(String::from("grabbed by another process"))
+        } else {
+            None
         };
 
-        let ret = unsafe { glue::libevdev_grab(evdev, glue::libevdev_grab_mode_LIBEVDEV_GRAB) };
-        if ret < 0 {
-            unsafe {
-                glue::libevdev_free(evdev);
-            }
-            // Device is probably grabbed by another process
+        devices.push(DeviceInfo {
+            path,
+            device,
+            skip_reason,
+            classes: udev.classes(),
+            serial: udev.serial(),
+        });
+    }
+
+    Ok(devices)
+}
+
+impl EventReader {
+    async fn new(path: &Path, shared: &SharedState) -> Result<Self, OpenError> {
+        let (forward_virtual, grab) = {
+            let state = shared.lock().unwrap();
+            let grab = *state.grab.borrow();
+            (state.forward_virtual.clone(), grab)
+        };
+        let mut evdev = open_evdev(path).await?;
+
+        if skip_reason(&evdev, &UdevProperties::for_node(path), &forward_virtual).is_some() {
             return Err(OpenError::AlreadyOpened);
         }
 
+        let device = describe_cached_device(&evdev, path, shared);
+
+        if grab && evdev.grab().is_err() {
+            // Device is probably grabbed by another process
+            return Err(OpenError::Busy(device.name));
+        }
+
         Ok(Self {
-            file,
-            evdev,
+            evdev: AsyncFd::new(evdev)?,
             device,
+            grabbed: grab,
         })
     }
 
+    // Grab or ungrab the device to match `grab`. Grabbing waits until no keys or buttons are held
+    // on the device, since whatever sees them go down would otherwise never see them come back up.
+    fn follow_grab(&mut self, grab: bool) {
+        if grab == self.grabbed || (grab && !self.device.held_keys.is_empty()) {
+            return;
+        }
+        // If this fails it is left at that until the next change, rather than tried again with
+        // every event.
+        self.grabbed = grab;
+
+        let evdev = self.evdev.get_mut();
+        let result = if grab { evdev.grab() } else { evdev.ungrab() };
+        match result {
+            Ok(()) => log::debug!("{} {}", if grab { "Grabbed" } else { "Ungrabbed" }, self.device.name),
+            Err(err) => log::warn!(
+                "Failed to {} {}: {}",
+                if grab { "grab" } else { "ungrab" },
+                self.device.name,
+                err,
+            ),
+        }
+    }
+
     pub async fn read(&mut self) -> Result<InputEvent, Error> {
         loop {
-            let result = self.file.readable().await?.try_io(|_| {
-                let mut event = MaybeUninit::uninit();
-                let ret = unsafe {
-                    glue::libevdev_next_event(
-                        self.evdev,
-                        glue::libevdev_read_flag_LIBEVDEV_READ_FLAG_NORMAL,
-                        event.as_mut_ptr(),
-                    )
-                };
-
-                if ret < 0 {
-                    return Err(Error::from_raw_os_error(-ret));
-                }
-
-                let event = unsafe { event.assume_init() };
-                Ok(event)
-            });
+            let result = self
+                .evdev
+                .readable_mut()
+                .await?
+                .try_io(|evdev| evdev.get_mut().next_event());
 
             let event = match result {
                 Ok(Ok(event)) => event,
@@ -210,18 +351,88 @@ impl EventReader {
     }
 }
 
-impl Drop for EventReader {
-    fn drop(&mut self) {
-        unsafe {
-            glue::libevdev_free(self.evdev);
+// Check whether another process holds an exclusive grab on the device at `path` by briefly trying
+// to grab it ourselves.
+pub fn is_grabbed(path: &Path) -> Result<bool, Error> {
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)?;
+
+    let mut evdev = Evdev::from_file(file)?;
+    match evdev.grab() {
+        Err(err) if err.raw_os_error() == Some(libc::EBUSY) => Ok(true),
+        Err(err) => Err(err),
+        Ok(()) => {
+            let _ = evdev.ungrab();
+            Ok(false)
         }
     }
 }
 
-unsafe impl Send for EventReader {}
+// Open the device at `path` again, for writing to its LED with the given code. Gives the device
+// and whether the LED is lit, or None if it doesn't have that LED.
+fn open_led(path: &Path, code: u16) -> Result<Option<(Evdev, bool)>, Error> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)?;
+
+    let evdev = Evdev::from_file(file)?;
+    let has_led = evdev.capabilities().iter().any(|capability| {
+        matches!(*capability, Capability::Other { type_, code: other } if type_ as u32 == glue::EV_LED && other == code)
+    });
+    if !has_led {
+        return Ok(None);
+    }
+    let lit = evdev.active_codes(glue::EV_LED).contains(&code);
+    Ok(Some((evdev, lit)))
+}
+
+fn set_led(evdev: &mut Evdev, code: u16, lit: bool) -> Result<(), Error> {
+    let led = InputEvent::Other { type_: glue::EV_LED as u16, code, value: lit as i32 };
+    let syn = InputEvent::Other { type_: glue::EV_SYN as u16, code: glue::SYN_REPORT as u16, value: 0 };
+    evdev.write_event(&led.to_raw())?;
+    evdev.write_event(&syn.to_raw())
+}
+
+// Describe the processes that have the device at `path` open, like "keyd (pid 812)", to help
+// work out which of them grabbed it. The kernel doesn't say who holds a grab, so this is every
+// process with the node open, found by looking through /proc/*/fd. Processes of other users can
+// only be seen when running as root, and platforms without /proc give nothing.
+pub fn grab_holders(path: &Path) -> Vec<String> {
+    let own_pid = std::process::id().to_string();
+    let mut holders = Vec::new();
+
+    let processes = match std::fs::read_dir("/proc") {
+        Ok(processes) => processes,
+        Err(_) => return holders,
+    };
+    for process in processes.flatten() {
+        let pid = process.file_name().to_string_lossy().into_owned();
+        if pid == own_pid || !pid.bytes().all(|byte| byte.is_ascii_digit()) {
+            continue;
+        }
+        let fds = match std::fs::read_dir(process.path().join("fd")) {
+            Ok(fds) => fds,
+            Err(_) => continue,
+        };
+        let has_open = fds
+            .flatten()
+            .any(|fd| std::fs::read_link(fd.path()).map(|target| target == path).unwrap_or(false));
+        if has_open {
+            let comm = std::fs::read_to_string(process.path().join("comm")).unwrap_or_default();
+            holders.push(format!("{} (pid {})", comm.trim(), pid));
+        }
+    }
+    holders
+}
 
 pub enum OpenError {
     AlreadyOpened,
+    // Another process has the device grabbed. Carries the device's name.
+    Busy(String),
     Io(Error),
 }
 
@@ -233,42 +444,83 @@ impl From<Error> for OpenError {
 
 pub struct ReaderManager {
     pub devices: HashMap<u16, Device>,
+    shared: SharedState,
+    grab: watch::Sender<bool>,
     event_receiver: mpsc::UnboundedReceiver<Result<Event, Error>>,
     watcher_receiver: oneshot::Receiver<Error>,
+    // Held while blinking LEDs. Overlapping blinks would take each other's flashes for the LEDs'
+    // real state, and leave them lit.
+    blink_lock: Arc<tokio::sync::Mutex<()>>,
+    // Where feedback from each origin is carried out, by origin and device id. Every origin gets
+    // its own, so that effects from different origins don't get mixed up.
+    feedback: HashMap<(u64, u16), FeedbackTarget>,
 }
 
 impl ReaderManager {
-    pub async fn new() -> Result<Self, Error> {
-        let (event_sender, event_receiver) = mpsc::unbounded_channel();
+    // `forward_virtual` lists the names of virtual devices to read from, which are skipped
+    // otherwise.
+    pub async fn new(forward_virtual: Vec<String>) -> Result<Self, Error> {
+        Self::with_watcher(forward_virtual, device_watcher::default_watcher()).await
+    }
 
-        // HACK: When rkvm is run from the terminal, a race condition happens where the enter key
-        // release event is swallowed and the key will remain in a "pressed" state until the user manually presses it again.
-        // This is presumably due to the event being generated while we're in the process of grabbing
-        // the keyboard input device.
-        //
-        // This won't prevent this from happenning with other keys if they happen to be pressed at an
-        // unfortunate time, but that is unlikely to happen and will ease the life of people who run rkvm
-        // directly from the terminal for the time being until a proper fix is made.
-        time::sleep(Duration::from_millis(500)).await;
+    // Like new, but finds out about devices that are plugged in later from `watcher`.
+    pub async fn with_watcher(
+        forward_virtual: Vec<String>,
+        watcher: Arc<dyn DeviceWatcher>,
+    ) -> Result<Self, Error> {
+        Self::open(forward_virtual, watcher, true).await
+    }
 
-        let devices: HashMap<u16, Device> = HashMap::new();
+    // Like new, but only reads from devices, leaving their input to go wherever it went before,
+    // until set_grab says otherwise.
+    pub async fn without_grabbing(forward_virtual: Vec<String>) -> Result<Self, Error> {
+        Self::open(forward_virtual, device_watcher::default_watcher(), false).await
+    }
+
+    async fn open(
+        forward_virtual: Vec<String>,
+        watcher: Arc<dyn DeviceWatcher>,
+        grab: bool,
+    ) -> Result<Self, Error> {
+        let (event_sender, event_receiver) = mpsc::unbounded_channel();
 
+        let devices: HashMap<u16, Device> = HashMap::new();
+        let (grab_sender, grab_receiver) = watch::channel(grab);
+        let shared = SharedState::new(Mutex::new(ReaderState {
+            forward_virtual,
+            grab: grab_receiver,
+            grabbed: HashSet::new(),
+            ungrabbed: BTreeMap::new(),
+            paused: BTreeMap::new(),
+            descriptors: DescriptorCache::new(),
+        }));
+
+        // Devices are opened and described all at once, rather than one after the other, since
+        // with many of them that adds up at startup.
+        let mut openers = Vec::new();
         let mut read_dir = fs::read_dir(EVENT_PATH).await?;
         while let Some(entry) = read_dir.next_entry().await? {
-            spawn_reader(&entry.path(), event_sender.clone()).await?;
+            let sender = event_sender.clone();
+            let shared = shared.clone();
+            openers.push(tokio::spawn(async move { spawn_reader(&entry.path(), sender, &shared).await }));
+        }
+        for opener in openers {
+            opener.await.map_err(Error::other)??;
         }
 
+        tokio::spawn(retry_grabs(event_sender.clone(), shared.clone()));
+
         let (watcher_sender, watcher_receiver) = oneshot::channel();
-        tokio::spawn(async {
-            if let Err(err) = handle_notify(event_sender).await {
-                let _ = watcher_sender.send(err);
-            }
-        });
+        tokio::spawn(supervise_notify(watcher, event_sender, shared.clone(), watcher_sender));
 
         Ok(ReaderManager {
             devices,
+            shared,
+            grab: grab_sender,
             event_receiver,
             watcher_receiver,
+            blink_lock: Arc::new(tokio::sync::Mutex::new(())),
+            feedback: HashMap::new(),
         })
     }
 
@@ -280,7 +532,7 @@ impl ReaderManager {
         let event_result = self.event_receiver
             .recv()
             .await
-            .ok_or_else(|| Error::new(ErrorKind::Other, "All devices closed"))?;
+            .ok_or_else(|| Error::other("All devices closed"))?;
 
         match event_result {
             Ok(Event::NewDevice(ref device)) => {
@@ -288,61 +540,306 @@ impl ReaderManager {
             },
             Ok(Event::RemoveDevice(device_id)) => {
                 self.devices.remove(&device_id);
+                self.feedback.retain(|&(_, id), _| id != device_id);
+            },
+            Ok(Event::Input { device_id, ref input, .. }) => {
+                if let Some(device) = self.devices.get_mut(&device_id) {
+                    device.update_state(input);
+                }
             },
             _ => {},
         }
 
         event_result
     }
+
+    // Blink the LED with the given code (LED_SCROLLL and so on) `times` times on every device being
+    // read from that has it, then put it back the way it was. This happens in the background.
+    pub fn blink_led(&self, code: u16, times: u32) {
+        let paths: Vec<PathBuf> = self.shared.lock().unwrap().grabbed.iter().cloned().collect();
+        let blink_lock = self.blink_lock.clone();
+        tokio::spawn(async move {
+            let _guard = blink_lock.lock().await;
+
+            let mut leds = Vec::new();
+            for path in paths {
+                match open_led(&path, code) {
+                    Ok(Some(led)) => leds.push(led),
+                    Ok(None) => {}
+                    Err(err) => log::debug!("Can't blink LEDs of {}: {}", path.display(), err),
+                }
+            }
+
+            for _ in 0..times {
+                for (evdev, _) in &mut leds {
+                    let _ = set_led(evdev, code, true);
+                }
+                time::sleep(BLINK_ON).await;
+                for (evdev, _) in &mut leds {
+                    let _ = set_led(evdev, code, false);
+                }
+                time::sleep(BLINK_OFF).await;
+            }
+
+            for (evdev, lit) in &mut leds {
+                let _ = set_led(evdev, code, *lit);
+            }
+        });
+    }
+
+    // Carry out feedback (force feedback effects, LEDs) that the virtual copy of a device was asked
+    // for, on the device itself. `origin` tells apart whoever the feedback comes from, like
+    // receivers, and end_feedback undoes what an origin asked for. Feedback for devices that are
+    // gone is ignored.
+    pub fn feedback(&mut self, origin: u64, device_id: u16, feedback: Feedback) -> Result<(), Error> {
+        if !self.devices.contains_key(&device_id) {
+            return Ok(());
+        }
+        let target = match self.feedback.entry((origin, device_id)) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let path = Path::new(EVENT_PATH).join(format!("event{}", device_id));
+                entry.insert(FeedbackTarget::open(&path)?)
+            }
+        };
+        target.apply(feedback)
+    }
+
+    // Stop and forget the effects `origin` uploaded, for when it goes away.
+    pub fn end_feedback(&mut self, origin: u64) {
+        self.feedback.retain(|&(other, _), _| other != origin);
+    }
+
+    // Grab all devices or let go of them. Devices that are plugged in later follow suit.
+    pub fn set_grab(&self, grab: bool) {
+        if *self.grab.borrow() != grab {
+            let _ = self.grab.send(grab);
+        }
+    }
+
+    // Stop forwarding the device with the given id and let go of it, so that it works on this
+    // machine as if evkvm weren't running, or start forwarding it again. While it is paused, the
+    // device looks unplugged to whoever reads events from here. Returns false if there is no such
+    // device.
+    pub fn pause(&self, device_id: u16, pause: bool) -> bool {
+        {
+            let mut state = self.shared.lock().unwrap();
+            let paused = state.paused.contains_key(&device_id);
+            if paused == pause {
+                return paused || self.devices.contains_key(&device_id);
+            }
+            if pause {
+                match self.devices.get(&device_id) {
+                    Some(device) => state.paused.insert(device_id, device.name.clone()),
+                    None => return false,
+                };
+            } else {
+                state.paused.remove(&device_id);
+            }
+        }
+        // Readers look at which devices are paused whenever the grab changes, so this wakes them
+        // up without changing it.
+        let grab = *self.grab.borrow();
+        let _ = self.grab.send(grab);
+        true
+    }
+
+    // The ids and names of the devices that are paused.
+    pub fn paused_devices(&self) -> Vec<(u16, String)> {
+        self.shared.lock().unwrap().paused.iter().map(|(&id, name)| (id, name.clone())).collect()
+    }
+
+    // Devices that are being skipped because another process has them grabbed. Grabbing them is
+    // retried every few seconds.
+    pub fn ungrabbed_devices(&self) -> Vec<UngrabbedDevice> {
+        self.shared
+            .lock()
+            .unwrap()
+            .ungrabbed
+            .iter()
+            .map(|(path, name)| UngrabbedDevice {
+                path: path.clone(),
+                name: name.clone(),
+                holders: grab_holders(path),
+            })
+            .collect()
+    }
+}
+
+fn is_event_node(path: &Path) -> bool {
+    // Skip directories and non input event files.
+    !path.is_dir() && path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.starts_with("event"))
+        .unwrap_or(false)
 }
 
 async fn spawn_reader(
     path: &Path,
     event_sender: mpsc::UnboundedSender<Result<Event, Error>>,
+    shared: &SharedState,
 ) -> Result<(), Error> {
-    if path.is_dir() {
+    // The device is claimed before it is opened, so that another task opening it meanwhile leaves
+    // it alone rather than finding it busy with our own grab.
+    if !is_event_node(path) || !shared.lock().unwrap().grabbed.insert(path.to_owned()) {
         return Ok(());
     }
 
-    // Skip non input event files.
-    if path
-        .file_name()
-        .and_then(|name| name.to_str())
-        .map(|name| !name.starts_with("event"))
-        .unwrap_or(true)
-    {
-        return Ok(());
+    let result = EventReader::new(path, shared).await;
+    if result.is_err() {
+        shared.lock().unwrap().grabbed.remove(path);
     }
 
-    let reader = match EventReader::new(path).await {
+    let was_ungrabbed = match result {
+        Err(OpenError::Busy(ref name)) => {
+            let mut state = shared.lock().unwrap();
+            if !state.ungrabbed.contains_key(path) {
+                let holders = grab_holders(path);
+                if holders.is_empty() {
+                    log::warn!(
+                        "{} ({}) is grabbed by another process, not forwarding it for now",
+                        path.display(),
+                        name,
+                    );
+                } else {
+                    log::warn!(
+                        "{} ({}) is grabbed by another process, not forwarding it for now. It is open in {}",
+                        path.display(),
+                        name,
+                        holders.join(", "),
+                    );
+                }
+                state.ungrabbed.insert(path.to_owned(), name.clone());
+            }
+            return Ok(());
+        }
+        // Grabbing worked, or the device is gone or was never wanted.
+        _ => shared.lock().unwrap().ungrabbed.remove(path).is_some(),
+    };
+
+    let mut reader = match result {
         Ok(reader) => reader,
         Err(OpenError::Io(err)) => return Err(err),
-        Err(OpenError::AlreadyOpened) => return Ok(()),
+        Err(OpenError::AlreadyOpened) | Err(OpenError::Busy(_)) => return Ok(()),
     };
 
+    if was_ungrabbed {
+        log::info!("{} ({}) is no longer grabbed by another process", path.display(), reader.device.name);
+    }
+
+    // The keys are released right below, so announce the device without them. Without a grab,
+    // whatever had the device still sees them come back up, so they are left alone.
+    let held_keys = if reader.grabbed { std::mem::take(&mut reader.device.held_keys) } else { Vec::new() };
+
     let event = Event::NewDevice(reader.device.clone());
-    event_sender.send(Ok(event)).unwrap();
+    if event_sender.send(Ok(event)).is_err() {
+        // Nobody is reading events anymore.
+        shared.lock().unwrap().grabbed.remove(path);
+        return Ok(());
+    }
 
-    tokio::spawn(handle_events(reader, event_sender));
+    // Whatever had the device before us saw these keys go down but will never see them come back
+    // up, since the release now comes to us instead. The classic case is the Enter key when evkvm
+    // is started from a terminal. Release them right away so nothing is left stuck.
+    for kind in held_keys {
+        let event = Event::Input {
+            device_id: reader.device.id,
+            input: InputEvent::Key { direction: Direction::Up, kind },
+            syn: true,
+        };
+        let _ = event_sender.send(Ok(event));
+    }
+
+    let grab = shared.lock().unwrap().grab.clone();
+    let path = path.to_owned();
+    let shared = shared.clone();
+    tokio::spawn(async move {
+        let device_id = reader.device.id;
+        let _ = handle_events(reader, event_sender, grab, &shared).await;
+        // A device that goes away isn't paused anymore, so whatever shows up on its node next is
+        // forwarded like any other.
+        let mut state = shared.lock().unwrap();
+        state.grabbed.remove(&path);
+        state.paused.remove(&device_id);
+    });
 
     Ok(())
 }
 
+async fn retry_grabs(sender: mpsc::UnboundedSender<Result<Event, Error>>, shared: SharedState) {
+    loop {
+        time::sleep(GRAB_RETRY_INTERVAL).await;
+        if sender.is_closed() {
+            return;
+        }
+
+        let paths: Vec<PathBuf> = shared.lock().unwrap().ungrabbed.keys().cloned().collect();
+        for path in paths {
+            if let Err(err) = spawn_reader(&path, sender.clone(), &shared).await {
+                log::warn!("{}: {}", path.display(), err);
+            }
+        }
+    }
+}
+
+async fn supervise_notify(
+    watcher: Arc<dyn DeviceWatcher>,
+    sender: mpsc::UnboundedSender<Result<Event, Error>>,
+    shared: SharedState,
+    watcher_sender: oneshot::Sender<Error>,
+) {
+    let mut failures = 0;
+    let mut backoff = WATCHER_BACKOFF_MIN;
+    loop {
+        let started = time::Instant::now();
+        let err = match handle_notify(&*watcher, sender.clone(), &shared).await {
+            Ok(()) => Error::other("Device watcher stopped"),
+            Err(err) => err,
+        };
+        if sender.is_closed() {
+            return;
+        }
+
+        if started.elapsed() >= WATCHER_STABLE_AFTER {
+            failures = 0;
+            backoff = WATCHER_BACKOFF_MIN;
+        }
+        failures += 1;
+        if failures >= WATCHER_MAX_FAILURES {
+            let _ = watcher_sender.send(err);
+            return;
+        }
+
+        log::warn!("Device watcher failed ({}), restarting in {:?}", err, backoff);
+        time::sleep(backoff).await;
+        backoff = (backoff * 2).min(WATCHER_BACKOFF_MAX);
+
+        // Pick up devices that were plugged in while the watcher was down.
+        match fs::read_dir(EVENT_PATH).await {
+            Ok(mut read_dir) => {
+                while let Ok(Some(entry)) = read_dir.next_entry().await {
+                    if let Err(err) = spawn_reader(&entry.path(), sender.clone(), &shared).await {
+                        log::warn!("{}: {}", entry.path().display(), err);
+                    }
+                }
+            }
+            Err(err) => log::warn!("Failed to list {}: {}", EVENT_PATH, err),
+        }
+    }
+}
+
 async fn handle_notify(
+    watcher: &dyn DeviceWatcher,
     sender: mpsc::UnboundedSender<Result<Event, Error>>,
+    shared: &SharedState,
 ) -> Result<(), Error> {
-    let mut inotify = Inotify::init()?;
-    inotify.add_watch(EVENT_PATH, WatchMask::CREATE)?;
-
-    // This buffer size should be OK, since we don't expect a lot of devices
-    // to be plugged in frequently.
-    let mut stream = inotify.event_stream([0u8; 512])?;
-    while let Some(event) = stream.next().await {
-        let event = event?;
-
-        if let Some(name) = event.name {
-            let path = Path::new(EVENT_PATH).join(&name);
-            spawn_reader(&path, sender.clone()).await?;
+    let mut paths = watcher.watch()?;
+    while let Some(path) = paths.next().await {
+        let path = path?;
+        // A device we can't open shouldn't stop us from noticing the next one.
+        if let Err(err) = spawn_reader(&path, sender.clone(), shared).await {
+            log::warn!("{}: {}", path.display(), err);
         }
     }
 
@@ -352,10 +849,47 @@ async fn handle_notify(
 async fn handle_events(
     mut reader: EventReader,
     sender: mpsc::UnboundedSender<Result<Event, Error>>,
+    mut grab: watch::Receiver<bool>,
+    shared: &SharedState,
 ) -> Result<(), watch::error::RecvError> {
+    let mut paused = false;
     loop {
-        let result = match reader.read().await {
+        let now_paused = shared.lock().unwrap().paused.contains_key(&reader.device.id);
+        if now_paused != paused {
+            paused = now_paused;
+            let event = if paused {
+                Event::RemoveDevice(reader.device.id)
+            } else {
+                // Whatever is held down now went down on this machine alone.
+                Event::NewDevice(Device { held_keys: Vec::new(), ..reader.device.clone() })
+            };
+            if sender.send(Ok(event)).is_err() {
+                return Ok(());
+            }
+        }
+        reader.follow_grab(*grab.borrow() && !paused);
+
+        let read = {
+            let read = reader.read();
+            let changed = grab.changed();
+            futures::pin_mut!(read, changed);
+            match future::select(read, changed).await {
+                Either::Left((read, _)) => read,
+                Either::Right((changed, _)) => {
+                    // The ReaderManager is gone once this fails.
+                    changed?;
+                    continue;
+                }
+            }
+        };
+
+        let result = match read {
             Ok(input_event) => {
+                // Grabbing waits for held keys to come back up.
+                reader.device.update_state(&input_event);
+                if paused {
+                    continue;
+                }
                 let event = Event::Input {
                     device_id: reader.device.id,
                     input: input_event,
@@ -364,14 +898,23 @@ async fn handle_events(
                 sender.send(Ok(event)).is_ok()
             }
             // This happens if the device is disconnected.
-            // In that case simply terminate the reading task.
+            // In that case simply terminate the reading task. A paused device was already
+            // reported gone.
             Err(ref err) if err.raw_os_error() == Some(libc::ENODEV) => {
-                let event = Event::RemoveDevice(reader.device.id);
-                let _ = sender.send(Ok(event));
+                if !paused {
+                    let event = Event::RemoveDevice(reader.device.id);
+                    let _ = sender.send(Ok(event));
+                }
                 false
             },
+            // Any other error only affects this device, so stop forwarding it instead of treating
+            // it like a failure of the whole reader.
             Err(err) => {
-                let _ = sender.send(Err(err));
+                log::error!("Error reading from {}: {}", reader.device.name, err);
+                if !paused {
+                    let event = Event::RemoveDevice(reader.device.id);
+                    let _ = sender.send(Ok(event));
+                }
                 false
             },
         };
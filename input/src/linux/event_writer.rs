@@ -1,52 +1,67 @@
-use crate::event::{Event, Device, InputEvent, Capability};
-use crate::linux::glue::{self, input_event, libevdev, libevdev_uinput};
+use crate::event::{Capability, Event, Device, Direction, Feedback, InputEvent};
+use crate::linux::device_error::{diagnose_uinput, DeviceError};
+use crate::linux::evdev::{Evdev, Uinput};
+use crate::linux::force_feedback;
+use crate::linux::glue::{self, input_event};
 use std::io::{Error, ErrorKind};
-use std::mem::MaybeUninit;
-use std::ffi;
 use std::collections::HashMap;
+use std::os::unix::io::AsRawFd;
+use tokio::sync::{mpsc, oneshot};
+
+// The phys of every device we create starts with this, so that we can tell them apart from the
+// devices we should read from.
+pub(crate) const OWN_PHYS: &str = "evkvm";
 
 pub struct EventWriter {
-    evdev: *mut libevdev,
-    uinput: *mut libevdev_uinput,
+    uinput: Uinput,
+    id: u16,
+    // The description the virtual device was created from, kept for as long as the device exists.
+    _evdev: Evdev,
+    // Stops passing on feedback for the device when dropped.
+    _feedback: Option<oneshot::Sender<()>>,
 }
 
 impl EventWriter {
-    pub async fn new(device: Device) -> Result<Self, Error> {
-        tokio::task::spawn_blocking(move || Self::new_sync(&device)).await?
-    }
-
-    fn new_sync(device: &Device) -> Result<Self, Error> {
-        let evdev = unsafe { glue::libevdev_new() };
-        if evdev.is_null() {
-            return Err(Error::new(ErrorKind::Other, "Failed to create device"));
+    // Force feedback and LED requests made of the virtual device are sent to `feedback`, if given.
+    pub async fn new(
+        device: Device,
+        phys: Option<String>,
+        feedback: Option<mpsc::UnboundedSender<(u16, Feedback)>>,
+    ) -> Result<Self, DeviceError> {
+        let mut writer = tokio::task::spawn_blocking(move || Self::new_sync(&device, phys.as_deref()))
+            .await
+            .map_err(Error::from)??;
+        if let Some(feedback) = feedback {
+            writer._feedback = Some(force_feedback::serve(writer.uinput.as_raw_fd(), writer.id, feedback)?);
         }
+        Ok(writer)
+    }
 
-        if let Err(err) = unsafe { setup_evdev(evdev, device) } {
-            unsafe {
-                glue::libevdev_free(evdev);
-            }
+    fn new_sync(device: &Device, phys: Option<&str>) -> Result<Self, DeviceError> {
+        let mut evdev = Evdev::new()?;
+        setup_evdev(&mut evdev, device, phys)?;
 
-            return Err(err);
-        }
+        let uinput = evdev.create_uinput().map_err(diagnose_uinput)?;
+        let mut writer = Self { uinput, _evdev: evdev, id: device.id, _feedback: None };
 
-        let mut uinput = MaybeUninit::uninit();
-        let ret = unsafe {
-            glue::libevdev_uinput_create_from_device(
-                evdev,
-                glue::libevdev_uinput_open_mode_LIBEVDEV_UINPUT_OPEN_MANAGED,
-                uinput.as_mut_ptr(),
-            )
+        // Match the state of the original device, in case something was already held down or lit
+        // up when it was announced.
+        let syn = InputEvent::Other {
+            type_: glue::EV_SYN as _,
+            code: glue::SYN_REPORT as _,
+            value: 0,
         };
-
-        if ret < 0 {
-            log::error!("Error creating a uinput device, is the uinput kernel module loaded?");
-            log::error!("Check `lsmod | grep uinput`");
-            unsafe { glue::libevdev_free(evdev) };
-            return Err(Error::from_raw_os_error(-ret));
+        for &kind in &device.held_keys {
+            writer.write_raw(InputEvent::Key { direction: Direction::Down, kind }.to_raw())?;
+            writer.write_raw(syn.to_raw())?;
+        }
+        for &code in &device.lit_leds {
+            let led = InputEvent::Other { type_: glue::EV_LED as u16, code, value: 1 };
+            writer.write_raw(led.to_raw())?;
+            writer.write_raw(syn.to_raw())?;
         }
 
-        let uinput = unsafe { uinput.assume_init() };
-        Ok(Self { evdev, uinput })
+        Ok(writer)
     }
 
     pub async fn write(&mut self, event: InputEvent) -> Result<(), Error> {
@@ -57,106 +72,104 @@ impl EventWriter {
         // As far as tokio is concerned, the FD never becomes ready for writing, so just write it normally.
         // If an error happens, it will be propagated to caller and the FD is opened in nonblocking mode anyway,
         // so it shouldn't be an issue.
-        let ret = unsafe {
-            glue::libevdev_uinput_write_event(
-                self.uinput as *const _,
-                event.type_ as _,
-                event.code as _,
-                event.value,
-            )
-        };
-
-        if ret < 0 {
+        if let Err(err) = self.uinput.write_event(&event) {
             log::error!("Error writing event {:?}", &event);
-            return Err(Error::from_raw_os_error(-ret));
+            return Err(err);
         }
 
         Ok(())
     }
 }
 
-impl Drop for EventWriter {
-    fn drop(&mut self) {
-        unsafe {
-            glue::libevdev_uinput_destroy(self.uinput);
-            glue::libevdev_free(self.evdev);
-        }
-    }
-}
-
-unsafe impl Send for EventWriter {}
+fn setup_evdev(evdev: &mut Evdev, device: &Device, phys: Option<&str>) -> Result<(), Error> {
+    evdev.set_ids(device.vendor, device.product, glue::BUS_VIRTUAL as _, device.version);
+    evdev.set_name(&device.name)?;
 
-unsafe fn setup_evdev(evdev: *mut libevdev, device: &Device) -> Result<(), Error> {
-    glue::libevdev_set_id_vendor(evdev, device.vendor as _);
-    glue::libevdev_set_id_product(evdev, device.product as _);
-    glue::libevdev_set_id_version(evdev, device.version as _);
-    glue::libevdev_set_id_bustype(evdev, glue::BUS_VIRTUAL as _);
-
-    let name_c_string = ffi::CString::new(device.name.clone()).unwrap();
-    glue::libevdev_set_name(evdev, name_c_string.as_ptr() as *const _);
+    // uinput has no way to set a device's uniq, so phys is the only free-form identifier we can
+    // give virtual devices.
+    let phys = phys.unwrap_or(OWN_PHYS);
+    if !phys.starts_with(OWN_PHYS) {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("phys must start with {}", OWN_PHYS)));
+    }
+    evdev.set_phys(phys)?;
 
     for capability in &device.capabilities {
-        let ret = match *capability {
-            Capability::Abs { code, info } => {
-                let absinfo = glue::input_absinfo {
-                    value: info.value,
-                    minimum: info.minimum,
-                    maximum: info.maximum,
-                    fuzz: info.fuzz,
-                    flat: info.flat,
-                    resolution: info.resolution,
-                };
-                glue::libevdev_enable_event_code(
-                    evdev,
-                    glue::EV_ABS,
-                    code as _,
-                    &absinfo as *const glue::input_absinfo as *const _,
-                )
-            },
-            Capability::Rep { code, value } => {
-                glue::libevdev_enable_event_code(
-                    evdev,
-                    glue::EV_REP,
-                    code as _,
-                    &value as *const i32 as *const _,
-                )
-            },
-            Capability::Other { type_, code } => {
-                glue::libevdev_enable_event_code(
-                    evdev,
-                    type_ as _,
-                    code as _,
-                    std::ptr::null_mut(),
-                )
-            },
-        };
-        if ret < 0 {
+        if let Err(err) = evdev.enable(capability) {
             log::error!("Error enabling capability {:?}", capability);
-            return Err(Error::from_raw_os_error(-ret));
+            return Err(err);
         }
     }
 
+    for &property in &device.properties {
+        evdev.enable_property(property)?;
+    }
+
     Ok(())
 }
 
 
 pub struct WriterManager {
     pub writers: HashMap<u16, EventWriter>,
+    feedback_sender: mpsc::UnboundedSender<(u16, Feedback)>,
+    feedback_receiver: mpsc::UnboundedReceiver<(u16, Feedback)>,
+    // With on_demand, the devices that were announced, which only get a virtual copy once
+    // something is written to them.
+    on_demand: Option<HashMap<u16, Device>>,
 }
 
 impl WriterManager {
     pub async fn new() -> Self {
         let writers: HashMap<u16, EventWriter> = HashMap::new();
+        let (feedback_sender, feedback_receiver) = mpsc::unbounded_channel();
 
-        WriterManager { writers }
+        WriterManager { writers, feedback_sender, feedback_receiver, on_demand: None }
+    }
+
+    // Like new, but a device's virtual copy is only created when the first event is written to
+    // it, and park destroys them again. Devices that nothing is written to never show up at all.
+    pub async fn on_demand() -> Self {
+        let mut manager = Self::new().await;
+        manager.on_demand = Some(HashMap::new());
+        manager
+    }
+
+    // With on_demand, destroy all virtual copies until something is written to them again.
+    // Anything still held down on them is released along with them.
+    pub fn park(&mut self) {
+        if self.on_demand.is_some() {
+            self.writers.clear();
+        }
     }
 
-    pub async fn write(&mut self, event: Event) -> Result<(), Error> {
+    pub async fn create_device(&mut self, device: Device, phys: Option<String>) -> Result<(), DeviceError> {
+        let id = device.id;
+        // Only devices with force feedback or LEDs have anything asked of them.
+        let takes_feedback = device.capabilities.iter().any(|capability| match *capability {
+            Capability::Other { type_, .. } => type_ as u32 == glue::EV_FF || type_ as u32 == glue::EV_LED,
+            _ => false,
+        });
+        let feedback = if takes_feedback { Some(self.feedback_sender.clone()) } else { None };
+        let writer = EventWriter::new(device, phys, feedback).await?;
+        self.writers.insert(id, writer);
+        Ok(())
+    }
+
+    pub async fn write(&mut self, event: Event) -> Result<(), DeviceError> {
         match event {
             Event::Input { device_id, input, syn } => {
+                if let Some(devices) = &self.on_demand {
+                    if let (false, Some(device)) = (self.writers.contains_key(&device_id), devices.get(&device_id)) {
+                        // What was held down or lit when the device was announced has long
+                        // changed since.
+                        let mut device = device.clone();
+                        device.held_keys.clear();
+                        device.lit_leds.clear();
+                        self.create_device(device, None).await?;
+                    }
+                }
                 match self.writers.get_mut(&device_id) {
                     Some(writer) => {
-                        if syn {
+                        let result = if syn {
                             let syn_input = InputEvent::Other {
                                 type_: glue::EV_SYN as _,
                                 code: glue::SYN_REPORT as _,
@@ -168,21 +181,38 @@ impl WriterManager {
                             }
                         } else {
                             writer.write(input).await
-                        }
+                        };
+                        result.map_err(DeviceError::from)
                     },
                     _ => Ok(()),
                 }
             },
-            Event::NewDevice(device) => {
-                let id = device.id;
-                let writer = EventWriter::new(device).await?;
-                self.writers.insert(id, writer);
-                Ok(())
+            Event::NewDevice(device) => match &mut self.on_demand {
+                Some(devices) => {
+                    // A copy of an earlier device with the same id would be out of date.
+                    self.writers.remove(&device.id);
+                    devices.insert(device.id, device);
+                    Ok(())
+                },
+                None => self.create_device(device, None).await,
             },
             Event::RemoveDevice(device_id) => {
                 self.writers.remove(&device_id);
+                if let Some(devices) = &mut self.on_demand {
+                    devices.remove(&device_id);
+                }
                 Ok(())
             },
         }
     }
+
+    // Wait for a program to ask something of one of the devices, like playing a rumble effect on a
+    // gamepad or lighting caps lock on a keyboard. Gives the id of the device and what was asked.
+    pub async fn feedback(&mut self) -> (u16, Feedback) {
+        match self.feedback_receiver.recv().await {
+            Some(feedback) => feedback,
+            // We hold a sender ourselves, so this can't happen.
+            None => futures::future::pending().await,
+        }
+    }
 }
@@ -0,0 +1,436 @@
+use crate::event::{Condition, Effect, EffectKind, Envelope, Feedback, InputEvent};
+use crate::linux::evdev::Evdev;
+use crate::linux::glue::{self, input_event};
+use crate::linux::ioctl::{ioc, ioctl, ioctl_int, IOC_INT, IOC_READ, IOC_WRITE};
+use futures::future::{self, Either};
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Error;
+use std::mem::{self, MaybeUninit};
+use std::os::raw::{c_int, c_ulong};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use tokio::io::unix::AsyncFd;
+use tokio::sync::{mpsc, oneshot};
+
+// Feedback goes the other way from input: programs upload and play force feedback effects on a
+// device, and set its LEDs. For a virtual device, uinput asks whoever created it to carry that out,
+// so the receiver answers for the copies it makes and passes the requests on, and the sender
+// carries them out on the real devices.
+
+// uinput's requests, read from its file along with EV_FF and EV_LED events.
+const EV_UINPUT: u16 = 0x0101;
+const UI_FF_UPLOAD: u16 = 1;
+const UI_FF_ERASE: u16 = 2;
+
+// Effect types and waveforms in linux/input.h. The condition effects are FF_SPRING up to
+// FF_INERTIA. EV_FF codes from FF_GAIN on are settings rather than effect ids.
+const FF_RUMBLE: u16 = 0x50;
+const FF_PERIODIC: u16 = 0x51;
+const FF_CONSTANT: u16 = 0x52;
+const FF_SPRING: u16 = 0x53;
+const FF_INERTIA: u16 = 0x56;
+const FF_RAMP: u16 = 0x57;
+const FF_CUSTOM: u16 = 0x5d;
+const FF_GAIN: u16 = 0x60;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FfEnvelope {
+    attack_length: u16,
+    attack_level: u16,
+    fade_length: u16,
+    fade_level: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FfConstant {
+    level: i16,
+    envelope: FfEnvelope,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FfRamp {
+    start_level: i16,
+    end_level: i16,
+    envelope: FfEnvelope,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FfPeriodic {
+    waveform: u16,
+    period: u16,
+    magnitude: i16,
+    offset: i16,
+    phase: u16,
+    envelope: FfEnvelope,
+    custom_len: u32,
+    custom_data: *mut i16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FfCondition {
+    right_saturation: u16,
+    left_saturation: u16,
+    right_coeff: i16,
+    left_coeff: i16,
+    deadband: u16,
+    center: i16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FfRumble {
+    strong_magnitude: u16,
+    weak_magnitude: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+union FfParameters {
+    constant: FfConstant,
+    ramp: FfRamp,
+    periodic: FfPeriodic,
+    condition: [FfCondition; 2],
+    rumble: FfRumble,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FfTrigger {
+    button: u16,
+    interval: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FfReplay {
+    length: u16,
+    delay: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FfEffect {
+    type_: u16,
+    id: i16,
+    direction: u16,
+    trigger: FfTrigger,
+    replay: FfReplay,
+    u: FfParameters,
+}
+
+#[repr(C)]
+struct UinputFfUpload {
+    request_id: u32,
+    retval: i32,
+    effect: FfEffect,
+    old: FfEffect,
+}
+
+#[repr(C)]
+struct UinputFfErase {
+    request_id: u32,
+    retval: i32,
+    effect_id: u32,
+}
+
+const UI_BEGIN_FF_UPLOAD: c_ulong = ioc(IOC_READ | IOC_WRITE, b'U', 200, mem::size_of::<UinputFfUpload>());
+const UI_END_FF_UPLOAD: c_ulong = ioc(IOC_WRITE, b'U', 201, mem::size_of::<UinputFfUpload>());
+const UI_BEGIN_FF_ERASE: c_ulong = ioc(IOC_READ | IOC_WRITE, b'U', 202, mem::size_of::<UinputFfErase>());
+const UI_END_FF_ERASE: c_ulong = ioc(IOC_WRITE, b'U', 203, mem::size_of::<UinputFfErase>());
+const EVIOCSFF: c_ulong = ioc(IOC_WRITE, b'E', 0x80, mem::size_of::<FfEffect>());
+const EVIOCRMFF: c_ulong = ioc(IOC_INT, b'E', 0x81, mem::size_of::<c_int>());
+
+fn envelope(raw: FfEnvelope) -> Envelope {
+    Envelope {
+        attack_length: raw.attack_length,
+        attack_level: raw.attack_level,
+        fade_length: raw.fade_length,
+        fade_level: raw.fade_level,
+    }
+}
+
+fn raw_envelope(envelope: Envelope) -> FfEnvelope {
+    FfEnvelope {
+        attack_length: envelope.attack_length,
+        attack_level: envelope.attack_level,
+        fade_length: envelope.fade_length,
+        fade_level: envelope.fade_level,
+    }
+}
+
+fn condition(raw: FfCondition) -> Condition {
+    Condition {
+        right_saturation: raw.right_saturation,
+        left_saturation: raw.left_saturation,
+        right_coeff: raw.right_coeff,
+        left_coeff: raw.left_coeff,
+        deadband: raw.deadband,
+        center: raw.center,
+    }
+}
+
+fn raw_condition(condition: Condition) -> FfCondition {
+    FfCondition {
+        right_saturation: condition.right_saturation,
+        left_saturation: condition.left_saturation,
+        right_coeff: condition.right_coeff,
+        left_coeff: condition.left_coeff,
+        deadband: condition.deadband,
+        center: condition.center,
+    }
+}
+
+// None for effects that can't be passed on.
+fn from_raw(raw: &FfEffect) -> Option<Effect> {
+    // The type says which of the parameters are meant.
+    let kind = unsafe {
+        match raw.type_ {
+            FF_RUMBLE => EffectKind::Rumble {
+                strong_magnitude: raw.u.rumble.strong_magnitude,
+                weak_magnitude: raw.u.rumble.weak_magnitude,
+            },
+            // Custom waveforms point into the uploading program's memory.
+            FF_PERIODIC if raw.u.periodic.waveform != FF_CUSTOM => EffectKind::Periodic {
+                waveform: raw.u.periodic.waveform,
+                period: raw.u.periodic.period,
+                magnitude: raw.u.periodic.magnitude,
+                offset: raw.u.periodic.offset,
+                phase: raw.u.periodic.phase,
+                envelope: envelope(raw.u.periodic.envelope),
+            },
+            FF_CONSTANT => EffectKind::Constant {
+                level: raw.u.constant.level,
+                envelope: envelope(raw.u.constant.envelope),
+            },
+            FF_RAMP => EffectKind::Ramp {
+                start_level: raw.u.ramp.start_level,
+                end_level: raw.u.ramp.end_level,
+                envelope: envelope(raw.u.ramp.envelope),
+            },
+            FF_SPRING..=FF_INERTIA => EffectKind::Condition {
+                type_: raw.type_,
+                axes: [condition(raw.u.condition[0]), condition(raw.u.condition[1])],
+            },
+            _ => return None,
+        }
+    };
+    Some(Effect {
+        kind,
+        direction: raw.direction,
+        trigger_button: raw.trigger.button,
+        trigger_interval: raw.trigger.interval,
+        replay_length: raw.replay.length,
+        replay_delay: raw.replay.delay,
+    })
+}
+
+fn to_raw(effect: &Effect, id: i16) -> FfEffect {
+    let mut raw: FfEffect = unsafe { mem::zeroed() };
+    raw.id = id;
+    raw.direction = effect.direction;
+    raw.trigger = FfTrigger { button: effect.trigger_button, interval: effect.trigger_interval };
+    raw.replay = FfReplay { length: effect.replay_length, delay: effect.replay_delay };
+    match effect.kind {
+        EffectKind::Rumble { strong_magnitude, weak_magnitude } => {
+            raw.type_ = FF_RUMBLE;
+            raw.u.rumble = FfRumble { strong_magnitude, weak_magnitude };
+        }
+        EffectKind::Periodic { waveform, period, magnitude, offset, phase, envelope } => {
+            raw.type_ = FF_PERIODIC;
+            raw.u.periodic = FfPeriodic {
+                waveform,
+                period,
+                magnitude,
+                offset,
+                phase,
+                envelope: raw_envelope(envelope),
+                custom_len: 0,
+                custom_data: std::ptr::null_mut(),
+            };
+        }
+        EffectKind::Constant { level, envelope } => {
+            raw.type_ = FF_CONSTANT;
+            raw.u.constant = FfConstant { level, envelope: raw_envelope(envelope) };
+        }
+        EffectKind::Ramp { start_level, end_level, envelope } => {
+            raw.type_ = FF_RAMP;
+            raw.u.ramp = FfRamp { start_level, end_level, envelope: raw_envelope(envelope) };
+        }
+        EffectKind::Condition { type_, axes } => {
+            raw.type_ = type_;
+            raw.u.condition = [raw_condition(axes[0]), raw_condition(axes[1])];
+        }
+    }
+    raw
+}
+
+// A duplicate of a virtual device's uinput file descriptor, closed when dropped.
+struct Duplicate(RawFd);
+
+impl AsRawFd for Duplicate {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl Drop for Duplicate {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+// Answer the requests made of the virtual device behind `uinput`, and send on what they ask for
+// along with `device_id`, in the background. This goes on until the returned sender is dropped.
+pub(crate) fn serve(
+    uinput: RawFd,
+    device_id: u16,
+    sender: mpsc::UnboundedSender<(u16, Feedback)>,
+) -> Result<oneshot::Sender<()>, Error> {
+    let fd = unsafe { libc::fcntl(uinput, libc::F_DUPFD_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(Error::last_os_error());
+    }
+    let duplicate = Duplicate(fd);
+
+    // libevdev opens uinput in blocking mode. The flag is shared with the original, but writing
+    // events to uinput never has to wait anyway.
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(Error::last_os_error());
+    }
+    let duplicate = AsyncFd::new(duplicate)?;
+
+    let (stop_sender, stop) = oneshot::channel();
+    tokio::spawn(async move {
+        if let Err(err) = answer(duplicate, device_id, sender, stop).await {
+            log::warn!("Stopped passing on feedback for device {}: {}", device_id, err);
+        }
+    });
+    Ok(stop_sender)
+}
+
+async fn answer(
+    uinput: AsyncFd<Duplicate>,
+    device_id: u16,
+    sender: mpsc::UnboundedSender<(u16, Feedback)>,
+    mut stop: oneshot::Receiver<()>,
+) -> Result<(), Error> {
+    loop {
+        let mut guard = match future::select(Box::pin(uinput.readable()), &mut stop).await {
+            Either::Left((guard, _)) => guard?,
+            Either::Right(_) => return Ok(()),
+        };
+        let event = match guard.try_io(|uinput| read_event(uinput.as_raw_fd())) {
+            Ok(result) => result?,
+            Err(_) => continue, // This means it would block.
+        };
+        if let Some(feedback) = handle(uinput.as_raw_fd(), event)? {
+            if sender.send((device_id, feedback)).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn read_event(fd: RawFd) -> Result<input_event, Error> {
+    let mut event = MaybeUninit::<input_event>::uninit();
+    let size = mem::size_of::<input_event>();
+    let ret = unsafe { libc::read(fd, event.as_mut_ptr() as *mut _, size) };
+    if ret < 0 {
+        return Err(Error::last_os_error());
+    }
+    // uinput only ever hands out whole events.
+    Ok(unsafe { event.assume_init() })
+}
+
+fn handle(fd: RawFd, event: input_event) -> Result<Option<Feedback>, Error> {
+    let feedback = match (event.type_, event.code) {
+        (EV_UINPUT, UI_FF_UPLOAD) => {
+            let mut upload: UinputFfUpload = unsafe { mem::zeroed() };
+            upload.request_id = event.value as u32;
+            unsafe { ioctl(fd, UI_BEGIN_FF_UPLOAD, &mut upload as *mut UinputFfUpload as *mut _) }?;
+            let effect = from_raw(&upload.effect);
+            // The program uploading the effect waits for this answer, so it is given right away
+            // rather than after the sender has tried the effect.
+            upload.retval = if effect.is_some() { 0 } else { -libc::EINVAL };
+            unsafe { ioctl(fd, UI_END_FF_UPLOAD, &mut upload as *mut UinputFfUpload as *mut _) }?;
+            effect.map(|effect| Feedback::Upload { id: upload.effect.id, effect })
+        }
+        (EV_UINPUT, UI_FF_ERASE) => {
+            let mut erase: UinputFfErase = unsafe { mem::zeroed() };
+            erase.request_id = event.value as u32;
+            unsafe { ioctl(fd, UI_BEGIN_FF_ERASE, &mut erase as *mut UinputFfErase as *mut _) }?;
+            erase.retval = 0;
+            unsafe { ioctl(fd, UI_END_FF_ERASE, &mut erase as *mut UinputFfErase as *mut _) }?;
+            Some(Feedback::Erase { id: erase.effect_id as i16 })
+        }
+        (type_, code) if type_ as u32 == glue::EV_FF => Some(Feedback::Play { code, value: event.value }),
+        (type_, code) if type_ as u32 == glue::EV_LED => Some(Feedback::Led { code, lit: event.value != 0 }),
+        _ => None,
+    };
+    Ok(feedback)
+}
+
+// Carries out feedback on a real device, through a file of its own. Effects belong to the file
+// they were uploaded through, so dropping this erases them, stopping any that are playing.
+pub(crate) struct FeedbackTarget {
+    evdev: Evdev,
+    // The ids effects have on the virtual device, and the ones the real device gave them.
+    effects: HashMap<i16, i16>,
+}
+
+impl FeedbackTarget {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC)
+            .open(path)?;
+        let evdev = Evdev::from_file(file)?;
+        Ok(FeedbackTarget { evdev, effects: HashMap::new() })
+    }
+
+    pub fn apply(&mut self, feedback: Feedback) -> Result<(), Error> {
+        let fd = self.evdev.as_raw_fd();
+        match feedback {
+            Feedback::Upload { id, effect } => {
+                // An effect the device doesn't have yet gets an id from it, and one it has is
+                // updated in place.
+                let mut raw = to_raw(&effect, self.effects.get(&id).copied().unwrap_or(-1));
+                unsafe { ioctl(fd, EVIOCSFF, &mut raw as *mut FfEffect as *mut _) }?;
+                self.effects.insert(id, raw.id);
+            }
+            Feedback::Erase { id } => {
+                if let Some(local_id) = self.effects.remove(&id) {
+                    unsafe { ioctl_int(fd, EVIOCRMFF, local_id as c_int) }?;
+                }
+            }
+            Feedback::Play { code, value } => {
+                let code = match code {
+                    code if code >= FF_GAIN => code,
+                    id => match self.effects.get(&(id as i16)) {
+                        Some(&local_id) => local_id as u16,
+                        None => return Ok(()),
+                    },
+                };
+                self.write(InputEvent::Other { type_: glue::EV_FF as u16, code, value })?;
+            }
+            Feedback::Led { code, lit } => {
+                self.write(InputEvent::Other { type_: glue::EV_LED as u16, code, value: lit as i32 })?;
+                self.write(InputEvent::Other { type_: glue::EV_SYN as u16, code: glue::SYN_REPORT as u16, value: 0 })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, event: InputEvent) -> Result<(), Error> {
+        self.evdev.write_event(&event.to_raw())
+    }
+}
@@ -0,0 +1,48 @@
+use std::io::Error;
+use std::os::raw::{c_int, c_ulong, c_void};
+use std::os::unix::io::RawFd;
+
+// Building ioctl requests and making them, for talking to evdev and uinput without libevdev, and
+// for what libevdev doesn't cover, like force feedback.
+
+// The directions used by most Linux architectures, including x86, ARM and RISC-V, and so by
+// Android. Requests that take an int by value are declared as writes, and only the ioctl backend
+// makes requests without an argument.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[allow(dead_code)]
+pub(crate) const IOC_NONE: c_ulong = 0;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) const IOC_WRITE: c_ulong = 1 << 30;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) const IOC_READ: c_ulong = 2 << 30;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) const IOC_INT: c_ulong = IOC_WRITE;
+
+// FreeBSD's, which has a direction of its own for ints passed by value (_IOWINT).
+#[cfg(target_os = "freebsd")]
+pub(crate) const IOC_NONE: c_ulong = 0x2000_0000;
+#[cfg(target_os = "freebsd")]
+pub(crate) const IOC_WRITE: c_ulong = 0x8000_0000;
+#[cfg(target_os = "freebsd")]
+pub(crate) const IOC_READ: c_ulong = 0x4000_0000;
+#[cfg(target_os = "freebsd")]
+pub(crate) const IOC_INT: c_ulong = IOC_NONE;
+
+pub(crate) const fn ioc(dir: c_ulong, type_: u8, nr: u32, size: usize) -> c_ulong {
+    dir | ((size as c_ulong) << 16) | ((type_ as c_ulong) << 8) | nr as c_ulong
+}
+
+pub(crate) unsafe fn ioctl(fd: RawFd, request: c_ulong, arg: *mut c_void) -> Result<(), Error> {
+    // The C libraries disagree on the type of the request.
+    if libc::ioctl(fd, request as _, arg) < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub(crate) unsafe fn ioctl_int(fd: RawFd, request: c_ulong, value: c_int) -> Result<(), Error> {
+    if libc::ioctl(fd, request as _, value) < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,473 @@
+use crate::event::{AbsInfo, Capability};
+use crate::linux::glue::{self, input_absinfo, input_event};
+use crate::linux::ioctl::{ioc, ioctl, ioctl_int, IOC_INT, IOC_NONE, IOC_READ, IOC_WRITE};
+use std::collections::BTreeSet;
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind};
+use std::mem::{self, MaybeUninit};
+use std::os::raw::{c_char, c_int, c_ulong};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+// The same interface as the libevdev backend, but talking to evdev and uinput with ioctls
+// directly, for builds without libevdev. A device node is described once when it is opened, since
+// apart from its events, the description is all anything asks of it. Unlike libevdev, this
+// doesn't resynchronize device state after the kernel drops events.
+
+const UINPUT_PATH: &str = "/dev/uinput";
+const STRING_SIZE: usize = 256;
+const UINPUT_MAX_NAME_SIZE: usize = 80;
+
+const EVIOCGID: c_ulong = ioc(IOC_READ, b'E', 0x02, mem::size_of::<InputId>());
+const EVIOCGREP: c_ulong = ioc(IOC_READ, b'E', 0x03, mem::size_of::<[u32; 2]>());
+const EVIOCGRAB: c_ulong = ioc(IOC_INT, b'E', 0x90, mem::size_of::<c_int>());
+
+const fn eviocgname(size: usize) -> c_ulong {
+    ioc(IOC_READ, b'E', 0x06, size)
+}
+
+const fn eviocgphys(size: usize) -> c_ulong {
+    ioc(IOC_READ, b'E', 0x07, size)
+}
+
+const fn eviocguniq(size: usize) -> c_ulong {
+    ioc(IOC_READ, b'E', 0x08, size)
+}
+
+const fn eviocgprop(size: usize) -> c_ulong {
+    ioc(IOC_READ, b'E', 0x09, size)
+}
+
+const fn eviocgkey(size: usize) -> c_ulong {
+    ioc(IOC_READ, b'E', 0x18, size)
+}
+
+const fn eviocgled(size: usize) -> c_ulong {
+    ioc(IOC_READ, b'E', 0x19, size)
+}
+
+const fn eviocgbit(type_: u32, size: usize) -> c_ulong {
+    ioc(IOC_READ, b'E', 0x20 + type_, size)
+}
+
+const fn eviocgabs(code: u32) -> c_ulong {
+    ioc(IOC_READ, b'E', 0x40 + code, mem::size_of::<input_absinfo>())
+}
+
+const UI_DEV_CREATE: c_ulong = ioc(IOC_NONE, b'U', 1, 0);
+const UI_DEV_DESTROY: c_ulong = ioc(IOC_NONE, b'U', 2, 0);
+const UI_DEV_SETUP: c_ulong = ioc(IOC_WRITE, b'U', 3, mem::size_of::<UinputSetup>());
+const UI_ABS_SETUP: c_ulong = ioc(IOC_WRITE, b'U', 4, mem::size_of::<UinputAbsSetup>());
+const UI_SET_EVBIT: c_ulong = ioc(IOC_INT, b'U', 100, mem::size_of::<c_int>());
+const UI_SET_KEYBIT: c_ulong = ioc(IOC_INT, b'U', 101, mem::size_of::<c_int>());
+const UI_SET_RELBIT: c_ulong = ioc(IOC_INT, b'U', 102, mem::size_of::<c_int>());
+const UI_SET_ABSBIT: c_ulong = ioc(IOC_INT, b'U', 103, mem::size_of::<c_int>());
+const UI_SET_MSCBIT: c_ulong = ioc(IOC_INT, b'U', 104, mem::size_of::<c_int>());
+const UI_SET_LEDBIT: c_ulong = ioc(IOC_INT, b'U', 105, mem::size_of::<c_int>());
+const UI_SET_SNDBIT: c_ulong = ioc(IOC_INT, b'U', 106, mem::size_of::<c_int>());
+const UI_SET_FFBIT: c_ulong = ioc(IOC_INT, b'U', 107, mem::size_of::<c_int>());
+// FreeBSD passes the pointer itself through, rather than copying in the pointer it points to.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const UI_SET_PHYS: c_ulong = ioc(IOC_WRITE, b'U', 108, mem::size_of::<*const c_char>());
+#[cfg(target_os = "freebsd")]
+const UI_SET_PHYS: c_ulong = ioc(IOC_NONE, b'U', 108, 0);
+const UI_SET_SWBIT: c_ulong = ioc(IOC_INT, b'U', 109, mem::size_of::<c_int>());
+const UI_SET_PROPBIT: c_ulong = ioc(IOC_INT, b'U', 110, mem::size_of::<c_int>());
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct InputId {
+    bustype: u16,
+    vendor: u16,
+    product: u16,
+    version: u16,
+}
+
+#[repr(C)]
+struct UinputSetup {
+    id: InputId,
+    name: [c_char; UINPUT_MAX_NAME_SIZE],
+    ff_effects_max: u32,
+}
+
+#[repr(C)]
+struct UinputAbsSetup {
+    code: u16,
+    absinfo: input_absinfo,
+}
+
+// The highest code of each event type. None of the types that are forwarded use it, and the
+// libevdev backend leaves it out too.
+fn code_max(type_: u32) -> u32 {
+    match type_ {
+        glue::EV_KEY => glue::KEY_MAX,
+        glue::EV_REL => glue::REL_MAX,
+        glue::EV_ABS => glue::ABS_MAX,
+        glue::EV_MSC => glue::MSC_MAX,
+        glue::EV_SW => glue::SW_MAX,
+        glue::EV_LED => glue::LED_MAX,
+        glue::EV_SND => glue::SND_MAX,
+        glue::EV_FF => glue::FF_MAX,
+        _ => 0,
+    }
+}
+
+// The codes below `end` that are set in the bitmask fetched by `request`, which is given the size
+// of the buffer to fill.
+fn read_codes<F>(fd: RawFd, request: F, end: u32) -> Result<Vec<u32>, Error>
+where
+    F: Fn(usize) -> c_ulong,
+{
+    let mut bits = vec![0u8; (end as usize).div_ceil(8)];
+    unsafe { ioctl(fd, request(bits.len()), bits.as_mut_ptr() as *mut _) }?;
+    Ok((0..end)
+        .filter(|&code| bits[code as usize / 8] & (1 << (code % 8)) != 0)
+        .collect())
+}
+
+fn read_string<F>(fd: RawFd, request: F) -> Result<String, Error>
+where
+    F: Fn(usize) -> c_ulong,
+{
+    let mut buf = [0u8; STRING_SIZE];
+    match unsafe { ioctl(fd, request(buf.len()), buf.as_mut_ptr() as *mut _) } {
+        Ok(()) => {}
+        // The device doesn't have one.
+        Err(err) if err.raw_os_error() == Some(libc::ENOENT) => return Ok(String::new()),
+        Err(err) => return Err(err),
+    }
+    let length = buf.iter().position(|&byte| byte == 0).unwrap_or(buf.len());
+    Ok(String::from_utf8_lossy(&buf[..length]).into_owned())
+}
+
+fn to_c_string(value: &str) -> Result<CString, Error> {
+    CString::new(value).map_err(|err| Error::new(ErrorKind::InvalidInput, err))
+}
+
+pub(crate) struct Evdev {
+    // The device node, if attached to one.
+    file: Option<File>,
+    name: String,
+    uniq: String,
+    phys: String,
+    id: InputId,
+    capabilities: Vec<Capability>,
+    properties: Vec<u16>,
+    keys: Vec<u16>,
+    leds: Vec<u16>,
+}
+
+impl Evdev {
+    pub fn new() -> Result<Self, Error> {
+        Ok(Evdev {
+            file: None,
+            name: String::new(),
+            uniq: String::new(),
+            phys: String::new(),
+            id: InputId::default(),
+            capabilities: Vec::new(),
+            properties: Vec::new(),
+            keys: Vec::new(),
+            leds: Vec::new(),
+        })
+    }
+
+    pub fn from_file(file: File) -> Result<Self, Error> {
+        let fd = file.as_raw_fd();
+
+        let mut id = InputId::default();
+        unsafe { ioctl(fd, EVIOCGID, &mut id as *mut InputId as *mut _) }?;
+
+        // Switches are left out, like in the libevdev backend.
+        let mut capabilities = Vec::new();
+        for type_ in read_codes(fd, |size| eviocgbit(0, size), glue::EV_MAX)? {
+            match type_ {
+                glue::EV_SYN | glue::EV_SW => {}
+                glue::EV_REP => {
+                    let mut rep = [0u32; 2];
+                    unsafe { ioctl(fd, EVIOCGREP, rep.as_mut_ptr() as *mut _) }?;
+                    capabilities.push(Capability::Rep { code: glue::REP_DELAY as u16, value: rep[0] as i32 });
+                    capabilities.push(Capability::Rep { code: glue::REP_PERIOD as u16, value: rep[1] as i32 });
+                }
+                _ => {
+                    let max = code_max(type_);
+                    if max == 0 {
+                        continue;
+                    }
+                    for code in read_codes(fd, |size| eviocgbit(type_, size), max)? {
+                        if type_ == glue::EV_ABS {
+                            let mut info = input_absinfo::default();
+                            unsafe { ioctl(fd, eviocgabs(code), &mut info as *mut input_absinfo as *mut _) }?;
+                            let info = AbsInfo {
+                                value: info.value,
+                                minimum: info.minimum,
+                                maximum: info.maximum,
+                                fuzz: info.fuzz,
+                                flat: info.flat,
+                                resolution: info.resolution,
+                            };
+                            capabilities.push(Capability::Abs { code: code as u16, info });
+                        } else {
+                            capabilities.push(Capability::Other { type_: type_ as u16, code: code as u16 });
+                        }
+                    }
+                }
+            }
+        }
+
+        let codes = |codes: Vec<u32>| -> Vec<u16> { codes.into_iter().map(|code| code as u16).collect() };
+        Ok(Evdev {
+            name: read_string(fd, eviocgname)?,
+            uniq: read_string(fd, eviocguniq)?,
+            phys: read_string(fd, eviocgphys)?,
+            id,
+            capabilities,
+            properties: codes(read_codes(fd, eviocgprop, glue::INPUT_PROP_MAX + 1)?),
+            keys: codes(read_codes(fd, eviocgkey, glue::KEY_MAX)?),
+            leds: codes(read_codes(fd, eviocgled, glue::LED_MAX)?),
+            file: Some(file),
+        })
+    }
+
+    fn fd(&self) -> RawFd {
+        self.file.as_ref().map(|file| file.as_raw_fd()).unwrap_or(-1)
+    }
+
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn uniq(&self) -> String {
+        self.uniq.clone()
+    }
+
+    pub fn phys(&self) -> String {
+        self.phys.clone()
+    }
+
+    pub fn vendor(&self) -> u16 {
+        self.id.vendor
+    }
+
+    pub fn product(&self) -> u16 {
+        self.id.product
+    }
+
+    pub fn bustype(&self) -> u16 {
+        self.id.bustype
+    }
+
+    pub fn version(&self) -> u16 {
+        self.id.version
+    }
+
+    pub fn is_virtual(&self) -> bool {
+        self.bustype() as u32 == glue::BUS_VIRTUAL
+    }
+
+    pub fn capabilities(&self) -> Vec<Capability> {
+        self.capabilities.clone()
+    }
+
+    pub fn abs_info(&self, code: u32) -> Option<AbsInfo> {
+        self.capabilities.iter().find_map(|capability| match *capability {
+            Capability::Abs { code: abs_code, info } if abs_code as u32 == code => Some(info),
+            _ => None,
+        })
+    }
+
+    // Codes of the given event type that were on when the device was opened. Only keys and LEDs
+    // are known.
+    pub fn active_codes(&self, type_: u32) -> Vec<u16> {
+        match type_ {
+            glue::EV_KEY => self.keys.clone(),
+            glue::EV_LED => self.leds.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn properties(&self) -> Vec<u16> {
+        self.properties.clone()
+    }
+
+    pub fn grab(&mut self) -> Result<(), Error> {
+        unsafe { ioctl_int(self.fd(), EVIOCGRAB, 1) }
+    }
+
+    pub fn ungrab(&mut self) -> Result<(), Error> {
+        unsafe { ioctl_int(self.fd(), EVIOCGRAB, 0) }
+    }
+
+    // The next event from the device. Fails with WouldBlock if there is none yet.
+    pub fn next_event(&mut self) -> Result<input_event, Error> {
+        let mut event = MaybeUninit::<input_event>::uninit();
+        let size = mem::size_of::<input_event>();
+        let ret = unsafe { libc::read(self.fd(), event.as_mut_ptr() as *mut _, size) };
+        if ret < 0 {
+            return Err(Error::last_os_error());
+        }
+        // evdev only ever hands out whole events.
+        if ret as usize != size {
+            return Err(Error::new(ErrorKind::InvalidData, "Partial event read from device"));
+        }
+        Ok(unsafe { event.assume_init() })
+    }
+
+    // Send an event to the device itself, like EV_LED to turn a keyboard's LED on or off.
+    pub fn write_event(&mut self, event: &input_event) -> Result<(), Error> {
+        let size = mem::size_of::<input_event>();
+        let ret = unsafe { libc::write(self.fd(), event as *const input_event as *const _, size) };
+        if ret < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn set_ids(&mut self, vendor: u16, product: u16, bustype: u16, version: u16) {
+        self.id = InputId { bustype, vendor, product, version };
+    }
+
+    pub fn set_name(&mut self, name: &str) -> Result<(), Error> {
+        to_c_string(name)?;
+        self.name = name.to_owned();
+        Ok(())
+    }
+
+    pub fn set_phys(&mut self, phys: &str) -> Result<(), Error> {
+        to_c_string(phys)?;
+        self.phys = phys.to_owned();
+        Ok(())
+    }
+
+    pub fn enable(&mut self, capability: &Capability) -> Result<(), Error> {
+        self.capabilities.push(*capability);
+        Ok(())
+    }
+
+    pub fn enable_property(&mut self, property: u16) -> Result<(), Error> {
+        if property as u32 > glue::INPUT_PROP_MAX {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Unknown property {}", property)));
+        }
+        self.properties.push(property);
+        Ok(())
+    }
+
+    // Create a virtual device that is a copy of this one.
+    pub fn create_uinput(&self) -> Result<Uinput, Error> {
+        let file = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC)
+            .open(UINPUT_PATH)?;
+        let mut uinput = Uinput { file };
+        let fd = uinput.file.as_raw_fd();
+
+        let mut types = BTreeSet::new();
+        for capability in &self.capabilities {
+            let (type_, code) = match *capability {
+                Capability::Abs { code, .. } => (glue::EV_ABS, code),
+                Capability::Rep { code, .. } => (glue::EV_REP, code),
+                Capability::Other { type_, code } => (type_ as u32, code),
+            };
+            if types.insert(type_) {
+                unsafe { ioctl_int(fd, UI_SET_EVBIT, type_ as c_int) }?;
+            }
+            let request = match type_ {
+                glue::EV_KEY => UI_SET_KEYBIT,
+                glue::EV_REL => UI_SET_RELBIT,
+                glue::EV_ABS => UI_SET_ABSBIT,
+                glue::EV_MSC => UI_SET_MSCBIT,
+                glue::EV_LED => UI_SET_LEDBIT,
+                glue::EV_SND => UI_SET_SNDBIT,
+                glue::EV_FF => UI_SET_FFBIT,
+                glue::EV_SW => UI_SET_SWBIT,
+                // Repeat settings are written to the device once it exists.
+                _ => continue,
+            };
+            unsafe { ioctl_int(fd, request, code as c_int) }?;
+        }
+        for &property in &self.properties {
+            unsafe { ioctl_int(fd, UI_SET_PROPBIT, property as c_int) }?;
+        }
+
+        let phys = to_c_string(&self.phys)?;
+        unsafe { ioctl(fd, UI_SET_PHYS, phys.as_ptr() as *mut _) }?;
+
+        let mut setup = UinputSetup {
+            id: self.id,
+            name: [0; UINPUT_MAX_NAME_SIZE],
+            // The same as libevdev gives devices with force feedback.
+            ff_effects_max: if types.contains(&glue::EV_FF) { 10 } else { 0 },
+        };
+        for (to, &from) in setup.name.iter_mut().zip(self.name.as_bytes().iter().take(UINPUT_MAX_NAME_SIZE - 1)) {
+            *to = from as c_char;
+        }
+        unsafe { ioctl(fd, UI_DEV_SETUP, &mut setup as *mut UinputSetup as *mut _) }?;
+
+        for capability in &self.capabilities {
+            if let Capability::Abs { code, info } = *capability {
+                let mut setup = UinputAbsSetup {
+                    code,
+                    absinfo: input_absinfo {
+                        value: info.value,
+                        minimum: info.minimum,
+                        maximum: info.maximum,
+                        fuzz: info.fuzz,
+                        flat: info.flat,
+                        resolution: info.resolution,
+                    },
+                };
+                unsafe { ioctl(fd, UI_ABS_SETUP, &mut setup as *mut UinputAbsSetup as *mut _) }?;
+            }
+        }
+
+        unsafe { ioctl(fd, UI_DEV_CREATE, std::ptr::null_mut()) }?;
+
+        for capability in &self.capabilities {
+            if let Capability::Rep { code, value } = *capability {
+                uinput.write_event(&input_event {
+                    type_: glue::EV_REP as u16,
+                    code,
+                    value,
+                    ..input_event::default()
+                })?;
+            }
+        }
+        Ok(uinput)
+    }
+}
+
+impl AsRawFd for Evdev {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd()
+    }
+}
+
+// A virtual device, which is destroyed when dropped.
+pub(crate) struct Uinput {
+    file: File,
+}
+
+impl Uinput {
+    pub fn write_event(&mut self, event: &input_event) -> Result<(), Error> {
+        let size = mem::size_of::<input_event>();
+        let ret = unsafe { libc::write(self.file.as_raw_fd(), event as *const input_event as *const _, size) };
+        if ret < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl AsRawFd for Uinput {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl Drop for Uinput {
+    fn drop(&mut self) {
+        // Closing the file would do this as well.
+        let _ = unsafe { ioctl(self.file.as_raw_fd(), UI_DEV_DESTROY, std::ptr::null_mut()) };
+    }
+}
@@ -0,0 +1,83 @@
+#![allow(dead_code, non_camel_case_types)]
+
+// What the rest of the backend needs from linux/input.h and linux/input-event-codes.h, for builds
+// without libevdev, where there are no generated bindings. Names and types match the ones bindgen
+// generates, so the two can be used interchangeably.
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::os::raw::c_long;
+
+pub const EV_SYN: u32 = 0x00;
+pub const EV_KEY: u32 = 0x01;
+pub const EV_REL: u32 = 0x02;
+pub const EV_ABS: u32 = 0x03;
+pub const EV_MSC: u32 = 0x04;
+pub const EV_SW: u32 = 0x05;
+pub const EV_LED: u32 = 0x11;
+pub const EV_SND: u32 = 0x12;
+pub const EV_REP: u32 = 0x14;
+pub const EV_FF: u32 = 0x15;
+pub const EV_MAX: u32 = 0x1f;
+
+pub const SYN_REPORT: u32 = 0;
+
+pub const REL_X: u32 = 0x00;
+pub const REL_Y: u32 = 0x01;
+pub const REL_HWHEEL: u32 = 0x06;
+pub const REL_WHEEL: u32 = 0x08;
+pub const REL_WHEEL_HI_RES: u32 = 0x0b;
+pub const REL_HWHEEL_HI_RES: u32 = 0x0c;
+
+pub const REP_DELAY: u32 = 0x00;
+pub const REP_PERIOD: u32 = 0x01;
+
+pub const KEY_MAX: u32 = 0x2ff;
+pub const REL_MAX: u32 = 0x0f;
+pub const ABS_MAX: u32 = 0x3f;
+pub const MSC_MAX: u32 = 0x07;
+pub const SW_MAX: u32 = 0x10;
+pub const LED_MAX: u32 = 0x0f;
+pub const SND_MAX: u32 = 0x07;
+pub const FF_MAX: u32 = 0x7f;
+
+pub const INPUT_PROP_MAX: u32 = 0x1f;
+
+pub const BUS_VIRTUAL: u32 = 0x06;
+
+// The kernel's own layout, which uses longs even where the C library's time_t is 64 bits wide.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct timeval {
+    pub tv_sec: c_long,
+    pub tv_usec: c_long,
+}
+
+// FreeBSD's evdev uses the C library's.
+#[cfg(target_os = "freebsd")]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct timeval {
+    pub tv_sec: libc::time_t,
+    pub tv_usec: libc::suseconds_t,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct input_event {
+    pub time: timeval,
+    pub type_: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct input_absinfo {
+    pub value: i32,
+    pub minimum: i32,
+    pub maximum: i32,
+    pub fuzz: i32,
+    pub flat: i32,
+    pub resolution: i32,
+}
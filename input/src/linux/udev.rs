@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+// What udev knows about a device, read straight from its database rather than through libudev.
+// udev keeps a file per device under /run/udev/data, named after the device number, with a line
+// like `E:ID_INPUT_KEYBOARD=1` for each property. Devices udev hasn't seen, and platforms without
+// udev, just have no properties.
+
+#[cfg(target_os = "linux")]
+const UDEV_DATA_PATH: &str = "/run/udev/data";
+
+// Set this to 1 in a udev rule to keep evkvm from ever forwarding a device, for example:
+// ACTION=="add", SUBSYSTEM=="input", ATTRS{name}=="Yubico*", ENV{EVKVM_IGNORE}="1"
+const IGNORE_PROPERTY: &str = "EVKVM_IGNORE";
+
+// The input_id builtin's classifications, and what to call them.
+const CLASSES: &[(&str, &str)] = &[
+    ("ID_INPUT_KEYBOARD", "keyboard"),
+    ("ID_INPUT_KEY", "keys"),
+    ("ID_INPUT_MOUSE", "mouse"),
+    ("ID_INPUT_TOUCHPAD", "touchpad"),
+    ("ID_INPUT_TOUCHSCREEN", "touchscreen"),
+    ("ID_INPUT_TABLET", "tablet"),
+    ("ID_INPUT_JOYSTICK", "joystick"),
+    ("ID_INPUT_ACCELEROMETER", "accelerometer"),
+    ("ID_INPUT_SWITCH", "switch"),
+];
+
+#[derive(Default)]
+pub(crate) struct UdevProperties {
+    properties: HashMap<String, String>,
+}
+
+impl UdevProperties {
+    pub fn for_node(path: &Path) -> Self {
+        match read_data(path) {
+            Some(data) => Self::parse(&data),
+            None => Self::default(),
+        }
+    }
+
+    fn parse(data: &str) -> Self {
+        let properties = data
+            .lines()
+            .filter_map(|line| line.strip_prefix("E:")?.split_once('='))
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect();
+        UdevProperties { properties }
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.properties.get(key).map(String::as_str)
+    }
+
+    pub fn ignored(&self) -> bool {
+        self.get(IGNORE_PROPERTY) == Some("1")
+    }
+
+    pub fn classes(&self) -> Vec<String> {
+        CLASSES
+            .iter()
+            .filter(|(key, _)| self.get(key) == Some("1"))
+            .map(|(_, class)| String::from(*class))
+            .collect()
+    }
+
+    // The serial number udev found for the device, which is often there when the device's own
+    // uniq isn't.
+    pub fn serial(&self) -> Option<String> {
+        self.get("ID_SERIAL_SHORT")
+            .or_else(|| self.get("ID_SERIAL"))
+            .map(String::from)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn read_data(path: &Path) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let rdev = std::fs::metadata(path).ok()?.rdev();
+    let major = ((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff);
+    let minor = (rdev & 0xff) | ((rdev >> 12) & !0xff);
+    std::fs::read_to_string(Path::new(UDEV_DATA_PATH).join(format!("c{}:{}", major, minor))).ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_data(_path: &Path) -> Option<String> {
+    None
+}
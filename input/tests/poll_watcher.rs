@@ -0,0 +1,72 @@
+use futures::StreamExt;
+use input::{Capability, Device, DeviceWatcher, PollWatcher, WriterManager};
+use std::time::Duration;
+
+// Android has no inotify watcher, and relies on PollWatcher to notice new devices instead. This
+// creates a keyboard through uinput and checks that PollWatcher reports its node, wherever the
+// test runs.
+//
+// It needs write access to /dev/uinput and read access to /dev/input, and is skipped without
+// them. Run it as root with `cargo test -p input --test poll_watcher`.
+
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const KEY_A: u16 = 30;
+
+const INTERVAL: Duration = Duration::from_millis(50);
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+fn keyboard(name: &str) -> Device {
+    Device {
+        id: 1,
+        name: name.to_owned(),
+        uniq: String::new(),
+        phys: String::new(),
+        vendor: 0x1234,
+        product: 0x5678,
+        bustype: 0x03,
+        version: 1,
+        capabilities: vec![
+            Capability::Other { type_: EV_SYN, code: 0 },
+            Capability::Other { type_: EV_KEY, code: KEY_A },
+        ],
+        properties: Vec::new(),
+        held_keys: Vec::new(),
+        lit_leds: Vec::new(),
+    }
+}
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap()
+}
+
+#[test]
+fn reports_new_nodes() {
+    runtime().block_on(async {
+        if let Err(err) = input::check_uinput() {
+            eprintln!("Skipping, uinput is not available: {}", err);
+            return;
+        }
+
+        let watcher = PollWatcher { interval: INTERVAL };
+        let mut paths = watcher.watch().expect("Failed to watch /dev/input");
+
+        let name = format!("evkvm test poll watcher {}", std::process::id());
+        let mut writer = WriterManager::new().await;
+        writer.create_device(keyboard(&name), None).await.expect("Failed to create keyboard");
+
+        // Other devices may come and go meanwhile, so this waits for the one that is ours.
+        let found = tokio::time::timeout(TIMEOUT, async {
+            while let Some(path) = paths.next().await {
+                let path = path.expect("Failed to list /dev/input");
+                let devices = input::list_devices(&[]).await.expect("Failed to list devices");
+                if devices.iter().any(|info| info.path == path && info.device.name == name) {
+                    return;
+                }
+            }
+            panic!("PollWatcher stopped");
+        })
+        .await;
+        assert!(found.is_ok(), "PollWatcher didn't report the keyboard's node");
+    });
+}
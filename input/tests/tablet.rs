@@ -0,0 +1,127 @@
+use input::{AbsInfo, Capability, Device, WriterManager};
+use std::time::{Duration, Instant};
+
+// Drawing applications on a receiver only treat a forwarded graphics tablet like the real one if
+// its virtual copy has the same axes, down to each one's range, resolution, fuzz and flat, along
+// with its tools and properties. This creates a pen display through uinput the way a receiver
+// does, and compares the node the kernel makes of it with what it was created from.
+//
+// It needs write access to /dev/uinput and read access to /dev/input, and is ignored unless asked
+// for. Run it as root with `cargo test -p input --test tablet -- --ignored`.
+
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_MSC: u16 = 0x04;
+
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+const ABS_PRESSURE: u16 = 0x18;
+const ABS_DISTANCE: u16 = 0x19;
+const ABS_TILT_X: u16 = 0x1a;
+const ABS_TILT_Y: u16 = 0x1b;
+const MSC_SERIAL: u16 = 0x00;
+
+const BTN_TOOL_PEN: u16 = 0x140;
+const BTN_TOOL_RUBBER: u16 = 0x141;
+const BTN_TOUCH: u16 = 0x14a;
+const BTN_STYLUS: u16 = 0x14b;
+const BTN_STYLUS2: u16 = 0x14c;
+
+const INPUT_PROP_DIRECT: u16 = 0x01;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+fn axis(code: u16, minimum: i32, maximum: i32, fuzz: i32, flat: i32, resolution: i32) -> (u16, AbsInfo) {
+    (code, AbsInfo { value: 0, minimum, maximum, fuzz, flat, resolution })
+}
+
+// Roughly what a Wacom pen display reports. The tilt axes go below zero, and pressure has no
+// resolution, both of which are easy to lose.
+fn axes() -> Vec<(u16, AbsInfo)> {
+    vec![
+        axis(ABS_X, 0, 59552, 4, 0, 200),
+        axis(ABS_Y, 0, 33848, 4, 0, 200),
+        axis(ABS_PRESSURE, 0, 8191, 0, 0, 0),
+        axis(ABS_DISTANCE, 0, 63, 1, 0, 0),
+        axis(ABS_TILT_X, -64, 63, 1, 0, 57),
+        axis(ABS_TILT_Y, -64, 63, 1, 0, 57),
+    ]
+}
+
+const BUTTONS: &[u16] = &[BTN_TOOL_PEN, BTN_TOOL_RUBBER, BTN_TOUCH, BTN_STYLUS, BTN_STYLUS2];
+
+fn tablet(name: &str) -> Device {
+    let mut capabilities = vec![
+        Capability::Other { type_: EV_SYN, code: 0 },
+        Capability::Other { type_: EV_MSC, code: MSC_SERIAL },
+    ];
+    capabilities.extend(axes().into_iter().map(|(code, info)| Capability::Abs { code, info }));
+    capabilities.extend(BUTTONS.iter().map(|&code| Capability::Other { type_: EV_KEY, code }));
+
+    Device {
+        id: 1,
+        name: name.to_owned(),
+        uniq: String::new(),
+        phys: String::new(),
+        vendor: 0x056a,
+        product: 0x0357,
+        bustype: 0x03,
+        version: 0x110,
+        capabilities,
+        properties: vec![INPUT_PROP_DIRECT],
+        held_keys: Vec::new(),
+        lit_leds: Vec::new(),
+    }
+}
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap()
+}
+
+#[test]
+#[ignore = "needs write access to /dev/uinput and read access to /dev/input"]
+fn replicates_axes_tools_and_properties() {
+    runtime().block_on(async {
+        if let Err(err) = input::check_uinput() {
+            panic!("uinput is not available: {}", err);
+        }
+
+        let name = format!("evkvm test tablet {}", std::process::id());
+        let mut writer = WriterManager::new().await;
+        writer.create_device(tablet(&name), None).await.expect("Failed to create tablet");
+
+        // udev creates the node in the background.
+        let deadline = Instant::now() + TIMEOUT;
+        let device = loop {
+            let devices = input::list_devices(&[]).await.expect("Failed to list devices");
+            if let Some(info) = devices.into_iter().find(|info| info.device.name == name) {
+                break info.device;
+            }
+            assert!(Instant::now() < deadline, "Tablet didn't show up in /dev/input");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        };
+
+        for (code, sent) in axes() {
+            let got = device.capabilities.iter().find_map(|capability| match *capability {
+                Capability::Abs { code: other, info } if other == code => Some(info),
+                _ => None,
+            });
+            let got = got.unwrap_or_else(|| panic!("Axis {:#x} is missing", code));
+            assert_eq!(
+                (got.minimum, got.maximum, got.fuzz, got.flat, got.resolution),
+                (sent.minimum, sent.maximum, sent.fuzz, sent.flat, sent.resolution),
+                "Axis {:#x} changed",
+                code,
+            );
+        }
+
+        for &code in BUTTONS {
+            let found = device.capabilities.iter().any(|capability| {
+                matches!(*capability, Capability::Other { type_: EV_KEY, code: other } if other == code)
+            });
+            assert!(found, "Button {:#x} is missing", code);
+        }
+
+        assert!(device.properties.contains(&INPUT_PROP_DIRECT), "Lost INPUT_PROP_DIRECT");
+    });
+}
@@ -0,0 +1,333 @@
+use input::{AbsInfo, Button, Capability, Device, Direction, Event, InputEvent, KeyKind, WriterManager};
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+// Forwarded multitouch touchpads are the most fragile thing evkvm replicates: libinput on the
+// receiver only recognizes two-finger scrolling and pinching if every slot change, tracking ID and
+// BTN_TOOL_* transition comes out of the virtual touchpad exactly as it went in, and if the device
+// itself looks like a touchpad. These tests create one through uinput the way a receiver does,
+// write gestures to it, and read back what the kernel hands to libinput.
+//
+// Those that create one need write access to /dev/uinput and read access to /dev/input, and are
+// ignored unless asked for. Run them as root with `cargo test -p input --test touchpad -- --ignored`.
+
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_ABS: u16 = 0x03;
+const SYN_REPORT: u16 = 0x00;
+
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+const ABS_MT_SLOT: u16 = 0x2f;
+const ABS_MT_POSITION_X: u16 = 0x35;
+const ABS_MT_POSITION_Y: u16 = 0x36;
+const ABS_MT_TRACKING_ID: u16 = 0x39;
+
+const INPUT_PROP_POINTER: u16 = 0x00;
+const INPUT_PROP_BUTTONPAD: u16 = 0x02;
+
+// The buttons a clickpad has, along with their evdev codes.
+const BUTTONS: &[(Button, u16)] = &[
+    (Button::Left, 0x110),
+    (Button::ToolFinger, 0x145),
+    (Button::Touch, 0x14a),
+    (Button::ToolDoubletap, 0x14d),
+    (Button::ToolTripletap, 0x14e),
+];
+
+const SLOTS: i32 = 5;
+const MAX_X: i32 = 3000;
+const MAX_Y: i32 = 2000;
+
+// How long to wait for the virtual touchpad to show up and for events to come out of it.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+fn axis(code: u16, maximum: i32, resolution: i32) -> Capability {
+    Capability::Abs {
+        code,
+        info: AbsInfo { value: 0, minimum: 0, maximum, fuzz: 0, flat: 0, resolution },
+    }
+}
+
+fn touchpad(name: &str) -> Device {
+    let mut capabilities = vec![
+        Capability::Other { type_: EV_SYN, code: SYN_REPORT },
+        axis(ABS_X, MAX_X, 30),
+        axis(ABS_Y, MAX_Y, 30),
+        axis(ABS_MT_SLOT, SLOTS - 1, 0),
+        axis(ABS_MT_POSITION_X, MAX_X, 30),
+        axis(ABS_MT_POSITION_Y, MAX_Y, 30),
+        axis(ABS_MT_TRACKING_ID, 65535, 0),
+    ];
+    capabilities.extend(BUTTONS.iter().map(|&(_, code)| Capability::Other { type_: EV_KEY, code }));
+
+    Device {
+        id: 1,
+        name: name.to_owned(),
+        uniq: String::new(),
+        phys: String::new(),
+        vendor: 0x06cb,
+        product: 0xcd8b,
+        bustype: 0x18,
+        version: 0x100,
+        capabilities,
+        properties: vec![INPUT_PROP_POINTER, INPUT_PROP_BUTTONPAD],
+        held_keys: Vec::new(),
+        lit_leds: Vec::new(),
+    }
+}
+
+fn abs(code: u16, value: i32) -> InputEvent {
+    InputEvent::Other { type_: EV_ABS, code, value }
+}
+
+fn button(button: Button, down: bool) -> InputEvent {
+    let direction = if down { Direction::Down } else { Direction::Up };
+    InputEvent::Key { direction, kind: KeyKind::Button(button) }
+}
+
+// A finger landing in or moving within the current slot.
+fn finger(tracking_id: Option<i32>, x: i32, y: i32) -> Vec<InputEvent> {
+    let mut events: Vec<InputEvent> = tracking_id.map(|id| abs(ABS_MT_TRACKING_ID, id)).into_iter().collect();
+    events.push(abs(ABS_MT_POSITION_X, x));
+    events.push(abs(ABS_MT_POSITION_Y, y));
+    events
+}
+
+// Frames, each ending in a SYN_REPORT, of two fingers moving from `start` by `steps` each frame.
+// The kernel drops values that didn't change and slot switches that don't lead anywhere, so the
+// frames only contain what a real touchpad would send.
+fn two_fingers(start: [(i32, i32); 2], steps: [(i32, i32); 2], frames: i32, first_id: i32) -> Vec<Vec<InputEvent>> {
+    let mut gesture = Vec::new();
+
+    let mut landing = finger(Some(first_id), start[0].0, start[0].1);
+    landing.push(abs(ABS_MT_SLOT, 1));
+    landing.extend(finger(Some(first_id + 1), start[1].0, start[1].1));
+    landing.push(button(Button::Touch, true));
+    landing.push(button(Button::ToolDoubletap, true));
+    landing.push(abs(ABS_X, start[0].0));
+    landing.push(abs(ABS_Y, start[0].1));
+    gesture.push(landing);
+
+    for frame in 1..=frames {
+        // Only the coordinates that move are sent.
+        let moved = |index: usize, x_code: u16, y_code: u16| {
+            let (x, y) = (start[index].0 + steps[index].0 * frame, start[index].1 + steps[index].1 * frame);
+            let mut events = Vec::new();
+            if steps[index].0 != 0 {
+                events.push(abs(x_code, x));
+            }
+            if steps[index].1 != 0 {
+                events.push(abs(y_code, y));
+            }
+            events
+        };
+        let mut events = vec![abs(ABS_MT_SLOT, 0)];
+        events.extend(moved(0, ABS_MT_POSITION_X, ABS_MT_POSITION_Y));
+        events.push(abs(ABS_MT_SLOT, 1));
+        events.extend(moved(1, ABS_MT_POSITION_X, ABS_MT_POSITION_Y));
+        events.extend(moved(0, ABS_X, ABS_Y));
+        gesture.push(events);
+    }
+
+    // The second finger lifts first, leaving one finger down for a frame.
+    gesture.push(vec![
+        abs(ABS_MT_TRACKING_ID, -1),
+        button(Button::ToolFinger, true),
+        button(Button::ToolDoubletap, false),
+    ]);
+    gesture.push(vec![
+        abs(ABS_MT_SLOT, 0),
+        abs(ABS_MT_TRACKING_ID, -1),
+        button(Button::Touch, false),
+        button(Button::ToolFinger, false),
+    ]);
+    gesture
+}
+
+fn raw(event: &InputEvent) -> (u16, u16, i32) {
+    match *event {
+        InputEvent::Other { type_, code, value } => (type_, code, value),
+        InputEvent::Key { direction, kind: KeyKind::Button(pressed) } => {
+            let code = BUTTONS
+                .iter()
+                .find(|&&(button, _)| button == pressed)
+                .map(|&(_, code)| code)
+                .expect("Button is missing from BUTTONS");
+            (EV_KEY, code, if direction == Direction::Down { 1 } else { 0 })
+        }
+        InputEvent::Key { kind: KeyKind::Key(_), .. } => panic!("Touchpads don't have keys"),
+    }
+}
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap()
+}
+
+// A virtual touchpad, created the way a receiver creates the devices it is sent, and its node.
+struct Touchpad {
+    writer: WriterManager,
+    path: PathBuf,
+    device: Device,
+}
+
+// Create a virtual touchpad.
+async fn create(test: &str) -> Touchpad {
+    if let Err(err) = input::check_uinput() {
+        panic!("uinput is not available: {}", err);
+    }
+
+    let name = format!("evkvm test touchpad {} {}", test, std::process::id());
+    let mut writer = WriterManager::new().await;
+    writer.create_device(touchpad(&name), None).await.expect("Failed to create touchpad");
+
+    // udev creates the node in the background.
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        let devices = input::list_devices(&[]).await.expect("Failed to list devices");
+        if let Some(info) = devices.into_iter().find(|info| info.device.name == name) {
+            return Touchpad { writer, path: info.path, device: info.device };
+        }
+        assert!(Instant::now() < deadline, "Touchpad didn't show up in /dev/input");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+// Write `gesture` to the touchpad and return what comes out of its node, frame by frame.
+async fn replay(touchpad: &mut Touchpad, gesture: &[Vec<InputEvent>]) -> Vec<Vec<(u16, u16, i32)>> {
+    let mut node = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(&touchpad.path)
+        .expect("Failed to open touchpad node");
+
+    for frame in gesture {
+        for (index, &input) in frame.iter().enumerate() {
+            let event = Event::Input { device_id: 1, input, syn: index == frame.len() - 1 };
+            touchpad.writer.write(event).await.expect("Failed to write to touchpad");
+        }
+    }
+
+    let mut frames = Vec::new();
+    let mut frame = Vec::new();
+    let deadline = Instant::now() + TIMEOUT;
+    while frames.len() < gesture.len() {
+        match read_event(&mut node) {
+            Some(event) if event.type_ == EV_SYN && event.code == SYN_REPORT => {
+                frames.push(std::mem::take(&mut frame));
+            }
+            Some(event) => frame.push((event.type_, event.code, event.value)),
+            None => {
+                assert!(Instant::now() < deadline, "Only got {} of {} frames back", frames.len(), gesture.len());
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+    }
+    frames
+}
+
+fn read_event(node: &mut File) -> Option<libc::input_event> {
+    let mut buffer = [0u8; std::mem::size_of::<libc::input_event>()];
+    match node.read(&mut buffer) {
+        Ok(length) => {
+            assert_eq!(length, buffer.len(), "Short read from touchpad node");
+            Some(unsafe { std::ptr::read_unaligned(buffer.as_ptr() as *const libc::input_event) })
+        }
+        Err(err) if err.kind() == ErrorKind::WouldBlock => None,
+        Err(err) => panic!("Failed to read from touchpad node: {}", err),
+    }
+}
+
+fn assert_replayed(gesture: &[Vec<InputEvent>], replayed: &[Vec<(u16, u16, i32)>]) {
+    for (index, (sent, got)) in gesture.iter().zip(replayed).enumerate() {
+        let sent: Vec<_> = sent.iter().map(raw).collect();
+        assert_eq!(&sent, got, "Frame {} came out differently", index);
+    }
+}
+
+// libinput goes by these to treat a device as a clickpad: no pointer emulation, and scrolling and
+// pinching measured in millimeters.
+fn assert_touchpad(device: &Device) {
+    let properties = &device.properties;
+    assert!(properties.contains(&INPUT_PROP_POINTER), "Lost INPUT_PROP_POINTER");
+    assert!(properties.contains(&INPUT_PROP_BUTTONPAD), "Lost INPUT_PROP_BUTTONPAD");
+
+    for &(code, maximum, resolution) in &[
+        (ABS_MT_SLOT, SLOTS - 1, 0),
+        (ABS_MT_POSITION_X, MAX_X, 30),
+        (ABS_MT_POSITION_Y, MAX_Y, 30),
+        (ABS_X, MAX_X, 30),
+    ] {
+        let info = device.capabilities.iter().find_map(|capability| match *capability {
+            Capability::Abs { code: other, info } if other == code => Some(info),
+            _ => None,
+        });
+        let info = info.unwrap_or_else(|| panic!("Axis {:#x} is missing", code));
+        assert_eq!((info.maximum, info.resolution), (maximum, resolution), "Axis {:#x} changed", code);
+    }
+
+    for &(button, code) in BUTTONS {
+        let found = device.capabilities.iter().any(|capability| {
+            matches!(*capability, Capability::Other { type_: EV_KEY, code: other } if other == code)
+        });
+        assert!(found, "{:?} is missing", button);
+    }
+}
+
+#[test]
+fn looks_like_a_touchpad() {
+    assert_touchpad(&touchpad("evkvm test touchpad"));
+}
+
+#[test]
+#[ignore = "needs write access to /dev/uinput and read access to /dev/input"]
+fn still_looks_like_a_touchpad_once_created() {
+    runtime().block_on(async {
+        let touchpad = create("device").await;
+        assert_touchpad(&touchpad.device);
+    });
+}
+
+#[test]
+#[ignore = "needs write access to /dev/uinput and read access to /dev/input"]
+fn two_finger_scroll() {
+    runtime().block_on(async {
+        let mut touchpad = create("scroll").await;
+
+        // Both fingers move down together.
+        let gesture = two_fingers([(1000, 500), (1400, 520)], [(0, 40), (0, 40)], 10, 100);
+        let replayed = replay(&mut touchpad, &gesture).await;
+        assert_replayed(&gesture, &replayed);
+    });
+}
+
+#[test]
+#[ignore = "needs write access to /dev/uinput and read access to /dev/input"]
+fn pinch() {
+    runtime().block_on(async {
+        let mut touchpad = create("pinch").await;
+
+        // The fingers move away from each other.
+        let gesture = two_fingers([(1300, 1000), (1700, 1000)], [(-30, -10), (30, 10)], 10, 200);
+        let replayed = replay(&mut touchpad, &gesture).await;
+        assert_replayed(&gesture, &replayed);
+    });
+}
+
+#[test]
+#[ignore = "needs write access to /dev/uinput and read access to /dev/input"]
+fn slots_are_reused_with_new_tracking_ids() {
+    runtime().block_on(async {
+        let mut touchpad = create("reuse").await;
+
+        // Two scrolls in a row land in the same slots. libinput tells the touches apart only by
+        // their tracking IDs, so the second one has to start out with new ones.
+        let mut gesture = two_fingers([(1000, 500), (1400, 520)], [(0, 40), (0, 40)], 5, 300);
+        gesture.extend(two_fingers([(900, 1500), (1300, 1480)], [(0, -40), (0, -40)], 5, 302));
+        let replayed = replay(&mut touchpad, &gesture).await;
+        assert_replayed(&gesture, &replayed);
+    });
+}
@@ -0,0 +1,118 @@
+use ipc::{ControlRequest, ControlResponse, Status};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+// A tray icon that shows which machine has focus, and switches to another one when it is picked
+// from the menu. It talks to the control socket like any other program would, so it needs to run
+// as a user that can open it, e.g. evkvm's. Usage: tray [CONTROL_SOCKET_PATH]
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Tray {
+    socket_path: PathBuf,
+    // The last status the daemon answered with, or the error it failed with.
+    status: Result<Status, String>,
+}
+
+impl Tray {
+    fn focus(&self, receiver: Option<String>) {
+        let request = ControlRequest::Focus { receiver };
+        match ipc::request(&self.socket_path, &request) {
+            Ok(ControlResponse::Done) => {}
+            Ok(ControlResponse::Error { message }) => eprintln!("Failed to switch: {}", message),
+            Ok(response) => eprintln!("Unexpected response from evkvm: {:?}", response),
+            Err(err) => eprintln!("Failed to reach evkvm: {}", err),
+        }
+    }
+}
+
+impl ksni::Tray for Tray {
+    fn id(&self) -> String {
+        "evkvm".to_owned()
+    }
+
+    fn title(&self) -> String {
+        match &self.status {
+            Ok(status) => format!("evkvm: {}", status.focus.as_deref().unwrap_or("this machine")),
+            Err(_) => "evkvm: not running".to_owned(),
+        }
+    }
+
+    fn icon_name(&self) -> String {
+        match &self.status {
+            Ok(status) if status.focus.is_some() => "input-keyboard".to_owned(),
+            Ok(_) => "computer".to_owned(),
+            Err(_) => "dialog-error".to_owned(),
+        }
+    }
+
+    fn menu(&self) -> Vec<ksni::MenuItem<Self>> {
+        let status = match &self.status {
+            Ok(status) => status,
+            Err(err) => {
+                return vec![ksni::menu::StandardItem {
+                    label: err.clone(),
+                    enabled: false,
+                    ..Default::default()
+                }
+                .into()]
+            }
+        };
+
+        // The first option is the sender itself, followed by the receivers in switching order.
+        let mut options = vec![ksni::menu::RadioItem {
+            label: "This machine".to_owned(),
+            ..Default::default()
+        }];
+        options.extend(status.receivers.iter().map(|receiver| ksni::menu::RadioItem {
+            label: receiver.name.clone(),
+            ..Default::default()
+        }));
+        let selected = status
+            .receivers
+            .iter()
+            .position(|receiver| receiver.focused)
+            .map(|position| position + 1)
+            .unwrap_or(0);
+        let names: Vec<String> = status.receivers.iter().map(|receiver| receiver.name.clone()).collect();
+
+        vec![ksni::menu::RadioGroup {
+            selected,
+            select: Box::new(move |tray: &mut Self, picked| {
+                let receiver = picked.checked_sub(1).and_then(|position| names.get(position).cloned());
+                tray.focus(receiver);
+            }),
+            options,
+        }
+        .into()]
+    }
+}
+
+fn poll(socket_path: &Path) -> Result<Status, String> {
+    match ipc::request(socket_path, &ControlRequest::Status) {
+        Ok(ControlResponse::Status(status)) => Ok(status),
+        Ok(ControlResponse::Error { message }) => Err(message),
+        Ok(response) => Err(format!("Unexpected response from evkvm: {:?}", response)),
+        Err(err) => Err(format!("Failed to reach evkvm: {}", err)),
+    }
+}
+
+fn main() {
+    let socket_path = std::env::args_os()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(ipc::DEFAULT_SOCKET_PATH));
+
+    let status = poll(&socket_path);
+    let service = ksni::TrayService::new(Tray { socket_path: socket_path.clone(), status });
+    let handle = service.handle();
+    service.spawn();
+
+    // The daemon doesn't announce changes, so ask it every now and then.
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let status = poll(&socket_path);
+        handle.update(|tray| tray.status = status);
+    }
+}
@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+// The messages exchanged over evkvm's control socket, which commands like `evkvm status` use to
+// talk to the running daemon, and which other programs can use too. Each connection carries a
+// single request and its response, both as one line of JSON.
+//
+// Programs outside of evkvm depend on this, so it only changes in ways that keep them working:
+// fields and variants are added, never renamed or removed, and existing ones keep their meaning.
+// Anything a client doesn't know about should be ignored. SCHEMA_VERSION goes up with every
+// addition, so clients can tell whether what they need is there.
+
+pub const SCHEMA_VERSION: u32 = 5;
+
+// Where the daemon listens unless its config says otherwise.
+pub const DEFAULT_SOCKET_PATH: &str = "/run/evkvm/control.sock";
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum ControlRequest {
+    Latency { receiver: String, count: u32 },
+    Command { receiver: String, name: String },
+    // Offer a file to a receiver, or to the focused one if none is given.
+    SendFile { receiver: Option<String>, path: PathBuf },
+    Status,
+    // Read the trusted receivers again.
+    Reload,
+    // Switch to a receiver, or back to the sender itself if none is given. Added in schema 2.
+    Focus { receiver: Option<String> },
+    // Switch to one of the profiles in the daemon's config, or back to the options outside of
+    // them if none is given. Added in schema 3.
+    Profile { name: Option<String> },
+    // Stop forwarding the input device with the given id and give it back to the sender, or start
+    // forwarding it again. The id is the number in its /dev/input/eventN path. Added in schema 4.
+    PauseDevice { id: u16 },
+    ResumeDevice { id: u16 },
+    // The input the sender read lately and where it went, as far back as its debug-history-secs.
+    // Added in schema 5.
+    DebugDump,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum ControlResponse {
+    // Microseconds from sending each probe to the receiver injecting it. Probes that weren't
+    // answered in time are left out.
+    Latency { samples: Vec<u64>, lost: u32 },
+    Status(Status),
+    // The answer to DebugDump, oldest entry first. Added in schema 5.
+    History { entries: Vec<HistoryEntry> },
+    // The request was passed on to the receiver.
+    Sent,
+    // The request was carried out.
+    Done,
+    Error { message: String },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct Status {
+    // The SCHEMA_VERSION of the daemon that answered.
+    pub schema_version: u32,
+    // Connected receivers, in the order switching goes through them.
+    pub receivers: Vec<ReceiverStatus>,
+    // The name of the receiver input is going to, if it isn't the sender itself.
+    pub focus: Option<String>,
+    // The profile in use, if it isn't the default one. Added in schema 3.
+    #[serde(default)]
+    pub profile: Option<String>,
+    // Devices that would be forwarded if another process didn't have them grabbed.
+    pub ungrabbed_devices: Vec<UngrabbedDevice>,
+    // Devices paused with PauseDevice, which work on the sender instead. Added in schema 4.
+    #[serde(default)]
+    pub paused_devices: Vec<PausedDevice>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReceiverStatus {
+    // Its nick where it has one, the hostname it reported otherwise.
+    pub name: String,
+    pub fingerprint: String,
+    // Empty if it didn't report one.
+    pub hostname: String,
+    // The protocol version negotiated with it.
+    pub protocol_version: u16,
+    pub focused: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct UngrabbedDevice {
+    pub path: String,
+    pub name: String,
+    // Processes that have the device open, one of which holds the grab.
+    #[serde(default)]
+    pub holders: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct PausedDevice {
+    pub id: u16,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct HistoryEntry {
+    // Milliseconds from the entry being recorded to the dump.
+    pub age_ms: u64,
+    // What happened, for people to read. Its wording may change between versions.
+    pub what: String,
+}
+
+// Send a request to the daemon listening on `path` and wait for its response. This blocks, which
+// suits small tools that don't have an async runtime.
+pub fn request(path: &Path, request: &ControlRequest) -> Result<ControlResponse, Error> {
+    let mut stream = UnixStream::connect(path)?;
+
+    let mut data = serde_json::to_vec(request)?;
+    data.push(b'\n');
+    stream.write_all(&data)?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    serde_json::from_str(&line).map_err(|err| Error::new(ErrorKind::InvalidData, err))
+}
@@ -0,0 +1,160 @@
+use input::{Capability, Device, Event, InputEvent, KeyKind};
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind};
+
+use crate::Message;
+
+// Messages as peers on older protocol versions serialize them. Most versions only added messages,
+// which are never sent to peers that don't know them, but a few changed how existing ones are
+// serialized:
+// - 11 added Device::uniq and Device::phys.
+// - 12 added Device::properties.
+// - 13 added the keys listed after ZoomReset, and Key::Other.
+const DEVICE_IDS_VERSION: u16 = 11;
+const DEVICE_PROPERTIES_VERSION: u16 = 12;
+const EXTENDED_KEYS_VERSION: u16 = 13;
+
+// How bincode starts a Message::Event holding an Event::NewDevice: the indices of both variants.
+const MESSAGE_EVENT: u32 = 0;
+const EVENT_NEW_DEVICE: u32 = 1;
+
+// A Device as protocol version 10 has it.
+#[derive(Serialize, Deserialize)]
+struct DeviceV10 {
+    id: u16,
+    name: String,
+    vendor: u16,
+    product: u16,
+    bustype: u16,
+    version: u16,
+    capabilities: Vec<Capability>,
+    held_keys: Vec<KeyKind>,
+    lit_leds: Vec<u16>,
+}
+
+// A Device as protocol version 11 has it.
+#[derive(Serialize, Deserialize)]
+struct DeviceV11 {
+    id: u16,
+    name: String,
+    uniq: String,
+    phys: String,
+    vendor: u16,
+    product: u16,
+    bustype: u16,
+    version: u16,
+    capabilities: Vec<Capability>,
+    held_keys: Vec<KeyKind>,
+    lit_leds: Vec<u16>,
+}
+
+fn serialize<T: Serialize>(value: &T) -> Result<Vec<u8>, Error> {
+    bincode::serialize(value).map_err(|err| Error::new(ErrorKind::InvalidInput, err))
+}
+
+fn deserialize<'a, T: Deserialize<'a>>(data: &'a [u8]) -> Result<T, Error> {
+    bincode::deserialize(data).map_err(|err| Error::new(ErrorKind::InvalidData, err))
+}
+
+fn is_extended(kind: &KeyKind) -> bool {
+    matches!(kind, KeyKind::Key(key) if key.is_extended())
+}
+
+// Serialize a message for a peer on protocol `version`, or return None if the message can't be
+// put in a form the peer understands. That only happens to keys it doesn't know, which senders on
+// its version never sent either.
+pub fn encode(message: &Message, version: u16) -> Result<Option<Vec<u8>>, Error> {
+    if version >= EXTENDED_KEYS_VERSION {
+        return serialize(message).map(Some);
+    }
+
+    match message {
+        Message::Event { event: Event::Input { input: InputEvent::Key { kind, .. }, .. }, .. }
+            if is_extended(kind) =>
+        {
+            Ok(None)
+        }
+        Message::Event { event: Event::NewDevice(device), time } => {
+            let mut device = device.clone();
+            device.held_keys.retain(|kind| !is_extended(kind));
+            if version >= DEVICE_PROPERTIES_VERSION {
+                serialize(&Message::Event { event: Event::NewDevice(device), time: *time }).map(Some)
+            } else if version >= DEVICE_IDS_VERSION {
+                let device = DeviceV11 {
+                    id: device.id,
+                    name: device.name,
+                    uniq: device.uniq,
+                    phys: device.phys,
+                    vendor: device.vendor,
+                    product: device.product,
+                    bustype: device.bustype,
+                    version: device.version,
+                    capabilities: device.capabilities,
+                    held_keys: device.held_keys,
+                    lit_leds: device.lit_leds,
+                };
+                serialize(&(MESSAGE_EVENT, EVENT_NEW_DEVICE, device, time)).map(Some)
+            } else {
+                let device = DeviceV10 {
+                    id: device.id,
+                    name: device.name,
+                    vendor: device.vendor,
+                    product: device.product,
+                    bustype: device.bustype,
+                    version: device.version,
+                    capabilities: device.capabilities,
+                    held_keys: device.held_keys,
+                    lit_leds: device.lit_leds,
+                };
+                serialize(&(MESSAGE_EVENT, EVENT_NEW_DEVICE, device, time)).map(Some)
+            }
+        }
+        message => serialize(message).map(Some),
+    }
+}
+
+// Deserialize a message from a peer on protocol `version`. What the version doesn't have is left
+// empty.
+pub fn decode(data: &[u8], version: u16) -> Result<Message, Error> {
+    let prefix = bincode::deserialize::<(u32, u32)>(data).ok();
+    if version >= DEVICE_PROPERTIES_VERSION || prefix != Some((MESSAGE_EVENT, EVENT_NEW_DEVICE)) {
+        return deserialize(data);
+    }
+
+    let (device, time) = if version >= DEVICE_IDS_VERSION {
+        let (_, _, device, time): (u32, u32, DeviceV11, Option<u64>) = deserialize(data)?;
+        let device = Device {
+            id: device.id,
+            name: device.name,
+            uniq: device.uniq,
+            phys: device.phys,
+            vendor: device.vendor,
+            product: device.product,
+            bustype: device.bustype,
+            version: device.version,
+            capabilities: device.capabilities,
+            properties: Vec::new(),
+            held_keys: device.held_keys,
+            lit_leds: device.lit_leds,
+        };
+        (device, time)
+    } else {
+        let (_, _, device, time): (u32, u32, DeviceV10, Option<u64>) = deserialize(data)?;
+        let device = Device {
+            id: device.id,
+            name: device.name,
+            uniq: String::new(),
+            phys: String::new(),
+            vendor: device.vendor,
+            product: device.product,
+            bustype: device.bustype,
+            version: device.version,
+            capabilities: device.capabilities,
+            properties: Vec::new(),
+            held_keys: device.held_keys,
+            lit_leds: device.lit_leds,
+        };
+        (device, time)
+    };
+    Ok(Message::Event { event: Event::NewDevice(device), time })
+}
@@ -1,70 +1,371 @@
-use input::Event;
+use input::{Event, Feedback};
 use serde::{Deserialize, Serialize};
-use std::convert::TryInto;
+use std::collections::VecDeque;
 use std::io::{Error, ErrorKind};
-use std::time::Duration;
+use std::mem;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
+mod compat;
+
 // Is it bold to assume there won't be more than 65536 protocol versions?
-pub const PROTOCOL_VERSION: u16 = 1;
+pub const PROTOCOL_VERSION: u16 = 13;
+// The oldest protocol version this build can still speak. Peers use the highest version they both
+// support, so as long as the ranges overlap, machines can be upgraded one at a time. Anything
+// added to the protocol after this version must only be used with peers that negotiated a version
+// that has it, and messages are serialized the way the negotiated version expects.
+pub const MIN_PROTOCOL_VERSION: u16 = 10;
 pub const MESSAGE_TIMEOUT: Duration = Duration::from_secs(5);
+// How long a receiver has to acknowledge that it was switched to before the sender gives up on it.
+pub const FOCUS_ACK_TIMEOUT: Duration = Duration::from_millis(500);
+
+// Wall clock time in microseconds, as used for timestamps in messages.
+pub fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+// Optional features a peer may support, as listed in its Hello.
+pub const FEATURE_FILES: &str = "files";
+pub const FEATURE_COMMANDS: &str = "commands";
+pub const FEATURE_FEEDBACK: &str = "feedback";
+pub const FEATURE_FOCUS_LOST: &str = "focus-lost";
+
+// Most bytes a Hello may take up.
+const MAX_HELLO_SIZE: usize = 64 * 1024;
+// The last protocol version that opened connections with nothing but the version, as 2 bytes.
+// Those are what a Hello's length starts with, and no Hello is short enough to start with them.
+const LAST_BARE_VERSION: u16 = 8;
+
+// Both ends send a Hello first thing on every connection. It is JSON, with a 32 bit length in
+// front, rather than bincode so that fields can be added without breaking older peers: unknown
+// fields are ignored, and missing ones get their defaults.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Hello {
+    // The newest protocol version the peer supports.
+    pub protocol_version: u16,
+    // The oldest protocol version the peer supports. Missing means the same as protocol_version.
+    pub min_protocol_version: u16,
+    pub role: Role,
+    // The version of evkvm the peer is running.
+    pub software_version: String,
+    pub hostname: String,
+    pub features: Vec<String>,
+    // What a receiver opened the connection for. Senders leave it at the default.
+    pub purpose: Purpose,
+    // Reserved for resuming a session after reconnecting. Nothing sends one yet.
+    pub resumption_token: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    #[default]
+    Sender,
+    Receiver,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Purpose {
+    // Carries input and everything else, for as long as the receiver stays connected.
+    #[default]
+    Input,
+    // Fetches a file offered with a FileOffer. The sender answers with the contents of the file
+    // and closes the connection.
+    FetchFile { id: u64 },
+    // Watches which receiver has focus and which ones are connected, for as long as the peer
+    // stays connected. The sender sends FocusChanged, ReceiverConnected and ReceiverDisconnected
+    // instead of input, starting with the current state. Only peers listed as observers may open
+    // one.
+    Observe,
+}
+
+impl Hello {
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|supported| supported == feature)
+    }
+
+    pub fn min_version(&self) -> u16 {
+        match self.min_protocol_version {
+            0 => self.protocol_version,
+            min => min,
+        }
+    }
+
+    // The protocol version to use with the peer that sent this Hello: the highest one both of us
+    // support, if there is one.
+    pub fn negotiate(&self) -> Option<u16> {
+        let version = self.protocol_version.min(PROTOCOL_VERSION);
+        if version >= self.min_version().max(MIN_PROTOCOL_VERSION) {
+            Some(version)
+        } else {
+            None
+        }
+    }
+}
 
-pub async fn read_version<R>(mut reader: R) -> Result<u16, Error>
+pub async fn read_hello<R>(mut reader: R) -> Result<Hello, Error>
 where
     R: AsyncRead + Unpin,
 {
-    let mut bytes = [0; 2];
-    reader.read_exact(&mut bytes).await?;
+    // Peers from before the Hello send their version and hang up once they get ours, so it is
+    // recognized before waiting for the rest of a length that never comes.
+    let mut length = [0; 4];
+    reader.read_exact(&mut length[..2]).await?;
+    let version = u16::from_le_bytes([length[0], length[1]]);
+    if (1..=LAST_BARE_VERSION).contains(&version) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Peer uses protocol version {}, which is no longer supported; upgrade it", version),
+        ));
+    }
+    reader.read_exact(&mut length[2..]).await?;
+    let length = u32::from_le_bytes(length) as usize;
+    if length > MAX_HELLO_SIZE {
+        return Err(Error::new(ErrorKind::InvalidData, "Hello is too large"));
+    }
 
-    Ok(u16::from_le_bytes(bytes))
+    let mut data = vec![0; length];
+    reader.read_exact(&mut data).await?;
+    serde_json::from_slice(&data).map_err(|err| Error::new(ErrorKind::InvalidData, err))
 }
 
-pub async fn write_version<W>(mut writer: W, version: u16) -> Result<(), Error>
+pub async fn write_hello<W>(mut writer: W, hello: &Hello) -> Result<(), Error>
 where
     W: AsyncWrite + Unpin,
 {
-    writer.write_all(&version.to_le_bytes()).await
+    let data = serde_json::to_vec(hello).map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+    writer.write_all(&(data.len() as u32).to_le_bytes()).await?;
+    writer.write_all(&data).await?;
+
+    Ok(())
 }
 
-pub async fn read_message<R>(mut reader: R) -> Result<Message, Error>
-where
-    R: AsyncRead + Unpin,
-{
-    let length = {
-        let mut bytes = [0; 4];
-        reader.read_exact(&mut bytes).await?;
-
-         (bytes[0] as u32) +
-        ((bytes[1] as u32) <<  8) +
-        ((bytes[2] as u32) << 16) +
-        ((bytes[3] as u32) << 24)
+// Messages are sent in frames of up to MAX_FRAME_SIZE bytes, each tagged with the channel its
+// message belongs to, so that the frames of a large message can be interleaved with messages on
+// other channels instead of holding them up. A frame starts with the channel, a byte that is 1
+// on the last frame of a message, and the length of the rest of the frame as a 16 bit integer,
+// followed by that part of the serialized message.
+pub const MAX_FRAME_SIZE: usize = 16 * 1024;
+// Messages are put back together in memory, so anything larger is refused.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+// In order of priority, highest first. Messages on the same channel are read in the order they
+// were sent, while a message on one channel can overtake those on a lower priority one. So
+// anything that has to stay in order with input goes on the input channel: a RemoveDevice can't
+// overtake input from the device it removes, and FocusLost can't overtake the last of the input
+// sent before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Channel {
+    // Input, and anything whose timing or order relative to input matters.
+    Input,
+    // Everything else that is small.
+    Control,
+    // Large transfers that can wait.
+    Bulk,
+}
+
+pub const CHANNELS: [Channel; 3] = [Channel::Input, Channel::Control, Channel::Bulk];
+
+impl Channel {
+    fn from_id(id: u8) -> Option<Channel> {
+        CHANNELS.get(id as usize).copied()
+    }
+}
+
+pub struct Frame {
+    pub channel: Channel,
+    pub end: bool,
+    pub data: Vec<u8>,
+}
+
+// Split a message into the frames it is sent in to a peer on protocol `version`. There are none if
+// the message means nothing to the peer.
+pub fn frames(message: &Message, version: u16) -> Result<Vec<Frame>, Error> {
+    let data = match compat::encode(message, version)? {
+        Some(data) => data,
+        None => return Ok(Vec::new()),
     };
+    if data.len() > MAX_MESSAGE_SIZE {
+        return Err(Error::new(ErrorKind::InvalidInput, "Serialized data is too large"));
+    }
 
-    let mut data = vec![0; length as usize];
-    reader.read_exact(&mut data).await?;
+    let channel = message.channel();
+    let count = data.len().div_ceil(MAX_FRAME_SIZE);
+    Ok(data
+        .chunks(MAX_FRAME_SIZE)
+        .enumerate()
+        .map(|(index, chunk)| Frame {
+            channel,
+            end: index + 1 == count,
+            data: chunk.to_vec(),
+        })
+        .collect())
+}
+
+// Frames waiting to be sent, by channel. They go out one at a time, always from the highest
+// priority channel that has any, so a large message on a lower priority channel only holds up
+// input for one frame. Within a channel, frames go out in the order their messages were pushed.
+pub struct FrameQueue {
+    version: u16,
+    queues: [VecDeque<Frame>; CHANNELS.len()],
+}
+
+impl FrameQueue {
+    // A queue of messages for a peer on protocol `version`.
+    pub fn new(version: u16) -> Self {
+        FrameQueue {
+            version,
+            queues: Default::default(),
+        }
+    }
+
+    pub fn push(&mut self, message: &Message) -> Result<(), Error> {
+        for frame in frames(message, self.version)? {
+            self.queues[frame.channel as usize].push_back(frame);
+        }
+        Ok(())
+    }
 
-    bincode::deserialize(&data).map_err(|err| Error::new(ErrorKind::InvalidData, err))
+    // The next frame to send, leaving the bulk channel out unless `bulk` is true, e.g. because
+    // it is over its rate.
+    pub fn pop(&mut self, bulk: bool) -> Option<Frame> {
+        let channel = CHANNELS.iter().copied().find(|&channel| {
+            !self.queues[channel as usize].is_empty() && (channel != Channel::Bulk || bulk)
+        })?;
+        self.queues[channel as usize].pop_front()
+    }
+
+    pub fn is_waiting(&self, channel: Channel) -> bool {
+        !self.queues[channel as usize].is_empty()
+    }
 }
 
-pub async fn write_message<W>(mut writer: W, message: &Message) -> Result<(), Error>
+pub async fn write_frame<W>(mut writer: W, frame: &Frame) -> Result<(), Error>
 where
     W: AsyncWrite + Unpin,
 {
-    let data =
-        bincode::serialize(&message).map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
-    let length: u32 = data
-        .len()
-        .try_into()
-        .map_err(|_| Error::new(ErrorKind::InvalidInput, "Serialized data is too large"))?;
-    writer.write_all(&length.to_le_bytes()).await?;
-    writer.write_all(&data).await?;
+    let length = (frame.data.len() as u16).to_le_bytes();
+    let header = [frame.channel as u8, frame.end as u8, length[0], length[1]];
+    writer.write_all(&header).await?;
+    writer.write_all(&frame.data).await?;
+
+    Ok(())
+}
+
+// Write a whole message at once, without interleaving it with anything else.
+pub async fn write_message<W>(mut writer: W, message: &Message, version: u16) -> Result<(), Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    for frame in frames(message, version)? {
+        write_frame(&mut writer, &frame).await?;
+    }
 
     Ok(())
 }
 
+// Reads messages, putting the ones that were split into frames back together.
+pub struct MessageReader<R> {
+    reader: R,
+    version: u16,
+    partial: [Vec<u8>; CHANNELS.len()],
+}
+
+impl<R> MessageReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    // A reader of messages from a peer on protocol `version`.
+    pub fn new(reader: R, version: u16) -> Self {
+        MessageReader {
+            reader,
+            version,
+            partial: Default::default(),
+        }
+    }
+
+    pub async fn read(&mut self) -> Result<Message, Error> {
+        loop {
+            let mut header = [0; 4];
+            self.reader.read_exact(&mut header).await?;
+            let channel = Channel::from_id(header[0])
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Unknown channel"))?;
+            let length = u16::from_le_bytes([header[2], header[3]]) as usize;
+
+            let partial = &mut self.partial[channel as usize];
+            if partial.len() + length > MAX_MESSAGE_SIZE {
+                return Err(Error::new(ErrorKind::InvalidData, "Message is too large"));
+            }
+            let start = partial.len();
+            partial.resize(start + length, 0);
+            self.reader.read_exact(&mut partial[start..]).await?;
+
+            if header[1] == 1 {
+                let data = mem::take(partial);
+                return compat::decode(&data, self.version);
+            }
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Message {
-    Event(Event),
-    // Sent only to keep the connection alive.
-    KeepAlive,
+    // `time` is when the event was read on the sender, converted to the receiver's clock. It is
+    // missing if the clocks haven't been compared yet or the event isn't real input.
+    Event { event: Event, time: Option<u64> },
+    // Sent by the sender to keep the connection alive. The receiver answers with a Pong carrying
+    // the same sequence number and time along with its own time, which lets the sender tell that
+    // the receiver is still there and estimate the offset between their clocks.
+    Ping { seq: u64, time: u64 },
+    Pong { seq: u64, ping_time: u64, time: u64 },
+    // Sent by the sender when switching to a receiver, which must answer with a FocusAck carrying
+    // the same id.
+    Focus { id: u64 },
+    FocusAck { id: u64 },
+    // Sent by `evkvm latency`. The receiver handles a probe like an input event, but instead of
+    // injecting anything it answers with a ProbeAck carrying the time it got to it.
+    Probe { id: u64, time: Option<u64> },
+    ProbeAck { id: u64, time: u64 },
+    // Asks the receiver to run one of the commands it has allowed the sender to run.
+    Command { name: String },
+    // Offers the receiver a file, which it can fetch over a connection of its own.
+    FileOffer { id: u64, name: String, size: u64 },
+    // Only sent on connections opened to observe. Peers that don't know these never open one, so
+    // they don't need a protocol version of their own. Receivers go by the name the sender calls
+    // them, and no receiver having focus means the sender itself does.
+    FocusChanged { receiver: Option<String> },
+    ReceiverConnected { name: String },
+    ReceiverDisconnected { name: String },
+    // Sent by the receiver when a program asked the virtual copy of a device for force feedback or
+    // to set its LEDs, for the sender to carry out on the device itself. Only sent to senders that
+    // support FEATURE_FEEDBACK.
+    Feedback { device_id: u16, feedback: Feedback },
+    // Sent by the sender when switching away from a receiver that supports FEATURE_FOCUS_LOST.
+    FocusLost,
+}
+
+impl Message {
+    pub fn channel(&self) -> Channel {
+        match self {
+            Message::Event { .. }
+            | Message::Focus { .. }
+            | Message::FocusAck { .. }
+            | Message::Probe { .. }
+            | Message::ProbeAck { .. }
+            | Message::Feedback { .. }
+            | Message::FocusLost => Channel::Input,
+            Message::Ping { .. }
+            | Message::Pong { .. }
+            | Message::Command { .. }
+            | Message::FileOffer { .. }
+            | Message::FocusChanged { .. }
+            | Message::ReceiverConnected { .. }
+            | Message::ReceiverDisconnected { .. } => Channel::Control,
+        }
+    }
 }
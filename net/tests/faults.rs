@@ -0,0 +1,171 @@
+use net::{Hello, Message, MessageReader, Role};
+use std::future::Future;
+use std::io::{Error, ErrorKind};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+use tokio::time::{self, Sleep};
+
+// Connections between senders and receivers go over real networks, which are slow, split writes
+// up, stall and drop. These put a wrapper between the two ends of an in-memory stream that does
+// all of that on purpose, and check that messages still come out whole and in order, or not at
+// all. Time is paused, so the delays cost nothing.
+
+#[derive(Clone, Copy, Default)]
+struct Faults {
+    // How long every write waits before going through.
+    latency: Duration,
+    // Most bytes a single write takes.
+    max_write: Option<usize>,
+    // After this many bytes, writes never complete, as if the network had gone quiet.
+    stall_after: Option<usize>,
+    // After this many bytes, the connection is gone: writes fail and the other end reads EOF.
+    drop_after: Option<usize>,
+}
+
+struct Faulty {
+    inner: Option<DuplexStream>,
+    faults: Faults,
+    delay: Option<Pin<Box<Sleep>>>,
+    written: usize,
+}
+
+impl Faulty {
+    fn new(inner: DuplexStream, faults: Faults) -> Self {
+        Faulty { inner: Some(inner), faults, delay: None, written: 0 }
+    }
+}
+
+impl AsyncRead for Faulty {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut ReadBuf) -> Poll<Result<(), Error>> {
+        match &mut self.get_mut().inner {
+            Some(inner) => Pin::new(inner).poll_read(cx, buf),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+impl AsyncWrite for Faulty {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+        if this.faults.drop_after.map(|limit| this.written >= limit).unwrap_or(false) {
+            this.inner = None;
+        }
+        if this.inner.is_none() {
+            return Poll::Ready(Err(ErrorKind::BrokenPipe.into()));
+        }
+        if this.faults.stall_after.map(|limit| this.written >= limit).unwrap_or(false) {
+            return Poll::Pending;
+        }
+
+        if !this.faults.latency.is_zero() {
+            let latency = this.faults.latency;
+            let delay = this.delay.get_or_insert_with(|| Box::pin(time::sleep(latency)));
+            if delay.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.delay = None;
+        }
+
+        // Writes stop short of wherever a fault kicks in, so that it happens at exactly that byte.
+        let until_fault = [this.faults.stall_after, this.faults.drop_after]
+            .iter()
+            .flatten()
+            .map(|&limit| limit - this.written)
+            .min();
+        let length = [this.faults.max_write, until_fault].iter().flatten().fold(buf.len(), |length, &limit| length.min(limit));
+        let result = Pin::new(this.inner.as_mut().unwrap()).poll_write(cx, &buf[..length]);
+        if let Poll::Ready(Ok(written)) = result {
+            this.written += written;
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        match &mut self.get_mut().inner {
+            Some(inner) => Pin::new(inner).poll_flush(cx),
+            None => Poll::Ready(Err(ErrorKind::BrokenPipe.into())),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        match &mut self.get_mut().inner {
+            Some(inner) => Pin::new(inner).poll_shutdown(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+// A faulty end to write to, and a plain one to read what comes through from.
+fn connection(faults: Faults) -> (Faulty, DuplexStream) {
+    let (writer, reader) = tokio::io::duplex(256);
+    (Faulty::new(writer, faults), reader)
+}
+
+fn messages() -> Vec<Message> {
+    vec![
+        Message::Ping { seq: 1, time: 2 },
+        Message::Command { name: "a".repeat(net::MAX_FRAME_SIZE + 5) },
+        Message::Focus { id: 3 },
+        Message::FocusLost,
+    ]
+}
+
+#[tokio::test(start_paused = true)]
+async fn messages_survive_latency_and_partial_writes() {
+    let faults = Faults { latency: Duration::from_millis(20), max_write: Some(3), ..Faults::default() };
+    let (mut writer, reader) = connection(faults);
+
+    let sent = messages();
+    let expected: Vec<String> = sent.iter().map(|message| format!("{:?}", message)).collect();
+    let write = tokio::spawn(async move {
+        for message in &sent {
+            net::write_message(&mut writer, message, net::PROTOCOL_VERSION).await.unwrap();
+        }
+    });
+
+    let mut reader = MessageReader::new(reader, net::PROTOCOL_VERSION);
+    for expected in expected {
+        let message = reader.read().await.unwrap();
+        assert_eq!(format!("{:?}", message), expected);
+    }
+    write.await.unwrap();
+}
+
+// The server and client give up on a Hello that doesn't arrive within MESSAGE_TIMEOUT. One that
+// stops halfway must not be taken for a whole one meanwhile.
+#[tokio::test(start_paused = true)]
+async fn stalled_hello_times_out() {
+    let (mut writer, mut reader) = connection(Faults { stall_after: Some(6), ..Faults::default() });
+    let hello = Hello { role: Role::Receiver, ..Hello::default() };
+    let _write = tokio::spawn(async move { net::write_hello(&mut writer, &hello).await });
+
+    let result = time::timeout(net::MESSAGE_TIMEOUT, net::read_hello(&mut reader)).await;
+    assert!(result.is_err(), "Read a Hello that was never sent in full");
+}
+
+// What was sent before the connection dropped is read as usual, and the message it dropped in the
+// middle of is an error rather than a truncated message.
+#[tokio::test(start_paused = true)]
+async fn dropped_connection_loses_only_the_unfinished_message() {
+    let ping = Message::Ping { seq: 1, time: 2 };
+    let ping_length: usize = net::frames(&ping, net::PROTOCOL_VERSION).unwrap().iter().map(|frame| 4 + frame.data.len()).sum();
+    let faults = Faults { drop_after: Some(ping_length + 100), max_write: Some(7), ..Faults::default() };
+    let (mut writer, reader) = connection(faults);
+
+    let write = tokio::spawn(async move {
+        net::write_message(&mut writer, &ping, net::PROTOCOL_VERSION).await.unwrap();
+        let command = Message::Command { name: "a".repeat(1000) };
+        net::write_message(&mut writer, &command, net::PROTOCOL_VERSION).await
+    });
+
+    let mut reader = MessageReader::new(reader, net::PROTOCOL_VERSION);
+    match reader.read().await.unwrap() {
+        Message::Ping { seq: 1, time: 2 } => {}
+        message => panic!("Read {:?}", message),
+    }
+    let err = reader.read().await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    assert_eq!(write.await.unwrap().unwrap_err().kind(), ErrorKind::BrokenPipe);
+}
@@ -0,0 +1,107 @@
+use input::{Direction, Event, InputEvent, Key, KeyKind};
+use net::{Channel, FrameQueue, Message, MessageReader};
+use proptest::prelude::*;
+
+// Messages on a channel must be read in the order they were queued, however they are interleaved
+// with those on other channels, since the receiver relies on it: input from a device can't be
+// overtaken by the RemoveDevice after it, or by FocusLost. These check that for the frames of any
+// mix of messages, including ones large enough to be split, taken from the queue at any point.
+
+#[derive(Clone, Debug)]
+enum Step {
+    Push(Message),
+    // Send the next frame, if any.
+    Send,
+}
+
+fn key(device_id: u16, down: bool) -> Message {
+    let direction = if down { Direction::Down } else { Direction::Up };
+    Message::Event {
+        event: Event::Input {
+            device_id,
+            input: InputEvent::Key { direction, kind: KeyKind::Key(Key::A) },
+            syn: true,
+        },
+        time: None,
+    }
+}
+
+fn message() -> impl Strategy<Value = Message> {
+    prop_oneof![
+        (0..4u16, any::<bool>()).prop_map(|(device_id, down)| key(device_id, down)),
+        (0..4u16).prop_map(|device_id| Message::Event { event: Event::RemoveDevice(device_id), time: None }),
+        Just(Message::FocusLost),
+        (0..3 * net::MAX_FRAME_SIZE).prop_map(|length| Message::Command { name: "a".repeat(length) }),
+        any::<u64>().prop_map(|seq| Message::Ping { seq, time: 0 }),
+    ]
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    prop_oneof![message().prop_map(Step::Push), Just(Step::Send)]
+}
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread().build().unwrap()
+}
+
+// Message doesn't implement PartialEq, but its debug output says everything about it.
+fn on_channel(messages: &[Message], channel: Channel) -> Vec<String> {
+    messages
+        .iter()
+        .filter(|message| message.channel() == channel)
+        .map(|message| format!("{:?}", message))
+        .collect()
+}
+
+proptest! {
+    #[test]
+    fn channels_keep_their_order(steps in prop::collection::vec(step(), 0..48)) {
+        let mut queue = FrameQueue::new(net::PROTOCOL_VERSION);
+        let mut pushed = Vec::new();
+        let mut frames = Vec::new();
+        for step in steps {
+            match step {
+                Step::Push(message) => {
+                    queue.push(&message).unwrap();
+                    pushed.push(message);
+                }
+                Step::Send => frames.extend(queue.pop(true)),
+            }
+        }
+        while let Some(frame) = queue.pop(true) {
+            frames.push(frame);
+        }
+
+        let read = runtime().block_on(async {
+            let mut written = Vec::new();
+            for frame in &frames {
+                net::write_frame(&mut written, frame).await.unwrap();
+            }
+            let mut reader = MessageReader::new(&written[..], net::PROTOCOL_VERSION);
+            let mut read = Vec::new();
+            for _ in 0..pushed.len() {
+                read.push(reader.read().await.unwrap());
+            }
+            assert!(reader.read().await.is_err());
+            read
+        });
+
+        for &channel in &net::CHANNELS {
+            prop_assert_eq!(on_channel(&pushed, channel), on_channel(&read, channel));
+        }
+    }
+
+    #[test]
+    fn input_goes_first(messages in prop::collection::vec(message(), 0..16)) {
+        let mut queue = FrameQueue::new(net::PROTOCOL_VERSION);
+        for message in &messages {
+            queue.push(message).unwrap();
+        }
+
+        let mut channels = Vec::new();
+        while let Some(frame) = queue.pop(true) {
+            channels.push(frame.channel);
+        }
+        prop_assert!(channels.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", channels);
+    }
+}
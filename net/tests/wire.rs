@@ -0,0 +1,562 @@
+use input::{Button, Device, Direction, Event, Feedback, InputEvent, Key, KeyKind};
+use net::{Channel, Frame, Hello, Message, MessageReader, Purpose, Role};
+use std::io::{Error, ErrorKind};
+
+// Messages as serialized by each protocol version from MIN_PROTOCOL_VERSION to PROTOCOL_VERSION.
+// Peers on the same protocol version must agree on these bytes exactly, so a change to any of them,
+// such as from reordering enum variants, has to come with a protocol version bump and new goldens,
+// and the old goldens stay for as long as the old version is supported. Most messages are
+// serialized the same way by every version, so only the ones that differ have a golden per version.
+
+const EVENT_KEY: &[u8] = &[
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf2, 0x00,
+    0x00, 0x00, 0x01, 0x01, 0x40, 0x42, 0x0f, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+const EVENT_UNKNOWN_KEY: &[u8] = &[
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x01,
+    0x00, 0x00, 0xff, 0x02, 0x01, 0x00,
+];
+
+const EVENT_BUTTON: &[u8] = &[
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x17, 0x00,
+    0x00, 0x00, 0x00, 0x00,
+];
+
+const EVENT_OTHER: &[u8] = &[
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x01, 0x00,
+    0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0xfb, 0xff, 0xff, 0xff, 0x01, 0x00,
+];
+
+// Devices as version 10 has them, without uniq, phys or properties.
+const EVENT_NEW_DEVICE_V10: &[u8] = &[
+    0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x03, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x6b, 0x62, 0x64, 0x6d, 0x04, 0x2b,
+    0xc5, 0x03, 0x00, 0x11, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x01, 0x00, 0x00,
+];
+
+// Version 11 added uniq and phys.
+const EVENT_NEW_DEVICE_V11: &[u8] = &[
+    0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x03, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x6b, 0x62, 0x64, 0x04, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x30, 0x31, 0x32, 0x33, 0x0c, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x75, 0x73, 0x62, 0x2d, 0x31, 0x2f, 0x69,
+    0x6e, 0x70, 0x75, 0x74, 0x30, 0x6d, 0x04, 0x2b, 0xc5, 0x03, 0x00, 0x11,
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+];
+
+// Version 12 added properties.
+const EVENT_NEW_DEVICE: &[u8] = &[
+    0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x03, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x6b, 0x62, 0x64, 0x04, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x30, 0x31, 0x32, 0x33, 0x0c, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x75, 0x73, 0x62, 0x2d, 0x31, 0x2f, 0x69,
+    0x6e, 0x70, 0x75, 0x74, 0x30, 0x6d, 0x04, 0x2b, 0xc5, 0x03, 0x00, 0x11,
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+];
+
+// A device with capabilities, as version 10 has it.
+const EVENT_NEW_DEVICE_WITH_CAPABILITIES_V10: &[u8] = &[
+    0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x03, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x6b, 0x62, 0x64, 0x6d, 0x04, 0x2b,
+    0xc5, 0x03, 0x00, 0x11, 0x01, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x1e, 0x00, 0x01, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80,
+    0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0a,
+    0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0xfa, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+// Version 11 added uniq and phys.
+const EVENT_NEW_DEVICE_WITH_CAPABILITIES_V11: &[u8] = &[
+    0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x03, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x6b, 0x62, 0x64, 0x04, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x30, 0x31, 0x32, 0x33, 0x0c, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x75, 0x73, 0x62, 0x2d, 0x31, 0x2f, 0x69,
+    0x6e, 0x70, 0x75, 0x74, 0x30, 0x6d, 0x04, 0x2b, 0xc5, 0x03, 0x00, 0x11,
+    0x01, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x01, 0x00, 0x1e, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x07, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x00, 0x02,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0xfa, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00,
+];
+
+// Version 12 added properties.
+const EVENT_NEW_DEVICE_WITH_CAPABILITIES: &[u8] = &[
+    0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x03, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x6b, 0x62, 0x64, 0x04, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x30, 0x31, 0x32, 0x33, 0x0c, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x75, 0x73, 0x62, 0x2d, 0x31, 0x2f, 0x69,
+    0x6e, 0x70, 0x75, 0x74, 0x30, 0x6d, 0x04, 0x2b, 0xc5, 0x03, 0x00, 0x11,
+    0x01, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x01, 0x00, 0x1e, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x07, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x00, 0x02,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0xfa, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00,
+];
+
+const EVENT_REMOVE_DEVICE: &[u8] = &[
+    0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+];
+
+const PING: &[u8] = &[
+    0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+const PONG: &[u8] = &[
+    0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00,
+];
+
+const FOCUS: &[u8] = &[
+    0x03, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+const FOCUS_ACK: &[u8] = &[
+    0x04, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+const PROBE: &[u8] = &[
+    0x05, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x09, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+const PROBE_ACK: &[u8] = &[
+    0x06, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x0a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+const COMMAND: &[u8] = &[
+    0x07, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x6c, 0x6f, 0x63, 0x6b,
+];
+
+const FILE_OFFER: &[u8] = &[
+    0x08, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x61, 0x2e, 0x74, 0x78,
+    0x74, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+const FOCUS_CHANGED: &[u8] = &[
+    0x09, 0x00, 0x00, 0x00, 0x01, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x6c, 0x61, 0x70, 0x74, 0x6f, 0x70,
+];
+
+const RECEIVER_CONNECTED: &[u8] = &[
+    0x0a, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x6c, 0x61, 0x70, 0x74, 0x6f, 0x70,
+];
+
+const RECEIVER_DISCONNECTED: &[u8] = &[
+    0x0b, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x6c, 0x61, 0x70, 0x74, 0x6f, 0x70,
+];
+
+const FEEDBACK: &[u8] = &[
+    0x0c, 0x00, 0x00, 0x00, 0x03, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x00, 0x00,
+];
+
+const FOCUS_LOST: &[u8] = &[0x0d, 0x00, 0x00, 0x00];
+
+fn key(direction: Direction, kind: KeyKind, syn: bool, time: Option<u64>) -> Message {
+    Message::Event {
+        event: Event::Input {
+            device_id: 3,
+            input: InputEvent::Key { direction, kind },
+            syn,
+        },
+        time,
+    }
+}
+
+fn device() -> Device {
+    Device {
+        id: 1,
+        name: "kbd".to_string(),
+        uniq: "0123".to_string(),
+        phys: "usb-1/input0".to_string(),
+        vendor: 0x046d,
+        product: 0xc52b,
+        bustype: 3,
+        version: 0x111,
+        capabilities: vec![],
+        properties: vec![],
+        held_keys: vec![KeyKind::Key(Key::A)],
+        lit_leds: vec![1],
+    }
+}
+
+// Every message protocol `version` has, along with how it is serialized.
+fn goldens(version: u16) -> Vec<(&'static str, Message, &'static [u8])> {
+    let new_device = match version {
+        10 => EVENT_NEW_DEVICE_V10,
+        11 => EVENT_NEW_DEVICE_V11,
+        _ => EVENT_NEW_DEVICE,
+    };
+    let mut goldens = vec![
+        (
+            "key",
+            key(Direction::Down, KeyKind::Key(Key::LeftCtrl), true, Some(1_000_000)),
+            EVENT_KEY,
+        ),
+        (
+            "button",
+            key(Direction::Up, KeyKind::Button(Button::Left), false, None),
+            EVENT_BUTTON,
+        ),
+        (
+            "other input",
+            Message::Event {
+                event: Event::Input {
+                    device_id: 3,
+                    input: InputEvent::Other { type_: 2, code: 0, value: -5 },
+                    syn: true,
+                },
+                time: None,
+            },
+            EVENT_OTHER,
+        ),
+        (
+            "new device",
+            Message::Event { event: Event::NewDevice(device()), time: None },
+            new_device,
+        ),
+        (
+            "remove device",
+            Message::Event { event: Event::RemoveDevice(1), time: None },
+            EVENT_REMOVE_DEVICE,
+        ),
+        ("ping", Message::Ping { seq: 1, time: 2 }, PING),
+        ("pong", Message::Pong { seq: 1, ping_time: 2, time: 3 }, PONG),
+        ("focus", Message::Focus { id: 7 }, FOCUS),
+        ("focus ack", Message::FocusAck { id: 7 }, FOCUS_ACK),
+        ("probe", Message::Probe { id: 8, time: Some(9) }, PROBE),
+        ("probe ack", Message::ProbeAck { id: 8, time: 10 }, PROBE_ACK),
+        ("command", Message::Command { name: "lock".to_string() }, COMMAND),
+        (
+            "file offer",
+            Message::FileOffer { id: 1, name: "a.txt".to_string(), size: 5 },
+            FILE_OFFER,
+        ),
+        (
+            "focus changed",
+            Message::FocusChanged { receiver: Some("laptop".to_string()) },
+            FOCUS_CHANGED,
+        ),
+        (
+            "receiver connected",
+            Message::ReceiverConnected { name: "laptop".to_string() },
+            RECEIVER_CONNECTED,
+        ),
+        (
+            "receiver disconnected",
+            Message::ReceiverDisconnected { name: "laptop".to_string() },
+            RECEIVER_DISCONNECTED,
+        ),
+        (
+            "feedback",
+            Message::Feedback { device_id: 3, feedback: Feedback::Play { code: 0, value: 1 } },
+            FEEDBACK,
+        ),
+        ("focus lost", Message::FocusLost, FOCUS_LOST),
+    ];
+    if version >= 13 {
+        goldens.push((
+            "unknown key",
+            key(Direction::Up, KeyKind::Key(Key::Other(0x2ff)), true, None),
+            EVENT_UNKNOWN_KEY,
+        ));
+    }
+    goldens
+}
+
+// A device with capabilities, as protocol `version` serializes it. Capabilities can't be built
+// outside of the input crate, so these are only read back.
+fn device_with_capabilities(version: u16) -> &'static [u8] {
+    match version {
+        10 => EVENT_NEW_DEVICE_WITH_CAPABILITIES_V10,
+        11 => EVENT_NEW_DEVICE_WITH_CAPABILITIES_V11,
+        _ => EVENT_NEW_DEVICE_WITH_CAPABILITIES,
+    }
+}
+
+fn versions() -> impl Iterator<Item = u16> {
+    net::MIN_PROTOCOL_VERSION..=net::PROTOCOL_VERSION
+}
+
+// Serialize a message the way it is sent to a peer on `version`.
+fn encode(message: &Message, version: u16) -> Vec<u8> {
+    let frames = net::frames(message, version).unwrap();
+    frames.into_iter().flat_map(|frame| frame.data).collect()
+}
+
+// Read a message the way a peer on `version` would, from a single frame.
+async fn decode(data: &[u8], version: u16) -> Result<Message, Error> {
+    let frame = Frame { channel: Channel::Input, end: true, data: data.to_vec() };
+    let mut written = Vec::new();
+    net::write_frame(&mut written, &frame).await?;
+    MessageReader::new(&written[..], version).read().await
+}
+
+#[test]
+fn goldens_cover_supported_versions() {
+    // If this fails, the supported versions changed: record goldens for a new version, and drop
+    // the ones for versions no longer supported.
+    assert_eq!((net::MIN_PROTOCOL_VERSION, net::PROTOCOL_VERSION), (10, 13));
+}
+
+#[test]
+fn messages_serialize_to_goldens() {
+    for version in versions() {
+        for (name, message, golden) in goldens(version) {
+            assert_eq!(encode(&message, version), golden, "{} in version {}", name, version);
+        }
+    }
+}
+
+#[tokio::test]
+async fn goldens_round_trip() {
+    for version in versions() {
+        let mut goldens: Vec<&[u8]> =
+            goldens(version).into_iter().map(|(_, _, golden)| golden).collect();
+        goldens.push(device_with_capabilities(version));
+
+        for golden in goldens {
+            let message = decode(golden, version).await.unwrap();
+            assert_eq!(encode(&message, version), golden, "{:?} in version {}", message, version);
+        }
+    }
+}
+
+#[tokio::test]
+async fn device_with_capabilities_decodes() {
+    for version in versions() {
+        match decode(device_with_capabilities(version), version).await.unwrap() {
+            Message::Event { event: Event::NewDevice(device), time: None } => {
+                assert_eq!(device.name, "kbd");
+                assert_eq!(device.uniq, if version < 11 { "" } else { "0123" });
+                assert_eq!(device.capabilities.len(), 3);
+                assert_eq!(device.properties, if version < 12 { vec![] } else { vec![0, 2] });
+                assert!(device.held_keys.is_empty());
+            }
+            message => panic!("Decoded as {:?} in version {}", message, version),
+        }
+    }
+}
+
+#[tokio::test]
+async fn truncated_messages_are_refused() {
+    for version in versions() {
+        for (name, _, golden) in goldens(version) {
+            let truncated = &golden[..golden.len() - 1];
+            assert!(decode(truncated, version).await.is_err(), "{} in version {}", name, version);
+        }
+    }
+}
+
+#[test]
+fn messages_use_their_channels() {
+    for (name, message, _) in goldens(net::PROTOCOL_VERSION) {
+        let expected = match name {
+            "ping" | "pong" | "command" | "file offer" | "focus changed" | "receiver connected"
+            | "receiver disconnected" => Channel::Control,
+            _ => Channel::Input,
+        };
+        assert_eq!(message.channel(), expected, "{}", name);
+    }
+}
+
+#[test]
+fn small_message_is_one_frame() {
+    let frames = net::frames(&Message::Ping { seq: 1, time: 2 }, net::PROTOCOL_VERSION).unwrap();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].channel, Channel::Control);
+    assert!(frames[0].end);
+    assert_eq!(frames[0].data, PING);
+}
+
+#[test]
+fn large_message_is_split() {
+    let name = "a".repeat(net::MAX_FRAME_SIZE * 2);
+    let message = Message::FileOffer { id: 1, name, size: 5 };
+    let data = bincode::serialize(&message).unwrap();
+
+    let frames = net::frames(&message, net::PROTOCOL_VERSION).unwrap();
+    assert_eq!(frames.len(), 3);
+    assert!(frames.iter().all(|frame| frame.channel == Channel::Control));
+    assert!(frames.iter().all(|frame| frame.data.len() <= net::MAX_FRAME_SIZE));
+    assert_eq!(
+        frames.iter().map(|frame| frame.end).collect::<Vec<_>>(),
+        vec![false, false, true]
+    );
+    let joined: Vec<u8> = frames.iter().flat_map(|frame| frame.data.iter().copied()).collect();
+    assert_eq!(joined, data);
+}
+
+#[tokio::test]
+async fn frame_header_layout() {
+    let mut written = Vec::new();
+    net::write_message(&mut written, &Message::Focus { id: 7 }, net::PROTOCOL_VERSION).await.unwrap();
+    // Input channel, last frame, 12 bytes.
+    assert_eq!(&written[..4], &[0, 1, 12, 0]);
+    assert_eq!(&written[4..], FOCUS);
+}
+
+#[tokio::test]
+async fn reader_reassembles_interleaved_frames() {
+    let offer = Message::FileOffer {
+        id: 1,
+        name: "a".repeat(net::MAX_FRAME_SIZE),
+        size: 5,
+    };
+    let mut offer_frames = net::frames(&offer, net::PROTOCOL_VERSION).unwrap().into_iter();
+    let focus_frames = net::frames(&Message::Focus { id: 7 }, net::PROTOCOL_VERSION).unwrap();
+
+    // The focus goes out between the two halves of the offer, the way the writer would send it.
+    let mut written = Vec::new();
+    net::write_frame(&mut written, &offer_frames.next().unwrap()).await.unwrap();
+    net::write_frame(&mut written, &focus_frames[0]).await.unwrap();
+    for frame in offer_frames {
+        net::write_frame(&mut written, &frame).await.unwrap();
+    }
+
+    let mut reader = MessageReader::new(&written[..], net::PROTOCOL_VERSION);
+    match reader.read().await.unwrap() {
+        Message::Focus { id: 7 } => {}
+        message => panic!("Read {:?}", message),
+    }
+    match reader.read().await.unwrap() {
+        Message::FileOffer { name, .. } => assert_eq!(name.len(), net::MAX_FRAME_SIZE),
+        message => panic!("Read {:?}", message),
+    }
+    assert!(reader.read().await.is_err());
+}
+
+// Each older version gets devices without the fields it doesn't have, and without keys it doesn't
+// know.
+#[tokio::test]
+async fn devices_are_sent_the_way_each_version_has_them() {
+    let mut device = device();
+    device.properties = vec![1];
+    device.held_keys.push(KeyKind::Key(Key::Dictate));
+    let message = Message::Event { event: Event::NewDevice(device), time: Some(5) };
+
+    for version in versions() {
+        let mut written = Vec::new();
+        net::write_message(&mut written, &message, version).await.unwrap();
+        let device = match MessageReader::new(&written[..], version).read().await.unwrap() {
+            Message::Event { event: Event::NewDevice(device), time: Some(5) } => device,
+            message => panic!("Read {:?}", message),
+        };
+        assert_eq!(device.name, "kbd", "{}", version);
+        assert_eq!(device.lit_leds, vec![1], "{}", version);
+        assert_eq!(device.uniq.is_empty(), version < 11, "{}", version);
+        assert_eq!(device.phys.is_empty(), version < 11, "{}", version);
+        assert_eq!(device.properties.is_empty(), version < 12, "{}", version);
+        assert_eq!(device.held_keys.len(), if version < 13 { 1 } else { 2 }, "{}", version);
+    }
+}
+
+#[test]
+fn keys_are_only_sent_to_versions_that_know_them() {
+    for version in versions() {
+        let known = key(Direction::Down, KeyKind::Key(Key::A), true, None);
+        assert_eq!(net::frames(&known, version).unwrap().len(), 1, "{}", version);
+        for unknown in [Key::Dictate, Key::Other(0x2ff)] {
+            let message = key(Direction::Down, KeyKind::Key(unknown), true, None);
+            let count = net::frames(&message, version).unwrap().len();
+            assert_eq!(count, if version < 13 { 0 } else { 1 }, "{}", version);
+        }
+    }
+}
+
+#[tokio::test]
+async fn reader_refuses_unknown_channel() {
+    let mut reader = MessageReader::new(&[3, 1, 0, 0][..], net::PROTOCOL_VERSION);
+    assert!(reader.read().await.is_err());
+}
+
+#[tokio::test]
+async fn hello_round_trips() {
+    let hello = Hello {
+        protocol_version: net::PROTOCOL_VERSION,
+        min_protocol_version: net::MIN_PROTOCOL_VERSION,
+        role: Role::Receiver,
+        software_version: "0.2.0".to_string(),
+        hostname: "laptop".to_string(),
+        features: vec![net::FEATURE_FILES.to_string()],
+        purpose: Purpose::FetchFile { id: 4 },
+        resumption_token: None,
+    };
+    let mut written = Vec::new();
+    net::write_hello(&mut written, &hello).await.unwrap();
+
+    let read = net::read_hello(&written[..]).await.unwrap();
+    assert_eq!(read.protocol_version, net::PROTOCOL_VERSION);
+    assert_eq!(read.role, Role::Receiver);
+    assert_eq!(read.hostname, "laptop");
+    assert!(read.supports(net::FEATURE_FILES));
+    assert!(!read.supports(net::FEATURE_COMMANDS));
+    assert_eq!(read.purpose, Purpose::FetchFile { id: 4 });
+}
+
+// Peers from before the Hello open with a bare 2-byte version and hang up once they get ours.
+#[tokio::test]
+async fn bare_version_is_taken_for_an_old_peer() {
+    let err = net::read_hello(&8u16.to_le_bytes()[..]).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert!(err.to_string().contains("protocol version 8"), "{}", err);
+}
+
+// A Hello from a peer that knows fewer or more fields than this one must still be understood.
+#[test]
+fn hello_tolerates_missing_and_unknown_fields() {
+    let hello: Hello =
+        serde_json::from_str(r#"{"protocol-version": 9, "role": "receiver", "flavor": "new"}"#)
+            .unwrap();
+    assert_eq!(hello.protocol_version, 9);
+    assert_eq!(hello.role, Role::Receiver);
+    assert!(hello.features.is_empty());
+    assert_eq!(hello.purpose, Purpose::Input);
+}
+
+#[test]
+fn versions_are_negotiated() {
+    let hello = |min_protocol_version, protocol_version| Hello {
+        protocol_version,
+        min_protocol_version,
+        ..Hello::default()
+    };
+    let (min, max) = (net::MIN_PROTOCOL_VERSION, net::PROTOCOL_VERSION);
+
+    assert_eq!(hello(min, max).negotiate(), Some(max));
+    // A newer peer that can still speak our version.
+    assert_eq!(hello(min, max + 1).negotiate(), Some(max));
+    // A newer peer that can't.
+    assert_eq!(hello(max + 1, max + 1).negotiate(), None);
+    // An older peer.
+    assert_eq!(hello(min - 1, min - 1).negotiate(), None);
+    // A peer that only gave a single version.
+    assert_eq!(hello(0, max).negotiate(), Some(max));
+    assert_eq!(hello(0, max + 1).negotiate(), None);
+}